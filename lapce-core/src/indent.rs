@@ -1,6 +1,9 @@
 use xi_rope::Rope;
 
-use crate::chars::{char_is_line_ending, char_is_whitespace};
+use crate::{
+    chars::{char_is_line_ending, char_is_whitespace},
+    language::LapceLanguage,
+};
 
 /// Enum representing indentation style.
 ///
@@ -170,3 +173,144 @@ pub fn auto_detect_indent_style(document_text: &Rope) -> Option<IndentStyle> {
         None
     }
 }
+
+/// The indentation of a new line inserted after `before`, the part of a
+/// line up to the cursor, going by the rules of the language. `indent` is
+/// the indentation of the line and `unit` is one level of indentation.
+pub fn new_line_indent(
+    language: LapceLanguage,
+    before: &str,
+    indent: &str,
+    unit: &str,
+) -> String {
+    let code = strip_comment(before, language.comment_token()).trim_end();
+    if has_unclosed_bracket(code)
+        || language
+            .indent_triggers()
+            .iter()
+            .any(|token| ends_with_token(code, token))
+    {
+        return format!("{indent}{unit}");
+    }
+
+    let first_word = code
+        .trim_start()
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or("");
+    if language.dedent_keywords().contains(&first_word) {
+        return dedent(indent, unit).to_string();
+    }
+    indent.to_string()
+}
+
+/// The indentation one level less than `indent`
+pub fn dedent<'a>(indent: &'a str, unit: &str) -> &'a str {
+    if let Some(indent) = indent.strip_suffix(unit) {
+        return indent;
+    }
+    if let Some(indent) = indent.strip_suffix('\t') {
+        return indent;
+    }
+    let spaces = indent.len() - indent.trim_end_matches(' ').len();
+    &indent[..indent.len() - spaces.min(unit.len())]
+}
+
+/// The line without a trailing line comment, which has to start the line or
+/// follow whitespace so that things like urls in strings are kept
+fn strip_comment<'a>(line: &'a str, comment_token: &str) -> &'a str {
+    if comment_token.is_empty() {
+        return line;
+    }
+    line.match_indices(comment_token)
+        .find(|(i, _)| {
+            line[..*i]
+                .chars()
+                .last()
+                .map(|c| c.is_whitespace())
+                .unwrap_or(true)
+        })
+        .map(|(i, _)| &line[..i])
+        .unwrap_or(line)
+}
+
+/// Whether the line opens a bracket that it doesn't close again
+fn has_unclosed_bracket(line: &str) -> bool {
+    let mut open = Vec::new();
+    for c in line.chars() {
+        match c {
+            '(' | '[' | '{' => open.push(c),
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if open.last() == Some(&expected) {
+                    open.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+    !open.is_empty()
+}
+
+/// Whether the line ends with the token, where a token that's a word
+/// mustn't be the end of a longer word
+fn ends_with_token(line: &str, token: &str) -> bool {
+    let rest = match line.strip_suffix(token) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    !token.starts_with(is_word_char)
+        || !rest.chars().last().map(is_word_char).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_line_indent() {
+        let rust = LapceLanguage::Rust;
+        assert_eq!(new_line_indent(rust, "fn x() {", "", "    "), "    ");
+        assert_eq!(
+            new_line_indent(rust, "    let a = foo(", "    ", "    "),
+            "        "
+        );
+        assert_eq!(
+            new_line_indent(rust, "    let a = foo(b);", "    ", "    "),
+            "    "
+        );
+        assert_eq!(new_line_indent(rust, "    } // {", "    ", "    "), "    ");
+        assert_eq!(new_line_indent(rust, "\tif a {", "\t", "\t"), "\t\t");
+
+        let python = LapceLanguage::Python;
+        assert_eq!(
+            new_line_indent(python, "def x(): # comment", "", "    "),
+            "    "
+        );
+        assert_eq!(
+            new_line_indent(python, "        return a", "        ", "    "),
+            "    "
+        );
+        assert_eq!(
+            new_line_indent(python, "    returned = 1", "    ", "    "),
+            "    "
+        );
+
+        let elixir = LapceLanguage::Elixir;
+        assert_eq!(new_line_indent(elixir, "def x do", "", "  "), "  ");
+        assert_eq!(new_line_indent(elixir, "undo", "", "  "), "");
+    }
+
+    #[test]
+    fn test_dedent() {
+        assert_eq!(dedent("        ", "    "), "    ");
+        assert_eq!(dedent("\t\t", "    "), "\t");
+        assert_eq!(dedent("  ", "    "), "");
+        assert_eq!(dedent("", "    "), "");
+    }
+}
@@ -51,6 +51,23 @@ pub enum LapceLanguage {
 }
 
 impl LapceLanguage {
+    /// Every supported language, in the order they're listed to pick from
+    pub const ALL: &'static [LapceLanguage] = &[
+        LapceLanguage::C,
+        LapceLanguage::Cpp,
+        LapceLanguage::Elixir,
+        LapceLanguage::Go,
+        LapceLanguage::Javascript,
+        LapceLanguage::Json,
+        LapceLanguage::Jsx,
+        LapceLanguage::Php,
+        LapceLanguage::Python,
+        LapceLanguage::Rust,
+        LapceLanguage::Toml,
+        LapceLanguage::Tsx,
+        LapceLanguage::Typescript,
+    ];
+
     pub fn from_path(path: &Path) -> Option<LapceLanguage> {
         let extension = path.extension()?.to_str()?;
         Some(match extension {
@@ -71,6 +88,25 @@ impl LapceLanguage {
         })
     }
 
+    /// The name of the language in the settings, like `[language.rust]`
+    pub fn name(&self) -> &'static str {
+        match self {
+            LapceLanguage::Rust => "rust",
+            LapceLanguage::Go => "go",
+            LapceLanguage::Javascript => "javascript",
+            LapceLanguage::Jsx => "jsx",
+            LapceLanguage::Typescript => "typescript",
+            LapceLanguage::Tsx => "tsx",
+            LapceLanguage::Python => "python",
+            LapceLanguage::Toml => "toml",
+            LapceLanguage::Php => "php",
+            LapceLanguage::Elixir => "elixir",
+            LapceLanguage::C => "c",
+            LapceLanguage::Cpp => "cpp",
+            LapceLanguage::Json => "json",
+        }
+    }
+
     pub fn comment_token(&self) -> &str {
         match self {
             LapceLanguage::Rust => "//",
@@ -89,6 +125,26 @@ impl LapceLanguage {
         }
     }
 
+    /// The tokens that open and close a block comment, `None` for the
+    /// languages without block comments
+    pub fn block_comment_tokens(&self) -> Option<(&str, &str)> {
+        match self {
+            LapceLanguage::Rust
+            | LapceLanguage::Go
+            | LapceLanguage::Javascript
+            | LapceLanguage::Jsx
+            | LapceLanguage::Typescript
+            | LapceLanguage::Tsx
+            | LapceLanguage::Php
+            | LapceLanguage::C
+            | LapceLanguage::Cpp => Some(("/*", "*/")),
+            LapceLanguage::Python
+            | LapceLanguage::Toml
+            | LapceLanguage::Elixir
+            | LapceLanguage::Json => None,
+        }
+    }
+
     pub fn indent_unit(&self) -> &str {
         match self {
             LapceLanguage::Rust => "    ",
@@ -107,6 +163,27 @@ impl LapceLanguage {
         }
     }
 
+    /// The tokens that open an indented block when a line ends with them,
+    /// on top of the opening brackets that every language indents after
+    pub fn indent_triggers(&self) -> &[&str] {
+        match self {
+            LapceLanguage::Python => &[":"],
+            LapceLanguage::Elixir => &["do", "->", "fn"],
+            _ => &[],
+        }
+    }
+
+    /// The keywords that end the block they are in, so that the line after
+    /// a statement starting with one of them is dedented
+    pub fn dedent_keywords(&self) -> &[&str] {
+        match self {
+            LapceLanguage::Python => {
+                &["return", "pass", "break", "continue", "raise"]
+            }
+            _ => &[],
+        }
+    }
+
     fn tree_sitter_language(&self) -> tree_sitter::Language {
         match self {
             LapceLanguage::Rust => tree_sitter_rust::language(),
@@ -127,14 +204,21 @@ impl LapceLanguage {
         }
     }
 
-    pub(crate) fn new_parser(&self) -> Parser {
+    /// A parser for the language, `None` if tree-sitter can't use its
+    /// grammar
+    pub(crate) fn new_parser(&self) -> Option<Parser> {
         let language = self.tree_sitter_language();
         let mut parser = Parser::new();
-        parser.set_language(language).unwrap();
-        parser
+        if let Err(err) = parser.set_language(language) {
+            log::error!("Could not load the {self:?} grammar: {err}");
+            return None;
+        }
+        Some(parser)
     }
 
-    pub(crate) fn new_highlight_config(&self) -> HighlightConfiguration {
+    /// The highlight configuration of the language, `None` if its highlight
+    /// query doesn't compile
+    pub(crate) fn new_highlight_config(&self) -> Option<HighlightConfiguration> {
         let language = self.tree_sitter_language();
         let query = match self {
             LapceLanguage::Rust => tree_sitter_rust::HIGHLIGHT_QUERY,
@@ -152,7 +236,13 @@ impl LapceLanguage {
             LapceLanguage::Json => tree_sitter_json::HIGHLIGHT_QUERY,
         };
 
-        HighlightConfiguration::new(language, query, "", "").unwrap()
+        match HighlightConfiguration::new(language, query, "", "") {
+            Ok(config) => Some(config),
+            Err(err) => {
+                log::error!("Could not load the {self:?} highlight query: {err:?}");
+                None
+            }
+        }
     }
 
     pub(crate) fn walk_tree(
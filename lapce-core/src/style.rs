@@ -82,6 +82,7 @@ pub struct HighlightConfiguration {
 /// is performing highlighting.
 pub struct Highlighter {
     cursors: Vec<QueryCursor>,
+    byte_range: Option<ops::Range<usize>>,
 }
 
 /// Converts a general-purpose syntax highlighting iterator into a sequence of lines of HTML.
@@ -142,9 +143,16 @@ impl Highlighter {
     pub fn new() -> Self {
         Highlighter {
             cursors: Vec::new(),
+            byte_range: None,
         }
     }
 
+    /// Restrict the highlighting to the given byte range of the source, so that
+    /// only the part of a document that changed needs to be highlighted again.
+    pub fn set_byte_range(&mut self, range: Option<ops::Range<usize>>) {
+        self.byte_range = range;
+    }
+
     /// Iterate over the highlighted regions for a given slice of source code.
     pub fn highlight<'a>(
         &'a mut self,
@@ -170,10 +178,15 @@ impl Highlighter {
             }],
         );
         assert_ne!(layers.len(), 0);
+        let byte_offset = self
+            .byte_range
+            .as_ref()
+            .map(|range| range.start)
+            .unwrap_or(0);
         let mut result = HighlightIter {
             tree,
             source,
-            byte_offset: 0,
+            byte_offset,
             injection_callback,
             cancellation_flag,
             highlighter: self,
@@ -374,6 +387,9 @@ impl<'a> HighlightIterLayer<'a> {
             let tree = tree.clone();
             let mut cursor =
                 highlighter.cursors.pop().unwrap_or_else(QueryCursor::new);
+            cursor.set_byte_range(
+                highlighter.byte_range.clone().unwrap_or(0..usize::MAX),
+            );
 
             // Process combined injections.
             if let Some(combined_injections_query) =
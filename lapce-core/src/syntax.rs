@@ -1,27 +1,42 @@
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
+    ops::Range,
     path::Path,
-    sync::Arc,
+    sync::{Arc, Mutex, PoisonError},
 };
 
 use itertools::Itertools;
 use lapce_rpc::style::Style;
+use lazy_static::lazy_static;
 use tree_sitter::{Node, Parser, Point, Tree};
 use xi_rope::{
     spans::{Spans, SpansBuilder},
-    Interval, Rope, RopeDelta,
+    Interval, LinesMetric, Rope, RopeDelta, Transformer,
 };
 
 use crate::{
     language::LapceLanguage,
     lens::{Lens, LensBuilder},
-    style::{Highlight, HighlightEvent, Highlighter, SCOPES},
+    style::{
+        Highlight, HighlightConfiguration, HighlightEvent, Highlighter, SCOPES,
+    },
 };
 
+/// Files larger than this only get highlighted around the lines that are
+/// actually shown, rather than all at once.
+pub const PARTIAL_HIGHLIGHT_THRESHOLD: usize = 1024 * 1024;
+
 thread_local! {
-   static PARSER: RefCell<HashMap<LapceLanguage, Parser>> = RefCell::new(HashMap::new());
-   static HIGHLIGHTS: RefCell<HashMap<LapceLanguage, crate::style::HighlightConfiguration>> = RefCell::new(HashMap::new());
+   static PARSER: RefCell<HashMap<LapceLanguage, Option<Parser>>> = RefCell::new(HashMap::new());
+}
+
+lazy_static! {
+    /// Compiling the highlight queries is expensive, so the configurations are
+    /// shared by every thread rather than built per thread. A language whose
+    /// query doesn't compile is kept as `None` so it's only tried once.
+    static ref HIGHLIGHTS: Mutex<HashMap<LapceLanguage, Option<Arc<HighlightConfiguration>>>> =
+        Mutex::new(HashMap::new());
 }
 
 #[derive(Clone)]
@@ -35,13 +50,24 @@ pub struct Syntax {
     pub line_height: usize,
     pub lens_height: usize,
     pub styles: Option<Arc<Spans<Style>>>,
+    /// The byte ranges of `styles` that have been highlighted, or `None` if
+    /// the whole text has been
+    highlighted: Option<Vec<Range<usize>>>,
+    /// The highlight configuration, `None` until a parse looks up the shared
+    /// one of the language, and `Some(None)` when there is none
+    highlight_config: Option<Option<Arc<HighlightConfiguration>>>,
 }
 
 impl Syntax {
     pub fn init(path: &Path) -> Option<Syntax> {
-        LapceLanguage::from_path(path).map(|l| Syntax {
+        LapceLanguage::from_path(path).map(Syntax::from_language)
+    }
+
+    /// An empty syntax of the language, which is yet to parse a text
+    pub fn from_language(language: LapceLanguage) -> Syntax {
+        Syntax {
             rev: 0,
-            language: l,
+            language,
             text: Rope::from(""),
             tree: None,
             lens: Self::lens_from_normal_lines(0, 0, 0, &Vec::new()),
@@ -49,7 +75,9 @@ impl Syntax {
             lens_height: 0,
             normal_lines: Vec::new(),
             styles: None,
-        })
+            highlighted: None,
+            highlight_config: None,
+        }
     }
 
     pub fn parse(
@@ -58,78 +86,25 @@ impl Syntax {
         new_text: Rope,
         delta: Option<RopeDelta>,
     ) -> Syntax {
-        let mut old_tree = None;
-        if new_rev == self.rev + 1 {
-            if let Some(delta) = delta {
-                fn point_at_offset(text: &Rope, offset: usize) -> Point {
-                    let line = text.line_of_offset(offset);
-                    let col = text.offset_of_line(line + 1) - offset;
-                    Point::new(line, col)
-                }
-                let (interval, _) = delta.summary();
-                let (start, end) = interval.start_end();
-                if let Some(inserted) = delta.as_simple_insert() {
-                    fn traverse(point: Point, text: &str) -> Point {
-                        let Point {
-                            mut row,
-                            mut column,
-                        } = point;
-
-                        for ch in text.chars() {
-                            if ch == '\n' {
-                                row += 1;
-                                column = 0;
-                            } else {
-                                column += 1;
-                            }
-                        }
-                        Point { row, column }
-                    }
-
-                    let start_position = point_at_offset(&self.text, start);
-
-                    let edit = tree_sitter::InputEdit {
-                        start_byte: start,
-                        old_end_byte: start,
-                        new_end_byte: start + inserted.len(),
-                        start_position,
-                        old_end_position: start_position,
-                        new_end_position: traverse(
-                            start_position,
-                            &inserted.slice_to_cow(0..inserted.len()),
-                        ),
-                    };
-                    old_tree = self.tree.as_ref().map(|tree| {
-                        let mut tree = tree.clone();
-                        tree.edit(&edit);
-                        tree
-                    });
-                } else if delta.is_simple_delete() {
-                    let start_position = point_at_offset(&self.text, start);
-                    let end_position = point_at_offset(&self.text, end);
-                    let edit = tree_sitter::InputEdit {
-                        start_byte: start,
-                        old_end_byte: end,
-                        new_end_byte: start,
-                        start_position,
-                        old_end_position: end_position,
-                        new_end_position: start_position,
-                    };
-                    old_tree = self.tree.as_ref().map(|tree| {
-                        let mut tree = tree.clone();
-                        tree.edit(&edit);
-                        tree
-                    });
-                };
+        // The previous tree can only be reused if it was parsed from the text
+        // right before this delta was applied.
+        let delta = delta.filter(|_| new_rev == self.rev + 1);
+        let old_tree = match (self.tree.as_ref(), delta.as_ref()) {
+            (Some(tree), Some(delta)) => {
+                let edit = Self::input_edit(&self.text, &new_text, delta);
+                let mut tree = tree.clone();
+                tree.edit(&edit);
+                Some(tree)
             }
-        }
+            _ => None,
+        };
 
         let new_tree = PARSER.with(|parsers| {
             let mut parsers = parsers.borrow_mut();
-            parsers
+            let parser = parsers
                 .entry(self.language)
-                .or_insert_with(|| self.language.new_parser());
-            let parser = parsers.get_mut(&self.language).unwrap();
+                .or_insert_with(|| self.language.new_parser())
+                .as_mut()?;
 
             parser.parse_with(
                 &mut |byte, _| {
@@ -147,47 +122,62 @@ impl Syntax {
             )
         });
 
+        let mut highlighted = None;
         let styles = if let Some(tree) = new_tree.as_ref() {
-            let styles = HIGHLIGHTS.with(|configs| {
-                let mut configs = configs.borrow_mut();
-                configs
-                    .entry(self.language)
-                    .or_insert_with(|| self.language.new_highlight_config());
-                let config = configs.get(&self.language).unwrap();
-                let mut current_hl: Option<Highlight> = None;
-                let mut highlights = SpansBuilder::new(new_text.len());
-                let mut highlighter = Highlighter::new();
-                for highlight in highlighter
-                    .highlight(
-                        tree.clone(),
-                        config,
-                        new_text.slice_to_cow(0..new_text.len()).as_bytes(),
-                        None,
-                        |_| None,
-                    )
-                    .flatten()
-                {
-                    match highlight {
-                        HighlightEvent::Source { start, end } => {
-                            if let Some(hl) = current_hl {
-                                if let Some(hl) = SCOPES.get(hl.0) {
-                                    highlights.add_span(
-                                        Interval::new(start, end),
-                                        Style {
-                                            fg_color: Some(hl.to_string()),
-                                        },
-                                    );
-                                }
-                            }
+            let changed =
+                match (old_tree.as_ref(), self.styles.as_ref(), delta.as_ref()) {
+                    (Some(old_tree), Some(styles), Some(delta)) => {
+                        let mut styles = (**styles).clone();
+                        // The buffer shifts the styles as the edit comes in,
+                        // so they might already match the new text.
+                        if styles.len() != new_text.len() {
+                            styles.apply_shape(delta);
                         }
-                        HighlightEvent::HighlightStart(hl) => {
-                            current_hl = Some(hl);
+                        if styles.len() == new_text.len() {
+                            let ranges = Self::changed_ranges(
+                                old_tree, tree, &new_text, delta,
+                            );
+                            Some((styles, ranges))
+                        } else {
+                            None
                         }
-                        HighlightEvent::HighlightEnd => current_hl = None,
                     }
+                    _ => None,
+                };
+
+            let source = new_text.slice_to_cow(0..new_text.len());
+            let styles = match changed {
+                Some((mut styles, ranges)) => {
+                    highlighted = self.highlighted.as_ref().map(|highlighted| {
+                        let mut transformer =
+                            Transformer::new(delta.as_ref().unwrap());
+                        highlighted
+                            .iter()
+                            .map(|r| {
+                                transformer.transform(r.start, false)
+                                    ..transformer.transform(r.end, true)
+                            })
+                            .chain(ranges.iter().cloned())
+                            .collect::<Vec<_>>()
+                    });
+                    for range in ranges {
+                        let new_styles = self.highlight(
+                            tree,
+                            source.as_bytes(),
+                            Some(range.start..range.end),
+                        );
+                        styles
+                            .edit(Interval::new(range.start, range.end), new_styles);
+                    }
+                    styles
                 }
-                highlights.build()
-            });
+                None if new_text.len() > PARTIAL_HIGHLIGHT_THRESHOLD => {
+                    // Leave it to the editor to ask for the visible lines
+                    highlighted = Some(Vec::new());
+                    SpansBuilder::new(new_text.len()).build()
+                }
+                None => self.highlight(tree, source.as_bytes(), None),
+            };
             Some(Arc::new(styles))
         } else {
             None
@@ -220,9 +210,188 @@ impl Syntax {
             lens_height: self.lens_height,
             normal_lines,
             styles,
+            highlighted: highlighted.map(merge_ranges),
+            highlight_config: Some(self.highlight_configuration()),
+        }
+    }
+
+    /// The highlight configuration of this syntax, the shared one of the
+    /// language unless it was already looked up
+    fn highlight_configuration(&self) -> Option<Arc<HighlightConfiguration>> {
+        match &self.highlight_config {
+            Some(config) => config.clone(),
+            None => highlight_config(self.language),
+        }
+    }
+
+    pub fn rev(&self) -> u64 {
+        self.rev
+    }
+
+    /// Returns the part of `range` that still needs to be highlighted, if
+    /// only some of the text has been highlighted so far.
+    pub fn unhighlighted_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let highlighted = self.highlighted.as_ref()?;
+        let mut start = range.start;
+        for r in highlighted {
+            if r.start <= start && start < r.end {
+                start = r.end;
+            }
+        }
+        let mut end = range.end;
+        for r in highlighted.iter().rev() {
+            if r.start < end && end <= r.end {
+                end = r.start;
+            }
+        }
+        if start < end {
+            Some(start..end)
+        } else {
+            None
         }
     }
 
+    /// Highlight the given byte range of the text
+    pub fn highlight_range(&self, range: Range<usize>) -> Option<Spans<Style>> {
+        let tree = self.tree.as_ref()?;
+        let range = range.start.min(self.text.len())..range.end.min(self.text.len());
+        let source = self.text.slice_to_cow(0..self.text.len());
+        Some(self.highlight(tree, source.as_bytes(), Some(range)))
+    }
+
+    /// Merge the styles produced by `highlight_range` back into the styles
+    /// of the whole text.
+    pub fn merge_highlights(
+        &mut self,
+        range: Range<usize>,
+        new_styles: Spans<Style>,
+    ) {
+        let styles = match self.styles.as_mut() {
+            Some(styles) => styles,
+            None => return,
+        };
+        if range.end > styles.len() || new_styles.len() != range.end - range.start {
+            return;
+        }
+        Arc::make_mut(styles)
+            .edit(Interval::new(range.start, range.end), new_styles);
+        if let Some(highlighted) = self.highlighted.take() {
+            let mut highlighted = highlighted;
+            highlighted.push(range);
+            self.highlighted = Some(merge_ranges(highlighted));
+        }
+    }
+
+    /// Describe the change made by `delta` in the form tree-sitter expects,
+    /// as a single edit covering everything that was touched.
+    fn input_edit(
+        old_text: &Rope,
+        new_text: &Rope,
+        delta: &RopeDelta,
+    ) -> tree_sitter::InputEdit {
+        fn point_at_offset(text: &Rope, offset: usize) -> Point {
+            let line = text.line_of_offset(offset);
+            let col = offset - text.offset_of_line(line);
+            Point::new(line, col)
+        }
+
+        let (interval, new_len) = delta.summary();
+        let (start, end) = interval.start_end();
+        let new_end = start + new_len;
+        tree_sitter::InputEdit {
+            start_byte: start,
+            old_end_byte: end,
+            new_end_byte: new_end,
+            start_position: point_at_offset(old_text, start),
+            old_end_position: point_at_offset(old_text, end),
+            new_end_position: point_at_offset(new_text, new_end),
+        }
+    }
+
+    /// The regions of the new text whose highlighting might have changed,
+    /// expanded to whole lines and merged when they overlap.
+    fn changed_ranges(
+        old_tree: &Tree,
+        new_tree: &Tree,
+        new_text: &Rope,
+        delta: &RopeDelta,
+    ) -> Vec<Range<usize>> {
+        let (interval, new_len) = delta.summary();
+        let edited = interval.start()..interval.start() + new_len;
+
+        let mut ranges: Vec<Range<usize>> = old_tree
+            .changed_ranges(new_tree)
+            .map(|r| r.start_byte..r.end_byte)
+            .chain(std::iter::once(edited))
+            .map(|r| {
+                let start_line =
+                    new_text.line_of_offset(r.start.min(new_text.len()));
+                let end_line = new_text.line_of_offset(r.end.min(new_text.len()));
+                new_text.offset_of_line(start_line)
+                    ..new_text.offset_of_line(
+                        (end_line + 1).min(new_text.measure::<LinesMetric>() + 1),
+                    )
+            })
+            .collect();
+        merge_ranges(ranges)
+    }
+
+    /// Highlight the tree, optionally only within the given byte range. The
+    /// returned spans cover just that range.
+    fn highlight(
+        &self,
+        tree: &Tree,
+        source: &[u8],
+        range: Option<Range<usize>>,
+    ) -> Spans<Style> {
+        let (start_offset, end_offset) = range
+            .as_ref()
+            .map(|r| (r.start, r.end))
+            .unwrap_or((0, source.len()));
+
+        let mut highlights = SpansBuilder::new(end_offset - start_offset);
+        // Without a configuration the language just isn't highlighted
+        let config = match self.highlight_configuration() {
+            Some(config) => config,
+            None => return highlights.build(),
+        };
+        let mut current_hl: Option<Highlight> = None;
+        let mut highlighter = Highlighter::new();
+        highlighter.set_byte_range(range);
+        for highlight in highlighter
+            .highlight(tree.clone(), &config, source, None, |_| None)
+            .flatten()
+        {
+            match highlight {
+                HighlightEvent::Source { start, end } => {
+                    let start = start.max(start_offset);
+                    let end = end.min(end_offset);
+                    if start >= end {
+                        continue;
+                    }
+                    if let Some(hl) = current_hl {
+                        if let Some(hl) = SCOPES.get(hl.0) {
+                            highlights.add_span(
+                                Interval::new(
+                                    start - start_offset,
+                                    end - start_offset,
+                                ),
+                                Style {
+                                    fg_color: Some(hl.to_string()),
+                                },
+                            );
+                        }
+                    }
+                }
+                HighlightEvent::HighlightStart(hl) => {
+                    current_hl = Some(hl);
+                }
+                HighlightEvent::HighlightEnd => current_hl = None,
+            }
+        }
+        highlights.build()
+    }
+
     pub fn update_lens_height(&mut self, line_height: usize, lens_height: usize) {
         self.lens = Self::lens_from_normal_lines(
             self.text.line_of_offset(self.text.len()) + 1,
@@ -275,6 +444,19 @@ impl Syntax {
         None
     }
 
+    /// The range of the smallest node around `range` that's larger than it,
+    /// to grow a selection by. A caret grows to the node it's in.
+    pub fn expand_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let tree = self.tree.as_ref()?;
+        let mut node = tree
+            .root_node()
+            .descendant_for_byte_range(range.start, range.end)?;
+        while node.start_byte() == range.start && node.end_byte() == range.end {
+            node = node.parent()?;
+        }
+        Some(node.start_byte()..node.end_byte())
+    }
+
     pub fn find_tag(
         &self,
         offset: usize,
@@ -338,6 +520,32 @@ impl Syntax {
     }
 }
 
+/// Get the shared highlight configuration for the language, compiling it the
+/// first time it's needed
+fn highlight_config(language: LapceLanguage) -> Option<Arc<HighlightConfiguration>> {
+    HIGHLIGHTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(language)
+        .or_insert_with(|| language.new_highlight_config().map(Arc::new))
+        .clone()
+}
+
+/// Sort the ranges and merge the ones that overlap or touch
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
 pub fn matching_char(c: char) -> Option<char> {
     Some(match c {
         '{' => '}',
@@ -368,6 +576,46 @@ mod tests {
         assert_eq!(33, lens.height_of_line(5));
     }
 
+    #[test]
+    fn test_input_edit() {
+        let old_text = Rope::from("fn main() {\n    let a = 1;\n}\n");
+        let delta = xi_rope::Delta::simple_edit(
+            Interval::new(16, 21),
+            Rope::from("b = 2;\n    let c"),
+            old_text.len(),
+        );
+        let new_text = delta.apply(&old_text);
+        let edit = Syntax::input_edit(&old_text, &new_text, &delta);
+        assert_eq!(16, edit.start_byte);
+        assert_eq!(21, edit.old_end_byte);
+        assert_eq!(32, edit.new_end_byte);
+        assert_eq!(Point::new(1, 4), edit.start_position);
+        assert_eq!(Point::new(1, 9), edit.old_end_position);
+        assert_eq!(Point::new(2, 9), edit.new_end_position);
+    }
+
+    #[test]
+    fn test_expand_range() {
+        let syntax = Syntax::from_language(LapceLanguage::Rust).parse(
+            0,
+            Rope::from("fn main() { let a = 1; }"),
+            None,
+        );
+        assert_eq!(Some(16..17), syntax.expand_range(16..16));
+        assert_eq!(Some(12..22), syntax.expand_range(16..17));
+        assert_eq!(Some(10..24), syntax.expand_range(12..22));
+        assert_eq!(Some(0..24), syntax.expand_range(10..24));
+        assert_eq!(None, syntax.expand_range(0..24));
+    }
+
+    #[test]
+    fn test_merge_ranges() {
+        assert_eq!(
+            vec![0..10, 12..20],
+            merge_ranges(vec![15..20, 0..5, 5..10, 12..16])
+        );
+    }
+
     #[test]
     fn test_lens_iter() {
         let lens = Syntax::lens_from_normal_lines(5, 25, 2, &[0, 2, 4]);
@@ -386,4 +634,29 @@ mod tests {
         assert_eq!(Some((90, 25)), iter.next());
         assert_eq!(None, iter.next());
     }
+
+    #[test]
+    fn test_parse_without_highlight_config() {
+        let syntax = Syntax {
+            highlight_config: Some(None),
+            ..Syntax::from_language(LapceLanguage::Json)
+        };
+        let text = Rope::from("{\"a\": 1}");
+        let syntax = syntax.parse(0, text.clone(), None);
+        let styles = syntax.styles.clone().unwrap();
+        assert_eq!(text.len(), styles.len());
+        assert_eq!(0, styles.iter().count());
+
+        let delta = xi_rope::Delta::simple_edit(
+            Interval::new(6, 6),
+            Rope::from("2"),
+            text.len(),
+        );
+        let new_text = delta.apply(&text);
+        let syntax = syntax.parse(1, new_text.clone(), Some(delta));
+        let styles = syntax.styles.clone().unwrap();
+        assert_eq!(new_text.len(), styles.len());
+        assert_eq!(0, styles.iter().count());
+        assert_eq!(0, syntax.highlight_range(0..4).unwrap().iter().count());
+    }
 }
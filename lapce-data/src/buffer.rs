@@ -1,3 +1,4 @@
+use chrono::{Local, TimeZone};
 use druid::PaintCtx;
 use druid::{piet::PietTextLayout, Vec2};
 use druid::{
@@ -5,9 +6,13 @@ use druid::{
     Data, ExtEventSink, Target, WidgetId, WindowId,
 };
 use lapce_core::indent::{auto_detect_indent_style, IndentStyle};
+use lapce_core::language::LapceLanguage;
 use lapce_core::style::line_styles;
 use lapce_core::syntax::Syntax;
-use lapce_rpc::buffer::{BufferHeadResponse, BufferId, NewBufferResponse};
+use lapce_rpc::buffer::{
+    BufferHeadResponse, BufferId, LineEnding, NewBufferResponse,
+};
+use lapce_rpc::source_control::BlameHunk;
 use lapce_rpc::style::{LineStyle, LineStyles, Style};
 use lsp_types::SemanticTokensLegend;
 use lsp_types::SemanticTokensServerCapabilities;
@@ -20,17 +25,28 @@ use std::ops::Range;
 use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::atomic::{self, AtomicU64};
-use std::{borrow::Cow, collections::BTreeSet, path::PathBuf, sync::Arc, thread};
+use std::time::{Duration, Instant};
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthChar;
 use xi_rope::{
     multiset::Subset, rope::Rope, spans::Spans, Cursor, Delta, DeltaBuilder,
-    Interval, RopeDelta, RopeInfo,
+    Interval, RopeDelta, RopeInfo, Transformer,
 };
 use xi_unicode::EmojiExt;
 
 use crate::config::{Config, LapceTheme};
 use crate::editor::EditorLocationNew;
 use crate::find::FindProgress;
+use crate::folding::indent_fold_ranges;
+use crate::inlay_hint::{InlayHint, LspInlayHint};
+use crate::wrap::WrapLayout;
 use crate::{
     command::LapceUICommand,
     command::LAPCE_UI_COMMAND,
@@ -45,6 +61,9 @@ const FIND_BATCH_SIZE: usize = 500000;
 
 const DEFAULT_INDENT: IndentStyle = IndentStyle::Spaces(4);
 
+/// Consecutive edits made further apart than this go into separate undo groups
+const UNDO_GROUP_TIMEOUT: Duration = Duration::from_millis(1000);
+
 #[derive(Debug, Clone)]
 pub struct InvalLines {
     pub start_line: usize,
@@ -60,6 +79,37 @@ pub enum DiffLines {
     Right(Range<usize>),
 }
 
+/// A run of changed lines against the git HEAD, as the lines it had in the
+/// HEAD version and the lines it has now
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeHunk {
+    pub head: Range<usize>,
+    pub lines: Range<usize>,
+}
+
+impl ChangeHunk {
+    /// Whether the cursor on `line` is on the hunk, a hunk of deleted lines
+    /// is on the line after the deletion
+    pub fn contains_line(&self, line: usize) -> bool {
+        self.lines.start <= line && line < self.lines.end.max(self.lines.start + 1)
+    }
+}
+
+/// The name of the history that holds the content of another file, for
+/// comparing a buffer with that file
+pub fn file_history(path: &Path) -> String {
+    format!("file:{}", path.display())
+}
+
+/// The name of the history that holds the content a file has on disk after
+/// another program changed it, for comparing it with the unsaved changes
+pub const DISK_HISTORY: &str = "disk";
+
+/// The file whose content a history holds, `None` for the git HEAD
+pub fn history_file(history: &str) -> Option<PathBuf> {
+    history.strip_prefix("file:").map(PathBuf::from)
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum DiffResult<T> {
     Left(T),
@@ -114,12 +164,49 @@ pub enum EditType {
 
 impl EditType {
     /// Checks whether a new undo group should be created between two edits.
-    fn breaks_undo_group(self, previous: EditType) -> bool {
-        self == EditType::Other || self != previous
+    /// Every newline starts its own group, and runs of typed characters or
+    /// deletions are only merged when they follow each other closely in
+    /// time and at the same cursor positions.
+    fn breaks_undo_group(
+        self,
+        previous: EditType,
+        elapsed: Duration,
+        contiguous: bool,
+    ) -> bool {
+        if self == EditType::Other
+            || self == EditType::InsertNewline
+            || self != previous
+        {
+            return true;
+        }
+        match self {
+            EditType::InsertChars | EditType::Delete => {
+                elapsed > UNDO_GROUP_TIMEOUT || !contiguous
+            }
+            _ => false,
+        }
     }
 }
 
-#[derive(Clone)]
+/// Whether the regions of an edit continue from the carets that the
+/// previous edit left behind, rather than after a cursor jump
+fn edit_is_contiguous(last_carets: &[usize], regions: &[SelRegion]) -> bool {
+    last_carets.len() == regions.len()
+        && last_carets
+            .iter()
+            .zip(regions.iter())
+            .all(|(caret, region)| region.min() <= *caret && *caret <= region.max())
+}
+
+/// Where the cursor was before the first edit of an undo group and where
+/// the edits of the group left it
+#[derive(Clone, Serialize, Deserialize)]
+struct UndoCursor {
+    before: Selection,
+    after: Selection,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 enum Contents {
     Edit {
         /// Groups related edits together so that they are undone and re-done
@@ -143,12 +230,93 @@ enum Contents {
     },
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Revision {
     max_undo_so_far: usize,
     edit: Contents,
 }
 
+/// The undo and redo history of a buffer, which can be stored and given back
+/// to the buffer when it has the same text again
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UndoHistory {
+    /// The hash of the text the history ends with
+    content_hash: u64,
+    revs: Vec<Revision>,
+    cur_undo: usize,
+    undos: BTreeSet<usize>,
+    undo_group_id: usize,
+    live_undos: Vec<usize>,
+    deletes_from_union: Subset,
+    undone_groups: BTreeSet<usize>,
+    tombstones: Rope,
+    undo_cursors: HashMap<usize, UndoCursor>,
+}
+
+impl UndoHistory {
+    /// Whether there's nothing to undo or redo
+    pub fn is_empty(&self) -> bool {
+        self.live_undos.len() <= 1
+    }
+
+    /// The length of the deleted text the history keeps, which the stored
+    /// history is at least as long as
+    pub fn deleted_len(&self) -> usize {
+        self.tombstones.len()
+    }
+}
+
+/// A hash of the text of `rope` to check that an undo history belongs to it.
+/// The hash is stored with the history, so it's the 64-bit FNV-1a hash, which
+/// unlike the std hasher doesn't change between Rust releases.
+pub fn content_hash(rope: &Rope) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for chunk in rope.iter_chunks(..) {
+        for byte in chunk.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// The byte range of `old` that has to be replaced, and the end of what
+/// replaces it in `new`, for `old` to become `new`
+fn changed_range(old: &str, new: &str) -> (usize, usize, usize) {
+    let mut start = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(old.len().min(new.len()) - start)
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix) {
+        suffix -= 1;
+    }
+    (start, old.len() - suffix, new.len() - suffix)
+}
+
+/// The author, date and summary of the commit of a blame hunk
+pub fn blame_text(hunk: &BlameHunk) -> String {
+    if hunk.commit.is_none() {
+        return "Not committed yet".to_string();
+    }
+    let date = Local
+        .timestamp_opt(hunk.time, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    format!("{}, {} • {}", hunk.author, date, hunk.summary)
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub enum LocalBufferKind {
     Search,
@@ -164,6 +332,9 @@ pub enum BufferContent {
     File(PathBuf),
     Local(LocalBufferKind),
     Value(String),
+    /// A buffer that isn't backed by a file until it's saved, with the name
+    /// it's shown with
+    Scratch(BufferId, String),
 }
 
 impl BufferContent {
@@ -179,6 +350,7 @@ impl BufferContent {
                 LocalBufferKind::Empty => false,
             },
             BufferContent::Value(_) => true,
+            BufferContent::Scratch(..) => false,
         }
     }
 
@@ -193,6 +365,7 @@ impl BufferContent {
                 LocalBufferKind::Empty | LocalBufferKind::SourceControl => false,
             },
             BufferContent::Value(_) => true,
+            BufferContent::Scratch(..) => false,
         }
     }
 
@@ -200,6 +373,7 @@ impl BufferContent {
         match &self {
             BufferContent::File(_) => false,
             BufferContent::Value(_) => false,
+            BufferContent::Scratch(..) => false,
             BufferContent::Local(local) => matches!(local, LocalBufferKind::Search),
         }
     }
@@ -212,6 +386,15 @@ pub struct Buffer {
     pub content: BufferContent,
     pub syntax: Option<Syntax>,
     pub indent_style: IndentStyle,
+    /// The line ending that is written when the buffer is saved, the rope
+    /// itself always uses `\n`
+    pub line_ending: LineEnding,
+    /// The name of the text encoding of the file on disk
+    pub encoding: String,
+    /// The file isn't text, so the buffer only holds a placeholder
+    pub binary: bool,
+    /// Edits to the buffer are rejected
+    pub read_only: bool,
     pub line_styles: Rc<RefCell<LineStyles>>,
     pub semantic_styles: Option<Arc<Spans<Style>>>,
     pub max_len: usize,
@@ -220,6 +403,12 @@ pub struct Buffer {
     pub rev: u64,
     pub atomic_rev: Arc<AtomicU64>,
     pub dirty: bool,
+    /// The file was modified by another program while the buffer had
+    /// unsaved changes
+    pub changed_on_disk: bool,
+    /// The characters that trigger signature help when typed, as the
+    /// language server of the buffer announced them
+    pub signature_trigger_characters: Vec<String>,
     pub loaded: bool,
     pub start_to_load: Rc<RefCell<bool>>,
     pub local: bool,
@@ -227,9 +416,20 @@ pub struct Buffer {
     pub history_styles: im::HashMap<String, Arc<Spans<Style>>>,
     pub history_line_styles: Rc<RefCell<HashMap<String, LineStyles>>>,
     pub history_changes: im::HashMap<String, Arc<Vec<DiffLines>>>,
+    /// The rev of the other file each file history was taken at
+    pub history_revs: im::HashMap<String, u64>,
+    /// The git blame of the file with the rev it was requested at, it only
+    /// applies while the buffer is saved at that rev
+    pub blame: Option<(u64, Arc<Vec<BlameHunk>>)>,
 
     pub find: Rc<RefCell<Find>>,
     pub find_progress: Rc<RefCell<FindProgress>>,
+    highlight_requested: Rc<RefCell<Option<(u64, Range<usize>)>>>,
+    /// The soft wrap layout of the last rev each editor view requested it
+    /// for, with the wrap column and tab width it was computed with, so that
+    /// split views of different widths don't rebuild each other's
+    wrap_layouts:
+        Rc<RefCell<HashMap<WidgetId, (u64, usize, usize, Arc<WrapLayout>)>>>,
 
     revs: Vec<Revision>,
     cur_undo: usize,
@@ -242,11 +442,20 @@ pub struct Buffer {
 
     this_edit_type: EditType,
     last_edit_type: EditType,
+    last_edit_time: Instant,
+    /// Where the carets ended up after the last edit
+    last_edit_carets: Vec<usize>,
+    /// The cursor positions to restore when an undo group is undone or redone
+    undo_cursors: HashMap<usize, UndoCursor>,
 
     pub cursor_offset: usize,
     pub scroll_offset: Vec2,
 
     pub code_actions: im::HashMap<usize, CodeActionResponse>,
+    /// The inlay hints of the lines around the visible region, sorted by
+    /// offset and moved along with the edits until they are requested again
+    pub inlay_hints: Arc<Vec<InlayHint>>,
+    inlay_hints_requested: Rc<RefCell<Option<(u64, Range<usize>)>>>,
 
     tab_id: WidgetId,
     event_sink: ExtEventSink,
@@ -263,18 +472,29 @@ impl Buffer {
             BufferContent::File(path) => Syntax::init(path),
             BufferContent::Local(_) => None,
             BufferContent::Value(_) => None,
+            BufferContent::Scratch(..) => None,
+        };
+        let id = match &content {
+            BufferContent::Scratch(id, _) => *id,
+            _ => BufferId::next(),
         };
 
         Self {
-            id: BufferId::next(),
+            id,
             rope,
             syntax,
             line_styles: Rc::new(RefCell::new(HashMap::new())),
             indent_style: DEFAULT_INDENT,
+            line_ending: LineEnding::default(),
+            encoding: "UTF-8".to_string(),
+            binary: false,
+            read_only: false,
             semantic_styles: None,
             content,
             find: Rc::new(RefCell::new(Find::new(0))),
             find_progress: Rc::new(RefCell::new(FindProgress::Ready)),
+            highlight_requested: Rc::new(RefCell::new(None)),
+            wrap_layouts: Rc::new(RefCell::new(HashMap::new())),
             max_len: 0,
             max_len_line: 0,
             num_lines: 0,
@@ -283,11 +503,15 @@ impl Buffer {
             start_to_load: Rc::new(RefCell::new(false)),
             loaded: false,
             dirty: false,
+            changed_on_disk: false,
+            signature_trigger_characters: Vec::new(),
             local: false,
             histories: im::HashMap::new(),
             history_styles: im::HashMap::new(),
             history_line_styles: Rc::new(RefCell::new(HashMap::new())),
             history_changes: im::HashMap::new(),
+            history_revs: im::HashMap::new(),
+            blame: None,
 
             revs: vec![Revision {
                 max_undo_so_far: 0,
@@ -306,11 +530,16 @@ impl Buffer {
 
             last_edit_type: EditType::Other,
             this_edit_type: EditType::Other,
+            last_edit_time: Instant::now(),
+            last_edit_carets: Vec::new(),
+            undo_cursors: HashMap::new(),
 
             cursor_offset: 0,
             scroll_offset: Vec2::ZERO,
 
             code_actions: im::HashMap::new(),
+            inlay_hints: Arc::new(Vec::new()),
+            inlay_hints_requested: Rc::new(RefCell::new(None)),
             tab_id,
             event_sink,
         }
@@ -336,6 +565,45 @@ impl Buffer {
         self.deletes_from_union = Subset::new(0);
         self.undone_groups = BTreeSet::new();
         self.tombstones = Rope::default();
+        self.undo_cursors.clear();
+    }
+
+    /// The undo history up to the current text
+    pub fn undo_history(&self) -> UndoHistory {
+        UndoHistory {
+            content_hash: content_hash(&self.rope),
+            revs: self.revs.clone(),
+            cur_undo: self.cur_undo,
+            undos: self.undos.clone(),
+            undo_group_id: self.undo_group_id,
+            live_undos: self.live_undos.clone(),
+            deletes_from_union: self.deletes_from_union.clone(),
+            undone_groups: self.undone_groups.clone(),
+            tombstones: self.tombstones.clone(),
+            undo_cursors: self.undo_cursors.clone(),
+        }
+    }
+
+    /// Bring back an undo history taken from this buffer, returning false and
+    /// leaving the buffer as it is if it doesn't end with the current text
+    pub fn restore_undo_history(&mut self, history: UndoHistory) -> bool {
+        if history.content_hash != content_hash(&self.rope)
+            || history.deletes_from_union.len_after_delete() != self.rope.len()
+        {
+            return false;
+        }
+        self.revs = history.revs;
+        self.cur_undo = history.cur_undo;
+        self.undos = history.undos;
+        self.undo_group_id = history.undo_group_id;
+        self.live_undos = history.live_undos;
+        self.deletes_from_union = history.deletes_from_union;
+        self.undone_groups = history.undone_groups;
+        self.tombstones = history.tombstones;
+        self.undo_cursors = history.undo_cursors;
+        self.last_edit_type = EditType::Other;
+        self.last_edit_carets.clear();
+        true
     }
 
     pub fn load_history(&mut self, version: &str, content: Rope) {
@@ -344,6 +612,15 @@ impl Buffer {
         self.retrieve_history_styles(version, content);
     }
 
+    /// Forget a history the buffer isn't compared with anymore
+    pub fn remove_history(&mut self, version: &str) {
+        self.histories.remove(version);
+        self.history_styles.remove(version);
+        self.history_line_styles.borrow_mut().remove(version);
+        self.history_changes.remove(version);
+        self.history_revs.remove(version);
+    }
+
     pub fn load_content(&mut self, content: &str) {
         self.reset_revs();
 
@@ -359,6 +636,8 @@ impl Buffer {
         }
 
         self.code_actions.clear();
+        // The rev isn't bumped for loaded content
+        self.wrap_layouts.borrow_mut().clear();
         let (max_len, max_len_line) = self.get_max_line_len();
         self.max_len = max_len;
         self.max_len_line = max_len_line;
@@ -378,6 +657,27 @@ impl Buffer {
             });
     }
 
+    /// Highlight the buffer as `language` from now on, whatever its file
+    /// extension is. The choice is kept for as long as the buffer is open.
+    pub fn set_language(&mut self, language: LapceLanguage) {
+        if self.syntax.as_ref().map(|s| s.language) == Some(language) {
+            return;
+        }
+        self.syntax = Some(Syntax::from_language(language));
+        self.line_styles.borrow_mut().clear();
+        self.trigger_syntax_change(None);
+    }
+
+    /// Turn a scratch buffer into the buffer of the file at `path`, which
+    /// it's highlighted and saved as from now on
+    pub fn set_path(&mut self, path: PathBuf) {
+        self.syntax = Syntax::init(&path);
+        self.content = BufferContent::File(path);
+        self.local = false;
+        self.line_styles.borrow_mut().clear();
+        self.trigger_syntax_change(None);
+    }
+
     pub fn indent_unit(&self) -> &'static str {
         self.indent_style.as_str()
     }
@@ -412,12 +712,13 @@ impl Buffer {
 
     fn trigger_history_change(&self) {
         if let BufferContent::File(path) = &self.content {
-            if let Some(head) = self.histories.get("head") {
+            for (history, left_rope) in self.histories.iter() {
                 let id = self.id;
                 let rev = self.rev;
                 let atomic_rev = self.atomic_rev.clone();
                 let path = path.clone();
-                let left_rope = head.clone();
+                let history = history.clone();
+                let left_rope = left_rope.clone();
                 let right_rope = self.rope.clone();
                 let event_sink = self.event_sink.clone();
                 let tab_id = self.tab_id;
@@ -441,7 +742,7 @@ impl Buffer {
                             id,
                             path,
                             rev,
-                            history: "head".to_string(),
+                            history,
                             changes: Arc::new(changes),
                         },
                         Target::Widget(tab_id),
@@ -490,7 +791,7 @@ impl Buffer {
                     }
                 }
             }
-            BufferContent::Value(_) => {}
+            BufferContent::Value(_) | BufferContent::Scratch(..) => {}
         }
 
         if let BufferContent::Local(LocalBufferKind::Search) = self.content {}
@@ -568,6 +869,54 @@ impl Buffer {
         }
     }
 
+    pub fn retrieve_blame(
+        &self,
+        tab_id: WidgetId,
+        proxy: Arc<LapceProxy>,
+        event_sink: ExtEventSink,
+    ) {
+        let id = self.id;
+        let rev = self.rev;
+        if let BufferContent::File(path) = &self.content {
+            let path = path.clone();
+            thread::spawn(move || {
+                proxy.git_blame(
+                    path.clone(),
+                    Box::new(move |result| {
+                        if let Ok(res) = result {
+                            if let Ok(hunks) =
+                                serde_json::from_value::<Vec<BlameHunk>>(res)
+                            {
+                                let _ = event_sink.submit_command(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::UpdateBlame {
+                                        id,
+                                        path,
+                                        rev,
+                                        hunks,
+                                    },
+                                    Target::Widget(tab_id),
+                                );
+                            }
+                        }
+                    }),
+                )
+            });
+        }
+    }
+
+    /// The blame hunk of the line, as long as the blame still matches
+    /// the content of the buffer
+    pub fn blame_of_line(&self, line: usize) -> Option<&BlameHunk> {
+        let (rev, hunks) = self.blame.as_ref()?;
+        if *rev != self.rev || self.dirty {
+            return None;
+        }
+        hunks.iter().find(|hunk| {
+            hunk.start_line <= line && line < hunk.start_line + hunk.line_count
+        })
+    }
+
     pub fn retrieve_file_history(&self) {}
 
     pub fn retrieve_file(
@@ -600,6 +949,10 @@ impl Buffer {
                                     LapceUICommand::LoadBuffer {
                                         path,
                                         content: resp.content,
+                                        line_ending: resp.line_ending,
+                                        encoding: resp.encoding,
+                                        binary: resp.binary,
+                                        read_only: resp.read_only,
                                         locations,
                                     },
                                     Target::Widget(tab_id),
@@ -708,6 +1061,241 @@ impl Buffer {
         }
     }
 
+    /// For large files only the lines around the visible region are
+    /// highlighted, so request highlighting for them when they're scrolled to.
+    pub fn update_highlights(&self, start_line: usize, end_line: usize) {
+        const EXTRA_LINES: usize = 100;
+
+        let syntax = match self.syntax.as_ref() {
+            Some(syntax) if syntax.rev() == self.rev => syntax,
+            _ => return,
+        };
+        let path = match &self.content {
+            BufferContent::File(path) => path.clone(),
+            _ => return,
+        };
+
+        let start = self.offset_of_line(start_line.saturating_sub(EXTRA_LINES));
+        let end = self.offset_of_line(end_line + EXTRA_LINES + 1);
+        let range = match syntax.unhighlighted_range(start..end) {
+            Some(range) => range,
+            None => return,
+        };
+
+        {
+            let mut requested = self.highlight_requested.borrow_mut();
+            if let Some((rev, requested_range)) = requested.as_ref() {
+                if *rev == self.rev
+                    && requested_range.start <= range.start
+                    && range.end <= requested_range.end
+                {
+                    return;
+                }
+            }
+            *requested = Some((self.rev, range.clone()));
+        }
+
+        let syntax = syntax.clone();
+        let rev = self.rev;
+        let atomic_rev = self.atomic_rev.clone();
+        let event_sink = self.event_sink.clone();
+        let tab_id = self.tab_id;
+        rayon::spawn(move || {
+            if atomic_rev.load(atomic::Ordering::Acquire) != rev {
+                return;
+            }
+            if let Some(styles) = syntax.highlight_range(range.clone()) {
+                let _ = event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdateSyntaxHighlights {
+                        path,
+                        rev,
+                        range,
+                        styles,
+                    },
+                    Target::Widget(tab_id),
+                );
+            }
+        });
+    }
+
+    /// Inlay hints are only requested for the lines around the visible
+    /// region, so request them again when other lines are scrolled to or
+    /// the buffer changes.
+    pub fn update_inlay_hints(
+        &self,
+        proxy: &LapceProxy,
+        start_line: usize,
+        end_line: usize,
+        tab_width: usize,
+    ) {
+        const EXTRA_LINES: usize = 50;
+
+        if !self.loaded || self.local {
+            return;
+        }
+        let path = match &self.content {
+            BufferContent::File(path) => path.clone(),
+            _ => return,
+        };
+
+        let end_line = end_line.min(self.last_line());
+        let lines = start_line.saturating_sub(EXTRA_LINES)
+            ..(end_line + EXTRA_LINES).min(self.last_line());
+        {
+            let mut requested = self.inlay_hints_requested.borrow_mut();
+            if let Some((rev, requested_lines)) = requested.as_ref() {
+                if *rev == self.rev
+                    && requested_lines.start <= start_line
+                    && end_line <= requested_lines.end
+                {
+                    return;
+                }
+            }
+            *requested = Some((self.rev, lines.clone()));
+        }
+
+        let range = lsp_types::Range {
+            start: self
+                .offset_to_position(self.offset_of_line(lines.start), tab_width),
+            end: self.offset_to_position(
+                self.line_end_offset(lines.end, true),
+                tab_width,
+            ),
+        };
+        let rev = self.rev;
+        let event_sink = self.event_sink.clone();
+        let tab_id = self.tab_id;
+        proxy.get_inlay_hints(
+            self.id,
+            range,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(hints) =
+                        serde_json::from_value::<Option<Vec<LspInlayHint>>>(res)
+                    {
+                        let _ = event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateInlayHints {
+                                path,
+                                rev,
+                                hints: hints.unwrap_or_default(),
+                            },
+                            Target::Widget(tab_id),
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    pub fn set_inlay_hints(&mut self, hints: &[LspInlayHint], tab_width: usize) {
+        let mut hints: Vec<InlayHint> = hints
+            .iter()
+            .map(|hint| InlayHint {
+                offset: self.offset_of_position(&hint.position, tab_width),
+                label: hint.text(),
+            })
+            .collect();
+        hints.sort_by_key(|hint| hint.offset);
+        self.inlay_hints = Arc::new(hints);
+    }
+
+    /// The inlay hints drawn on `line`, including the one at its end
+    pub fn inlay_hints_on_line(&self, line: usize) -> &[InlayHint] {
+        let start = self.offset_of_line(line);
+        let end = if line >= self.last_line() {
+            usize::MAX
+        } else {
+            self.offset_of_line(line + 1)
+        };
+        let first = self.inlay_hints.partition_point(|hint| hint.offset < start);
+        let last = self.inlay_hints.partition_point(|hint| hint.offset < end);
+        &self.inlay_hints[first..last]
+    }
+
+    /// The column that `col` of `line` is drawn at, past the inlay hints
+    /// drawn before it. A hint at `col` itself is drawn before its character.
+    pub fn visual_col(&self, line: usize, col: usize, tab_width: usize) -> usize {
+        let mut visual_col = col;
+        for hint in self.inlay_hints_on_line(line) {
+            let (_, hint_col) = self.offset_to_line_col(hint.offset, tab_width);
+            if hint_col > col {
+                break;
+            }
+            visual_col += str_col(&hint.label, tab_width);
+        }
+        visual_col
+    }
+
+    /// The column of `line` drawn at `visual_col`, the inverse of
+    /// `visual_col`. A column inside an inlay hint is the one the hint is
+    /// drawn before.
+    pub fn col_of_visual_col(
+        &self,
+        line: usize,
+        visual_col: usize,
+        tab_width: usize,
+    ) -> usize {
+        let mut shift = 0;
+        for hint in self.inlay_hints_on_line(line) {
+            let (_, hint_col) = self.offset_to_line_col(hint.offset, tab_width);
+            let width = str_col(&hint.label, tab_width);
+            if visual_col < hint_col + shift {
+                break;
+            }
+            if visual_col < hint_col + shift + width {
+                return hint_col;
+            }
+            shift += width;
+        }
+        visual_col - shift
+    }
+
+    pub fn update_syntax_highlights(
+        &mut self,
+        rev: u64,
+        range: Range<usize>,
+        styles: Spans<Style>,
+    ) {
+        if self.rev != rev {
+            return;
+        }
+        if let Some(syntax) = self.syntax.as_mut() {
+            if syntax.rev() != rev {
+                return;
+            }
+            syntax.merge_highlights(range, styles);
+            if self.semantic_styles.is_none() {
+                self.line_styles.borrow_mut().clear();
+            }
+        }
+    }
+
+    /// Apply the semantic styles of the whole buffer, or of a `range` of it,
+    /// `false` if the styles of a range don't fit the ones the buffer has
+    pub fn update_semantic_styles(
+        &mut self,
+        range: Option<Range<usize>>,
+        styles: Arc<Spans<Style>>,
+    ) -> bool {
+        match (range, self.semantic_styles.as_mut()) {
+            (None, _) => self.semantic_styles = Some(styles),
+            (Some(range), Some(semantic_styles)) => {
+                if range.end > semantic_styles.len()
+                    || styles.len() != range.end - range.start
+                {
+                    return false;
+                }
+                Arc::make_mut(semantic_styles)
+                    .edit(Interval::new(range.start, range.end), (*styles).clone());
+            }
+            (Some(_), None) => return false,
+        }
+        self.line_styles.borrow_mut().clear();
+        true
+    }
+
     pub fn num_lines(&self) -> usize {
         self.line_of_offset(self.rope.len()) + 1
     }
@@ -742,8 +1330,87 @@ impl Buffer {
         self.rope.offset_of_line(line)
     }
 
-    pub fn select_word(&self, offset: usize) -> (usize, usize) {
-        WordCursor::new(&self.rope, offset).select_word()
+    /// The hunks of lines changed since the git HEAD, in order
+    pub fn change_hunks(&self) -> Vec<ChangeHunk> {
+        self.change_hunks_of("head")
+    }
+
+    /// The hunks of lines that differ from `history`, in order
+    pub fn change_hunks_of(&self, history: &str) -> Vec<ChangeHunk> {
+        let changes = match self.history_changes.get(history) {
+            Some(changes) => changes,
+            None => return Vec::new(),
+        };
+        let mut hunks = Vec::new();
+        let mut current: Option<ChangeHunk> = None;
+        // The lines right after the changes so far, on either side
+        let (mut head_line, mut line) = (0, 0);
+        for change in changes.iter() {
+            match change {
+                DiffLines::Left(head) => {
+                    current
+                        .get_or_insert(ChangeHunk {
+                            head: head.start..head.start,
+                            lines: line..line,
+                        })
+                        .head
+                        .end = head.end;
+                    head_line = head.end;
+                }
+                DiffLines::Right(lines) => {
+                    current
+                        .get_or_insert(ChangeHunk {
+                            head: head_line..head_line,
+                            lines: lines.start..lines.start,
+                        })
+                        .lines
+                        .end = lines.end;
+                    line = lines.end;
+                }
+                DiffLines::Both(head, lines) | DiffLines::Skip(head, lines) => {
+                    hunks.extend(current.take());
+                    head_line = head.end;
+                    line = lines.end;
+                }
+            }
+        }
+        hunks.extend(current);
+        hunks
+    }
+
+    /// The edit that brings back the HEAD version of the lines of `hunk`
+    pub fn revert_hunk_edit(
+        &self,
+        hunk: &ChangeHunk,
+    ) -> Option<(Selection, String)> {
+        self.revert_hunk_edit_of("head", hunk)
+    }
+
+    /// The edit that replaces the lines of `hunk` with the ones they have
+    /// in `history`
+    pub fn revert_hunk_edit_of(
+        &self,
+        history: &str,
+        hunk: &ChangeHunk,
+    ) -> Option<(Selection, String)> {
+        let head = self.histories.get(history)?;
+        let head_offset = |line: usize| {
+            head.offset_of_line(line.min(head.line_of_offset(head.len()) + 1))
+        };
+        let content = head
+            .slice_to_cow(head_offset(hunk.head.start)..head_offset(hunk.head.end))
+            .to_string();
+        let selection = Selection::region(
+            self.offset_of_line(hunk.lines.start),
+            self.offset_of_line(hunk.lines.end),
+        );
+        Some((selection, content))
+    }
+
+    pub fn select_word(&self, offset: usize, word_chars: &str) -> (usize, usize) {
+        WordCursor::new(&self.rope, offset)
+            .word_chars(word_chars)
+            .select_word()
     }
 
     pub fn char_at_offset(&self, offset: usize) -> Option<char> {
@@ -831,6 +1498,36 @@ impl Buffer {
         Some(line_styles)
     }
 
+    /// Whether `offset` is within a string or a comment, as far as the
+    /// highlighting can tell
+    pub fn in_string_or_comment(&self, offset: usize) -> bool {
+        let styles = match self
+            .syntax
+            .as_ref()
+            .and_then(|s| s.styles.as_ref())
+            .or(self.semantic_styles.as_ref())
+        {
+            Some(styles) => styles,
+            None => return false,
+        };
+        if offset == 0 || offset >= styles.len() {
+            return false;
+        }
+        styles
+            .iter_chunks(offset - 1..offset + 1)
+            .any(|(iv, style)| match style.fg_color.as_deref() {
+                // A string has to go on after the offset, while a comment
+                // can end there, at the end of its line
+                Some(scope) if scope.starts_with("string") => {
+                    iv.start() < offset && iv.end() > offset
+                }
+                Some(scope) if scope.starts_with("comment") => {
+                    iv.start() < offset && iv.end() >= offset
+                }
+                _ => false,
+            })
+    }
+
     pub fn styles(&self) -> Option<&Arc<Spans<Style>>> {
         let styles = self
             .semantic_styles
@@ -901,21 +1598,57 @@ impl Buffer {
         ))
     }
 
+    /// The layout of `line`, with the `inlay_hints` of the line drawn in
+    /// it. The hints are inserted in the text, so the indices of the line
+    /// are moved past the hints before them.
     #[allow(clippy::too_many_arguments)]
     pub fn new_text_layout(
         &self,
         ctx: &mut PaintCtx,
         line: usize,
         line_content: &str,
+        inlay_hints: &[InlayHint],
         cursor_index: Option<usize>,
         font_size: usize,
         bounds: [f64; 2],
         config: &Config,
     ) -> PietTextLayout {
         let styles = self.line_style(line);
+
+        let line_start = self.offset_of_line(line);
+        let mut text = String::with_capacity(line_content.len());
+        // the index of each hint in the line, and its start and length in
+        // the text
+        let mut hints = Vec::new();
+        let mut last = 0;
+        for hint in inlay_hints {
+            let index = hint.offset.saturating_sub(line_start);
+            if index < last
+                || index > line_content.len()
+                || !line_content.is_char_boundary(index)
+            {
+                continue;
+            }
+            text.push_str(&line_content[last..index]);
+            hints.push((index, text.len(), hint.label.len()));
+            text.push_str(&hint.label);
+            last = index;
+        }
+        text.push_str(&line_content[last..]);
+        // a hint at the index itself is drawn before the character, but
+        // after the end of a range
+        let shift = |index: usize, is_end: bool| -> usize {
+            index
+                + hints
+                    .iter()
+                    .filter(|(i, _, _)| *i < index || (!is_end && *i == index))
+                    .map(|(_, _, len)| len)
+                    .sum::<usize>()
+        };
+
         let mut layout_builder = ctx
             .text()
-            .new_text_layout(line_content.to_string())
+            .new_text_layout(text)
             .font(config.editor.font_family(), font_size as f64)
             .text_color(
                 config
@@ -924,6 +1657,7 @@ impl Buffer {
             );
 
         if let Some(index) = cursor_index {
+            let index = shift(index, false);
             layout_builder = layout_builder.range_attribute(
                 index..index + 1,
                 TextAttribute::TextColor(
@@ -938,12 +1672,23 @@ impl Buffer {
             if let Some(fg_color) = line_style.style.fg_color.as_ref() {
                 if let Some(fg_color) = config.get_style_color(fg_color) {
                     layout_builder = layout_builder.range_attribute(
-                        line_style.start..line_style.end,
+                        shift(line_style.start, false)..shift(line_style.end, true),
                         TextAttribute::TextColor(fg_color.clone()),
                     );
                 }
             }
         }
+
+        for (_, start, len) in hints.iter() {
+            layout_builder = layout_builder.range_attribute(
+                *start..*start + *len,
+                TextAttribute::TextColor(
+                    config
+                        .get_color_unchecked(LapceTheme::INLAY_HINT_FOREGROUND)
+                        .clone(),
+                ),
+            );
+        }
         layout_builder.build_with_info(true, config.editor.tab_width, Some(bounds))
     }
 
@@ -1023,6 +1768,53 @@ impl Buffer {
         (line, col)
     }
 
+    /// How the lines of the buffer break into visual lines in the editor
+    /// `view_id` when they are soft wrapped at `wrap_col` and the `folds`
+    /// are folded, it's cached for each view until the buffer changes
+    pub fn wrap_layout(
+        &self,
+        view_id: WidgetId,
+        wrap_col: usize,
+        tab_width: usize,
+        folds: &[(usize, usize)],
+    ) -> Arc<WrapLayout> {
+        if let Some((rev, col, width, layout)) =
+            self.wrap_layouts.borrow().get(&view_id)
+        {
+            if *rev == self.rev
+                && *col == wrap_col
+                && *width == tab_width
+                && layout.folds == folds
+            {
+                return layout.clone();
+            }
+        }
+        let layout = Arc::new(WrapLayout::new(
+            (0..self.num_lines()).map(|line| self.line_content(line)),
+            wrap_col,
+            tab_width,
+            folds,
+        ));
+        self.wrap_layouts
+            .borrow_mut()
+            .insert(view_id, (self.rev, wrap_col, tab_width, layout.clone()));
+        layout
+    }
+
+    /// Drop the wrap layout cached for an editor view that was closed
+    pub fn remove_wrap_layout(&self, view_id: WidgetId) {
+        self.wrap_layouts.borrow_mut().remove(&view_id);
+    }
+
+    /// The blocks of the buffer that can be folded, as the line each one
+    /// starts on and the last line under it
+    pub fn fold_ranges(&self, tab_width: usize) -> Vec<(usize, usize)> {
+        indent_fold_ranges(
+            (0..self.num_lines()).map(|line| self.line_content(line)),
+            tab_width,
+        )
+    }
+
     pub fn line_end_col(&self, line: usize, caret: bool, tab_width: usize) -> usize {
         let line_start = self.offset_of_line(line);
         let offset = self.line_end_offset(line, caret);
@@ -1084,7 +1876,8 @@ impl Buffer {
             ColPosition::End => self.line_end_col(line, caret, tab_width),
             ColPosition::Start => 0,
             ColPosition::FirstNonBlank => {
-                self.first_non_blank_character_on_line(line)
+                let offset = self.first_non_blank_character_on_line(line);
+                self.offset_to_line_col(offset, tab_width).1
             }
         }
     }
@@ -1510,6 +2303,16 @@ impl Buffer {
                 let new_offset = self.offset_of_line(line);
                 (new_offset, ColPosition::Start)
             }
+            Movement::SmartLineStart => {
+                let line = self.line_of_offset(offset);
+                let line_start = self.offset_of_line(line);
+                let first_non_blank = self.first_non_blank_character_on_line(line);
+                if offset == first_non_blank && offset != line_start {
+                    (line_start, ColPosition::Start)
+                } else {
+                    (first_non_blank, ColPosition::FirstNonBlank)
+                }
+            }
             Movement::EndOfLine => {
                 let new_offset = self.offset_line_end(offset, mode != Mode::Normal);
                 (new_offset, ColPosition::End)
@@ -1547,6 +2350,7 @@ impl Buffer {
             }
             Movement::WordEndForward => {
                 let mut new_offset = WordCursor::new(&self.rope, offset)
+                    .word_chars(&config.editor.word_chars)
                     .end_boundary()
                     .unwrap_or(offset);
                 if mode != Mode::Insert {
@@ -1558,6 +2362,7 @@ impl Buffer {
             }
             Movement::WordForward => {
                 let new_offset = WordCursor::new(&self.rope, offset)
+                    .word_chars(&config.editor.word_chars)
                     .next_boundary()
                     .unwrap_or(offset);
                 let (_, col) =
@@ -1566,6 +2371,25 @@ impl Buffer {
             }
             Movement::WordBackward => {
                 let new_offset = WordCursor::new(&self.rope, offset)
+                    .word_chars(&config.editor.word_chars)
+                    .prev_boundary()
+                    .unwrap_or(offset);
+                let (_, col) =
+                    self.offset_to_line_col(new_offset, config.editor.tab_width);
+                (new_offset, ColPosition::Col(col))
+            }
+            Movement::BigWordForward => {
+                let new_offset = WordCursor::new(&self.rope, offset)
+                    .big_words()
+                    .next_boundary()
+                    .unwrap_or(offset);
+                let (_, col) =
+                    self.offset_to_line_col(new_offset, config.editor.tab_width);
+                (new_offset, ColPosition::Col(col))
+            }
+            Movement::BigWordBackward => {
+                let new_offset = WordCursor::new(&self.rope, offset)
+                    .big_words()
                     .prev_boundary()
                     .unwrap_or(offset);
                 let (_, col) =
@@ -1610,24 +2434,48 @@ impl Buffer {
                 }
             }
             Movement::MatchPairs => {
-                if let Some(syntax) = self.syntax.as_ref() {
-                    let new_offset =
-                        syntax.find_matching_pair(offset).unwrap_or(offset);
-                    let (_, col) =
-                        self.offset_to_line_col(new_offset, config.editor.tab_width);
-                    (new_offset, ColPosition::Col(col))
-                } else {
-                    let new_offset = WordCursor::new(&self.rope, offset)
-                        .match_pairs()
-                        .unwrap_or(offset);
-                    let (_, col) =
-                        self.offset_to_line_col(new_offset, config.editor.tab_width);
-                    (new_offset, ColPosition::Col(col))
-                }
+                let new_offset = self.find_matching_pair(offset).unwrap_or(offset);
+                let (_, col) =
+                    self.offset_to_line_col(new_offset, config.editor.tab_width);
+                (new_offset, ColPosition::Col(col))
             }
+            // The buffer doesn't know about the viewport, the editor turns
+            // these into line movements before they get here
+            Movement::TopOfWindow
+            | Movement::MiddleOfWindow
+            | Movement::BottomOfWindow => (offset, horiz),
         }
     }
 
+    /// The offset of the bracket matching the one at `offset`
+    pub fn find_matching_pair(&self, offset: usize) -> Option<usize> {
+        if let Some(syntax) = self.syntax.as_ref() {
+            syntax.find_matching_pair(offset)
+        } else {
+            WordCursor::new(&self.rope, offset).match_pairs()
+        }
+    }
+
+    /// The bracket at `offset`, or else the one right before it, together
+    /// with the offset of its matching bracket if it has one
+    pub fn bracket_pair_at(&self, offset: usize) -> Option<(usize, Option<usize>)> {
+        let is_bracket = |offset: usize| {
+            self.char_at_offset(offset)
+                .map(|c| matching_char(c).is_some())
+                .unwrap_or(false)
+        };
+        let bracket = if is_bracket(offset) {
+            offset
+        } else {
+            let prev = self.rope.prev_codepoint_offset(offset)?;
+            if !is_bracket(prev) {
+                return None;
+            }
+            prev
+        };
+        Some((bracket, self.find_matching_pair(bracket)))
+    }
+
     pub fn previous_unmatched(&self, c: char, offset: usize) -> Option<usize> {
         if let Some(syntax) = self.syntax.as_ref() {
             syntax.find_tag(offset, true, &c.to_string())
@@ -1636,12 +2484,33 @@ impl Buffer {
         }
     }
 
-    pub fn prev_code_boundary(&self, offset: usize) -> usize {
-        WordCursor::new(&self.rope, offset).prev_code_boundary()
+    pub fn next_unmatched(&self, c: char, offset: usize) -> Option<usize> {
+        if let Some(syntax) = self.syntax.as_ref() {
+            syntax.find_tag(offset, false, &c.to_string())
+        } else {
+            WordCursor::new(&self.rope, offset)
+                .next_unmatched(c)
+                .map(|offset| offset - 1)
+        }
+    }
+
+    pub fn prev_code_boundary(&self, offset: usize, word_chars: &str) -> usize {
+        WordCursor::new(&self.rope, offset)
+            .word_chars(word_chars)
+            .prev_code_boundary()
+    }
+
+    pub fn next_code_boundary(&self, offset: usize, word_chars: &str) -> usize {
+        WordCursor::new(&self.rope, offset)
+            .word_chars(word_chars)
+            .next_code_boundary()
     }
 
-    pub fn next_code_boundary(&self, offset: usize) -> usize {
-        WordCursor::new(&self.rope, offset).next_code_boundary()
+    /// The offset the code actions for the cursor at `offset` are requested
+    /// at and kept under. It doesn't depend on the word chars of the
+    /// language, so every editor of the buffer finds the same ones.
+    pub fn code_action_offset(&self, offset: usize) -> usize {
+        self.prev_code_boundary(offset, "")
     }
 
     pub fn update_history_changes(
@@ -1650,7 +2519,8 @@ impl Buffer {
         history: &str,
         changes: Arc<Vec<DiffLines>>,
     ) {
-        if rev != self.rev {
+        // The history may have been removed while its changes were computed
+        if rev != self.rev || !self.histories.contains_key(history) {
             return;
         }
         self.history_changes.insert(history.to_string(), changes);
@@ -1701,6 +2571,13 @@ impl Buffer {
             syntax.lens.apply_delta(delta);
         }
 
+        if !self.inlay_hints.is_empty() {
+            let mut transformer = Transformer::new(delta);
+            for hint in Arc::make_mut(&mut self.inlay_hints).iter_mut() {
+                hint.offset = transformer.transform(hint.offset, true);
+            }
+        }
+
         self.line_styles.borrow_mut().clear();
     }
 
@@ -1756,10 +2633,13 @@ impl Buffer {
         )
     }
 
-    fn calculate_undo_group(&mut self) -> usize {
+    fn calculate_undo_group(&mut self, regions: &[SelRegion]) -> usize {
         let has_undos = !self.live_undos.is_empty();
-        let is_unbroken_group =
-            !self.this_edit_type.breaks_undo_group(self.last_edit_type);
+        let is_unbroken_group = !self.this_edit_type.breaks_undo_group(
+            self.last_edit_type,
+            self.last_edit_time.elapsed(),
+            edit_is_contiguous(&self.last_edit_carets, regions),
+        );
 
         if has_undos && is_unbroken_group {
             *self.live_undos.last().unwrap()
@@ -1775,7 +2655,7 @@ impl Buffer {
 
     fn apply_edit(
         &mut self,
-        proxy: Arc<LapceProxy>,
+        proxy: Option<Arc<LapceProxy>>,
         delta: &RopeDelta,
         new_rev: Revision,
         new_text: Rope,
@@ -1792,7 +2672,7 @@ impl Buffer {
         let (iv, newlen) = delta.summary();
         let old_logical_end_line = self.rope.line_of_offset(iv.end) + 1;
 
-        if !self.local {
+        if let Some(proxy) = proxy.filter(|_| !self.local) {
             proxy.update(self.id, delta, self.rev);
         }
 
@@ -1824,20 +2704,27 @@ impl Buffer {
         self.last_edit_type = EditType::Other;
     }
 
+    /// Apply the edits as a single revision. The cursor, if given, is the
+    /// selection of the editor before the edits and is restored when the
+    /// undo group of the edits is undone.
     pub fn edit_multiple(
         &mut self,
         edits: &[(&Selection, &str)],
         proxy: Arc<LapceProxy>,
         edit_type: EditType,
+        cursor: Option<&Selection>,
     ) -> RopeDelta {
         let mut builder = DeltaBuilder::new(self.len());
         let mut interval_rope = Vec::new();
+        let mut regions = Vec::new();
         for (selection, content) in edits {
             let rope = Rope::from(content);
             for region in selection.regions() {
                 interval_rope.push((region.min(), region.max(), rope.clone()));
+                regions.push(*region);
             }
         }
+        regions.sort_by_key(|region| region.min());
         interval_rope.sort_by(|a, b| {
             if a.0 == b.0 && a.1 == b.1 {
                 Ordering::Equal
@@ -1852,14 +2739,33 @@ impl Buffer {
         }
         let delta = builder.build();
         self.this_edit_type = edit_type;
-        let undo_group = self.calculate_undo_group();
+        let undo_group = self.calculate_undo_group(&regions);
         self.last_edit_type = self.this_edit_type;
+        self.last_edit_time = Instant::now();
+        let mut transformer = Transformer::new(&delta);
+        self.last_edit_carets = regions
+            .iter()
+            .map(|region| transformer.transform(region.max(), true))
+            .collect();
+        if let Some(cursor) = cursor {
+            let mut after = Selection::new();
+            for caret in self.last_edit_carets.iter() {
+                after.add_region(SelRegion::caret(*caret));
+            }
+            self.undo_cursors
+                .entry(undo_group)
+                .and_modify(|undo_cursor| undo_cursor.after = after.clone())
+                .or_insert_with(|| UndoCursor {
+                    before: cursor.clone(),
+                    after,
+                });
+        }
 
         let (new_rev, new_text, new_tombstones, new_deletes_from_union) =
             self.mk_new_rev(undo_group, delta.clone());
 
         self.apply_edit(
-            proxy,
+            Some(proxy),
             &delta,
             new_rev,
             new_text,
@@ -1870,6 +2776,37 @@ impl Buffer {
         delta
     }
 
+    /// Replace the text with the `content` the file has on disk at `rev`,
+    /// as an undoable edit of the part that changed, so that the history of
+    /// the buffer is kept. The proxy already has the new content.
+    pub fn reload_content(&mut self, content: &str, rev: u64) {
+        let old = self.rope.to_string();
+        let (start, old_end, new_end) = changed_range(&old, content);
+        if start != old_end || start != new_end {
+            let delta = Delta::simple_edit(
+                Interval::new(start, old_end),
+                Rope::from(&content[start..new_end]),
+                self.len(),
+            );
+            self.this_edit_type = EditType::Other;
+            let undo_group = self.calculate_undo_group(&[]);
+            self.last_edit_type = EditType::Other;
+            self.last_edit_carets.clear();
+            let (new_rev, new_text, new_tombstones, new_deletes_from_union) =
+                self.mk_new_rev(undo_group, delta.clone());
+            self.apply_edit(
+                None,
+                &delta,
+                new_rev,
+                new_text,
+                new_tombstones,
+                new_deletes_from_union,
+            );
+        }
+        self.rev = rev;
+        self.atomic_rev.store(self.rev, atomic::Ordering::Release);
+    }
+
     pub fn edit(
         &mut self,
         selection: &Selection,
@@ -1877,26 +2814,50 @@ impl Buffer {
         proxy: Arc<LapceProxy>,
         edit_type: EditType,
     ) -> RopeDelta {
-        self.edit_multiple(&[(selection, content)], proxy, edit_type)
+        self.edit_multiple(&[(selection, content)], proxy, edit_type, None)
     }
 
-    pub fn do_undo(&mut self, proxy: Arc<LapceProxy>) -> Option<RopeDelta> {
+    /// Undo the last undo group, returning the delta together with the
+    /// cursor from before the group's edits, if it was recorded
+    pub fn do_undo(
+        &mut self,
+        proxy: Arc<LapceProxy>,
+    ) -> Option<(RopeDelta, Option<Selection>)> {
         if self.cur_undo > 1 {
             self.cur_undo -= 1;
-            self.undos.insert(self.live_undos[self.cur_undo]);
+            let undo_group = self.live_undos[self.cur_undo];
+            self.undos.insert(undo_group);
             self.last_edit_type = EditType::Undo;
-            Some(self.undo(self.undos.clone(), proxy))
+            let delta = self.undo(self.undos.clone(), proxy);
+            let cursor = self
+                .undo_cursors
+                .get(&undo_group)
+                .map(|c| c.before.clone())
+                .filter(|s| s.max_offset() <= self.len());
+            Some((delta, cursor))
         } else {
             None
         }
     }
 
-    pub fn do_redo(&mut self, proxy: Arc<LapceProxy>) -> Option<RopeDelta> {
+    /// Redo the next undo group, returning the delta together with the
+    /// cursor the group's edits left behind, if it was recorded
+    pub fn do_redo(
+        &mut self,
+        proxy: Arc<LapceProxy>,
+    ) -> Option<(RopeDelta, Option<Selection>)> {
         if self.cur_undo < self.live_undos.len() {
-            self.undos.remove(&self.live_undos[self.cur_undo]);
+            let undo_group = self.live_undos[self.cur_undo];
+            self.undos.remove(&undo_group);
             self.cur_undo += 1;
             self.last_edit_type = EditType::Redo;
-            Some(self.undo(self.undos.clone(), proxy))
+            let delta = self.undo(self.undos.clone(), proxy);
+            let cursor = self
+                .undo_cursors
+                .get(&undo_group)
+                .map(|c| c.after.clone())
+                .filter(|s| s.max_offset() <= self.len());
+            Some((delta, cursor))
         } else {
             None
         }
@@ -1922,7 +2883,7 @@ impl Buffer {
         );
         self.undone_groups = groups;
         self.apply_edit(
-            proxy,
+            Some(proxy),
             &delta,
             new_rev,
             new_text,
@@ -2090,21 +3051,49 @@ fn shuffle(
 
 pub struct WordCursor<'a> {
     inner: Cursor<'a, RopeInfo>,
+    /// Chars that are part of words on top of the letters and digits
+    word_chars: &'a str,
+    /// Whether words only end at whitespace, like the WORDs of vim
+    big_words: bool,
 }
 
 impl<'a> WordCursor<'a> {
     pub fn new(text: &'a Rope, pos: usize) -> WordCursor<'a> {
         let inner = Cursor::new(text, pos);
-        WordCursor { inner }
+        WordCursor {
+            inner,
+            word_chars: "",
+            big_words: false,
+        }
+    }
+
+    pub fn word_chars(mut self, word_chars: &'a str) -> Self {
+        self.word_chars = word_chars;
+        self
+    }
+
+    pub fn big_words(mut self) -> Self {
+        self.big_words = true;
+        self
+    }
+
+    fn word_property(&self, c: char) -> WordProperty {
+        if self.word_chars.contains(c) {
+            return WordProperty::Other;
+        }
+        match get_word_property(c) {
+            WordProperty::Punctuation if self.big_words => WordProperty::Other,
+            prop => prop,
+        }
     }
 
     /// Get previous boundary, and set the cursor at the boundary found.
     pub fn prev_boundary(&mut self) -> Option<usize> {
         if let Some(ch) = self.inner.prev_codepoint() {
-            let mut prop = get_word_property(ch);
+            let mut prop = self.word_property(ch);
             let mut candidate = self.inner.pos();
             while let Some(prev) = self.inner.prev_codepoint() {
-                let prop_prev = get_word_property(prev);
+                let prop_prev = self.word_property(prev);
                 if classify_boundary(prop_prev, prop).is_start() {
                     break;
                 }
@@ -2120,7 +3109,7 @@ impl<'a> WordCursor<'a> {
     pub fn next_non_blank_char(&mut self) -> usize {
         let mut candidate = self.inner.pos();
         while let Some(next) = self.inner.next_codepoint() {
-            let prop = get_word_property(next);
+            let prop = self.word_property(next);
             if prop != WordProperty::Space {
                 break;
             }
@@ -2133,10 +3122,10 @@ impl<'a> WordCursor<'a> {
     /// Get next boundary, and set the cursor at the boundary found.
     pub fn next_boundary(&mut self) -> Option<usize> {
         if let Some(ch) = self.inner.next_codepoint() {
-            let mut prop = get_word_property(ch);
+            let mut prop = self.word_property(ch);
             let mut candidate = self.inner.pos();
             while let Some(next) = self.inner.next_codepoint() {
-                let prop_next = get_word_property(next);
+                let prop_next = self.word_property(next);
                 if classify_boundary(prop, prop_next).is_start() {
                     break;
                 }
@@ -2152,10 +3141,10 @@ impl<'a> WordCursor<'a> {
     pub fn end_boundary(&mut self) -> Option<usize> {
         self.inner.next_codepoint();
         if let Some(ch) = self.inner.next_codepoint() {
-            let mut prop = get_word_property(ch);
+            let mut prop = self.word_property(ch);
             let mut candidate = self.inner.pos();
             while let Some(next) = self.inner.next_codepoint() {
-                let prop_next = get_word_property(next);
+                let prop_next = self.word_property(next);
                 if classify_boundary(prop, prop_next).is_end() {
                     break;
                 }
@@ -2171,7 +3160,7 @@ impl<'a> WordCursor<'a> {
     pub fn prev_code_boundary(&mut self) -> usize {
         let mut candidate = self.inner.pos();
         while let Some(prev) = self.inner.prev_codepoint() {
-            let prop_prev = get_word_property(prev);
+            let prop_prev = self.word_property(prev);
             if prop_prev != WordProperty::Other {
                 break;
             }
@@ -2183,7 +3172,7 @@ impl<'a> WordCursor<'a> {
     pub fn next_code_boundary(&mut self) -> usize {
         let mut candidate = self.inner.pos();
         while let Some(prev) = self.inner.next_codepoint() {
-            let prop_prev = get_word_property(prev);
+            let prop_prev = self.word_property(prev);
             if prop_prev != WordProperty::Other {
                 break;
             }
@@ -2249,9 +3238,11 @@ impl<'a> WordCursor<'a> {
     /// cursor is moved to the end of that selection.
     pub fn select_word_old(&mut self) -> (usize, usize) {
         let initial = self.inner.pos();
-        let init_prop_after = self.inner.next_codepoint().map(get_word_property);
+        let init_prop_after =
+            self.inner.next_codepoint().map(|c| self.word_property(c));
         self.inner.set(initial);
-        let init_prop_before = self.inner.prev_codepoint().map(get_word_property);
+        let init_prop_before =
+            self.inner.prev_codepoint().map(|c| self.word_property(c));
         let mut start = initial;
         let init_boundary =
             if let (Some(pb), Some(pa)) = (init_prop_before, init_prop_after) {
@@ -2264,7 +3255,7 @@ impl<'a> WordCursor<'a> {
         if prop_after.is_none() {
             start = self.inner.pos();
             prop_after = prop_before;
-            prop_before = self.inner.prev_codepoint().map(get_word_property);
+            prop_before = self.inner.prev_codepoint().map(|c| self.word_property(c));
         }
         while let (Some(pb), Some(pa)) = (prop_before, prop_after) {
             if start == initial {
@@ -2280,16 +3271,16 @@ impl<'a> WordCursor<'a> {
             }
             start = self.inner.pos();
             prop_after = prop_before;
-            prop_before = self.inner.prev_codepoint().map(get_word_property);
+            prop_before = self.inner.prev_codepoint().map(|c| self.word_property(c));
         }
         self.inner.set(initial);
         let mut end = initial;
         prop_after = init_prop_after;
         prop_before = init_prop_before;
         if prop_before.is_none() {
-            prop_before = self.inner.next_codepoint().map(get_word_property);
+            prop_before = self.inner.next_codepoint().map(|c| self.word_property(c));
             end = self.inner.pos();
-            prop_after = self.inner.next_codepoint().map(get_word_property);
+            prop_after = self.inner.next_codepoint().map(|c| self.word_property(c));
         }
         while let (Some(pb), Some(pa)) = (prop_before, prop_after) {
             if end == initial {
@@ -2305,7 +3296,7 @@ impl<'a> WordCursor<'a> {
             }
             end = self.inner.pos();
             prop_before = prop_after;
-            prop_after = self.inner.next_codepoint().map(get_word_property);
+            prop_after = self.inner.next_codepoint().map(|c| self.word_property(c));
         }
         self.inner.set(end);
         (start, end)
@@ -3063,3 +4054,320 @@ where
 //     }
 //     UnicodeWidthStr::width(s)
 // }
+
+/// The range of `count` whole lines starting at `line` together with their
+/// content, which always ends with a newline so that it can be pasted as
+/// whole lines. When the lines run to the end of the text, the newline
+/// before them is part of the range so that no empty line is left behind.
+pub fn linewise_range(
+    text: &Rope,
+    line: usize,
+    count: usize,
+) -> (usize, usize, String) {
+    let last_line = text.line_of_offset(text.len());
+    let line = line.min(last_line);
+    let end_line = (line + count.max(1)).min(last_line + 1);
+    let mut start = text.offset_of_line(line);
+    let end = text.offset_of_line(end_line);
+    let mut content = text.slice_to_cow(start..end).to_string();
+    if !content.ends_with('\n') {
+        content.push('\n');
+        if start > 0 {
+            start -= 1;
+            if start > 0 && text.byte_at(start - 1) == b'\r' {
+                start -= 1;
+            }
+        }
+    }
+    (start, end, content)
+}
+
+/// The edits that clean up the whitespace of a text before it's saved, as
+/// the ranges to replace and what to replace them with. With `trim` the
+/// spaces and tabs at the end of every line except `keep_line` are removed,
+/// and with a `final_newline` the text ends with exactly that one newline.
+pub fn whitespace_cleanup_edits(
+    text: &str,
+    trim: bool,
+    final_newline: Option<&'static str>,
+    keep_line: Option<usize>,
+) -> Vec<(usize, usize, &'static str)> {
+    // The range of the trailing whitespace of every line
+    let mut trailing = Vec::new();
+    let mut line_start = 0;
+    for content in text.split_inclusive('\n') {
+        let body = content.trim_end_matches(['\n', '\r']);
+        let trimmed = body.trim_end_matches([' ', '\t']);
+        trailing.push((line_start + trimmed.len(), line_start + body.len()));
+        line_start += content.len();
+    }
+
+    // Where the text ends once the blank lines at the end are gone
+    let mut tail_start = text.len();
+    if final_newline.is_some() {
+        let keep_end = keep_line
+            .and_then(|line| trailing.get(line))
+            .map(|(_, end)| *end)
+            .unwrap_or(0);
+        tail_start = text
+            .trim_end_matches(|c| {
+                matches!(c, '\n' | '\r') || (trim && matches!(c, ' ' | '\t'))
+            })
+            .len()
+            .max(keep_end);
+    }
+
+    let mut edits = Vec::new();
+    if trim {
+        for (line, (start, end)) in trailing.into_iter().enumerate() {
+            if Some(line) != keep_line && start < end && start < tail_start {
+                edits.push((start, end.min(tail_start), ""));
+            }
+        }
+    }
+    if let Some(newline) = final_newline {
+        let newline = if tail_start == 0 { "" } else { newline };
+        if text[tail_start..] != *newline {
+            edits.push((tail_start, text.len(), newline));
+        }
+    }
+    edits
+}
+
+/// The number of lines, characters and words in a piece of text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextStats {
+    pub lines: usize,
+    pub chars: usize,
+    pub words: usize,
+}
+
+impl TextStats {
+    pub fn new(text: &str) -> Self {
+        Self {
+            lines: text.lines().count(),
+            chars: text.chars().count(),
+            words: text.unicode_words().count(),
+        }
+    }
+
+    /// The stats of all the regions of a selection together
+    pub fn of_selection(buffer: &Buffer, selection: &Selection) -> Self {
+        selection
+            .regions()
+            .iter()
+            .map(|region| {
+                TextStats::new(&buffer.slice_to_cow(region.min()..region.max()))
+            })
+            .fold(TextStats::default(), |total, stats| TextStats {
+                lines: total.lines + stats.lines,
+                chars: total.chars + stats.chars,
+                words: total.words + stats.words,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        command::LapceCommand,
+        headless::HeadlessEditor,
+        movement::{Cursor, CursorMode},
+    };
+
+    use super::*;
+
+    /// Count the undo groups that a sequence of single caret edits ends up
+    /// in, each edit given as its type, offset and the caret after it
+    fn count_undo_groups(edits: &[(EditType, usize, usize, u64)]) -> usize {
+        let mut groups = 0;
+        let mut last_edit_type = EditType::Other;
+        let mut last_carets = Vec::new();
+        for (edit_type, offset, caret, elapsed) in edits {
+            let regions = [SelRegion::caret(*offset)];
+            if edit_type.breaks_undo_group(
+                last_edit_type,
+                Duration::from_millis(*elapsed),
+                edit_is_contiguous(&last_carets, &regions),
+            ) {
+                groups += 1;
+            }
+            last_edit_type = *edit_type;
+            last_carets = vec![*caret];
+        }
+        groups
+    }
+
+    /// Type `text` one char at a time at the end of `editor`, like the
+    /// editor does in insert mode
+    fn type_text(editor: &mut HeadlessEditor, text: &str) {
+        let end = editor.buffer.len();
+        editor.cursor = Cursor::new(CursorMode::Insert(Selection::caret(end)), None);
+        for c in text.chars() {
+            if c == '\n' {
+                editor.run(&LapceCommand::InsertNewLine, None);
+            } else {
+                editor.insert(&c.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn test_linewise_range() {
+        let text = Rope::from("a\nb\nc\n");
+        assert_eq!(linewise_range(&text, 0, 1), (0, 2, "a\n".to_string()));
+        assert_eq!(linewise_range(&text, 1, 2), (2, 6, "b\nc\n".to_string()));
+        assert_eq!(linewise_range(&text, 1, 10), (2, 6, "b\nc\n".to_string()));
+        let text = Rope::from("a\nb");
+        assert_eq!(linewise_range(&text, 1, 1), (1, 3, "b\n".to_string()));
+        let text = Rope::from("a\r\nb");
+        assert_eq!(linewise_range(&text, 1, 1), (1, 4, "b\n".to_string()));
+    }
+
+    #[test]
+    fn test_typing_a_word_is_one_undo_group() {
+        let typing: Vec<_> = (0..4)
+            .map(|i| (EditType::InsertChars, i, i + 1, 100))
+            .collect();
+        assert_eq!(count_undo_groups(&typing), 1);
+    }
+
+    #[test]
+    fn test_undo_group_boundaries() {
+        use EditType::*;
+        // a pause while typing
+        assert_eq!(
+            count_undo_groups(&[(InsertChars, 0, 1, 0), (InsertChars, 1, 2, 2000)]),
+            2
+        );
+        // a cursor jump while typing
+        assert_eq!(
+            count_undo_groups(&[(InsertChars, 0, 1, 0), (InsertChars, 5, 6, 100)]),
+            2
+        );
+        // a newline between typed characters
+        assert_eq!(
+            count_undo_groups(&[
+                (InsertChars, 0, 1, 0),
+                (InsertNewline, 1, 2, 100),
+                (InsertChars, 2, 3, 100),
+            ]),
+            3
+        );
+        // switching from typing to deleting, then a run of deletes
+        assert_eq!(
+            count_undo_groups(&[
+                (InsertChars, 0, 1, 0),
+                (Delete, 1, 0, 100),
+                (Delete, 0, 0, 100),
+            ]),
+            2
+        );
+        // newlines one after the other
+        assert_eq!(
+            count_undo_groups(&[
+                (InsertNewline, 0, 1, 0),
+                (InsertNewline, 1, 2, 100),
+            ]),
+            2
+        );
+        // a forced boundary, like leaving insert mode
+        assert_eq!(
+            count_undo_groups(&[
+                (InsertChars, 0, 1, 0),
+                (Other, 1, 2, 100),
+                (InsertChars, 2, 3, 100),
+            ]),
+            3
+        );
+    }
+
+    #[test]
+    fn test_undo_typed_word() {
+        let mut editor = HeadlessEditor::new("a ", false);
+        type_text(&mut editor, "word");
+        assert_eq!(editor.text(), "a word");
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "a ");
+
+        let mut editor = HeadlessEditor::new("", false);
+        type_text(&mut editor, "ab\n\ncd");
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "ab\n\n");
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "ab\n");
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "ab");
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "");
+    }
+
+    /// Apply the whitespace cleanup edits to a text
+    fn cleanup(
+        text: &str,
+        trim: bool,
+        final_newline: bool,
+        keep_line: Option<usize>,
+    ) -> String {
+        let final_newline = if final_newline { Some("\n") } else { None };
+        let mut text = text.to_string();
+        let edits = whitespace_cleanup_edits(&text, trim, final_newline, keep_line);
+        for (start, end, content) in edits.into_iter().rev() {
+            text.replace_range(start..end, content);
+        }
+        text
+    }
+
+    #[test]
+    fn test_whitespace_cleanup_edits() {
+        let text = "a  \n\tb\t\n  \nc \n\n\n";
+        assert_eq!(cleanup(text, true, false, None), "a\n\tb\n\nc\n\n\n");
+        assert_eq!(cleanup(text, false, true, None), "a  \n\tb\t\n  \nc \n");
+        assert_eq!(cleanup(text, true, true, None), "a\n\tb\n\nc\n");
+        assert_eq!(cleanup(text, true, true, Some(0)), "a  \n\tb\n\nc\n");
+        assert_eq!(cleanup(text, true, true, Some(3)), "a\n\tb\n\nc \n");
+        assert_eq!(cleanup("a\nb  ", true, true, None), "a\nb\n");
+        assert_eq!(cleanup("a\n", true, true, None), "a\n");
+        assert_eq!(cleanup("\n \n", true, true, None), "");
+        assert_eq!(cleanup("", true, true, None), "");
+    }
+
+    #[test]
+    fn test_content_hash() {
+        // The hash is stored, so it must not change
+        assert_eq!(content_hash(&Rope::from("")), 0xcbf29ce484222325);
+        assert_eq!(content_hash(&Rope::from("a")), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn test_changed_range() {
+        assert_eq!(changed_range("abc", "abc"), (3, 3, 3));
+        assert_eq!(changed_range("abc", "aXc"), (1, 2, 2));
+        assert_eq!(changed_range("abc", "abbc"), (2, 2, 3));
+        assert_eq!(changed_range("aa", "a"), (1, 2, 1));
+        assert_eq!(changed_range("", "ab"), (0, 0, 2));
+        // Not in the middle of a character
+        assert_eq!(changed_range("é", "è"), (0, 2, 2));
+    }
+
+    #[test]
+    fn test_text_stats() {
+        let stats = |lines, chars, words| TextStats {
+            lines,
+            chars,
+            words,
+        };
+        assert_eq!(TextStats::new(""), stats(0, 0, 0));
+        assert_eq!(TextStats::new("one two\nthree\n"), stats(2, 14, 3));
+        assert_eq!(TextStats::new("can't stop, won't-stop"), stats(1, 22, 4));
+        assert_eq!(TextStats::new("héllo wörld\n\n"), stats(2, 13, 2));
+        assert_eq!(TextStats::new("  \t\n// ..."), stats(2, 10, 0));
+    }
+
+    #[test]
+    fn test_file_history() {
+        let path = PathBuf::from("/a/b.rs");
+        assert_eq!(history_file(&file_history(&path)), Some(path));
+        assert_eq!(history_file("head"), None);
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+
+/// A path given on the command line, with the line and column to put the
+/// cursor at, both counted from 0
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathArg {
+    pub path: PathBuf,
+    pub position: Option<(usize, usize)>,
+}
+
+/// Parse the paths given on the command line. A path can end in `:line` or
+/// `:line:col`, like in compiler and grep output, or follow a vim-style
+/// `+line`. Relative paths are resolved against `cwd`.
+pub fn parse_path_args(
+    args: impl IntoIterator<Item = String>,
+    cwd: &Path,
+) -> Vec<PathArg> {
+    let mut paths = Vec::new();
+    let mut pending_line = None;
+    for arg in args {
+        if let Some(line) = arg.strip_prefix('+') {
+            if let Ok(line) = line.parse::<usize>() {
+                pending_line = Some(line);
+                continue;
+            }
+        }
+        let mut path_arg = parse_path_arg(&arg, cwd);
+        if let Some(line) = pending_line.take() {
+            path_arg.position = Some((line.saturating_sub(1), 0));
+        }
+        paths.push(path_arg);
+    }
+    // `lapce file +42` puts the line after the path
+    if let (Some(line), Some(path_arg)) = (pending_line, paths.last_mut()) {
+        path_arg.position = Some((line.saturating_sub(1), 0));
+    }
+    paths
+}
+
+fn parse_path_arg(arg: &str, cwd: &Path) -> PathArg {
+    let path = cwd.join(arg);
+    // A file can have a name that looks like a position
+    if !path.exists() {
+        let mut parts = arg.rsplitn(3, ':');
+        let last = parts.next().and_then(|s| s.parse::<usize>().ok());
+        let second = parts.next();
+        let rest = parts.next();
+        match (rest, second, last) {
+            (Some(rest), Some(line), Some(col)) if !rest.is_empty() => {
+                if let Ok(line) = line.parse::<usize>() {
+                    return PathArg {
+                        path: cwd.join(rest),
+                        position: Some((
+                            line.saturating_sub(1),
+                            col.saturating_sub(1),
+                        )),
+                    };
+                }
+                return PathArg {
+                    path: cwd.join(format!("{rest}:{line}")),
+                    position: Some((col.saturating_sub(1), 0)),
+                };
+            }
+            (None, Some(rest), Some(line)) if !rest.is_empty() => {
+                return PathArg {
+                    path: cwd.join(rest),
+                    position: Some((line.saturating_sub(1), 0)),
+                };
+            }
+            _ => {}
+        }
+    }
+    PathArg {
+        path,
+        position: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Vec<PathArg> {
+        parse_path_args(
+            args.iter().map(|s| s.to_string()),
+            Path::new("/nonexistent"),
+        )
+    }
+
+    fn arg(path: &str, position: Option<(usize, usize)>) -> PathArg {
+        PathArg {
+            path: PathBuf::from(path),
+            position,
+        }
+    }
+
+    #[test]
+    fn test_parse_line_and_column() {
+        assert_eq!(
+            parse(&["src/main.rs:42:7"]),
+            vec![arg("/nonexistent/src/main.rs", Some((41, 6)))]
+        );
+        assert_eq!(
+            parse(&["src/main.rs:42"]),
+            vec![arg("/nonexistent/src/main.rs", Some((41, 0)))]
+        );
+        assert_eq!(parse(&["/tmp/a.rs"]), vec![arg("/tmp/a.rs", None)]);
+        assert_eq!(parse(&["a:b"]), vec![arg("/nonexistent/a:b", None)]);
+    }
+
+    #[test]
+    fn test_parse_vim_style_line() {
+        assert_eq!(
+            parse(&["+42", "a.rs", "b.rs"]),
+            vec![
+                arg("/nonexistent/a.rs", Some((41, 0))),
+                arg("/nonexistent/b.rs", None)
+            ]
+        );
+        assert_eq!(
+            parse(&["a.rs", "+3"]),
+            vec![arg("/nonexistent/a.rs", Some((2, 0)))]
+        );
+    }
+}
@@ -84,7 +84,7 @@ impl CodeActionData {
         if let BufferContent::File(path) = &editor.content {
             let buffer = self.main_split.open_files.get(path).unwrap();
             let offset = editor.cursor.offset();
-            let prev_offset = buffer.prev_code_boundary(offset);
+            let prev_offset = buffer.code_action_offset(offset);
             let empty_vec = Vec::new();
             let code_actions =
                 buffer.code_actions.get(&prev_offset).unwrap_or(&empty_vec);
@@ -107,7 +107,7 @@ impl CodeActionData {
         if let BufferContent::File(path) = &editor.content {
             let buffer = self.main_split.open_files.get(path).unwrap();
             let offset = editor.cursor.offset();
-            let prev_offset = buffer.prev_code_boundary(offset);
+            let prev_offset = buffer.code_action_offset(offset);
             let empty_vec = Vec::new();
             let code_actions =
                 buffer.code_actions.get(&prev_offset).unwrap_or(&empty_vec);
@@ -169,7 +169,7 @@ impl CodeActionData {
         if let BufferContent::File(path) = &editor.content {
             let buffer = self.main_split.open_files.get(path).unwrap();
             let offset = editor.cursor.offset();
-            let prev_offset = buffer.prev_code_boundary(offset);
+            let prev_offset = buffer.code_action_offset(offset);
             let empty_vec = Vec::new();
             let code_actions =
                 buffer.code_actions.get(&prev_offset).unwrap_or(&empty_vec);
@@ -3,14 +3,18 @@ use std::{collections::HashMap, path::PathBuf, sync::Arc};
 use anyhow::Result;
 use druid::{Point, Rect, Selector, Size, WidgetId, WindowId};
 use indexmap::IndexMap;
-use lapce_core::syntax::Syntax;
+use lapce_core::{language::LapceLanguage, syntax::Syntax};
 use lapce_rpc::{
-    buffer::BufferId, file::FileNodeItem, plugin::PluginDescription,
-    source_control::DiffInfo, style::Style, terminal::TermId,
+    buffer::{BufferId, LineEnding},
+    file::FileNodeItem,
+    plugin::PluginDescription,
+    source_control::{BlameHunk, DiffInfo},
+    style::Style,
+    terminal::TermId,
 };
 use lsp_types::{
     CodeActionResponse, CompletionItem, CompletionResponse, Hover, Location,
-    Position, ProgressParams, PublishDiagnosticsParams, TextEdit,
+    Position, ProgressParams, PublishDiagnosticsParams, SignatureHelp, TextEdit,
 };
 use serde_json::Value;
 use strum::{self, EnumMessage, IntoEnumIterator};
@@ -21,6 +25,7 @@ use crate::{
     buffer::DiffLines,
     data::{EditorTabChild, MotionMode, SplitContent},
     editor::EditorLocationNew,
+    inlay_hint::LspInlayHint,
     keypress::{KeyMap, KeyPress},
     menu::MenuItem,
     movement::{LinePosition, Movement},
@@ -98,6 +103,30 @@ pub enum LapceWorkbenchCommand {
     #[strum(message = "Disable Modal Editing")]
     DisableModal,
 
+    #[strum(serialize = "toggle_inlay_hints")]
+    #[strum(message = "Toggle Inlay Hints")]
+    ToggleInlayHints,
+
+    #[strum(serialize = "show_diagnostics_in_location_list")]
+    #[strum(message = "Show Diagnostics in Location List")]
+    ShowDiagnosticsInLocationList,
+
+    #[strum(serialize = "next_location")]
+    #[strum(message = "Go to Next Location in Location List")]
+    NextLocation,
+
+    #[strum(serialize = "previous_location")]
+    #[strum(message = "Go to Previous Location in Location List")]
+    PreviousLocation,
+
+    #[strum(serialize = "toggle_blame")]
+    #[strum(message = "Toggle Blame of the Current Line")]
+    ToggleBlame,
+
+    #[strum(serialize = "show_blame")]
+    #[strum(message = "Show Blame of the File")]
+    ShowBlame,
+
     #[strum(serialize = "open_folder")]
     #[strum(message = "Open Folder")]
     OpenFolder,
@@ -110,10 +139,38 @@ pub enum LapceWorkbenchCommand {
     #[strum(message = "Open File")]
     OpenFile,
 
+    #[strum(serialize = "diff_with_head")]
+    #[strum(message = "Compare With HEAD Side by Side")]
+    DiffWithHead,
+
+    #[strum(serialize = "diff_with_previous_file")]
+    #[strum(message = "Compare With the Previously Focused File")]
+    DiffWithPreviousFile,
+
+    #[strum(serialize = "new_scratch_buffer")]
+    #[strum(message = "New Untitled File")]
+    NewScratchBuffer,
+
+    #[strum(serialize = "open_recent_file")]
+    #[strum(message = "Open Recent File")]
+    OpenRecentFile,
+
+    #[strum(serialize = "reopen_closed")]
+    #[strum(message = "Reopen Closed Editor")]
+    ReopenClosed,
+
+    #[strum(serialize = "new_terminal")]
+    #[strum(message = "New Terminal")]
+    NewTerminal,
+
     #[strum(serialize = "change_theme")]
     #[strum(message = "Change Theme")]
     ChangeTheme,
 
+    #[strum(serialize = "set_language")]
+    #[strum(message = "Change Language")]
+    SetLanguage,
+
     #[strum(serialize = "open_settings")]
     #[strum(message = "Open Settings")]
     OpenSettings,
@@ -187,6 +244,10 @@ pub enum LapceWorkbenchCommand {
     #[strum(serialize = "palette.workspace")]
     PaletteWorkspace,
 
+    #[strum(message = "Switch Between Open Files")]
+    #[strum(serialize = "palette.buffer")]
+    PaletteBuffer,
+
     #[strum(serialize = "source_control.checkout_branch")]
     CheckoutBranch,
 
@@ -223,9 +284,20 @@ pub enum LapceWorkbenchCommand {
     #[strum(serialize = "toggle_problem_focus")]
     ToggleProblemFocus,
 
+    #[strum(serialize = "toggle_location_list_focus")]
+    ToggleLocationListFocus,
+
     #[strum(serialize = "toggle_search_focus")]
     ToggleSearchFocus,
 
+    #[strum(serialize = "toggle_search_case_sensitive")]
+    #[strum(message = "Toggle Case Sensitive Search")]
+    ToggleSearchCaseSensitive,
+
+    #[strum(serialize = "toggle_search_whole_word")]
+    #[strum(message = "Toggle Whole Word Search")]
+    ToggleSearchWholeWord,
+
     // Visual toggle commands
     #[strum(serialize = "toggle_terminal_visual")]
     ToggleTerminalVisual,
@@ -242,6 +314,9 @@ pub enum LapceWorkbenchCommand {
     #[strum(serialize = "toggle_problem_visual")]
     ToggleProblemVisual,
 
+    #[strum(serialize = "toggle_location_list_visual")]
+    ToggleLocationListVisual,
+
     #[strum(serialize = "toggle_search_visual")]
     ToggleSearchVisual,
 
@@ -267,6 +342,8 @@ pub enum LapceCommand {
     InsertCursorBelow,
     #[strum(serialize = "insert_cursor_end_of_line")]
     InsertCursorEndOfLine,
+    #[strum(serialize = "insert_cursor_next_match")]
+    InsertCursorNextMatch,
     #[strum(serialize = "select_undo")]
     SelectUndo,
     #[strum(serialize = "select_current_line")]
@@ -281,6 +358,12 @@ pub enum LapceCommand {
     FileExplorer,
     #[strum(serialize = "file_explorer.cancel")]
     FileExplorerCancel,
+    #[strum(serialize = "file_explorer.new_file")]
+    FileExplorerNewFile,
+    #[strum(serialize = "file_explorer.rename")]
+    FileExplorerRename,
+    #[strum(serialize = "file_explorer.delete")]
+    FileExplorerDelete,
     #[strum(serialize = "source_control")]
     SourceControl,
     #[strum(serialize = "source_control.cancel")]
@@ -303,14 +386,39 @@ pub enum LapceCommand {
     DeleteWordBackward,
     #[strum(serialize = "delete_word_forward")]
     DeleteWordForward,
+    /// Delete the word before the cursor, with the words only delimited by
+    /// whitespace like the WORDs of vim
+    #[strum(serialize = "delete_big_word_backward")]
+    DeleteBigWordBackward,
+    /// Delete the word after the cursor, with the words only delimited by
+    /// whitespace like the WORDs of vim
+    #[strum(serialize = "delete_big_word_forward")]
+    DeleteBigWordForward,
     #[strum(serialize = "delete_to_beginning_of_line")]
     DeleteToBeginningOfLine,
+    #[strum(serialize = "delete_to_end_of_line")]
+    DeleteToEndOfLine,
+    #[strum(serialize = "change_to_end_of_line")]
+    ChangeToEndOfLine,
+    #[strum(serialize = "yank_line")]
+    YankLine,
     #[strum(serialize = "inline_find_right")]
     InlineFindRight,
     #[strum(serialize = "inline_find_left")]
     InlineFindLeft,
+    #[strum(message = "Surround With")]
+    #[strum(serialize = "add_surround")]
+    AddSurround,
+    #[strum(message = "Delete Surround")]
+    #[strum(serialize = "delete_surround")]
+    DeleteSurround,
+    #[strum(message = "Change Surround")]
+    #[strum(serialize = "change_surround")]
+    ChangeSurround,
     #[strum(serialize = "repeat_last_inline_find")]
     RepeatLastInlineFind,
+    #[strum(serialize = "repeat_last_inline_find_reverse")]
+    RepeatLastInlineFindReverse,
     #[strum(serialize = "down")]
     Down,
     #[strum(serialize = "up")]
@@ -360,6 +468,10 @@ pub enum LapceCommand {
     #[strum(serialize = "toggle_line_comment")]
     ToggleLineComment,
 
+    #[strum(message = "Toggle Block Comment")]
+    #[strum(serialize = "toggle_block_comment")]
+    ToggleBlockComment,
+
     #[strum(message = "Indent Line")]
     #[strum(serialize = "indent_line")]
     IndentLine,
@@ -376,8 +488,14 @@ pub enum LapceCommand {
     ToggleLinewiseVisualMode,
     #[strum(serialize = "toggle_blockwise_visual_mode")]
     ToggleBlockwiseVisualMode,
+    #[strum(serialize = "select_last_visual")]
+    SelectLastVisual,
+    #[strum(serialize = "visual_swap_ends")]
+    VisualSwapEnds,
     #[strum(serialize = "motion_mode_delete")]
     MotionModeDelete,
+    #[strum(serialize = "motion_mode_change")]
+    MotionModeChange,
     #[strum(serialize = "motion_mode_indent")]
     MotionModeIndent,
     #[strum(serialize = "motion_mode_outdent")]
@@ -388,8 +506,17 @@ pub enum LapceCommand {
     NewLineAbove,
     #[strum(serialize = "new_line_below")]
     NewLineBelow,
+    /// Request completion at the cursor even where typing wouldn't, like in
+    /// the middle of a word or after a space
+    #[strum(message = "Trigger Completion")]
+    #[strum(serialize = "trigger_completion")]
+    // The name the command had before, still bound in older keymaps
     #[strum(serialize = "get_completion")]
-    GetCompletion,
+    TriggerCompletion,
+    /// Insert the prefix that all the completion items share, keeping the
+    /// completion open
+    #[strum(serialize = "accept_completion_prefix")]
+    AcceptCompletionPrefix,
     #[strum(serialize = "get_references")]
     GetReferences,
     #[strum(serialize = "insert_new_line")]
@@ -414,6 +541,10 @@ pub enum LapceCommand {
     LineStart,
     #[strum(serialize = "line_start_non_blank")]
     LineStartNonBlank,
+    /// Go to the first non-blank character of the line, or to its start when
+    /// the cursor is already there
+    #[strum(serialize = "smart_line_start")]
+    SmartLineStart,
     #[strum(serialize = "go_to_line_deault_last")]
     GotoLineDefaultLast,
     #[strum(serialize = "go_to_line_deault_first")]
@@ -441,9 +572,52 @@ pub enum LapceCommand {
     #[strum(serialize = "toggle_code_lens")]
     ToggleCodeLens,
 
+    #[strum(message = "Toggle Fold")]
+    #[strum(serialize = "toggle_fold")]
+    ToggleFold,
+
+    #[strum(message = "Fold All")]
+    #[strum(serialize = "fold_all")]
+    FoldAll,
+
+    #[strum(message = "Unfold All")]
+    #[strum(serialize = "unfold_all")]
+    UnfoldAll,
+
+    #[strum(message = "Show Word Count")]
+    #[strum(serialize = "show_stats")]
+    ShowStats,
+
+    #[strum(message = "Expand Selection")]
+    #[strum(serialize = "expand_selection")]
+    ExpandSelection,
+
+    #[strum(message = "Shrink Selection")]
+    #[strum(serialize = "shrink_selection")]
+    ShrinkSelection,
+
+    #[strum(message = "Reflow Paragraph")]
+    #[strum(serialize = "reflow_paragraph")]
+    ReflowParagraph,
+
     #[strum(serialize = "center_of_window")]
     CenterOfWindow,
 
+    #[strum(serialize = "scroll_cursor_top")]
+    ScrollCursorTop,
+
+    #[strum(serialize = "scroll_cursor_bottom")]
+    ScrollCursorBottom,
+
+    #[strum(serialize = "top_of_window")]
+    TopOfWindow,
+
+    #[strum(serialize = "middle_of_window")]
+    MiddleOfWindow,
+
+    #[strum(serialize = "bottom_of_window")]
+    BottomOfWindow,
+
     #[strum(message = "Go to Definition")]
     #[strum(serialize = "goto_definition")]
     GotoDefinition,
@@ -462,12 +636,42 @@ pub enum LapceCommand {
     #[strum(message = "Go to Previous Difference")]
     #[strum(serialize = "previous_diff")]
     PreviousDiff,
+    #[strum(message = "Go to Next Change")]
+    #[strum(serialize = "next_change")]
+    NextChange,
+    #[strum(message = "Go to Previous Change")]
+    #[strum(serialize = "previous_change")]
+    PreviousChange,
+    #[strum(message = "Revert Change")]
+    #[strum(serialize = "revert_change")]
+    RevertChange,
+    #[strum(message = "Copy the Change to the Right Side of the Diff")]
+    #[strum(serialize = "diff_copy_to_right")]
+    DiffCopyToRight,
+    #[strum(message = "Copy the Change to the Left Side of the Diff")]
+    #[strum(serialize = "diff_copy_to_left")]
+    DiffCopyToLeft,
     #[strum(serialize = "format_document")]
     #[strum(message = "Format Document")]
     FormatDocument,
     #[strum(message = "Save")]
     #[strum(serialize = "save")]
     Save,
+    #[strum(message = "Change Line Ending to LF")]
+    #[strum(serialize = "set_line_ending_lf")]
+    SetLineEndingLf,
+    #[strum(message = "Change Line Ending to CRLF")]
+    #[strum(serialize = "set_line_ending_crlf")]
+    SetLineEndingCrlf,
+    #[strum(message = "Reload File From Disk")]
+    #[strum(serialize = "reload_from_disk")]
+    ReloadFromDisk,
+    #[strum(message = "Keep Unsaved Changes")]
+    #[strum(serialize = "keep_unsaved_changes")]
+    KeepUnsavedChanges,
+    #[strum(message = "Toggle Read Only")]
+    #[strum(serialize = "toggle_read_only")]
+    ToggleReadOnly,
     #[strum(serialize = "show_code_actions")]
     ShowCodeActions,
     #[strum(serialize = "match_pairs")]
@@ -508,6 +712,7 @@ impl LapceCommand {
         let mode = match self {
             LapceCommand::MotionModeYank => MotionMode::Yank,
             LapceCommand::MotionModeDelete => MotionMode::Delete,
+            LapceCommand::MotionModeChange => MotionMode::Change,
             LapceCommand::MotionModeIndent => MotionMode::Indent,
             LapceCommand::MotionModeOutdent => MotionMode::Outdent,
             _ => return None,
@@ -515,6 +720,61 @@ impl LapceCommand {
         Some(mode)
     }
 
+    /// Whether the command changes the content of the buffer, so that it
+    /// can't be run in a read only buffer
+    pub fn is_edit_command(&self) -> bool {
+        matches!(
+            self,
+            LapceCommand::MoveLineUp
+                | LapceCommand::MoveLineDown
+                | LapceCommand::DeleteBackward
+                | LapceCommand::DeleteForward
+                | LapceCommand::DeleteForwardAndInsert
+                | LapceCommand::DeleteVisual
+                | LapceCommand::DeleteOperator
+                | LapceCommand::DeleteWordBackward
+                | LapceCommand::DeleteWordForward
+                | LapceCommand::DeleteBigWordBackward
+                | LapceCommand::DeleteBigWordForward
+                | LapceCommand::DeleteToBeginningOfLine
+                | LapceCommand::DeleteToEndOfLine
+                | LapceCommand::ChangeToEndOfLine
+                | LapceCommand::InsertMode
+                | LapceCommand::InsertFirstNonBlank
+                | LapceCommand::ToggleLineComment
+                | LapceCommand::ToggleBlockComment
+                | LapceCommand::RevertChange
+                | LapceCommand::IndentLine
+                | LapceCommand::OutdentLine
+                | LapceCommand::MotionModeDelete
+                | LapceCommand::MotionModeChange
+                | LapceCommand::MotionModeIndent
+                | LapceCommand::MotionModeOutdent
+                | LapceCommand::NewLineAbove
+                | LapceCommand::NewLineBelow
+                | LapceCommand::InsertNewLine
+                | LapceCommand::InsertTab
+                | LapceCommand::Append
+                | LapceCommand::AppendEndOfLine
+                | LapceCommand::Paste
+                | LapceCommand::ClipboardCut
+                | LapceCommand::ClipboardPaste
+                | LapceCommand::Undo
+                | LapceCommand::Redo
+                | LapceCommand::FormatDocument
+                | LapceCommand::JoinLines
+                | LapceCommand::SetLineEndingLf
+                | LapceCommand::SetLineEndingCrlf
+                | LapceCommand::AddSurround
+                | LapceCommand::DeleteSurround
+                | LapceCommand::ChangeSurround
+                | LapceCommand::ReflowParagraph
+                | LapceCommand::AcceptCompletionPrefix
+                | LapceCommand::DiffCopyToRight
+                | LapceCommand::DiffCopyToLeft
+        )
+    }
+
     pub fn move_command(&self, count: Option<usize>) -> Option<Movement> {
         match self {
             LapceCommand::Left => Some(Movement::Left),
@@ -525,6 +785,7 @@ impl LapceCommand {
             LapceCommand::DocumentEnd => Some(Movement::DocumentEnd),
             LapceCommand::LineStart => Some(Movement::StartOfLine),
             LapceCommand::LineStartNonBlank => Some(Movement::FirstNonBlank),
+            LapceCommand::SmartLineStart => Some(Movement::SmartLineStart),
             LapceCommand::LineEnd => Some(Movement::EndOfLine),
             LapceCommand::GotoLineDefaultFirst => Some(match count {
                 Some(n) => Movement::Line(LinePosition::Line(n)),
@@ -538,6 +799,9 @@ impl LapceCommand {
             LapceCommand::WordForward => Some(Movement::WordForward),
             LapceCommand::WordEndForward => Some(Movement::WordEndForward),
             LapceCommand::MatchPairs => Some(Movement::MatchPairs),
+            LapceCommand::TopOfWindow => Some(Movement::TopOfWindow),
+            LapceCommand::MiddleOfWindow => Some(Movement::MiddleOfWindow),
+            LapceCommand::BottomOfWindow => Some(Movement::BottomOfWindow),
             LapceCommand::NextUnmatchedRightBracket => {
                 Some(Movement::NextUnmatched(')'))
             }
@@ -567,6 +831,10 @@ pub enum LapceUICommand {
     LoadBuffer {
         path: PathBuf,
         content: String,
+        line_ending: Option<LineEnding>,
+        encoding: String,
+        binary: bool,
+        read_only: bool,
         locations: Vec<(WidgetId, EditorLocationNew)>,
     },
     LoadBufferHead {
@@ -574,6 +842,12 @@ pub enum LapceUICommand {
         id: String,
         content: Rope,
     },
+    UpdateBlame {
+        id: BufferId,
+        path: PathBuf,
+        rev: u64,
+        hunks: Vec<BlameHunk>,
+    },
     LoadBufferAndGoToPosition {
         path: PathBuf,
         content: String,
@@ -583,10 +857,12 @@ pub enum LapceUICommand {
     HideMenu,
     ShowMenu(Point, Arc<Vec<MenuItem>>),
     UpdateSearch(String),
-    GlobalSearchResult(String, Arc<HashMap<PathBuf, Vec<Match>>>),
+    GlobalSearchMatches(u64, PathBuf, Vec<Match>),
     CancelFilePicker,
     SetWorkspace(LapceWorkspace),
     SetTheme(String, bool),
+    /// Highlight the buffer of the active editor as this language
+    SetLanguage(LapceLanguage),
     UpdateKeymap(KeyMap, Vec<KeyPress>),
     OpenFile(PathBuf),
     OpenFileDiff(PathBuf, String),
@@ -594,6 +870,7 @@ pub enum LapceUICommand {
     ResolveCompletion(BufferId, u64, usize, Box<CompletionItem>),
     UpdateCompletion(usize, String, CompletionResponse),
     UpdateHover(usize, Hover),
+    UpdateSignature(usize, SignatureHelp),
     UpdateCodeActions(PathBuf, u64, usize, CodeActionResponse),
     CancelPalette,
     ShowCodeActions,
@@ -641,7 +918,15 @@ pub enum LapceUICommand {
     DocumentFormat(PathBuf, u64, Result<Value>),
     DocumentFormatAndSave(PathBuf, u64, Result<Value>),
     BufferSave(PathBuf, u64),
-    UpdateSemanticStyles(BufferId, PathBuf, u64, Arc<Spans<Style>>),
+    /// Save the scratch buffer with this id as the file at the path
+    SaveScratchBuffer(BufferId, PathBuf),
+    UpdateSemanticStyles(
+        BufferId,
+        PathBuf,
+        u64,
+        Option<std::ops::Range<usize>>,
+        Arc<Spans<Style>>,
+    ),
     UpdateTerminalTitle(TermId, String),
     UpdateHistoryStyle {
         id: BufferId,
@@ -654,6 +939,17 @@ pub enum LapceUICommand {
         rev: u64,
         syntax: Syntax,
     },
+    UpdateInlayHints {
+        path: PathBuf,
+        rev: u64,
+        hints: Vec<LspInlayHint>,
+    },
+    UpdateSyntaxHighlights {
+        path: PathBuf,
+        rev: u64,
+        range: std::ops::Range<usize>,
+        styles: Spans<Style>,
+    },
     UpdateHistoryChanges {
         id: BufferId,
         path: PathBuf,
@@ -667,6 +963,8 @@ pub enum LapceUICommand {
     WorkDoneProgress(ProgressParams),
     UpdateDiffInfo(DiffInfo),
     ReloadBuffer(BufferId, u64, String),
+    BufferChangedOnDisk(BufferId, Rope),
+    UpdateSignatureTriggerCharacters(BufferId, Vec<String>),
     EnsureVisible((Rect, (f64, f64), Option<EnsureVisiblePosition>)),
     EnsureRectVisible(Rect),
     EnsureCursorVisible(Option<EnsureVisiblePosition>),
@@ -676,6 +974,8 @@ pub enum LapceUICommand {
     ScrollTo((f64, f64)),
     ForceScrollTo(f64, f64),
     HomeDir(PathBuf),
+    /// The errors of the workspace settings file
+    SettingsError(PathBuf, Vec<String>),
     ProxyUpdateStatus(ProxyStatus),
     CloseTerminal(TermId),
     SplitTerminal(bool, WidgetId),
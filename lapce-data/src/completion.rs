@@ -1,7 +1,7 @@
 use std::{cmp::Ordering, fmt::Display, sync::Arc};
 
 use anyhow::Error;
-use druid::{Command, EventCtx, ExtEventSink, Size, Target, WidgetId};
+use druid::{Command, EventCtx, ExtEventSink, Point, Size, Target, WidgetId};
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use itertools::Itertools;
 use lapce_rpc::buffer::BufferId;
@@ -244,6 +244,10 @@ pub struct CompletionData {
     pub input: String,
     pub index: usize,
     pub input_items: im::HashMap<String, Arc<Vec<ScoredCompletionItem>>>,
+    /// The last response only had some of the items matching its input, so
+    /// the items have to be requested again as the input changes instead of
+    /// being filtered
+    pub is_incomplete: bool,
     empty: Arc<Vec<ScoredCompletionItem>>,
     pub filtered_items: Arc<Vec<ScoredCompletionItem>>,
     pub matcher: Arc<SkimMatcherV2>,
@@ -262,6 +266,7 @@ impl CompletionData {
             buffer_id: BufferId(0),
             input: "".to_string(),
             input_items: im::HashMap::new(),
+            is_incomplete: false,
             filtered_items: Arc::new(Vec::new()),
             matcher: Arc::new(SkimMatcherV2::default().ignore_case()),
             size: Size::new(400.0, 300.0),
@@ -307,6 +312,38 @@ impl CompletionData {
         self.current_items()[self.index].item.label.as_str()
     }
 
+    /// Where the popup goes for a cursor whose character has its top left
+    /// at `cursor` in the tab: below the line with the labels lined up under
+    /// the cursor, or ending at the cursor if it doesn't fit on the right,
+    /// and above the line if there's more room there than below
+    pub fn popup_origin(
+        &self,
+        cursor: Point,
+        line_height: f64,
+        tab_size: Size,
+    ) -> Point {
+        let width = self.size.width;
+        let height = self.size.height.min(self.len() as f64 * line_height);
+
+        // The labels come after an icon as wide as the line height
+        let mut x = cursor.x - line_height - 5.0;
+        if x + width + 1.0 > tab_size.width {
+            x = cursor.x - width;
+        }
+        let x = x.min(tab_size.width - width - 1.0).max(0.0);
+
+        let below = cursor.y + line_height;
+        let room_below = tab_size.height - below;
+        let room_above = cursor.y;
+        let y = if height + 1.0 <= room_below || room_below >= room_above {
+            below
+        } else {
+            (cursor.y - height).max(0.0)
+        };
+
+        Point::new(x, y)
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn request(
         &self,
@@ -323,19 +360,20 @@ impl CompletionData {
             buffer_id,
             position,
             Box::new(move |result| {
-                if let Ok(res) = result {
-                    if let Ok(resp) =
-                        serde_json::from_value::<CompletionResponse>(res)
-                    {
-                        let _ = event_sink.submit_command(
-                            LAPCE_UI_COMMAND,
-                            LapceUICommand::UpdateCompletion(
-                                request_id, input, resp,
-                            ),
-                            Target::Widget(completion_widget_id),
-                        );
-                    }
-                }
+                // a failed request, like one the proxy stopped before
+                // answering, has no items so that the completion doesn't
+                // keep waiting for them
+                let resp = result
+                    .ok()
+                    .and_then(|res| {
+                        serde_json::from_value::<CompletionResponse>(res).ok()
+                    })
+                    .unwrap_or_else(|| CompletionResponse::Array(Vec::new()));
+                let _ = event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdateCompletion(request_id, input, resp),
+                    Target::Widget(completion_widget_id),
+                );
             }),
         );
     }
@@ -347,16 +385,67 @@ impl CompletionData {
         self.status = CompletionStatus::Inactive;
         self.input = "".to_string();
         self.input_items.clear();
+        self.is_incomplete = false;
         self.index = 0;
     }
 
     pub fn update_input(&mut self, input: String) {
-        self.input = input;
-        self.index = 0;
         if self.status == CompletionStatus::Inactive {
+            self.input = input;
+            self.index = 0;
             return;
         }
-        self.filter_items();
+        self.keep_selection(|completion| {
+            completion.input = input;
+            completion.filter_items();
+        });
+    }
+
+    /// The longest prefix of the words that all the items insert, `None`
+    /// when it's no longer than the input
+    pub fn common_prefix(&self) -> Option<String> {
+        let mut items = self.current_items().iter().map(|i| {
+            let text = i.item.filter_text.as_ref().unwrap_or(&i.item.label);
+            let end = text
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(text.len());
+            &text[..end]
+        });
+        let mut prefix = items.next()?;
+        for text in items {
+            let len = prefix
+                .char_indices()
+                .zip(text.chars())
+                .find(|((_, a), b)| a != b)
+                .map(|((i, _), _)| i)
+                .unwrap_or_else(|| prefix.len().min(text.len()));
+            prefix = &prefix[..len];
+        }
+        if prefix.chars().count() <= self.input.chars().count()
+            || !prefix
+                .to_lowercase()
+                .starts_with(&self.input.to_lowercase())
+        {
+            return None;
+        }
+        Some(prefix.to_string())
+    }
+
+    /// Change the items with `update`, keeping the selected item selected
+    /// when it's still one of them and selecting the first one otherwise
+    fn keep_selection(&mut self, update: impl FnOnce(&mut Self)) {
+        let selected = self
+            .current_items()
+            .get(self.index)
+            .map(|i| (i.item.label.clone(), i.item.kind));
+        update(self);
+        self.index = selected
+            .and_then(|(label, kind)| {
+                self.current_items()
+                    .iter()
+                    .position(|i| i.item.label == label && i.item.kind == kind)
+            })
+            .unwrap_or(0);
     }
 
     pub fn receive(
@@ -371,8 +460,14 @@ impl CompletionData {
         }
 
         let items = match resp {
-            CompletionResponse::Array(items) => items,
-            CompletionResponse::List(list) => list.items,
+            CompletionResponse::Array(items) => {
+                self.is_incomplete = false;
+                items
+            }
+            CompletionResponse::List(list) => {
+                self.is_incomplete = list.is_incomplete;
+                list.items
+            }
         };
         let items = items
             .iter()
@@ -385,8 +480,10 @@ impl CompletionData {
             })
             .collect();
 
-        self.input_items.insert(input, Arc::new(items));
-        self.filter_items();
+        self.keep_selection(|completion| {
+            completion.input_items.insert(input, Arc::new(items));
+            completion.filter_items();
+        });
     }
 
     pub fn filter_items(&mut self) {
@@ -563,4 +660,110 @@ mod tests {
             parsed.tabs(0)
         );
     }
+
+    #[test]
+    fn test_receive_incomplete() {
+        let mut completion = CompletionData::new();
+        completion.status = CompletionStatus::Started;
+        let item = CompletionItem::new_simple("item".to_string(), "".to_string());
+
+        completion.receive(
+            0,
+            "".to_string(),
+            CompletionResponse::List(lsp_types::CompletionList {
+                is_incomplete: true,
+                items: vec![item.clone()],
+            }),
+        );
+        assert!(completion.is_incomplete);
+        assert_eq!(completion.all_items().len(), 1);
+
+        completion.receive(
+            0,
+            "i".to_string(),
+            CompletionResponse::Array(vec![item]),
+        );
+        assert!(!completion.is_incomplete);
+    }
+
+    fn started_completion(labels: &[&str]) -> CompletionData {
+        let mut completion = CompletionData::new();
+        completion.status = CompletionStatus::Started;
+        let items = labels
+            .iter()
+            .map(|label| {
+                CompletionItem::new_simple(label.to_string(), "".to_string())
+            })
+            .collect();
+        completion.receive(0, "".to_string(), CompletionResponse::Array(items));
+        completion
+    }
+
+    #[test]
+    fn test_filter_keeps_selection() {
+        let mut completion =
+            started_completion(&["an", "banana", "abacus", "cab", "bandana"]);
+        completion.update_input("a".to_string());
+        completion.index = 2;
+        let selected = completion.current().to_string();
+
+        let input = selected[..2].to_string();
+        completion.update_input(input);
+        assert_eq!(completion.current(), selected);
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        let mut completion =
+            started_completion(&["push_str", "push_back", "pop", "len"]);
+        completion.update_input("pu".to_string());
+        assert_eq!(completion.common_prefix().as_deref(), Some("push_"));
+
+        completion.update_input("push_".to_string());
+        assert_eq!(completion.common_prefix(), None);
+
+        completion.update_input("p".to_string());
+        assert_eq!(completion.common_prefix(), None);
+    }
+
+    #[test]
+    fn test_filter_resets_lost_selection() {
+        let mut completion = started_completion(&["foo", "bar", "baz"]);
+        completion.update_input("b".to_string());
+        completion.index = 1;
+        assert_eq!(completion.current(), "baz");
+
+        completion.update_input("bar".to_string());
+        assert_eq!(completion.index, 0);
+        assert_eq!(completion.current(), "bar");
+    }
+
+    #[test]
+    fn test_popup_origin() {
+        let completion = started_completion(&["foo", "bar", "baz"]);
+        let tab_size = Size::new(800.0, 600.0);
+        let origin =
+            |x, y| completion.popup_origin(Point::new(x, y), 20.0, tab_size);
+
+        assert_eq!(origin(100.0, 100.0), Point::new(75.0, 120.0));
+        // Near the right edge the popup ends at the cursor
+        assert_eq!(origin(780.0, 100.0), Point::new(380.0, 120.0));
+        // and near the bottom it's above the line
+        assert_eq!(origin(780.0, 580.0), Point::new(380.0, 520.0));
+        assert_eq!(origin(0.0, 580.0), Point::new(0.0, 520.0));
+    }
+
+    #[test]
+    fn test_popup_origin_without_room() {
+        let labels: Vec<String> = (0..10).map(|i| format!("item{i}")).collect();
+        let labels: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+        let completion = started_completion(&labels);
+        let tab_size = Size::new(300.0, 100.0);
+        let origin =
+            |x, y| completion.popup_origin(Point::new(x, y), 20.0, tab_size);
+
+        // The side with more room is used when the popup fits on neither
+        assert_eq!(origin(100.0, 20.0), Point::new(0.0, 40.0));
+        assert_eq!(origin(100.0, 60.0), Point::new(0.0, 0.0));
+    }
 }
@@ -1,4 +1,12 @@
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::Result;
 use directories::ProjectDirs;
@@ -7,14 +15,18 @@ use druid::{
     Color, ExtEventSink, FontFamily, Size, Target,
 };
 use hashbrown::HashMap;
+use lapce_core::language::LapceLanguage;
+use lapce_rpc::buffer::LineEnding;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use structdesc::FieldNames;
 use thiserror::Error;
 
 use crate::{
+    buffer::char_width,
     command::{LapceUICommand, LAPCE_UI_COMMAND},
-    data::hex_to_color,
-    state::{LapceWorkspace, LapceWorkspaceType},
+    data::parse_color,
+    state::{LapceWorkspace, LapceWorkspaceType, Mode},
 };
 
 const DEFAULT_SETTINGS: &str = include_str!("../../defaults/settings.toml");
@@ -40,6 +52,15 @@ impl LapceTheme {
     pub const EDITOR_CARET: &'static str = "editor.caret";
     pub const EDITOR_SELECTION: &'static str = "editor.selection";
     pub const EDITOR_CURRENT_LINE: &'static str = "editor.current_line";
+    pub const EDITOR_LINE_NUMBER: &'static str = "editor.line_number";
+    pub const EDITOR_MATCHING_BRACKET: &'static str = "editor.matching_bracket";
+    pub const EDITOR_CURRENT_LINE_NUMBER: &'static str =
+        "editor.current_line_number";
+    pub const EDITOR_VISIBLE_WHITESPACE: &'static str = "editor.visible_whitespace";
+    pub const EDITOR_RULER: &'static str = "editor.ruler";
+
+    pub const INLAY_HINT_FOREGROUND: &'static str = "inlay_hint.foreground";
+    pub const INLAY_HINT_BACKGROUND: &'static str = "inlay_hint.background";
 
     pub const SOURCE_CONTROL_ADDED: &'static str = "source_control.added";
     pub const SOURCE_CONTROL_REMOVED: &'static str = "source_control.removed";
@@ -79,6 +100,10 @@ impl LapceTheme {
     pub const PANEL_HOVERED: &'static str = "panel.hovered";
 
     pub const STATUS_BACKGROUND: &'static str = "status.background";
+    pub const STATUS_MODAL_NORMAL: &'static str = "status.modal.normal";
+    pub const STATUS_MODAL_INSERT: &'static str = "status.modal.insert";
+    pub const STATUS_MODAL_VISUAL: &'static str = "status.modal.visual";
+    pub const STATUS_MODAL_TERMINAL: &'static str = "status.modal.terminal";
 
     pub const INPUT_LINE_HEIGHT: druid::Key<f64> =
         druid::Key::new("lapce.input_line_height");
@@ -110,10 +135,32 @@ pub trait GetConfig {
 pub struct LapceConfig {
     #[field_names(desc = "Enable modal editing (Vim like)")]
     pub modal: bool,
+    #[field_names(
+        desc = "The keymap preset loaded over the default keymaps: \"default\", or \"emacs\" which also turns modal editing off"
+    )]
+    pub keymap_preset: String,
+    #[field_names(
+        desc = "How long to wait for the next key of a key sequence, in milliseconds, before running the command of the keys typed so far"
+    )]
+    pub key_sequence_timeout: u64,
+    #[field_names(
+        desc = "Show the keys that can follow a key sequence paused on, instead of dropping it"
+    )]
+    pub which_key: bool,
     #[field_names(desc = "Set the color theme of Lapce")]
     pub color_theme: String,
     #[field_names(desc = "Set the terminal Shell")]
     pub terminal_shell: String,
+    #[field_names(desc = "Close the window when its last tab is closed")]
+    pub close_window_on_last_tab: bool,
+}
+
+/// How the caret is drawn, picked for each mode by the cursor shape settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
 }
 
 #[derive(FieldNames, Debug, Clone, Deserialize, Serialize, Default)]
@@ -131,16 +178,262 @@ pub struct EditorConfig {
     pub tab_width: usize,
     #[field_names(desc = "If opened editors are shown in a tab")]
     pub show_tab: bool,
+    #[field_names(
+        desc = "How line numbers are shown: \"off\", \"absolute\", \"relative\" to the cursor line, or \"hybrid\" which is only relative in the active editor outside of insert mode"
+    )]
+    pub line_numbers: String,
+    #[field_names(desc = "Highlight the background of the line with the cursor")]
+    pub highlight_current_line: bool,
+    #[field_names(
+        desc = "The minimal number of lines to keep visible above and below the cursor"
+    )]
+    pub scroll_off: usize,
+    #[field_names(
+        desc = "Allow scrolling past the end of the file until the last line reaches the top of the editor"
+    )]
+    pub scroll_past_end: bool,
+    #[field_names(
+        desc = "Soft wrap long lines: \"off\", \"viewport\" to wrap at the width of the editor, or a column number to wrap at"
+    )]
+    pub word_wrap: String,
+    #[field_names(
+        desc = "Move up and down by visual lines instead of whole lines when soft wrap is on"
+    )]
+    pub wrap_visual_line_movement: bool,
+    #[field_names(
+        desc = "Animate scrolling caused by the keyboard, like page moves and jumps"
+    )]
+    pub smooth_scroll: bool,
+    #[field_names(desc = "How long (in ms) an animated scroll takes")]
+    pub smooth_scroll_duration: u64,
+    #[field_names(
+        desc = "The easing of animated scrolls: \"linear\", \"ease-out\" or \"ease-in-out\""
+    )]
+    pub smooth_scroll_easing: String,
+    #[field_names(
+        desc = "The fastest (in lines per second) the editor scrolls when a selection is dragged past its top or bottom, 0 turns it off"
+    )]
+    pub autoscroll_max_speed: u64,
     #[field_names(
         desc = "How long (in ms) it should take before the hover information appears"
     )]
     pub hover_delay: u64,
+    #[field_names(desc = "Blink the caret while it isn't moving")]
+    pub blink_cursor: bool,
+    #[field_names(
+        desc = "The shape of the caret in normal mode: \"block\", \"bar\" or \"underline\""
+    )]
+    pub normal_cursor_shape: String,
+    #[field_names(
+        desc = "The shape of the caret in insert mode: \"block\", \"bar\" or \"underline\""
+    )]
+    pub insert_cursor_shape: String,
+    #[field_names(
+        desc = "The shape of the caret at the active end of the selection in visual mode: \"block\", \"bar\" or \"underline\""
+    )]
+    pub visual_cursor_shape: String,
+    #[field_names(
+        desc = "How long (in ms) the caret stays shown, and then hidden, when it blinks"
+    )]
+    pub cursor_blink_interval: u64,
+    #[field_names(
+        desc = "The line ending of saved files: \"auto\" keeps the one each file was opened with, \"lf\" or \"crlf\" overrides it"
+    )]
+    pub line_ending: String,
+    #[field_names(
+        desc = "Save files automatically after they haven't been edited for a while"
+    )]
+    pub auto_save: bool,
+    #[field_names(
+        desc = "How long (in ms) a file must be left unedited before it is saved automatically"
+    )]
+    pub auto_save_delay: u64,
+    #[field_names(desc = "Remove the whitespace at the end of lines when saving")]
+    pub trim_trailing_whitespace: bool,
+    #[field_names(desc = "Make files end with exactly one newline when saving")]
+    pub insert_final_newline: bool,
+    #[field_names(
+        desc = "Also trim the whitespace at the end of the line with the cursor, which is left alone by default so that saving doesn't eat what's being typed"
+    )]
+    pub trim_cursor_line: bool,
+    #[field_names(
+        desc = "Draw the whitespace characters: \"none\", \"boundary\" for the whitespace at the start and end of lines, or \"all\""
+    )]
+    pub render_whitespace: String,
+    #[field_names(desc = "The columns to draw vertical rulers at, like [80, 120]")]
+    pub rulers: Vec<usize>,
+    #[field_names(desc = "The column that paragraphs are rewrapped at")]
+    pub text_width: usize,
+    #[field_names(
+        desc = "Show the inlay hints of the language server, like inferred types and parameter names"
+    )]
+    pub enable_inlay_hints: bool,
+    #[field_names(
+        desc = "Show who last changed the cursor line at the end of it, for files under git"
+    )]
+    pub show_blame: bool,
+    #[field_names(
+        desc = "The pairs that get closed when their opening char is typed, like [\"()\", \"''\"]"
+    )]
+    pub auto_closing_pairs: Vec<String>,
+    #[field_names(
+        desc = "Chars that are part of words on top of letters, digits and \"_\", for the word motions, the word under the cursor and completion. Mostly set per language, like \"-\" for CSS"
+    )]
+    pub word_chars: String,
 }
 
 impl EditorConfig {
     pub fn font_family(&self) -> FontFamily {
         FontFamily::new_unchecked(self.font_family.clone())
     }
+
+    /// The height the editor can scroll through for content of
+    /// `content_height` shown in a viewport of `viewport_height`
+    pub fn scroll_height(&self, content_height: f64, viewport_height: f64) -> f64 {
+        if self.scroll_past_end {
+            (content_height - self.line_height as f64).max(0.0) + viewport_height
+        } else {
+            content_height.max(viewport_height)
+        }
+    }
+
+    /// The char that closes the auto-closed pair opened by `c`
+    pub fn auto_closing_char(&self, c: char) -> Option<char> {
+        self.auto_closing_pairs()
+            .find(|(open, _)| *open == c)
+            .map(|(_, close)| close)
+    }
+
+    /// Whether `c` closes one of the auto-closed pairs
+    pub fn is_auto_closing_char(&self, c: char) -> bool {
+        self.auto_closing_pairs().any(|(_, close)| close == c)
+    }
+
+    fn auto_closing_pairs(&self) -> impl Iterator<Item = (char, char)> + '_ {
+        self.auto_closing_pairs.iter().filter_map(|pair| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next()) {
+                (Some(open), Some(close), None) => Some((open, close)),
+                _ => None,
+            }
+        })
+    }
+
+    /// The number shown in the gutter next to `line`, `None` if line numbers
+    /// are turned off. The cursor line always shows its absolute number, as
+    /// vim does. `relative` is whether the view is active and not in insert
+    /// mode, which "hybrid" needs to show relative numbers and "relative"
+    /// doesn't.
+    pub fn line_number(
+        &self,
+        line: usize,
+        current_line: usize,
+        relative: bool,
+    ) -> Option<usize> {
+        let distance = if line > current_line {
+            line - current_line
+        } else {
+            current_line - line
+        };
+        match self.line_numbers.as_str() {
+            "off" => None,
+            "absolute" => Some(line + 1),
+            _ if line == current_line => Some(line + 1),
+            "relative" => Some(distance),
+            _ if relative => Some(distance),
+            _ => Some(line + 1),
+        }
+    }
+
+    /// The shape the caret is drawn with in `mode`
+    pub fn cursor_shape(&self, mode: Mode) -> CursorShape {
+        let shape = match mode {
+            Mode::Insert | Mode::Terminal => &self.insert_cursor_shape,
+            Mode::Visual => &self.visual_cursor_shape,
+            Mode::Normal => &self.normal_cursor_shape,
+        };
+        match shape.as_str() {
+            "block" => CursorShape::Block,
+            "bar" => CursorShape::Bar,
+            "underline" => CursorShape::Underline,
+            _ if mode == Mode::Insert => CursorShape::Bar,
+            _ => CursorShape::Block,
+        }
+    }
+
+    /// The columns of the spaces and tabs in `line` that are drawn visibly
+    /// by the `render-whitespace` setting, each with whether it's a tab
+    pub fn visible_whitespace(&self, line: &str) -> Vec<(usize, bool)> {
+        let all = match self.render_whitespace.as_str() {
+            "all" => true,
+            "boundary" => false,
+            _ => return Vec::new(),
+        };
+        let line = line.trim_end_matches(['\n', '\r']);
+        let leading_end = line.len() - line.trim_start_matches([' ', '\t']).len();
+        let trailing_start = line.trim_end_matches([' ', '\t']).len();
+
+        let mut whitespace = Vec::new();
+        let mut col = 0;
+        for (i, c) in line.char_indices() {
+            let is_whitespace = c == ' ' || c == '\t';
+            if is_whitespace && (all || i < leading_end || i >= trailing_start) {
+                whitespace.push((col, c == '\t'));
+            }
+            col += if c == '\t' {
+                self.tab_width - col % self.tab_width
+            } else {
+                char_width(c)
+            };
+        }
+        whitespace
+    }
+
+    /// The column that lines are soft wrapped at in an editor that's
+    /// `viewport_width` wide, `None` if soft wrap is turned off
+    pub fn wrap_column(
+        &self,
+        viewport_width: f64,
+        char_width: f64,
+    ) -> Option<usize> {
+        match self.word_wrap.as_str() {
+            "off" => None,
+            "viewport" => {
+                if viewport_width <= 0.0 || char_width <= 0.0 {
+                    return None;
+                }
+                Some(((viewport_width / char_width).floor() as usize).max(1))
+            }
+            col => col.parse::<usize>().ok().filter(|col| *col > 0),
+        }
+    }
+
+    /// How far an animated scroll has moved, between 0.0 and 1.0, after
+    /// `progress` of its duration has passed
+    pub fn smooth_scroll_ease(&self, progress: f64) -> f64 {
+        let t = progress.clamp(0.0, 1.0);
+        match self.smooth_scroll_easing.as_str() {
+            "linear" => t,
+            "ease-in-out" => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            _ => 1.0 - (1.0 - t).powi(3),
+        }
+    }
+
+    /// The line ending of a file that was opened with `detected`, taking the
+    /// `line-ending` override into account
+    pub fn line_ending_for(&self, detected: Option<LineEnding>) -> LineEnding {
+        match self.line_ending.as_str() {
+            "lf" => LineEnding::Lf,
+            "crlf" => LineEnding::CrLf,
+            _ => detected.unwrap_or_default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -161,14 +454,27 @@ impl Theme {
             toml::from_str(content)?;
         let mut theme = HashMap::new();
         for (k, v) in theme_colors.iter() {
-            if let Some(stripped) = v.strip_prefix('$') {
-                if let Some(hex) = theme_colors.get(stripped) {
-                    if let Ok(color) = hex_to_color(hex) {
-                        theme.insert(k.clone(), color);
+            // Invalid entries are skipped, so that the key falls back to the
+            // color of the theme that is being extended
+            let color = match v.strip_prefix('$') {
+                Some(stripped) => match theme_colors.get(stripped) {
+                    Some(color) => color,
+                    None => {
+                        log::warn!(
+                            r#"Theme key "{k}" refers to the undefined color "{stripped}""#
+                        );
+                        continue;
                     }
+                },
+                None => v,
+            };
+            match parse_color(color) {
+                Ok(color) => {
+                    theme.insert(k.clone(), color);
+                }
+                Err(_) => {
+                    log::warn!(r#"Theme key "{k}" has an invalid color "{color}""#);
                 }
-            } else if let Ok(color) = hex_to_color(v) {
-                theme.insert(k.clone(), color);
             }
         }
 
@@ -327,17 +633,37 @@ impl Themes {
 pub struct Config {
     pub lapce: LapceConfig,
     pub editor: EditorConfig,
+    /// The settings of each `[language.<name>]` table, which override the
+    /// `[editor]` ones in the configs `Config::for_language` hands out
+    #[serde(skip)]
+    language_overrides: HashMap<String, Vec<(String, config::Value)>>,
+    /// This config with the `language_overrides` of each language applied,
+    /// kept so that `Config::for_language` doesn't clone the whole config
+    #[serde(skip)]
+    language_configs: HashMap<String, Arc<Config>>,
     #[serde(skip)]
     pub themes: Themes,
+    /// The measured advance of the editor font, by font family and size
+    #[serde(skip)]
+    char_widths: Arc<Mutex<HashMap<(String, u64), f64>>>,
 }
 
 pub struct ConfigWatcher {
     event_sink: ExtEventSink,
+    /// Whether a reload has already been scheduled, so that the burst of
+    /// events from a single save only reloads the config once
+    reload_pending: Arc<AtomicBool>,
 }
 
 impl ConfigWatcher {
+    /// How long to wait for more file events before reloading
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+
     pub fn new(event_sink: ExtEventSink) -> Self {
-        Self { event_sink }
+        Self {
+            event_sink,
+            reload_pending: Arc::new(AtomicBool::new(false)),
+        }
     }
 }
 
@@ -348,11 +674,20 @@ impl notify::EventHandler for ConfigWatcher {
                 notify::EventKind::Create(_)
                 | notify::EventKind::Modify(_)
                 | notify::EventKind::Remove(_) => {
-                    let _ = self.event_sink.submit_command(
-                        LAPCE_UI_COMMAND,
-                        LapceUICommand::ReloadConfig,
-                        Target::Auto,
-                    );
+                    if self.reload_pending.swap(true, Ordering::SeqCst) {
+                        return;
+                    }
+                    let event_sink = self.event_sink.clone();
+                    let reload_pending = self.reload_pending.clone();
+                    std::thread::spawn(move || {
+                        std::thread::sleep(Self::DEBOUNCE);
+                        reload_pending.store(false, Ordering::SeqCst);
+                        let _ = event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::ReloadConfig,
+                            Target::Auto,
+                        );
+                    });
                 }
                 _ => (),
             }
@@ -361,6 +696,8 @@ impl notify::EventHandler for ConfigWatcher {
 }
 
 impl Config {
+    const RECENT_FILES_LIMIT: usize = 50;
+
     pub fn load(workspace: &LapceWorkspace) -> Result<Self> {
         let mut settings = config::Config::default().with_merged(
             config::File::from_str(DEFAULT_SETTINGS, config::FileFormat::Toml),
@@ -383,15 +720,111 @@ impl Config {
             LapceWorkspaceType::RemoteWSL => {}
         }
 
-        let mut config: Config = settings.try_into()?;
+        let mut config: Config = settings.clone().try_into()?;
 
+        // The emacs keymaps are all bound in insert mode
+        if config.lapce.keymap_preset == "emacs" {
+            config.lapce.modal = false;
+        }
+        config.language_overrides = Self::language_overrides(&settings);
         config.themes = Themes::default();
 
         let _ = config.themes.apply_theme(&config.lapce.color_theme);
+        config.update_language_configs();
 
         Ok(config)
     }
 
+    /// The config of the default settings alone, without the user's or the
+    /// workspace's settings files
+    pub fn default_settings() -> Result<Self> {
+        let settings = config::Config::default().with_merged(
+            config::File::from_str(DEFAULT_SETTINGS, config::FileFormat::Toml),
+        )?;
+        let mut config: Config = settings.clone().try_into()?;
+        config.language_overrides = Self::language_overrides(&settings);
+        config.update_language_configs();
+        Ok(config)
+    }
+
+    /// Collect the settings of every `[language.<name>]` table. The tables
+    /// are merged from the same files as `[editor]`, the workspace settings
+    /// over the user's over the defaults, and a setting in a language's
+    /// table then beats the `[editor]` one from any file.
+    fn language_overrides(
+        settings: &config::Config,
+    ) -> HashMap<String, Vec<(String, config::Value)>> {
+        let mut overrides = HashMap::new();
+        let languages = match settings.get_table("language") {
+            Ok(languages) => languages,
+            Err(_) => return overrides,
+        };
+        for (name, table) in languages {
+            match table.into_table() {
+                Ok(table) => {
+                    overrides
+                        .insert(name.to_lowercase(), table.into_iter().collect());
+                }
+                Err(e) => {
+                    log::warn!(
+                        r#"Settings of language "{name}" aren't a table: {e}"#
+                    );
+                }
+            }
+        }
+        overrides
+    }
+
+    /// Rebuild the configs that `Config::for_language` hands out, which has
+    /// to be done after every change to this config. The overrides are
+    /// layered over the current `[editor]` settings, so the settings a
+    /// language doesn't set follow the toggles.
+    pub fn update_language_configs(&mut self) {
+        self.language_configs.clear();
+        let editor = match config::Config::try_from(&self.editor) {
+            Ok(editor) => editor,
+            Err(e) => {
+                log::warn!("Can't apply the language settings: {e}");
+                return;
+            }
+        };
+        let mut base = self.clone();
+        base.language_overrides.clear();
+        self.language_configs = self
+            .language_overrides
+            .iter()
+            .filter_map(|(name, overrides)| {
+                let mut settings = editor.clone();
+                for (key, value) in overrides {
+                    let _ = settings.set(key, value.clone());
+                }
+                match settings.try_into::<EditorConfig>() {
+                    Ok(editor) => {
+                        let mut config = base.clone();
+                        config.editor = editor;
+                        Some((name.clone(), Arc::new(config)))
+                    }
+                    Err(e) => {
+                        log::warn!(r#"Invalid settings for language "{name}": {e}"#);
+                        None
+                    }
+                }
+            })
+            .collect();
+    }
+
+    /// The config of a buffer in `language`: this config with the editor
+    /// settings of that language when it has any, or this config as it is
+    pub fn for_language(
+        self: &Arc<Self>,
+        language: Option<LapceLanguage>,
+    ) -> Arc<Config> {
+        language
+            .and_then(|l| self.language_configs.get(l.name()))
+            .unwrap_or(self)
+            .clone()
+    }
+
     pub fn dir() -> Option<PathBuf> {
         ProjectDirs::from("", "", "Lapce").map(|d| PathBuf::from(d.config_dir()))
     }
@@ -498,12 +931,35 @@ impl Config {
         Some(())
     }
 
+    /// The names of the built-in themes, the ones already loaded, and the
+    /// ones available in the themes folder, sorted alphabetically
+    pub fn available_themes(&self) -> Vec<String> {
+        let mut themes: Vec<String> = self.themes.keys().cloned().collect();
+        if let Some(entries) =
+            Self::themes_folder().and_then(|folder| std::fs::read_dir(folder).ok())
+        {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                    continue;
+                }
+                if let Some(name) = path.file_stem().and_then(|n| n.to_str()) {
+                    themes.push(name.to_string());
+                }
+            }
+        }
+        themes.sort();
+        themes.dedup();
+        themes
+    }
+
     pub fn set_theme(&mut self, theme: &str, preview: bool) -> bool {
         if self.themes.apply_theme(theme).is_err() {
             return false;
         }
 
         self.lapce.color_theme = theme.to_string();
+        self.update_language_configs();
 
         if !preview
             && Config::update_file(
@@ -538,13 +994,21 @@ impl Config {
         self.themes.style_color(name)
     }
 
+    /// The advance of a character in the editor font at `font_size`,
+    /// measured once per font family and size
     pub fn char_width(&self, text: &mut PietText, font_size: f64) -> f64 {
+        let key = (self.editor.font_family.clone(), font_size.to_bits());
+        if let Some(width) = self.char_widths.lock().get(&key) {
+            return *width;
+        }
         let text_layout = text
             .new_text_layout("W")
             .font(self.editor.font_family(), font_size)
             .build()
             .unwrap();
-        text_layout.size().width
+        let width = text_layout.size().width;
+        self.char_widths.lock().insert(key, width);
+        width
     }
 
     pub fn editor_text_width(&self, text: &mut PietText, c: &str) -> f64 {
@@ -654,4 +1118,58 @@ impl Config {
         }
         Some(path)
     }
+
+    /// Put the file first in the recently opened files, which keep the
+    /// last `RECENT_FILES_LIMIT` files opened
+    pub fn add_recent_file(file: &Path) -> Option<()> {
+        let mut files = Self::recent_files().unwrap_or_default();
+        files.retain(|f| f != file);
+        files.insert(0, file.to_path_buf());
+        files.truncate(Self::RECENT_FILES_LIMIT);
+
+        let array = files
+            .iter()
+            .filter_map(|f| Some(toml::Value::String(f.to_str()?.to_string())))
+            .collect();
+        let mut table = toml::value::Table::new();
+        table.insert("files".to_string(), toml::Value::Array(array));
+        let content = toml::to_string(&table).ok()?;
+
+        let path = Self::recent_files_file()?;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .ok()?;
+        file.write_all(content.as_bytes()).ok()?;
+        Some(())
+    }
+
+    /// The recently opened files, the last opened first
+    pub fn recent_files() -> Option<Vec<PathBuf>> {
+        let path = Self::recent_files_file()?;
+        let content = std::fs::read_to_string(&path).ok()?;
+        let value: toml::Value = toml::from_str(&content).ok()?;
+        Some(
+            value
+                .get("files")
+                .and_then(|v| v.as_array())?
+                .iter()
+                .filter_map(|f| Some(PathBuf::from(f.as_str()?)))
+                .collect(),
+        )
+    }
+
+    pub fn recent_files_file() -> Option<PathBuf> {
+        let proj_dirs = ProjectDirs::from("", "", "Lapce")?;
+        let _ = std::fs::create_dir_all(proj_dirs.config_dir());
+        let path = proj_dirs.config_dir().join("recent-files.toml");
+        {
+            let _ = std::fs::OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(&path);
+        }
+        Some(path)
+    }
 }
@@ -6,6 +6,7 @@ use std::{
     str::FromStr,
     sync::Arc,
     thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Result};
@@ -17,8 +18,8 @@ use druid::{
 };
 
 use lapce_rpc::{
-    file::FileNodeItem, plugin::PluginDescription, source_control::FileDiff,
-    terminal::TermId,
+    buffer::BufferId, file::FileNodeItem, plugin::PluginDescription,
+    source_control::FileDiff, terminal::TermId,
 };
 use lsp_types::{
     CodeActionOrCommand, Diagnostic, Position, ProgressToken, TextEdit,
@@ -26,13 +27,14 @@ use lsp_types::{
 use notify::Watcher;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use xi_rope::{RopeDelta, Transformer};
+use xi_rope::{Interval, Rope, RopeDelta, Transformer};
 
 use crate::{
     buffer::{
-        matching_char, matching_pair_direction, Buffer, BufferContent, EditType,
-        LocalBufferKind,
+        file_history, history_file, matching_char, matching_pair_direction, Buffer,
+        BufferContent, EditType, LocalBufferKind, TextStats, DISK_HISTORY,
     },
+    cli::PathArg,
     command::{
         CommandTarget, EnsureVisiblePosition, LapceCommandNew, LapceUICommand,
         LapceWorkbenchCommand, LAPCE_NEW_COMMAND, LAPCE_UI_COMMAND,
@@ -48,6 +50,7 @@ use crate::{
     find::Find,
     hover::HoverData,
     keypress::KeyPressData,
+    location_list::{diagnostic_items, LocationListData, LocationListSource},
     menu::MenuData,
     movement::{Cursor, CursorMode, Movement, Selection},
     palette::{PaletteData, PaletteType, PaletteViewData},
@@ -58,11 +61,13 @@ use crate::{
     proxy::{LapceProxy, ProxyStatus, TermEvent},
     search::SearchData,
     settings::LapceSettingsPanelData,
+    signature::SignatureData,
     source_control::SourceControlData,
     split::{SplitDirection, SplitMoveDirection},
     state::{LapceWorkspace, LapceWorkspaceType, VisualMode},
     svg::get_svg,
     terminal::TerminalSplitData,
+    wrap::WrapLayout,
 };
 
 #[derive(Clone, Data)]
@@ -125,6 +130,102 @@ impl LapceData {
         }
     }
 
+    /// Open the paths given on the command line. A directory becomes the
+    /// workspace of a new window, unless a window has it open already. The
+    /// files are opened in the active tab of that window, or of the window
+    /// whose workspace they are in, and the first one gets the focus.
+    pub fn open_paths(&mut self, paths: &[PathArg], event_sink: ExtEventSink) {
+        let (dirs, files): (Vec<&PathArg>, Vec<&PathArg>) =
+            paths.iter().partition(|p| p.path.is_dir());
+
+        let mut window_id = None;
+        if let Some(dir) = dirs.first() {
+            let dir = dir.path.canonicalize().unwrap_or_else(|_| dir.path.clone());
+            window_id = self
+                .windows
+                .values()
+                .find(|window| {
+                    window.tabs.get(&window.active_id).map(|tab| {
+                        tab.workspace.kind == LapceWorkspaceType::Local
+                            && tab.workspace.path.as_ref() == Some(&dir)
+                    }) == Some(true)
+                })
+                .map(|window| window.window_id);
+            if window_id.is_none() {
+                let (size, pos) = self
+                    .db
+                    .get_last_window_info()
+                    .map(|info| (info.size, info.pos))
+                    .unwrap_or((Size::new(800.0, 600.0), Point::new(0.0, 0.0)));
+                let info = WindowInfo {
+                    size,
+                    pos,
+                    tabs: TabsInfo {
+                        active_tab: 0,
+                        workspaces: vec![LapceWorkspace {
+                            kind: LapceWorkspaceType::Local,
+                            path: Some(dir),
+                            last_open: 0,
+                        }],
+                    },
+                };
+                let window = LapceWindowData::new(
+                    self.keypress.clone(),
+                    event_sink.clone(),
+                    &info,
+                    self.db.clone(),
+                );
+                window_id = Some(window.window_id);
+                self.windows.insert(window.window_id, window);
+            }
+        }
+
+        let first = match files.first() {
+            Some(first) => first,
+            None => return,
+        };
+        let window = window_id
+            .and_then(|id| self.windows.get(&id))
+            .or_else(|| {
+                self.windows.values().find(|window| {
+                    window
+                        .tabs
+                        .get(&window.active_id)
+                        .and_then(|tab| tab.workspace.path.as_ref())
+                        .map(|path| first.path.starts_with(path))
+                        .unwrap_or(false)
+                })
+            })
+            .or_else(|| self.windows.values().next());
+        let tab_id = match window {
+            Some(window) => window.active_id,
+            None => return,
+        };
+        let mut to_open = files.clone();
+        // Going back to the first file after opening them all focuses it
+        if files.len() > 1 {
+            to_open.push(first);
+        }
+        for path_arg in to_open {
+            let _ = event_sink.submit_command(
+                LAPCE_UI_COMMAND,
+                LapceUICommand::JumpToLocation(
+                    None,
+                    EditorLocationNew {
+                        path: path_arg.path.clone(),
+                        position: path_arg.position.map(|(line, col)| Position {
+                            line: line as u32,
+                            character: col as u32,
+                        }),
+                        scroll_offset: None,
+                        history: None,
+                    },
+                ),
+                Target::Widget(tab_id),
+            );
+        }
+    }
+
     pub fn reload_env(&self, env: &mut Env) {
         env.set(theme::SCROLLBAR_WIDTH, 10.0);
         env.set(theme::SCROLLBAR_EDGE_WIDTH, 0.0);
@@ -254,6 +355,9 @@ impl LapceWindowData {
         if let Some(path) = KeyPressData::file() {
             let _ = watcher.watch(&path, notify::RecursiveMode::Recursive);
         }
+        if let Some(path) = Config::themes_folder() {
+            let _ = watcher.watch(&path, notify::RecursiveMode::Recursive);
+        }
         let menu = MenuData::new();
 
         Self {
@@ -312,6 +416,7 @@ pub enum PanelKind {
     Terminal,
     Search,
     Problem,
+    LocationList,
 }
 
 impl PanelKind {
@@ -323,6 +428,7 @@ impl PanelKind {
             PanelKind::Terminal => "terminal.svg",
             PanelKind::Search => "search.svg",
             PanelKind::Problem => "error.svg",
+            PanelKind::LocationList => "link.svg",
         }
     }
 
@@ -388,11 +494,13 @@ pub struct LapceTabData {
     pub main_split: LapceMainSplitData,
     pub completion: Arc<CompletionData>,
     pub hover: Arc<HoverData>,
+    pub signature: Arc<SignatureData>,
     pub terminal: Arc<TerminalSplitData>,
     pub palette: Arc<PaletteData>,
     pub find: Arc<Find>,
     pub source_control: Arc<SourceControlData>,
     pub problem: Arc<ProblemData>,
+    pub location_list: Arc<LocationListData>,
     pub search: Arc<SearchData>,
     pub plugin: Arc<PluginData>,
     pub picker: Arc<FilePickerData>,
@@ -422,6 +530,7 @@ impl Data for LapceTabData {
         self.main_split.same(&other.main_split)
             && self.completion.same(&other.completion)
             && self.hover.same(&other.hover)
+            && self.signature.same(&other.signature)
             && self.palette.same(&other.palette)
             && self.workspace.same(&other.workspace)
             && self.source_control.same(&other.source_control)
@@ -439,6 +548,7 @@ impl Data for LapceTabData {
             && self.file_explorer.same(&other.file_explorer)
             && self.plugin.same(&other.plugin)
             && self.problem.same(&other.problem)
+            && self.location_list.same(&other.location_list)
             && self.search.same(&other.search)
             && self.installed_plugins.same(&other.installed_plugins)
             && self.picker.same(&other.picker)
@@ -481,6 +591,7 @@ impl LapceTabData {
         let palette = Arc::new(PaletteData::new(proxy.clone()));
         let completion = Arc::new(CompletionData::new());
         let hover = Arc::new(HoverData::new());
+        let signature = Arc::new(SignatureData::new());
         let source_control = Arc::new(SourceControlData::new());
         let settings = Arc::new(LapceSettingsPanelData::new());
         let plugin = Arc::new(PluginData::new());
@@ -541,6 +652,7 @@ impl LapceTabData {
 
         let terminal = Arc::new(TerminalSplitData::new(proxy.clone()));
         let problem = Arc::new(ProblemData::new());
+        let location_list = Arc::new(LocationListData::new());
 
         let mut panels = im::HashMap::new();
         panels.insert(
@@ -564,6 +676,7 @@ impl LapceTabData {
                     PanelKind::Terminal,
                     PanelKind::Search,
                     PanelKind::Problem,
+                    PanelKind::LocationList,
                 ],
                 shown: true,
                 maximized: false,
@@ -578,9 +691,11 @@ impl LapceTabData {
             main_split,
             completion,
             hover,
+            signature,
             terminal,
             plugin,
             problem,
+            location_list,
             search,
             plugins: Arc::new(Vec::new()),
             installed_plugins: Arc::new(HashMap::new()),
@@ -669,18 +784,24 @@ impl LapceTabData {
             BufferContent::Value(name) => {
                 self.main_split.value_buffers.get(name).unwrap().clone()
             }
+            BufferContent::Scratch(id, _) => {
+                self.main_split.scratch_buffers.get(id).unwrap().clone()
+            }
         };
         LapceEditorBufferData {
             view_id: editor_view_id,
             main_split: self.main_split.clone(),
             completion: self.completion.clone(),
             hover: self.hover.clone(),
+            signature: self.signature.clone(),
             source_control: self.source_control.clone(),
             proxy: self.proxy.clone(),
             find: self.find.clone(),
+            config: self
+                .config
+                .for_language(buffer.syntax.as_ref().map(|s| s.language)),
             buffer,
             editor: editor.clone(),
-            config: self.config.clone(),
         }
     }
 
@@ -695,10 +816,14 @@ impl LapceTabData {
         match &editor.content {
             BufferContent::Local(_) => Size::ZERO,
             BufferContent::Value(_) => Size::ZERO,
+            BufferContent::Scratch(..) => Size::ZERO,
             BufferContent::File(path) => {
-                let buffer = self.main_split.open_files.get(path).unwrap();
+                let buffer = match self.main_split.open_files.get(path) {
+                    Some(buffer) => buffer,
+                    None => return Size::ZERO,
+                };
                 let offset = editor.cursor.offset();
-                let prev_offset = buffer.prev_code_boundary(offset);
+                let prev_offset = buffer.code_action_offset(offset);
                 let empty_vec = Vec::new();
                 let code_actions =
                     buffer.code_actions.get(&prev_offset).unwrap_or(&empty_vec);
@@ -752,6 +877,7 @@ impl LapceTabData {
     ) {
         self.completion = editor_buffer_data.completion.clone();
         self.hover = editor_buffer_data.hover.clone();
+        self.signature = editor_buffer_data.signature.clone();
         self.main_split = editor_buffer_data.main_split.clone();
         self.find = editor_buffer_data.find.clone();
         if !editor_buffer_data.editor.same(editor) {
@@ -762,9 +888,13 @@ impl LapceTabData {
         if !editor_buffer_data.buffer.same(buffer) {
             match &buffer.content {
                 BufferContent::File(path) => {
+                    let edited = editor_buffer_data.buffer.rev != buffer.rev;
                     self.main_split
                         .open_files
                         .insert(path.clone(), editor_buffer_data.buffer);
+                    if edited {
+                        self.main_split.refresh_file_histories(path);
+                    }
                 }
                 BufferContent::Local(kind) => {
                     self.main_split
@@ -776,6 +906,11 @@ impl LapceTabData {
                         .value_buffers
                         .insert(name.clone(), editor_buffer_data.buffer);
                 }
+                BufferContent::Scratch(id, _) => {
+                    self.main_split
+                        .scratch_buffers
+                        .insert(*id, editor_buffer_data.buffer);
+                }
             }
         }
     }
@@ -798,15 +933,20 @@ impl LapceTabData {
             BufferContent::Local(_) => {
                 editor.window_origin - self.window_origin.to_vec2()
             }
-            BufferContent::Value(_) => {
+            BufferContent::Value(_) | BufferContent::Scratch(..) => {
                 editor.window_origin - self.window_origin.to_vec2()
             }
             BufferContent::File(path) => {
-                let buffer = self.main_split.open_files.get(path).unwrap();
+                let buffer = match self.main_split.open_files.get(path) {
+                    Some(buffer) => buffer,
+                    None => {
+                        return editor.window_origin - self.window_origin.to_vec2()
+                    }
+                };
                 let offset = editor.cursor.offset();
                 let (line, col) =
-                    buffer.offset_to_line_col(offset, self.config.editor.tab_width);
-                let width = config.editor_text_width(text, "W");
+                    editor.visual_line_col(buffer, offset, text, &self.config);
+                let width = config.char_width(text, config.editor.font_size as f64);
                 let x = col as f64 * width;
                 let y = (line + 1) as f64 * line_height;
 
@@ -833,37 +973,23 @@ impl LapceTabData {
             BufferContent::Local(_) => {
                 editor.window_origin - self.window_origin.to_vec2()
             }
-            BufferContent::Value(_) => {
+            BufferContent::Value(_) | BufferContent::Scratch(..) => {
                 editor.window_origin - self.window_origin.to_vec2()
             }
             BufferContent::File(path) => {
-                let buffer = self.main_split.open_files.get(path).unwrap();
+                let buffer = match self.main_split.open_files.get(path) {
+                    Some(buffer) => buffer,
+                    None => {
+                        return editor.window_origin - self.window_origin.to_vec2()
+                    }
+                };
                 let offset = self.completion.offset;
                 let (line, col) =
-                    buffer.offset_to_line_col(offset, self.config.editor.tab_width);
-                let width = config.editor_text_width(text, "W");
-                let x = col as f64 * width - line_height - 5.0;
-                let y = (line + 1) as f64 * line_height;
-                let mut origin = editor.window_origin - self.window_origin.to_vec2()
-                    + Vec2::new(x, y);
-                if origin.y + self.completion.size.height + 1.0 > tab_size.height {
-                    let height = self
-                        .completion
-                        .size
-                        .height
-                        .min(self.completion.len() as f64 * line_height);
-                    origin.y = editor.window_origin.y - self.window_origin.y
-                        + line as f64 * line_height
-                        - height;
-                }
-                if origin.x + self.completion.size.width + 1.0 > tab_size.width {
-                    origin.x = tab_size.width - self.completion.size.width - 1.0;
-                }
-                if origin.x <= 0.0 {
-                    origin.x = 0.0;
-                }
-
-                origin
+                    editor.visual_line_col(buffer, offset, text, &self.config);
+                let width = config.char_width(text, config.editor.font_size as f64);
+                let cursor = editor.window_origin - self.window_origin.to_vec2()
+                    + Vec2::new(col as f64 * width, line as f64 * line_height);
+                self.completion.popup_origin(cursor, line_height, tab_size)
             }
         }
     }
@@ -886,15 +1012,20 @@ impl LapceTabData {
             BufferContent::Local(_) => {
                 editor.window_origin - self.window_origin.to_vec2()
             }
-            BufferContent::Value(_) => {
+            BufferContent::Value(_) | BufferContent::Scratch(..) => {
                 editor.window_origin - self.window_origin.to_vec2()
             }
             BufferContent::File(path) => {
-                let buffer = self.main_split.open_files.get(path).unwrap();
+                let buffer = match self.main_split.open_files.get(path) {
+                    Some(buffer) => buffer,
+                    None => {
+                        return editor.window_origin - self.window_origin.to_vec2()
+                    }
+                };
                 let offset = self.hover.offset;
                 let (line, col) =
-                    buffer.offset_to_line_col(offset, self.config.editor.tab_width);
-                let width = config.editor_text_width(text, "W");
+                    editor.visual_line_col(buffer, offset, text, &self.config);
+                let width = config.char_width(text, config.editor.font_size as f64);
                 let x = col as f64 * width - line_height - 5.0;
                 let y = (line + 1) as f64 * line_height;
                 let mut origin = editor.window_origin - self.window_origin.to_vec2()
@@ -917,6 +1048,58 @@ impl LapceTabData {
         }
     }
 
+    pub fn signature_origin(
+        &self,
+        text: &mut PietText,
+        tab_size: Size,
+        popup_size: Size,
+        config: &Config,
+    ) -> Point {
+        let line_height = self.config.editor.line_height as f64;
+
+        let editor = self.main_split.active_editor();
+        let editor = match editor {
+            Some(editor) => editor,
+            None => return Point::ZERO,
+        };
+
+        match &editor.content {
+            BufferContent::Local(_)
+            | BufferContent::Value(_)
+            | BufferContent::Scratch(..) => {
+                editor.window_origin - self.window_origin.to_vec2()
+            }
+            BufferContent::File(path) => {
+                let buffer = match self.main_split.open_files.get(path) {
+                    Some(buffer) => buffer,
+                    None => {
+                        return editor.window_origin - self.window_origin.to_vec2()
+                    }
+                };
+                let offset = self.signature.offset;
+                let (line, col) =
+                    editor.visual_line_col(buffer, offset, text, &self.config);
+                let width = config.char_width(text, config.editor.font_size as f64);
+                let x = col as f64 * width;
+                let y = line as f64 * line_height - popup_size.height;
+                let mut origin = editor.window_origin - self.window_origin.to_vec2()
+                    + Vec2::new(x, y);
+                // Show it below the cursor line if there's no room above
+                if origin.y < 0.0 {
+                    origin.y += popup_size.height + line_height;
+                }
+                if origin.x + popup_size.width + 1.0 > tab_size.width {
+                    origin.x = tab_size.width - popup_size.width - 1.0;
+                }
+                if origin.x <= 0.0 {
+                    origin.x = 0.0;
+                }
+
+                origin
+            }
+        }
+    }
+
     pub fn palette_view_data(&self) -> PaletteViewData {
         PaletteViewData {
             palette: self.palette.clone(),
@@ -1097,13 +1280,49 @@ impl LapceTabData {
             LapceWorkbenchCommand::EnableModal => {
                 let config = Arc::make_mut(&mut self.config);
                 config.lapce.modal = true;
+                config.update_language_configs();
                 Config::update_file("lapce.modal", toml::Value::Boolean(true));
             }
             LapceWorkbenchCommand::DisableModal => {
                 let config = Arc::make_mut(&mut self.config);
                 config.lapce.modal = false;
+                config.update_language_configs();
                 Config::update_file("lapce.modal", toml::Value::Boolean(false));
             }
+            LapceWorkbenchCommand::ToggleInlayHints => {
+                let config = Arc::make_mut(&mut self.config);
+                config.editor.enable_inlay_hints = !config.editor.enable_inlay_hints;
+                config.update_language_configs();
+                Config::update_file(
+                    "editor.enable-inlay-hints",
+                    toml::Value::Boolean(config.editor.enable_inlay_hints),
+                );
+            }
+            LapceWorkbenchCommand::ToggleBlame => {
+                let config = Arc::make_mut(&mut self.config);
+                config.editor.show_blame = !config.editor.show_blame;
+                config.update_language_configs();
+                Config::update_file(
+                    "editor.show-blame",
+                    toml::Value::Boolean(config.editor.show_blame),
+                );
+                if config.editor.show_blame {
+                    for (_path, buffer) in self.main_split.open_files.iter() {
+                        buffer.retrieve_blame(
+                            self.id,
+                            self.proxy.clone(),
+                            ctx.get_external_handle(),
+                        );
+                    }
+                }
+            }
+            LapceWorkbenchCommand::ShowBlame => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunPalette(Some(PaletteType::Blame)),
+                    Target::Widget(self.palette.widget_id),
+                ));
+            }
             LapceWorkbenchCommand::ChangeTheme => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -1111,6 +1330,55 @@ impl LapceTabData {
                     Target::Widget(self.palette.widget_id),
                 ));
             }
+            LapceWorkbenchCommand::OpenRecentFile => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunPalette(Some(PaletteType::RecentFile)),
+                    Target::Widget(self.palette.widget_id),
+                ));
+            }
+            LapceWorkbenchCommand::ReopenClosed => {
+                self.main_split.reopen_closed_editor(ctx, &self.config);
+            }
+            LapceWorkbenchCommand::DiffWithHead => {
+                self.main_split.open_diff_view(
+                    ctx,
+                    "head".to_string(),
+                    &self.config,
+                );
+            }
+            LapceWorkbenchCommand::DiffWithPreviousFile => {
+                let current =
+                    self.main_split
+                        .active_editor()
+                        .and_then(|editor| match &editor.content {
+                            BufferContent::File(path) => Some(path.clone()),
+                            _ => None,
+                        });
+                // The file that was focused before the current one
+                let history = self
+                    .main_split
+                    .recent_buffers
+                    .iter()
+                    .find(|path| {
+                        Some(*path) != current.as_ref()
+                            && self.main_split.open_files.contains_key(*path)
+                    })
+                    .map(|path| file_history(path));
+                if let Some(history) = history {
+                    self.main_split.open_diff_view(ctx, history, &self.config);
+                }
+            }
+            LapceWorkbenchCommand::NewScratchBuffer => {
+                self.main_split.new_scratch_buffer(ctx, "", &self.config);
+            }
+            LapceWorkbenchCommand::SetLanguage => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunPalette(Some(PaletteType::Language)),
+                    Target::Widget(self.palette.widget_id),
+                ));
+            }
             LapceWorkbenchCommand::OpenLogFile => {
                 if let Some(path) = Config::log_file() {
                     let editor_view_id = self.main_split.active.clone();
@@ -1205,6 +1473,13 @@ impl LapceTabData {
                     Target::Widget(self.palette.widget_id),
                 ));
             }
+            LapceWorkbenchCommand::PaletteBuffer => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunPalette(Some(PaletteType::Buffer)),
+                    Target::Widget(self.palette.widget_id),
+                ));
+            }
             LapceWorkbenchCommand::PaletteWorkspace => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -1305,6 +1580,9 @@ impl LapceTabData {
             LapceWorkbenchCommand::ToggleProblemVisual => {
                 self.toggle_panel_visual(ctx, PanelKind::Problem);
             }
+            LapceWorkbenchCommand::ToggleLocationListVisual => {
+                self.toggle_panel_visual(ctx, PanelKind::LocationList);
+            }
             LapceWorkbenchCommand::ToggleTerminalVisual => {
                 self.toggle_panel_visual(ctx, PanelKind::Terminal);
             }
@@ -1328,12 +1606,78 @@ impl LapceTabData {
             LapceWorkbenchCommand::ToggleSearchFocus => {
                 self.toggle_panel_focus(ctx, PanelKind::Search);
             }
+            LapceWorkbenchCommand::ToggleSearchCaseSensitive
+            | LapceWorkbenchCommand::ToggleSearchWholeWord => {
+                let search = Arc::make_mut(&mut self.search);
+                if command == &LapceWorkbenchCommand::ToggleSearchCaseSensitive {
+                    search.case_sensitive = !search.case_sensitive;
+                } else {
+                    search.whole_word = !search.whole_word;
+                }
+                let pattern = self
+                    .main_split
+                    .local_buffers
+                    .get(&LocalBufferKind::Search)
+                    .map(|buffer| buffer.rope.to_string())
+                    .unwrap_or_default();
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdateSearch(pattern),
+                    Target::Widget(self.id),
+                ));
+            }
             LapceWorkbenchCommand::ToggleProblemFocus => {
                 self.toggle_panel_focus(ctx, PanelKind::Problem);
             }
+            LapceWorkbenchCommand::ToggleLocationListFocus => {
+                self.toggle_panel_focus(ctx, PanelKind::LocationList);
+            }
+            LapceWorkbenchCommand::ShowDiagnosticsInLocationList => {
+                Arc::make_mut(&mut self.location_list).set_items(
+                    LocationListSource::Diagnostics,
+                    diagnostic_items(&self.main_split.diagnostics),
+                );
+                self.show_panel(ctx, PanelKind::LocationList);
+            }
+            LapceWorkbenchCommand::NextLocation => {
+                let location_list = Arc::make_mut(&mut self.location_list);
+                if let Some(item) = location_list.next() {
+                    let location = item.location();
+                    self.main_split.jump_to_location(
+                        ctx,
+                        None,
+                        location,
+                        &self.config,
+                    );
+                }
+            }
+            LapceWorkbenchCommand::PreviousLocation => {
+                let location_list = Arc::make_mut(&mut self.location_list);
+                if let Some(item) = location_list.previous() {
+                    let location = item.location();
+                    self.main_split.jump_to_location(
+                        ctx,
+                        None,
+                        location,
+                        &self.config,
+                    );
+                }
+            }
             LapceWorkbenchCommand::ToggleTerminalFocus => {
                 self.toggle_panel_focus(ctx, PanelKind::Terminal);
             }
+            LapceWorkbenchCommand::NewTerminal => {
+                // the panel starts its first terminal itself when shown
+                let is_empty = self.terminal.terminals.is_empty();
+                self.show_panel(ctx, PanelKind::Terminal);
+                if !is_empty {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::SplitTerminal(true, self.terminal.active),
+                        Target::Widget(self.terminal.split_id),
+                    ));
+                }
+            }
             LapceWorkbenchCommand::TogglePanelFocus => {
                 if let Some(data) = data {
                     if let Ok(kind) = serde_json::from_value::<PanelKind>(data) {
@@ -1558,6 +1902,7 @@ impl LapceTabData {
                     PanelKind::Terminal => self.terminal.widget_id,
                     PanelKind::Search => self.search.active,
                     PanelKind::Problem => self.problem.widget_id,
+                    PanelKind::LocationList => self.location_list.widget_id,
                 };
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -1579,14 +1924,15 @@ impl LapceTabData {
 
     fn toggle_panel_focus(&mut self, ctx: &mut EventCtx, kind: PanelKind) {
         let should_hide = match kind {
-            PanelKind::FileExplorer | PanelKind::Plugin | PanelKind::Problem => {
+            PanelKind::Plugin | PanelKind::Problem | PanelKind::LocationList => {
                 // Some panels don't accept focus (yet). Fall back to visibility check
                 // in those cases.
                 self.is_panel_visible(kind)
             }
-            PanelKind::Terminal | PanelKind::SourceControl | PanelKind::Search => {
-                self.is_panel_focused(kind)
-            }
+            PanelKind::FileExplorer
+            | PanelKind::Terminal
+            | PanelKind::SourceControl
+            | PanelKind::Search => self.is_panel_focused(kind),
         };
         if should_hide {
             self.hide_panel(ctx, kind);
@@ -1858,6 +2204,19 @@ impl Register {
 //     SplitActive,
 // }
 
+/// How many closed editors are kept to be opened again
+const MAX_CLOSED_EDITORS: usize = 20;
+/// How long closing waits for the proxy to save a file
+const CLOSE_SAVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A closed editor of a file, with where it was to open it there again
+#[derive(Clone, Debug)]
+pub struct ClosedEditor {
+    pub location: EditorLocationNew,
+    pub editor_tab: WidgetId,
+    pub index: usize,
+}
+
 #[derive(Clone, Data, Lens)]
 pub struct LapceMainSplitData {
     pub tab_id: Arc<WidgetId>,
@@ -1867,9 +2226,14 @@ pub struct LapceMainSplitData {
     pub editors: im::HashMap<WidgetId, Arc<LapceEditorData>>,
     pub editor_tabs: im::HashMap<WidgetId, Arc<LapceEditorTabData>>,
     pub open_files: im::HashMap<PathBuf, Arc<Buffer>>,
+    /// The paths of the open files, the most recently focused first
+    pub recent_buffers: im::Vector<PathBuf>,
+    /// The closed editors of files, the most recently closed first
+    pub closed_editors: Arc<Vec<ClosedEditor>>,
     pub splits: im::HashMap<WidgetId, Arc<SplitData>>,
     pub local_buffers: im::HashMap<LocalBufferKind, Arc<Buffer>>,
     pub value_buffers: im::HashMap<String, Arc<Buffer>>,
+    pub scratch_buffers: im::HashMap<BufferId, Arc<Buffer>>,
     pub register: Arc<Register>,
     pub proxy: Arc<LapceProxy>,
     pub palette_preview_editor: Arc<WidgetId>,
@@ -1888,18 +2252,237 @@ impl LapceMainSplitData {
         Some(self.editors.get(&id)?.as_ref())
     }
 
-    pub fn editor_buffer(&self, editor_view_id: WidgetId) -> Arc<Buffer> {
-        let editor = self.editors.get(&editor_view_id).unwrap();
-        let buffer = match &editor.content {
-            BufferContent::File(path) => self.open_files.get(path).unwrap().clone(),
-            BufferContent::Local(kind) => {
-                self.local_buffers.get(kind).unwrap().clone()
+    /// Move the file to the front of the most recently used buffers
+    pub fn touch_recent_buffer(&mut self, path: &Path) {
+        if self.recent_buffers.front().map(|p| p.as_path()) == Some(path) {
+            return;
+        }
+        if let Some(index) = self.recent_buffers.iter().position(|p| p == path) {
+            self.recent_buffers.remove(index);
+        }
+        self.recent_buffers.push_front(path.to_path_buf());
+    }
+
+    /// All the open files and scratch buffers that have unsaved changes
+    pub fn unsaved_buffers(&self) -> Vec<BufferContent> {
+        let mut paths: Vec<PathBuf> = self
+            .open_files
+            .iter()
+            .filter(|(_, buffer)| buffer.dirty)
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.sort();
+        let mut scratch: Vec<BufferContent> = self
+            .scratch_buffers
+            .values()
+            .filter(|buffer| buffer.dirty)
+            .map(|buffer| buffer.content.clone())
+            .collect();
+        scratch.sort_by_key(content_name);
+        paths
+            .into_iter()
+            .map(BufferContent::File)
+            .chain(scratch)
+            .collect()
+    }
+
+    /// The files and scratch buffers with unsaved changes that would no
+    /// longer be shown if the given editors were closed, the ones still
+    /// shown in other editors are left out
+    pub fn unsaved_buffers_of_editors(
+        &self,
+        view_ids: &[WidgetId],
+    ) -> Vec<BufferContent> {
+        let mut unsaved = Vec::new();
+        for view_id in view_ids {
+            let content = match self.editors.get(view_id) {
+                Some(editor) => &editor.content,
+                None => continue,
+            };
+            if !matches!(
+                content,
+                BufferContent::File(_) | BufferContent::Scratch(..)
+            ) {
+                continue;
             }
-            BufferContent::Value(name) => {
-                self.value_buffers.get(name).unwrap().clone()
+            let dirty = self
+                .content_buffer(content)
+                .map(|buffer| buffer.dirty)
+                .unwrap_or(false);
+            let open_elsewhere = self.editors.iter().any(|(id, editor)| {
+                !view_ids.contains(id) && &editor.content == content
+            });
+            if dirty && !open_elsewhere && !unsaved.contains(content) {
+                unsaved.push(content.clone());
             }
+        }
+        unsaved
+    }
+
+    /// Save the files and scratch buffers as they are, without formatting
+    /// them first, asking where to save each scratch buffer, and wait for
+    /// the proxy to save them. Returns false if any of them wasn't saved.
+    pub fn save_buffers(
+        &self,
+        contents: &[BufferContent],
+        event_sink: ExtEventSink,
+    ) -> bool {
+        let (sender, receiver) = unbounded();
+        let mut pending = 0;
+        for content in contents {
+            let buffer = match self.content_buffer(content) {
+                Some(buffer) => buffer,
+                None => continue,
+            };
+            let path = match content {
+                BufferContent::File(path) => path.clone(),
+                BufferContent::Scratch(_, name) => {
+                    let path = match self.pick_scratch_path(name) {
+                        Some(path) => path,
+                        None => return false,
+                    };
+                    // The scratch buffer is about to be closed, so only the
+                    // proxy learns about its path
+                    self.proxy.reopen_buffer(
+                        buffer.id,
+                        &path,
+                        &buffer.rope.to_string(),
+                        buffer.rev,
+                    );
+                    path
+                }
+                _ => continue,
+            };
+            let rev = buffer.rev;
+            let event_sink = event_sink.clone();
+            let sender = sender.clone();
+            self.proxy.save(
+                rev,
+                buffer.id,
+                buffer.line_ending,
+                Box::new(move |result| {
+                    let saved = match result {
+                        Ok(_) => {
+                            let _ = event_sink.submit_command(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::BufferSave(path, rev),
+                                Target::Auto,
+                            );
+                            true
+                        }
+                        Err(e) => {
+                            show_save_error(&path, &e);
+                            false
+                        }
+                    };
+                    let _ = sender.send(saved);
+                }),
+            );
+            pending += 1;
+        }
+        (0..pending)
+            .all(|_| receiver.recv_timeout(CLOSE_SAVE_TIMEOUT).unwrap_or(false))
+    }
+
+    /// Ask where to save the scratch buffer with the given name, `None` if
+    /// the dialog was cancelled or the path is already open in a buffer
+    pub fn pick_scratch_path(&self, name: &str) -> Option<PathBuf> {
+        let default_path = self
+            .workspace
+            .path
+            .as_ref()
+            .map(|workspace| workspace.join(name))
+            .unwrap_or_else(|| PathBuf::from(name));
+        let path = PathBuf::from(tinyfiledialogs::save_file_dialog(
+            "Save As",
+            &default_path.to_string_lossy(),
+        )?);
+        // Two buffers of one file would overwrite each other's changes
+        if self.open_files.contains_key(&path) {
+            tinyfiledialogs::message_box_ok(
+                "Save Failed",
+                &format!(
+                    "{} is already open, save it from its own editor or pick \
+                     another path.",
+                    path.to_string_lossy()
+                ),
+                tinyfiledialogs::MessageBoxIcon::Error,
+            );
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Give the scratch buffer a path and save it there, the editors that
+    /// showed the scratch buffer show the file from then on
+    pub fn save_scratch_buffer(
+        &mut self,
+        id: BufferId,
+        path: PathBuf,
+        event_sink: ExtEventSink,
+    ) {
+        // The file may have been opened while the dialog was up
+        if self.open_files.contains_key(&path) {
+            show_save_failure(
+                &path,
+                "It's already open, save it from that editor or close it first.",
+            );
+            return;
+        }
+        let mut buffer = match self.scratch_buffers.remove(&id) {
+            Some(buffer) => buffer,
+            None => return,
         };
-        buffer
+        Arc::make_mut(&mut buffer).set_path(path.clone());
+        self.proxy.reopen_buffer(
+            buffer.id,
+            &path,
+            &buffer.rope.to_string(),
+            buffer.rev,
+        );
+        for editor in self.editors.values_mut() {
+            if matches!(&editor.content, BufferContent::Scratch(e, _) if e == &id) {
+                Arc::make_mut(editor).content = BufferContent::File(path.clone());
+            }
+        }
+        self.open_files.insert(path.clone(), buffer);
+        self.touch_recent_buffer(&path);
+        self.save_buffers(&[BufferContent::File(path)], event_sink);
+    }
+
+    /// Ask what to do with the unsaved buffers before they are closed and
+    /// save them if asked to, returns false if the close should be
+    /// cancelled, which it also is when saving them fails
+    pub fn confirm_close(
+        &self,
+        unsaved: &[BufferContent],
+        event_sink: ExtEventSink,
+    ) -> bool {
+        if unsaved.is_empty() {
+            return true;
+        }
+        match confirm_unsaved_files(unsaved, self.workspace.path.as_deref()) {
+            UnsavedChoice::SaveAll => self.save_buffers(unsaved, event_sink),
+            UnsavedChoice::Discard => true,
+            UnsavedChoice::Cancel => false,
+        }
+    }
+
+    /// The buffer shown in the editor, `None` if the editor or its buffer
+    /// was already closed
+    pub fn editor_buffer(&self, editor_view_id: WidgetId) -> Option<Arc<Buffer>> {
+        let editor = self.editors.get(&editor_view_id)?;
+        self.content_buffer(&editor.content).cloned()
+    }
+
+    /// The buffer with the given content, if it's open
+    fn content_buffer(&self, content: &BufferContent) -> Option<&Arc<Buffer>> {
+        match content {
+            BufferContent::File(path) => self.open_files.get(path),
+            BufferContent::Local(kind) => self.local_buffers.get(kind),
+            BufferContent::Value(name) => self.value_buffers.get(name),
+            BufferContent::Scratch(id, _) => self.scratch_buffers.get(id),
+        }
     }
 
     pub fn document_format(
@@ -1909,7 +2492,10 @@ impl LapceMainSplitData {
         result: &Result<Value>,
         config: &Config,
     ) {
-        let buffer = self.open_files.get(path).unwrap();
+        let buffer = match self.open_files.get(path) {
+            Some(buffer) => buffer,
+            None => return,
+        };
         if buffer.rev != rev {
             return;
         }
@@ -1919,8 +2505,6 @@ impl LapceMainSplitData {
                 serde_json::from_value(res.clone());
             if let Ok(edits) = edits {
                 if !edits.is_empty() {
-                    let buffer = self.open_files.get_mut(path).unwrap();
-
                     let edits: Vec<(Selection, String)> = edits
                         .iter()
                         .map(|edit| {
@@ -1963,22 +2547,28 @@ impl LapceMainSplitData {
     ) {
         self.document_format(path, rev, result, config);
 
-        let buffer = self.open_files.get(path).unwrap();
+        let buffer = match self.open_files.get(path) {
+            Some(buffer) => buffer,
+            None => return,
+        };
         let rev = buffer.rev;
         let buffer_id = buffer.id;
+        let line_ending = buffer.line_ending;
         let event_sink = ctx.get_external_handle();
         let path = PathBuf::from(path);
         self.proxy.save(
             rev,
             buffer_id,
-            Box::new(move |result| {
-                if let Ok(_r) = result {
+            line_ending,
+            Box::new(move |result| match result {
+                Ok(_) => {
                     let _ = event_sink.submit_command(
                         LAPCE_UI_COMMAND,
                         LapceUICommand::BufferSave(path, rev),
                         Target::Auto,
                     );
                 }
+                Err(e) => show_save_error(&path, &e),
             }),
         );
     }
@@ -2066,7 +2656,8 @@ impl LapceMainSplitData {
             }
         }
 
-        let delta = Arc::make_mut(buffer).edit_multiple(edits, proxy, edit_type);
+        let delta =
+            Arc::make_mut(buffer).edit_multiple(edits, proxy, edit_type, None);
         if move_cursor {
             self.cursor_apply_delta(path, &delta);
         }
@@ -2119,7 +2710,7 @@ impl LapceMainSplitData {
         &mut self,
         ctx: &mut EventCtx,
         editor_view_id: Option<WidgetId>,
-        path: Option<PathBuf>,
+        content: Option<BufferContent>,
         config: &Config,
     ) -> &mut LapceEditorData {
         match editor_view_id {
@@ -2131,7 +2722,7 @@ impl LapceMainSplitData {
                     match &editor_tab.children[editor_tab.active] {
                         EditorTabChild::Editor(id, _) => {
                             if config.editor.show_tab {
-                                if let Some(path) = path {
+                                if let Some(content) = content {
                                     let mut editor_size = Size::ZERO;
                                     for (i, child) in
                                         editor_tab.children.iter().enumerate()
@@ -2145,11 +2736,7 @@ impl LapceMainSplitData {
                                                 if current_size.height > 0.0 {
                                                     editor_size = current_size;
                                                 }
-                                                if editor.content
-                                                    == BufferContent::File(
-                                                        path.clone(),
-                                                    )
-                                                {
+                                                if editor.content == content {
                                                     editor_tab.active = i;
                                                     ctx.submit_command(
                                                         Command::new(
@@ -2306,7 +2893,7 @@ impl LapceMainSplitData {
             .get_editor_or_new(
                 ctx,
                 editor_view_id,
-                Some(location.path.clone()),
+                Some(BufferContent::File(location.path.clone())),
                 config,
             )
             .view_id;
@@ -2314,10 +2901,12 @@ impl LapceMainSplitData {
         let editor = self.get_editor_or_new(
             ctx,
             Some(editor_view_id),
-            Some(location.path.clone()),
+            Some(BufferContent::File(location.path.clone())),
             config,
         );
-        editor.save_jump_location(&buffer, config.editor.tab_width);
+        if let Some(buffer) = buffer {
+            editor.save_jump_location(&buffer, config.editor.tab_width);
+        }
         self.go_to_location(ctx, Some(editor_view_id), location, config);
         editor_view_id
     }
@@ -2333,18 +2922,24 @@ impl LapceMainSplitData {
             .get_editor_or_new(
                 ctx,
                 editor_view_id,
-                Some(location.path.clone()),
+                Some(BufferContent::File(location.path.clone())),
                 config,
             )
             .view_id;
         let buffer = self.editor_buffer(editor_view_id);
-        let new_buffer = match &buffer.content {
-            BufferContent::File(path) => path != &location.path,
-            BufferContent::Local(_) => true,
-            BufferContent::Value(_) => true,
+        let new_buffer = match buffer.as_ref().map(|b| &b.content) {
+            Some(BufferContent::File(path)) => path != &location.path,
+            Some(BufferContent::Local(_)) => true,
+            Some(BufferContent::Value(_)) => true,
+            Some(BufferContent::Scratch(..)) => true,
+            None => true,
         };
         if new_buffer {
-            self.db.save_buffer_position(&self.workspace, &buffer);
+            if let Some(buffer) = buffer.as_ref() {
+                self.db.save_buffer_position(&self.workspace, buffer);
+                self.db.save_undo_history(buffer);
+            }
+            self.end_diff_view(editor_view_id);
         } else if location.position.is_none()
             && location.scroll_offset.is_none()
             && location.history.is_none()
@@ -2404,11 +2999,14 @@ impl LapceMainSplitData {
             let editor = self.get_editor_or_new(
                 ctx,
                 Some(editor_view_id),
-                Some(location.path.clone()),
+                Some(BufferContent::File(location.path.clone())),
                 config,
             );
             editor.content = BufferContent::File(path.clone());
-            editor.compare = location.history.clone();
+            if editor.diff_view.is_none() {
+                editor.compare = location.history.clone();
+            }
+            let is_preview = editor.view_id == *self.palette_preview_editor;
             editor.cursor = if config.lapce.modal {
                 Cursor::new(CursorMode::Normal(offset), None)
             } else {
@@ -2436,6 +3034,10 @@ impl LapceMainSplitData {
                     Target::Widget(editor_view_id),
                 ));
             }
+
+            if !is_preview {
+                self.touch_recent_buffer(&path);
+            }
         }
     }
 
@@ -2449,7 +3051,10 @@ impl LapceMainSplitData {
         let editor_view_id = self
             .get_editor_or_new(ctx, editor_view_id, None, config)
             .view_id;
-        let buffer = self.editor_buffer(editor_view_id);
+        let buffer = match self.editor_buffer(editor_view_id) {
+            Some(buffer) => buffer,
+            None => return,
+        };
         let offset = buffer.first_non_blank_character_on_line(if line > 0 {
             line - 1
         } else {
@@ -2504,8 +3109,11 @@ impl LapceMainSplitData {
             editor_tabs,
             splits,
             open_files,
+            recent_buffers: im::Vector::new(),
+            closed_editors: Arc::new(Vec::new()),
             local_buffers,
             value_buffers: im::HashMap::new(),
+            scratch_buffers: im::HashMap::new(),
             active: Arc::new(None),
             active_tab: Arc::new(None),
             register: Arc::new(Register::default()),
@@ -2521,8 +3129,13 @@ impl LapceMainSplitData {
         };
 
         if let Some(info) = workspace_info {
+            let mut split_info = info.split.clone();
+            if !main_split_data.workspace.kind.is_remote() {
+                // Files could have been removed since the session was saved
+                split_info.retain_existing_files();
+            }
             let mut positions = HashMap::new();
-            let split_data = info.split.to_data(
+            let split_data = split_info.to_data(
                 &mut main_split_data,
                 None,
                 &mut positions,
@@ -2635,23 +3248,352 @@ impl LapceMainSplitData {
     }
 
     pub fn update_editor_tab_layout_rect(&self, widget_id: WidgetId, rect: Rect) {
-        let editor_tab = self.editor_tabs.get(&widget_id).unwrap();
-        *editor_tab.layout_rect.borrow_mut() = rect;
+        if let Some(editor_tab) = self.editor_tabs.get(&widget_id) {
+            *editor_tab.layout_rect.borrow_mut() = rect;
+        }
     }
 
     pub fn update_split_layout_rect(&self, split_id: WidgetId, rect: Rect) {
-        let split = self.splits.get(&split_id).unwrap();
-        *split.layout_rect.borrow_mut() = rect;
+        if let Some(split) = self.splits.get(&split_id) {
+            *split.layout_rect.borrow_mut() = rect;
+        }
+    }
+
+    /// Remember the editor at `index` of its editor tab, which is being
+    /// closed, to be able to open it there again
+    /// Remove a closed editor, and the scratch buffer it showed if no other
+    /// editor shows it
+    pub fn remove_editor(&mut self, view_id: &WidgetId) {
+        let editor = match self.editors.remove(view_id) {
+            Some(editor) => editor,
+            None => return,
+        };
+        if let BufferContent::File(path) = &editor.content {
+            if let Some(buffer) = self.open_files.get(path) {
+                buffer.remove_wrap_layout(*view_id);
+            }
+        }
+        if let BufferContent::Scratch(id, _) = &editor.content {
+            if !self.editors.values().any(|e| e.content == editor.content) {
+                self.scratch_buffers.remove(id);
+            }
+        }
+    }
+
+    pub fn remember_closed_editor(
+        &mut self,
+        view_id: WidgetId,
+        index: usize,
+        config: &Config,
+    ) {
+        let editor = match self.editors.get(&view_id) {
+            Some(editor) => editor,
+            None => return,
+        };
+        let (path, editor_tab) = match (&editor.content, editor.tab_id) {
+            (BufferContent::File(path), Some(editor_tab)) => (path, editor_tab),
+            _ => return,
+        };
+        let position = self.open_files.get(path).map(|buffer| {
+            buffer
+                .offset_to_position(editor.cursor.offset(), config.editor.tab_width)
+        });
+        let closed = ClosedEditor {
+            location: EditorLocationNew {
+                path: path.clone(),
+                position,
+                scroll_offset: Some(editor.scroll_offset),
+                history: None,
+            },
+            editor_tab,
+            index,
+        };
+        let closed_editors = Arc::make_mut(&mut self.closed_editors);
+        closed_editors.retain(|c| c.location.path != closed.location.path);
+        closed_editors.insert(0, closed);
+        closed_editors.truncate(MAX_CLOSED_EDITORS);
+    }
+
+    /// Open the most recently closed editor whose file still exists again,
+    /// at its place in its editor tab if the tab is still there
+    pub fn reopen_closed_editor(&mut self, ctx: &mut EventCtx, config: &Config) {
+        let closed = loop {
+            if self.closed_editors.is_empty() {
+                return;
+            }
+            let closed = Arc::make_mut(&mut self.closed_editors).remove(0);
+            if closed.location.path.exists() {
+                break closed;
+            }
+        };
+
+        let editor_tab = match self.editor_tabs.get_mut(&closed.editor_tab) {
+            Some(editor_tab) => Arc::make_mut(editor_tab),
+            None => {
+                self.jump_to_location(ctx, None, closed.location, config);
+                return;
+            }
+        };
+        let editor_size = editor_tab
+            .children
+            .iter()
+            .filter_map(|child| self.editors.get(&child.widget_id()))
+            .map(|editor| *editor.size.borrow())
+            .find(|size| size.height > 0.0)
+            .unwrap_or(Size::ZERO);
+        let new_editor = Arc::new(LapceEditorData::new(
+            None,
+            Some(editor_tab.widget_id),
+            BufferContent::Local(LocalBufferKind::Empty),
+            config,
+        ));
+        *new_editor.size.borrow_mut() = editor_size;
+        let index = closed.index.min(editor_tab.children.len());
+        let child =
+            EditorTabChild::Editor(new_editor.view_id, new_editor.find_view_id);
+        editor_tab.children.insert(index, child.clone());
+        editor_tab.active = index;
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::EditorTabAdd(index, child),
+            Target::Widget(editor_tab.widget_id),
+        ));
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::Focus,
+            Target::Widget(new_editor.view_id),
+        ));
+        let view_id = new_editor.view_id;
+        self.insert_editor(new_editor, config);
+        self.jump_to_location(ctx, Some(view_id), closed.location, config);
+    }
+
+    /// Open a buffer that isn't backed by a file until it's saved in a new
+    /// editor, with `content` as its text
+    pub fn new_scratch_buffer(
+        &mut self,
+        ctx: &mut EventCtx,
+        content: &str,
+        config: &Config,
+    ) -> WidgetId {
+        let n = (1..)
+            .find(|n| {
+                let name = format!("Untitled-{n}");
+                !self.scratch_buffers.values().any(|buffer| {
+                    matches!(&buffer.content, BufferContent::Scratch(_, s) if s == &name)
+                })
+            })
+            .unwrap();
+        let buffer_content =
+            BufferContent::Scratch(BufferId::next(), format!("Untitled-{n}"));
+        let mut buffer = Buffer::new(
+            buffer_content.clone(),
+            *self.tab_id,
+            ctx.get_external_handle(),
+        )
+        .set_local();
+        buffer.load_content(content);
+        self.scratch_buffers.insert(buffer.id, Arc::new(buffer));
+
+        let editor =
+            self.get_editor_or_new(ctx, None, Some(buffer_content.clone()), config);
+        editor.content = buffer_content;
+        editor.compare = None;
+        editor.cursor = if config.lapce.modal {
+            Cursor::new(CursorMode::Normal(0), None)
+        } else {
+            Cursor::new(CursorMode::Insert(Selection::caret(0)), None)
+        };
+        let view_id = editor.view_id;
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::ForceScrollTo(0.0, 0.0),
+            Target::Widget(view_id),
+        ));
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::Focus,
+            Target::Widget(view_id),
+        ));
+        view_id
+    }
+
+    /// Show the file of the active editor and `history` side by side, with
+    /// the version in `history` on the left in a new split. Both sides show
+    /// the buffer, so the left one can't be edited.
+    pub fn open_diff_view(
+        &mut self,
+        ctx: &mut EventCtx,
+        history: String,
+        config: &Config,
+    ) {
+        let view_id = match *self.active {
+            Some(view_id) => view_id,
+            None => return,
+        };
+        let mut editor = match self.editors.get(&view_id) {
+            Some(editor) if editor.diff_view.is_none() => (**editor).clone(),
+            _ => return,
+        };
+        let path = match &editor.content {
+            BufferContent::File(path) => path.clone(),
+            _ => return,
+        };
+        if let Some(other_path) = history_file(&history) {
+            let (other, rev) = match self.open_files.get(&other_path) {
+                Some(other) => (other.rope.clone(), other.rev),
+                None => return,
+            };
+            match self.open_files.get_mut(&path) {
+                Some(buffer) => {
+                    let buffer = Arc::make_mut(buffer);
+                    buffer.load_history(&history, other);
+                    buffer.history_revs.insert(history.clone(), rev);
+                }
+                None => return,
+            }
+        } else {
+            let buffer = match self.open_files.get(&path) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            if !buffer.histories.contains_key(&history) {
+                buffer.retrieve_file_head(
+                    *self.tab_id,
+                    self.proxy.clone(),
+                    ctx.get_external_handle(),
+                );
+            }
+        }
+
+        editor.compare = Some(history);
+        let right_id = match self.split_editor(
+            ctx,
+            &mut editor,
+            SplitDirection::Vertical,
+            config,
+        ) {
+            Some(right_id) => right_id,
+            None => return,
+        };
+        editor.diff_view = Some(EditorDiffView {
+            side: DiffSide::Left,
+            other: right_id,
+        });
+        self.editors.insert(view_id, Arc::new(editor));
+        if let Some(right) = self.editors.get_mut(&right_id) {
+            Arc::make_mut(right).diff_view = Some(EditorDiffView {
+                side: DiffSide::Right,
+                other: view_id,
+            });
+        }
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::Focus,
+            Target::Widget(right_id),
+        ));
+    }
+
+    /// Compare the unsaved changes of the file at `path` with `content`,
+    /// what the file has on disk now, in a diff view of an editor of the
+    /// file, the active one if it shows the file
+    pub fn open_disk_diff_view(
+        &mut self,
+        ctx: &mut EventCtx,
+        path: &Path,
+        content: Rope,
+        config: &Config,
+    ) {
+        let shows_path = |editor: &LapceEditorData| {
+            editor.diff_view.is_none()
+                && matches!(&editor.content, BufferContent::File(p) if p == path)
+        };
+        let view_id = match self.active_editor() {
+            Some(editor) if shows_path(editor) => editor.view_id,
+            _ => match self.editors.values().find(|editor| shows_path(editor)) {
+                Some(editor) => editor.view_id,
+                None => return,
+            },
+        };
+        match self.open_files.get_mut(path) {
+            Some(buffer) => {
+                Arc::make_mut(buffer).load_history(DISK_HISTORY, content)
+            }
+            None => return,
+        }
+        self.active = Arc::new(Some(view_id));
+        self.open_diff_view(ctx, DISK_HISTORY.to_string(), config);
+    }
+
+    /// Turn both sides of the diff view the editor is in back into normal
+    /// editors, and drop the content of the other file it compared with
+    /// unless another editor still compares with it
+    pub fn end_diff_view(&mut self, view_id: WidgetId) {
+        let other = match self
+            .editors
+            .get(&view_id)
+            .and_then(|editor| editor.diff_view.as_ref())
+        {
+            Some(diff_view) => diff_view.other,
+            None => return,
+        };
+        let mut compared = Vec::new();
+        for id in [view_id, other] {
+            if let Some(editor) = self.editors.get_mut(&id) {
+                let editor = Arc::make_mut(editor);
+                editor.diff_view = None;
+                if let (BufferContent::File(path), Some(history)) =
+                    (&editor.content, editor.compare.take())
+                {
+                    compared.push((path.clone(), history));
+                }
+            }
+        }
+        for (path, history) in compared {
+            let still_compared = self.editors.values().any(|editor| {
+                editor.compare.as_ref() == Some(&history)
+                    && matches!(&editor.content, BufferContent::File(p) if p == &path)
+            });
+            if history_file(&history).is_none() || still_compared {
+                continue;
+            }
+            if let Some(buffer) = self.open_files.get_mut(&path) {
+                Arc::make_mut(buffer).remove_history(&history);
+            }
+        }
+    }
+
+    /// Give the buffers compared with the file at `path` its new content
+    pub fn refresh_file_histories(&mut self, path: &Path) {
+        let content = match self.open_files.get(path) {
+            Some(buffer) => buffer.rope.clone(),
+            None => return,
+        };
+        let history = file_history(path);
+        for (_, buffer) in self.open_files.iter_mut() {
+            if buffer.histories.contains_key(&history) {
+                Arc::make_mut(buffer).load_history(&history, content.clone());
+            }
+        }
     }
 
     pub fn editor_close(&mut self, ctx: &mut EventCtx, view_id: WidgetId) {
-        let editor = self.editors.get(&view_id).unwrap();
+        self.end_diff_view(view_id);
+        let editor = match self.editors.get(&view_id) {
+            Some(editor) => editor,
+            None => return,
+        };
         if let BufferContent::File(path) = &editor.content {
-            let buffer = self.open_files.get(path).unwrap();
-            self.db.save_buffer_position(&self.workspace, buffer);
+            if let Some(buffer) = self.open_files.get(path) {
+                self.db.save_buffer_position(&self.workspace, buffer);
+                self.db.save_undo_history(buffer);
+            }
         }
         if let Some(tab_id) = editor.tab_id {
-            let editor_tab = self.editor_tabs.get(&tab_id).unwrap();
+            let editor_tab = match self.editor_tabs.get(&tab_id) {
+                Some(editor_tab) => editor_tab,
+                None => return,
+            };
             let mut index = 0;
             for (i, child) in editor_tab.children.iter().enumerate() {
                 if child.widget_id() == view_id {
@@ -2844,11 +3786,12 @@ impl LapceMainSplitData {
         editor: &mut LapceEditorData,
         direction: SplitDirection,
         config: &Config,
-    ) {
+    ) -> Option<WidgetId> {
         if let Some(editor_tab_id) = editor.tab_id {
             let editor_tab = self.editor_tabs.get(&editor_tab_id).unwrap();
             let split_id = editor_tab.split;
             let mut new_editor = editor.copy(WidgetId::next());
+            new_editor.diff_view = None;
             let mut new_editor_tab = LapceEditorTabData {
                 widget_id: WidgetId::next(),
                 split: split_id,
@@ -2879,10 +3822,13 @@ impl LapceMainSplitData {
                 editor_tab.split = new_split_id;
             }
 
+            let new_view_id = new_editor.view_id;
             self.insert_editor(Arc::new(new_editor), config);
             self.editor_tabs
                 .insert(new_editor_tab.widget_id, Arc::new(new_editor_tab));
+            return Some(new_view_id);
         }
+        None
     }
 }
 
@@ -2892,12 +3838,40 @@ pub enum EditorContent {
     None,
 }
 
+/// The side of a side by side diff an editor shows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffSide {
+    /// The version the file is compared with, which can't be edited
+    Left,
+    /// The file itself
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EditorDiffView {
+    pub side: DiffSide,
+    /// The editor of the other side, in the split next to this one
+    pub other: WidgetId,
+}
+
 #[derive(Clone, Debug)]
 pub enum InlineFindDirection {
     Left,
     Right,
 }
 
+/// What is done with the pair of the next char typed
+#[derive(Clone, Debug, PartialEq)]
+pub enum SurroundAction {
+    /// Wrap the selection, or the word under the cursor, in the pair
+    Add,
+    /// Take away the pair around the cursor
+    Delete,
+    /// Replace the pair of the first char around the cursor with the pair
+    /// of the char typed after it
+    Change(Option<char>),
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum EditorTabChild {
     Editor(WidgetId, Option<WidgetId>),
@@ -2969,6 +3943,7 @@ pub struct SelectionHistory {
 #[derive(Clone, Debug, PartialEq)]
 pub enum MotionMode {
     Delete,
+    Change,
     Yank,
     Indent,
     Outdent,
@@ -2982,11 +3957,17 @@ pub struct LapceEditorData {
     pub find_view_id: Option<WidgetId>,
     pub content: BufferContent,
     pub compare: Option<String>,
+    /// The side of the diff view the editor is, both sides show the buffer
+    /// compared with `compare` with their lines aligned
+    pub diff_view: Option<EditorDiffView>,
     pub code_lens: bool,
     pub scroll_offset: Vec2,
     pub cursor: Cursor,
     pub selection_history: SelectionHistory,
     pub size: Rc<RefCell<Size>>,
+    /// When the caret last moved or the buffer was edited, the caret blinks
+    /// from then on
+    pub last_cursor_instant: Rc<RefCell<Instant>>,
     pub window_origin: Point,
     pub snippet: Option<Vec<(usize, (usize, usize))>>,
     pub locations: Vec<EditorLocationNew>,
@@ -2994,7 +3975,21 @@ pub struct LapceEditorData {
     pub last_movement: Movement,
     pub last_inline_find: Option<(InlineFindDirection, String)>,
     pub inline_find: Option<InlineFindDirection>,
+    pub surround: Option<SurroundAction>,
     pub motion_mode: Option<MotionMode>,
+    /// The count given with the key that started the motion mode
+    pub motion_mode_count: Option<usize>,
+    /// The selection and mode of the last visual mode, kept up to date with
+    /// the edits since so that it can be selected again
+    pub last_visual: Option<(Selection, VisualMode)>,
+    /// The folded blocks, each one covering the text of the lines it hides
+    pub folds: Vec<Interval>,
+    /// The stats shown in the status bar until the next command, for the
+    /// whole buffer and for the selection when there is one
+    pub stats: Option<(TextStats, Option<TextStats>)>,
+    /// The cursor before each syntax node expansion of the selection, ending
+    /// with the one the last expansion made, to shrink the selection back
+    pub selection_expansions: Vec<CursorMode>,
 }
 
 impl LapceEditorData {
@@ -3026,7 +4021,9 @@ impl LapceEditorData {
                 Cursor::new(CursorMode::Insert(Selection::caret(0)), None)
             },
             size: Rc::new(RefCell::new(Size::ZERO)),
+            last_cursor_instant: Rc::new(RefCell::new(Instant::now())),
             compare: None,
+            diff_view: None,
             code_lens: false,
             window_origin: Point::ZERO,
             snippet: None,
@@ -3034,8 +4031,14 @@ impl LapceEditorData {
             current_location: 0,
             last_movement: Movement::Left,
             inline_find: None,
+            surround: None,
             last_inline_find: None,
             motion_mode: None,
+            motion_mode_count: None,
+            last_visual: None,
+            folds: Vec::new(),
+            stats: None,
+            selection_expansions: Vec::new(),
         }
     }
 
@@ -3044,6 +4047,7 @@ impl LapceEditorData {
         new_editor.view_id = new_view_id;
         new_editor.find_view_id = new_editor.find_view_id.map(|_| WidgetId::next());
         new_editor.size = Rc::new(RefCell::new(Size::ZERO));
+        new_editor.last_cursor_instant = Rc::new(RefCell::new(Instant::now()));
         new_editor
     }
 
@@ -3074,6 +4078,122 @@ impl LapceEditorData {
         placeholders.extend_from_slice(&v[1..]);
     }
 
+    /// The soft wrap layout of the buffer in this editor, `None` if it isn't
+    /// wrapped and has nothing folded. Only files are wrapped or folded, and
+    /// never in diff or code lens views.
+    pub fn wrap_layout(
+        &self,
+        buffer: &Buffer,
+        text: &mut PietText,
+        config: &Config,
+    ) -> Option<Arc<WrapLayout>> {
+        if !matches!(self.content, BufferContent::File(_))
+            || self.code_lens
+            || self.compare.is_some()
+        {
+            return None;
+        }
+        let char_width = config.char_width(text, config.editor.font_size as f64);
+        let wrap_col = config
+            .editor
+            .wrap_column(self.size.borrow().width, char_width);
+        let folds = self.folded_lines(buffer);
+        if wrap_col.is_none() && folds.is_empty() {
+            return None;
+        }
+        Some(buffer.wrap_layout(
+            self.view_id,
+            wrap_col.unwrap_or(usize::MAX),
+            config.editor.tab_width,
+            &folds,
+        ))
+    }
+
+    /// The folded blocks as the line that stays visible and the last line
+    /// hidden under it
+    pub fn folded_lines(&self, buffer: &Buffer) -> Vec<(usize, usize)> {
+        let mut lines = self
+            .folds
+            .iter()
+            .filter_map(|fold| {
+                let start = buffer.line_of_offset(fold.start().min(buffer.len()));
+                let end = buffer.line_of_offset(fold.end().min(buffer.len()));
+                if start == 0 || end < start {
+                    return None;
+                }
+                Some((start - 1, end))
+            })
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// Fold the lines after `start` up to `end`
+    pub fn add_fold(&mut self, buffer: &Buffer, start: usize, end: usize) {
+        let fold = Interval::new(
+            buffer.offset_of_line(start + 1),
+            buffer.line_end_offset(end, true),
+        );
+        if !fold.is_empty() && !self.folds.contains(&fold) {
+            self.folds.push(fold);
+        }
+    }
+
+    /// Move the folds along with an edit, dropping the ones that were
+    /// deleted
+    pub fn apply_delta_to_folds(&mut self, delta: &RopeDelta) {
+        if self.folds.is_empty() {
+            return;
+        }
+        let mut transformer = Transformer::new(delta);
+        self.folds = self
+            .folds
+            .iter()
+            .map(|fold| {
+                Interval::new(
+                    transformer.transform(fold.start(), false),
+                    transformer.transform(fold.end(), false),
+                )
+            })
+            .filter(|fold| !fold.is_empty())
+            .collect();
+    }
+
+    /// The line and column that an offset is shown at, which is on the
+    /// visual line it falls in when the buffer is soft wrapped
+    pub fn visual_line_col(
+        &self,
+        buffer: &Buffer,
+        offset: usize,
+        text: &mut PietText,
+        config: &Config,
+    ) -> (usize, usize) {
+        let (line, col) = buffer.offset_to_line_col(offset, config.editor.tab_width);
+        match self.wrap_layout(buffer, text, config) {
+            Some(wrap) => {
+                let (visual_line, start_col) = wrap.visual_line_of_col(line, col);
+                (visual_line, col - start_col)
+            }
+            None if self.show_inlay_hints(config) => {
+                (line, buffer.visual_col(line, col, config.editor.tab_width))
+            }
+            None => (line, col),
+        }
+    }
+
+    /// Inlay hints are only drawn in editors that lay out the lines of a
+    /// file as they are, not soft wrapped or folded, nor in diffs or the
+    /// code lens
+    pub fn show_inlay_hints(&self, config: &Config) -> bool {
+        config.editor.enable_inlay_hints
+            && matches!(self.content, BufferContent::File(_))
+            && !self.code_lens
+            && self.compare.is_none()
+            && config.editor.word_wrap == "off"
+            && self.folds.is_empty()
+    }
+
     pub fn save_jump_location(&mut self, buffer: &Buffer, tab_width: usize) {
         if let BufferContent::File(path) = &buffer.content {
             let location = EditorLocationNew {
@@ -3091,19 +4211,156 @@ impl LapceEditorData {
 
     pub fn editor_info(&self, data: &LapceTabData, tab_width: usize) -> EditorInfo {
         let info = EditorInfo {
-            content: self.content.clone(),
+            content: match &self.content {
+                // Scratch buffers aren't kept across sessions
+                BufferContent::Scratch(..) => {
+                    BufferContent::Local(LocalBufferKind::Empty)
+                }
+                content => content.clone(),
+            },
             scroll_offset: (self.scroll_offset.x, self.scroll_offset.y),
-            position: if let BufferContent::File(path) = &self.content {
-                let buffer = data.main_split.open_files.get(path).unwrap().clone();
-                Some(buffer.offset_to_position(self.cursor.offset(), tab_width))
-            } else {
-                None
+            position: match &self.content {
+                BufferContent::File(path) => {
+                    data.main_split.open_files.get(path).map(|buffer| {
+                        buffer.offset_to_position(self.cursor.offset(), tab_width)
+                    })
+                }
+                _ => None,
             },
         };
         info
     }
 }
 
+/// What to do with files that have unsaved changes when they are closed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsavedChoice {
+    SaveAll,
+    Discard,
+    Cancel,
+}
+
+/// What to do with the unsaved changes of a file that another program
+/// changed on disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiskChangeChoice {
+    /// Throw the changes away and load the file again
+    Reload,
+    /// Keep the changes, saving them overwrites the file
+    Keep,
+    /// Compare the changes with the file on disk before deciding
+    Diff,
+}
+
+/// Ask the user whether to reload the file that changed on disk, keep the
+/// unsaved changes or compare them with the file
+pub fn confirm_disk_change(path: &Path) -> DiskChangeChoice {
+    let reload = tinyfiledialogs::message_box_yes_no(
+        "File Changed on Disk",
+        &format!(
+            "{} was changed by another program and has unsaved changes here.\n\nDo you want to reload it and lose your changes?",
+            path.to_string_lossy()
+        ),
+        tinyfiledialogs::MessageBoxIcon::Warning,
+        tinyfiledialogs::YesNo::No,
+    );
+    if let tinyfiledialogs::YesNo::Yes = reload {
+        return DiskChangeChoice::Reload;
+    }
+    let diff = tinyfiledialogs::message_box_yes_no(
+        "File Changed on Disk",
+        "Do you want to compare your changes with the file on disk? Otherwise your changes are kept and saving them overwrites the file.",
+        tinyfiledialogs::MessageBoxIcon::Question,
+        tinyfiledialogs::YesNo::Yes,
+    );
+    match diff {
+        tinyfiledialogs::YesNo::Yes => DiskChangeChoice::Diff,
+        tinyfiledialogs::YesNo::No => DiskChangeChoice::Keep,
+    }
+}
+
+/// Ask the user whether to save, discard or keep the unsaved files and
+/// scratch buffers that are about to be closed
+pub fn confirm_unsaved_files(
+    unsaved: &[BufferContent],
+    workspace_path: Option<&Path>,
+) -> UnsavedChoice {
+    if unsaved.is_empty() {
+        return UnsavedChoice::Discard;
+    }
+    let files = unsaved
+        .iter()
+        .map(|content| match content {
+            BufferContent::File(path) => {
+                let path = workspace_path
+                    .and_then(|w| path.strip_prefix(w).ok())
+                    .unwrap_or(path);
+                format!("    {}", path.to_string_lossy())
+            }
+            _ => format!("    {}", content_name(content)),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let save = tinyfiledialogs::message_box_yes_no(
+        "Unsaved Changes",
+        &format!(
+            "Do you want to save the changes to the following files?\n\n{}",
+            files
+        ),
+        tinyfiledialogs::MessageBoxIcon::Question,
+        tinyfiledialogs::YesNo::Yes,
+    );
+    if let tinyfiledialogs::YesNo::Yes = save {
+        return UnsavedChoice::SaveAll;
+    }
+    let discard = tinyfiledialogs::message_box_ok_cancel(
+        "Discard Changes",
+        "Your changes will be lost if you close without saving.",
+        tinyfiledialogs::MessageBoxIcon::Warning,
+        tinyfiledialogs::OkCancel::Cancel,
+    );
+    match discard {
+        tinyfiledialogs::OkCancel::Ok => UnsavedChoice::Discard,
+        tinyfiledialogs::OkCancel::Cancel => UnsavedChoice::Cancel,
+    }
+}
+
+/// The name a buffer is shown with in the unsaved changes prompt
+fn content_name(content: &BufferContent) -> String {
+    match content {
+        BufferContent::File(path) => path.to_string_lossy().to_string(),
+        BufferContent::Scratch(_, name) => name.clone(),
+        BufferContent::Local(kind) => format!("{kind:?}"),
+        BufferContent::Value(name) => name.clone(),
+    }
+}
+
+/// Tell the user that a file couldn't be saved, with the error the proxy
+/// responded with
+fn show_save_error(path: &Path, error: &Value) {
+    let message = error
+        .get("message")
+        .and_then(|m| m.as_str())
+        .map(|m| m.to_string())
+        .unwrap_or_else(|| error.to_string());
+    show_save_failure(path, &message);
+}
+
+fn show_save_failure(path: &Path, message: &str) {
+    log::error!("Failed to save {path:?}: {message}");
+    let message =
+        format!("Couldn't save {}:\n\n{}", path.to_string_lossy(), message);
+    // The callback runs on the thread that reads the proxy's responses,
+    // which mustn't wait for the dialog
+    thread::spawn(move || {
+        tinyfiledialogs::message_box_ok(
+            "Save Failed",
+            &message,
+            tinyfiledialogs::MessageBoxIcon::Error,
+        );
+    });
+}
+
 pub fn hex_to_color(hex: &str) -> Result<Color> {
     let hex = hex.trim_start_matches('#');
     match hex.len() {
@@ -3136,6 +4393,65 @@ pub fn hex_to_color(hex: &str) -> Result<Color> {
     }
 }
 
+/// Parse a color in either the hex form accepted by [`hex_to_color`], or the
+/// CSS-like functional forms `rgb(r, g, b)` and `rgba(r, g, b, a)`.
+pub fn parse_color(color: &str) -> Result<Color> {
+    let color = color.trim();
+    if let Some(args) = color
+        .strip_prefix("rgba(")
+        .and_then(|c| c.strip_suffix(')'))
+    {
+        let args: Vec<&str> = args.split(',').map(|a| a.trim()).collect();
+        if args.len() != 4 {
+            return Err(anyhow!("rgba() takes 4 arguments"));
+        }
+        Ok(Color::rgba8(
+            parse_color_channel(args[0])?,
+            parse_color_channel(args[1])?,
+            parse_color_channel(args[2])?,
+            parse_color_alpha(args[3])?,
+        ))
+    } else if let Some(args) =
+        color.strip_prefix("rgb(").and_then(|c| c.strip_suffix(')'))
+    {
+        let args: Vec<&str> = args.split(',').map(|a| a.trim()).collect();
+        if args.len() != 3 {
+            return Err(anyhow!("rgb() takes 3 arguments"));
+        }
+        Ok(Color::rgba8(
+            parse_color_channel(args[0])?,
+            parse_color_channel(args[1])?,
+            parse_color_channel(args[2])?,
+            255,
+        ))
+    } else {
+        hex_to_color(color)
+    }
+}
+
+/// A color channel, which is an integer from 0 to 255
+fn parse_color_channel(channel: &str) -> Result<u8> {
+    channel
+        .parse::<u8>()
+        .map_err(|_| anyhow!("invalid color channel {channel}"))
+}
+
+/// An alpha value, which is either an integer from 0 to 255, a number with a
+/// decimal point from 0.0 to 1.0, or a percentage
+fn parse_color_alpha(alpha: &str) -> Result<u8> {
+    let fraction = if let Some(percentage) = alpha.strip_suffix('%') {
+        percentage.trim().parse::<f64>()? / 100.0
+    } else if alpha.contains('.') {
+        alpha.parse::<f64>()?
+    } else {
+        return parse_color_channel(alpha);
+    };
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(anyhow!("alpha {alpha} is out of range"));
+    }
+    Ok((fraction * 255.0).round() as u8)
+}
+
 #[allow(dead_code)]
 fn str_is_pair_left(c: &str) -> bool {
     if c.chars().count() == 1 {
@@ -3170,7 +4486,7 @@ fn progress_term_event() {}
 
 #[cfg(test)]
 mod hex_to_color_tests {
-    use super::hex_to_color;
+    use super::{hex_to_color, parse_color};
     use druid::piet::Color;
 
     #[test]
@@ -3240,4 +4556,57 @@ mod hex_to_color_tests {
             Color::rgba8(0xA0, 0x02, 0x0F, 0xFF)
         );
     }
+
+    #[test]
+    pub fn parse_color_for_hex_colors() {
+        assert_eq!(
+            parse_color("#A0020F").unwrap(),
+            Color::rgba8(0xA0, 0x02, 0x0F, 0xFF)
+        );
+        assert_eq!(
+            parse_color(" #11223300 ").unwrap(),
+            Color::rgba8(0x11, 0x22, 0x33, 0x00)
+        );
+    }
+
+    #[test]
+    pub fn parse_color_for_rgb_colors() {
+        assert_eq!(
+            parse_color("rgb(17, 34, 51)").unwrap(),
+            Color::rgba8(17, 34, 51, 255)
+        );
+        assert_eq!(
+            parse_color("rgb(0,255,0)").unwrap(),
+            Color::rgba8(0, 255, 0, 255)
+        );
+        assert!(parse_color("rgb(256, 0, 0)").is_err());
+        assert!(parse_color("rgb(-1, 0, 0)").is_err());
+        assert!(parse_color("rgb(0, 0)").is_err());
+        assert!(parse_color("rgb(0, 0, 0, 0)").is_err());
+        assert!(parse_color("rgb(0, 0, 0").is_err());
+    }
+
+    #[test]
+    pub fn parse_color_for_rgba_colors() {
+        assert_eq!(
+            parse_color("rgba(17, 34, 51, 128)").unwrap(),
+            Color::rgba8(17, 34, 51, 128)
+        );
+        assert_eq!(
+            parse_color("rgba(17, 34, 51, 0.5)").unwrap(),
+            Color::rgba8(17, 34, 51, 128)
+        );
+        assert_eq!(
+            parse_color("rgba(17, 34, 51, 1.0)").unwrap(),
+            Color::rgba8(17, 34, 51, 255)
+        );
+        assert_eq!(
+            parse_color("rgba(17, 34, 51, 25%)").unwrap(),
+            Color::rgba8(17, 34, 51, 64)
+        );
+        assert!(parse_color("rgba(17, 34, 51, 1.5)").is_err());
+        assert!(parse_color("rgba(17, 34, 51, 256)").is_err());
+        assert!(parse_color("rgba(17, 34, 51, 150%)").is_err());
+        assert!(parse_color("rgba(17, 34, 51)").is_err());
+    }
 }
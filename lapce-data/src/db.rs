@@ -4,6 +4,7 @@ use std::{
     path::{Path, PathBuf},
     rc::Rc,
     sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Result};
@@ -14,7 +15,7 @@ use lsp_types::Position;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    buffer::{Buffer, BufferContent},
+    buffer::{Buffer, BufferContent, UndoHistory},
     config::Config,
     data::{
         EditorTabChild, LapceData, LapceEditorData, LapceEditorTabData,
@@ -29,8 +30,15 @@ pub enum SaveEvent {
     Workspace(LapceWorkspace, WorkspaceInfo),
     Tabs(TabsInfo),
     Buffer(BufferInfo),
+    UndoHistory(UndoHistoryInfo),
 }
 
+/// Undo histories bigger than this, serialized, aren't stored
+const MAX_UNDO_HISTORY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Stored undo histories older than this are dropped
+const MAX_UNDO_HISTORY_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
 #[derive(Clone)]
 pub struct LapceDb {
     save_tx: Sender<SaveEvent>,
@@ -88,6 +96,19 @@ pub struct EditorTabInfo {
 }
 
 impl EditorTabInfo {
+    /// Drop the editors of files that no longer exist on disk
+    fn retain_existing_files(&mut self) {
+        self.children.retain(|child| match child {
+            EditorTabChildInfo::Editor(editor_info) => match &editor_info.content {
+                BufferContent::File(path) => path.exists(),
+                _ => true,
+            },
+        });
+        if self.active >= self.children.len() {
+            self.active = self.children.len().saturating_sub(1);
+        }
+    }
+
     pub fn to_data(
         &self,
         data: &mut LapceMainSplitData,
@@ -169,6 +190,31 @@ pub struct SplitInfo {
 }
 
 impl SplitInfo {
+    /// Drop the editors of files that no longer exist on disk, along with
+    /// the editor tabs and splits that are left empty by that
+    pub fn retain_existing_files(&mut self) {
+        self.children = std::mem::take(&mut self.children)
+            .into_iter()
+            .filter_map(|mut child| {
+                let is_empty = match &mut child {
+                    SplitContentInfo::EditorTab(tab_info) => {
+                        tab_info.retain_existing_files();
+                        tab_info.children.is_empty()
+                    }
+                    SplitContentInfo::Split(split_info) => {
+                        split_info.retain_existing_files();
+                        split_info.children.is_empty()
+                    }
+                };
+                if is_empty {
+                    None
+                } else {
+                    Some(child)
+                }
+            })
+            .collect();
+    }
+
     pub fn to_data(
         &self,
         data: &mut LapceMainSplitData,
@@ -230,6 +276,14 @@ pub struct BufferInfo {
     pub cursor_offset: usize,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UndoHistoryInfo {
+    pub path: PathBuf,
+    /// When the history was stored, in seconds since the unix epoch
+    pub saved_at: u64,
+    pub history: UndoHistory,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct EditorInfo {
     pub content: BufferContent,
@@ -322,6 +376,9 @@ impl LapceDb {
                     SaveEvent::Buffer(info) => {
                         let _ = local_db.insert_buffer(&info);
                     }
+                    SaveEvent::UndoHistory(info) => {
+                        let _ = local_db.insert_undo_history(&info);
+                    }
                 }
             }
         });
@@ -424,6 +481,52 @@ impl LapceDb {
         Ok(())
     }
 
+    /// The stored undo history of the file at `path`, if it isn't too old
+    pub fn get_undo_history(&self, path: &Path) -> Result<UndoHistory> {
+        let key = Self::undo_history_key(path);
+        let sled_db = self.get_db()?;
+        let info = sled_db
+            .get(key.as_str())?
+            .ok_or_else(|| anyhow!("can't find undo history"))?;
+        let info = std::str::from_utf8(&info)?;
+        let info: UndoHistoryInfo = serde_json::from_str(info)?;
+        if unix_secs().saturating_sub(info.saved_at) > MAX_UNDO_HISTORY_AGE.as_secs()
+        {
+            sled_db.remove(key.as_str())?;
+            return Err(anyhow!("undo history is too old"));
+        }
+        Ok(info.history)
+    }
+
+    fn insert_undo_history(&self, info: &UndoHistoryInfo) -> Result<()> {
+        let key = Self::undo_history_key(&info.path);
+        let sled_db = self.get_db()?;
+        // Histories that are surely too large aren't serialized at all
+        let history = if info.history.is_empty()
+            || info.history.deleted_len() > MAX_UNDO_HISTORY_SIZE
+        {
+            None
+        } else {
+            Some(serde_json::to_string(info)?)
+                .filter(|history| history.len() <= MAX_UNDO_HISTORY_SIZE)
+        };
+        match history {
+            Some(history) => {
+                sled_db.insert(key.as_str(), history.as_str())?;
+            }
+            None => {
+                // Don't leave an older history behind for the same path
+                sled_db.remove(key.as_str())?;
+            }
+        }
+        sled_db.flush()?;
+        Ok(())
+    }
+
+    fn undo_history_key(path: &Path) -> String {
+        format!("undo:{}", path.to_str().unwrap_or(""))
+    }
+
     fn insert_tabs(&self, info: &TabsInfo) -> Result<()> {
         let tabs_info = serde_json::to_string(info)?;
         let sled_db = self.get_db()?;
@@ -473,6 +576,9 @@ impl LapceDb {
         let workspace_info = data.workspace_info();
 
         self.insert_workspace(&workspace, &workspace_info)?;
+        for buffer in data.main_split.open_files.values() {
+            self.save_undo_history(buffer);
+        }
         Ok(())
     }
 
@@ -497,6 +603,25 @@ impl LapceDb {
         }
     }
 
+    /// Store the undo history of `buffer` so that it can be brought back
+    /// when the file is opened again with the same content
+    pub fn save_undo_history(&self, buffer: &Buffer) {
+        if let Some(info) = Self::undo_history_info(buffer) {
+            let _ = self.save_tx.send(SaveEvent::UndoHistory(info));
+        }
+    }
+
+    fn undo_history_info(buffer: &Buffer) -> Option<UndoHistoryInfo> {
+        match &buffer.content {
+            BufferContent::File(path) if buffer.loaded => Some(UndoHistoryInfo {
+                path: path.clone(),
+                saved_at: unix_secs(),
+                history: buffer.undo_history(),
+            }),
+            _ => None,
+        }
+    }
+
     pub fn get_tabs_info(&self) -> Result<TabsInfo> {
         let sled_db = self.get_db()?;
         let tabs = sled_db
@@ -529,3 +654,10 @@ impl LapceDb {
         Ok(())
     }
 }
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
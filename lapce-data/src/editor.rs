@@ -1,25 +1,34 @@
 use crate::buffer::get_word_property;
 use crate::buffer::matching_char;
 use crate::buffer::{
-    has_unmatched_pair, BufferContent, DiffLines, EditType, LocalBufferKind,
+    has_unmatched_pair, history_file, BufferContent, DiffLines, EditType,
+    LocalBufferKind,
+};
+use crate::buffer::{
+    linewise_range, matching_pair_direction, whitespace_cleanup_edits, Buffer,
+    TextStats,
 };
-use crate::buffer::{matching_pair_direction, Buffer};
 use crate::command::CommandExecuted;
 use crate::command::CommandTarget;
 use crate::command::LapceCommandNew;
 use crate::command::LAPCE_NEW_COMMAND;
 use crate::completion::{CompletionData, CompletionStatus, Snippet};
-use crate::config::Config;
+use crate::config::{Config, EditorConfig};
 use crate::data::MotionMode;
 use crate::data::RegisterKind;
 use crate::data::{
-    EditorDiagnostic, InlineFindDirection, LapceEditorData, LapceMainSplitData,
-    RegisterData, SplitContent,
+    DiffSide, EditorDiagnostic, InlineFindDirection, LapceEditorData,
+    LapceMainSplitData, RegisterData, SplitContent, SurroundAction,
 };
+use crate::folding::fold_range_at;
 use crate::hover::HoverData;
 use crate::hover::HoverStatus;
-use crate::movement::InsertDrift;
+use crate::movement::{ColPosition, InsertDrift, LinePosition};
+use crate::primary_selection;
 use crate::proxy::path_from_url;
+use crate::reflow::{line_prefix, paragraph_key, reflow};
+use crate::signature::{SignatureData, SignatureStatus};
+use crate::wrap::WrapLayout;
 use crate::{buffer::WordProperty, movement::CursorMode};
 use crate::{
     command::{LapceCommand, LapceUICommand, LAPCE_UI_COMMAND},
@@ -41,8 +50,9 @@ use druid::{
     WidgetId,
 };
 use druid::{Application, ExtEventSink, MouseEvent};
+use lapce_core::indent::{dedent, new_line_indent};
 pub use lapce_core::syntax::Syntax;
-use lapce_rpc::buffer::BufferId;
+use lapce_rpc::buffer::{BufferId, LineEnding};
 use lsp_types::CompletionTextEdit;
 use lsp_types::{
     CodeActionResponse, CompletionItem, DiagnosticSeverity, GotoDefinitionResponse,
@@ -50,13 +60,13 @@ use lsp_types::{
 };
 use serde_json::Value;
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
+use std::sync::Arc;
 use std::thread;
-use std::{collections::HashMap, sync::Arc};
 use std::{iter::Iterator, path::PathBuf};
 use std::{str::FromStr, time::Duration};
-use xi_rope::{RopeDelta, Transformer};
+use xi_rope::{Delta, Interval, Rope, RopeDelta, Transformer};
 
 pub struct LapceUI {}
 
@@ -69,6 +79,15 @@ pub enum EditorOperator {
     Yank(EditorCount),
 }
 
+/// What a mouse drag extends the selection by, which depends on the number
+/// of clicks that started it.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum DragSelect {
+    Char,
+    Word,
+    Line,
+}
+
 #[derive(Clone)]
 pub struct EditorUIState {
     pub buffer_id: BufferId,
@@ -127,6 +146,7 @@ pub struct LapceEditorBufferData {
     pub buffer: Arc<Buffer>,
     pub completion: Arc<CompletionData>,
     pub hover: Arc<HoverData>,
+    pub signature: Arc<SignatureData>,
     pub main_split: LapceMainSplitData,
     pub source_control: Arc<SourceControlData>,
     pub find: Arc<Find>,
@@ -139,6 +159,16 @@ impl LapceEditorBufferData {
         Arc::make_mut(&mut self.buffer)
     }
 
+    /// The buffer can't be edited from this editor, either because it's read
+    /// only or because the editor is the left side of a diff view
+    fn is_read_only(&self) -> bool {
+        self.buffer.read_only
+            || matches!(
+                self.editor.diff_view.as_ref(),
+                Some(diff_view) if diff_view.side == DiffSide::Left
+            )
+    }
+
     pub fn sync_buffer_position(&mut self, scroll_offset: Vec2) {
         let cursor_offset = self.editor.cursor.offset();
         if self.buffer.cursor_offset != cursor_offset
@@ -150,35 +180,90 @@ impl LapceEditorBufferData {
         }
     }
 
-    fn inline_find(&mut self, direction: InlineFindDirection, c: &str) {
+    /// Put `open` and `close` around each region of the selection, which
+    /// stays selected between them
+    fn wrap_selection(&mut self, selection: &Selection, open: char, close: char) {
+        let edits = wrap_selection_edits(selection, open, close);
+        let edits: Vec<(&Selection, &str)> = edits
+            .iter()
+            .map(|(selection, content)| (selection, content.as_str()))
+            .collect();
+        self.edit(&edits, true, EditType::InsertChars);
+        let selection = selection_in_wrap(selection, open, close);
+        self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
+    }
+
+    /// Wrap in, take away or replace the pair of `c` in a single edit, and
+    /// put the cursor on where the opening half of the pair is
+    fn surround(&mut self, action: &SurroundAction, c: char) {
+        if self.is_read_only() {
+            return;
+        }
+        if let Some((edits, offset)) = surround_edits(
+            &self.buffer,
+            &self.editor.cursor,
+            action,
+            c,
+            self.config.editor.tab_width,
+            &self.config.editor.word_chars,
+        ) {
+            let edits: Vec<(&Selection, &str)> = edits
+                .iter()
+                .map(|(selection, content)| (selection, content.as_str()))
+                .collect();
+            // Leave visual mode first, the selection isn't deleted so it
+            // shouldn't end up in the register
+            let cursor_offset = self.editor.cursor.offset();
+            Arc::make_mut(&mut self.editor).cursor.mode =
+                CursorMode::Normal(cursor_offset);
+            self.edit(&edits, true, EditType::Other);
+            self.set_cursor(Cursor::new(CursorMode::Normal(offset), None));
+        }
+    }
+
+    /// Move to the `count`th occurrence of the character on the current
+    /// line, the cursor stays where it is if there aren't that many
+    fn inline_find(
+        &mut self,
+        direction: InlineFindDirection,
+        c: &str,
+        count: usize,
+    ) {
         let offset = self.editor.cursor.offset();
         let line = self.buffer.line_of_offset(offset);
         let line_content = self.buffer.line_content(line);
         let line_start_offset = self.buffer.offset_of_line(line);
-        let index = offset - line_start_offset;
-        if let Some(new_index) = match direction {
-            InlineFindDirection::Left => line_content[..index].rfind(c),
-            InlineFindDirection::Right => {
-                if index + 1 >= line_content.len() {
-                    None
-                } else {
-                    let index = index
-                        + self.buffer.next_grapheme_offset(
-                            offset,
-                            1,
-                            self.buffer.offset_line_end(offset, false),
-                        )
-                        - offset;
-                    line_content[index..].find(c).map(|i| i + index)
+        let line_end_offset = self.buffer.offset_line_end(offset, false);
+        let mut index = offset - line_start_offset;
+        for _ in 0..count.max(1) {
+            let new_index = match direction {
+                InlineFindDirection::Left => line_content[..index].rfind(c),
+                InlineFindDirection::Right => {
+                    if index + 1 >= line_content.len() {
+                        None
+                    } else {
+                        let offset = line_start_offset + index;
+                        let index = index
+                            + self.buffer.next_grapheme_offset(
+                                offset,
+                                1,
+                                line_end_offset,
+                            )
+                            - offset;
+                        line_content[index..].find(c).map(|i| i + index)
+                    }
                 }
+            };
+            match new_index {
+                Some(new_index) => index = new_index,
+                None => return,
             }
-        } {
-            self.do_move(
-                &Movement::Offset(new_index + line_start_offset),
-                1,
-                Modifiers::empty(),
-            );
         }
+        self.do_move(
+            &Movement::Offset(index + line_start_offset),
+            1,
+            Modifiers::empty(),
+        );
     }
 
     pub fn get_code_actions(&self, ctx: &mut EventCtx) {
@@ -191,7 +276,7 @@ impl LapceEditorBufferData {
         if let BufferContent::File(path) = &self.buffer.content {
             let path = path.clone();
             let offset = self.editor.cursor.offset();
-            let prev_offset = self.buffer.prev_code_boundary(offset);
+            let prev_offset = self.buffer.code_action_offset(offset);
             if self.buffer.code_actions.get(&prev_offset).is_none() {
                 let buffer_id = self.buffer.id;
                 let position = self
@@ -225,15 +310,26 @@ impl LapceEditorBufferData {
         }
     }
 
-    fn set_motion_mode(&mut self, mode: MotionMode) {
+    fn set_motion_mode(&mut self, mode: MotionMode, count: Option<usize>) {
         if let Some(m) = &self.editor.motion_mode {
             if m == &mode {
+                // Repeating the operator, like `dd`, acts on whole lines, and
+                // the count can be given before either of the keys
+                let count =
+                    count.unwrap_or(1) * self.editor.motion_mode_count.unwrap_or(1);
                 let offset = self.editor.cursor.offset();
-                self.execute_motion_mode(offset, offset, true);
+                let line = self.buffer.line_of_offset(offset);
+                let end_line = (line + count - 1).min(self.buffer.last_line());
+                let end = self.buffer.offset_of_line(end_line);
+                self.execute_motion_mode(offset, end, true);
             }
-            Arc::make_mut(&mut self.editor).motion_mode = None;
+            let editor = Arc::make_mut(&mut self.editor);
+            editor.motion_mode = None;
+            editor.motion_mode_count = None;
         } else {
-            Arc::make_mut(&mut self.editor).motion_mode = Some(mode);
+            let editor = Arc::make_mut(&mut self.editor);
+            editor.motion_mode = Some(mode);
+            editor.motion_mode_count = count;
         }
     }
 
@@ -276,18 +372,103 @@ impl LapceEditorBufferData {
         register.add(kind, data);
     }
 
+    /// Delete from each caret to the end of its line, or the line break when
+    /// the caret is already there, and the selected text of the other
+    /// regions, putting what was deleted on the clipboard like emacs' kill
+    fn kill_line(&mut self, mut selection: Selection) {
+        for region in selection.regions_mut() {
+            if region.is_caret() {
+                let line_end = self.buffer.offset_line_end(region.start, true);
+                region.end = if region.start < line_end {
+                    line_end
+                } else {
+                    let line = self.buffer.line_of_offset(region.start);
+                    self.buffer.offset_of_line(line + 1)
+                };
+            }
+        }
+        selection.merge_overlapping();
+        let killed: Vec<String> = selection
+            .regions()
+            .iter()
+            .map(|region| {
+                self.buffer
+                    .slice_to_cow(region.min()..region.max())
+                    .to_string()
+            })
+            .collect();
+        if killed.iter().all(|s| s.is_empty()) {
+            return;
+        }
+        Application::global()
+            .clipboard()
+            .put_string(killed.join("\n"));
+        let delta = self.edit(&[(&selection, "")], true, EditType::Delete);
+        let selection = selection.apply_delta(&delta, true, InsertDrift::Default);
+        self.set_cursor_after_change(selection);
+    }
+
+    /// The end of the line `count - 1` lines below the offset, which is
+    /// where `D`, `C` and `Y` act up to
+    fn line_end_after(&self, offset: usize, count: usize) -> usize {
+        let line = self.buffer.line_of_offset(offset);
+        let line = (line + count.max(1) - 1).min(self.buffer.last_line());
+        self.buffer
+            .move_offset(
+                self.buffer.offset_of_line(line),
+                None,
+                1,
+                &Movement::EndOfLine,
+                Mode::Insert,
+                self.editor.code_lens,
+                self.editor.compare.clone(),
+                &self.config,
+            )
+            .0
+    }
+
     fn execute_motion_mode(&mut self, start: usize, end: usize, is_vertical: bool) {
-        if let Some(mode) = &self.editor.motion_mode {
+        if let Some(mode) = self.editor.motion_mode.clone() {
+            if is_vertical
+                && matches!(
+                    mode,
+                    MotionMode::Delete | MotionMode::Yank | MotionMode::Change
+                )
+            {
+                let start_line = self.buffer.line_of_offset(start.min(end));
+                let end_line = self.buffer.line_of_offset(start.max(end));
+                self.execute_linewise_motion_mode(
+                    &mode,
+                    start_line,
+                    end_line - start_line + 1,
+                );
+                return;
+            }
             match mode {
                 MotionMode::Delete => {
                     let (start, end) =
                         self.format_start_end(start, end, is_vertical);
-                    self.add_register(start, end, is_vertical, RegisterKind::Yank);
+                    self.add_register(start, end, is_vertical, RegisterKind::Delete);
                     let selection = Selection::region(start, end);
                     let delta =
                         self.edit(&[(&selection, "")], true, EditType::Delete);
                     Arc::make_mut(&mut self.editor).cursor.apply_delta(&delta);
                 }
+                MotionMode::Change => {
+                    let (start, end) =
+                        self.format_start_end(start, end, is_vertical);
+                    self.add_register(start, end, is_vertical, RegisterKind::Delete);
+                    let selection = Selection::region(start, end);
+                    let delta =
+                        self.edit(&[(&selection, "")], true, EditType::Delete);
+                    let selection =
+                        selection.apply_delta(&delta, true, InsertDrift::Default);
+                    self.buffer_mut().update_edit_type();
+                    self.set_cursor(Cursor::new(
+                        CursorMode::Insert(selection),
+                        None,
+                    ));
+                }
                 MotionMode::Yank => {
                     let (start, end) =
                         self.format_start_end(start, end, is_vertical);
@@ -305,6 +486,37 @@ impl LapceEditorBufferData {
         }
     }
 
+    /// Run `dd`, `yy` or `cc` on `count` lines starting at `line`, the lines
+    /// go into the register linewise so that they are pasted as whole lines
+    fn execute_linewise_motion_mode(
+        &mut self,
+        mode: &MotionMode,
+        line: usize,
+        count: usize,
+    ) {
+        let edit = match LinewiseEdit::new(&self.buffer, mode, line, count) {
+            Some(edit) => edit,
+            None => return,
+        };
+        let register = Arc::make_mut(&mut self.main_split.register);
+        let selection = match &edit.selection {
+            Some(selection) => {
+                register.add_delete(edit.data.clone());
+                selection
+            }
+            None => {
+                register.add_yank(edit.data.clone());
+                return;
+            }
+        };
+        let delta = self.edit(&[(selection, "")], true, EditType::Delete);
+        let selection = selection.apply_delta(&delta, true, InsertDrift::Default);
+        if mode == &MotionMode::Change {
+            self.buffer_mut().update_edit_type();
+        }
+        self.set_cursor(edit.cursor(&self.buffer, selection));
+    }
+
     fn do_move(&mut self, movement: &Movement, count: usize, mods: Modifiers) {
         if movement.is_jump() && movement != &self.editor.last_movement {
             let editor = Arc::make_mut(&mut self.editor);
@@ -314,8 +526,8 @@ impl LapceEditorBufferData {
         editor.last_movement = movement.clone();
         let compare = editor.compare.clone();
         match &self.editor.cursor.mode {
-            &CursorMode::Normal(offset) => {
-                let (new_offset, horiz) = self.buffer.move_offset(
+            &CursorMode::Normal(offset) if self.editor.motion_mode.is_some() => {
+                let (new_offset, _) = self.buffer.move_offset(
                     offset,
                     self.editor.cursor.horiz.as_ref(),
                     count,
@@ -326,9 +538,22 @@ impl LapceEditorBufferData {
                     &self.config,
                 );
 
-                if self.editor.motion_mode.is_some() {
-                    let (start, end) = match movement {
-                        Movement::EndOfLine | Movement::WordEndForward => {
+                let (start, end) = match movement {
+                    Movement::EndOfLine | Movement::WordEndForward => {
+                        let (end, _) = self.buffer.move_offset(
+                            new_offset,
+                            None,
+                            1,
+                            &Movement::Right,
+                            Mode::Insert,
+                            false,
+                            None,
+                            &self.config,
+                        );
+                        (offset, end)
+                    }
+                    Movement::MatchPairs => {
+                        if new_offset > offset {
                             let (end, _) = self.buffer.move_offset(
                                 new_offset,
                                 None,
@@ -340,76 +565,47 @@ impl LapceEditorBufferData {
                                 &self.config,
                             );
                             (offset, end)
+                        } else {
+                            let (start, _) = self.buffer.move_offset(
+                                offset,
+                                None,
+                                1,
+                                &Movement::Right,
+                                Mode::Insert,
+                                false,
+                                None,
+                                &self.config,
+                            );
+                            (start, new_offset)
                         }
-                        Movement::MatchPairs => {
-                            if new_offset > offset {
-                                let (end, _) = self.buffer.move_offset(
-                                    new_offset,
-                                    None,
-                                    1,
-                                    &Movement::Right,
-                                    Mode::Insert,
-                                    false,
-                                    None,
-                                    &self.config,
-                                );
-                                (offset, end)
-                            } else {
-                                let (start, _) = self.buffer.move_offset(
-                                    offset,
-                                    None,
-                                    1,
-                                    &Movement::Right,
-                                    Mode::Insert,
-                                    false,
-                                    None,
-                                    &self.config,
-                                );
-                                (start, new_offset)
-                            }
-                        }
-                        _ => (offset, new_offset),
-                    };
-                    self.execute_motion_mode(start, end, movement.is_vertical());
-                } else {
-                    let editor = Arc::make_mut(&mut self.editor);
-                    editor.cursor.mode = CursorMode::Normal(new_offset);
-                    editor.cursor.horiz = Some(horiz);
-                }
+                    }
+                    _ => (offset, new_offset),
+                };
+                self.execute_motion_mode(start, end, movement.is_vertical());
             }
-            CursorMode::Visual { start, end, mode } => {
-                let (new_offset, horiz) = self.buffer.move_offset(
-                    *end,
-                    self.editor.cursor.horiz.as_ref(),
-                    count,
+            CursorMode::Insert(_) => {
+                let cursor = self.editor.cursor.moved(
+                    &self.buffer,
                     movement,
-                    Mode::Visual,
+                    count,
+                    mods.shift(),
                     self.editor.code_lens,
                     compare,
                     &self.config,
                 );
-                let start = *start;
-                let mode = *mode;
-                let editor = Arc::make_mut(&mut self.editor);
-                editor.cursor.mode = CursorMode::Visual {
-                    start,
-                    end: new_offset,
-                    mode,
-                };
-                editor.cursor.horiz = Some(horiz);
+                self.set_cursor(cursor);
             }
-            CursorMode::Insert(selection) => {
-                let selection = self.buffer.update_selection(
-                    selection,
-                    count,
+            _ => {
+                let cursor = self.editor.cursor.moved(
+                    &self.buffer,
                     movement,
-                    Mode::Insert,
-                    mods.shift(),
+                    count,
+                    false,
                     self.editor.code_lens,
                     compare,
                     &self.config,
                 );
-                self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
+                Arc::make_mut(&mut self.editor).cursor = cursor;
             }
         }
     }
@@ -518,7 +714,14 @@ impl LapceEditorBufferData {
             if view_id != &self.editor.view_id
                 && self.buffer.content == editor.content
             {
-                Arc::make_mut(editor).cursor.apply_delta(delta);
+                let editor = Arc::make_mut(editor);
+                editor.cursor.apply_delta(delta);
+                if let Some((selection, mode)) = editor.last_visual.take() {
+                    let selection =
+                        selection.apply_delta(delta, false, InsertDrift::Default);
+                    editor.last_visual = Some((selection, mode));
+                }
+                editor.apply_delta_to_folds(delta);
             }
         }
     }
@@ -567,8 +770,12 @@ impl LapceEditorBufferData {
             match edit {
                 CompletionTextEdit::Edit(edit) => {
                     let offset = self.editor.cursor.offset();
-                    let start_offset = self.buffer.prev_code_boundary(offset);
-                    let end_offset = self.buffer.next_code_boundary(offset);
+                    let start_offset = self
+                        .buffer
+                        .prev_code_boundary(offset, &self.config.editor.word_chars);
+                    let end_offset = self
+                        .buffer
+                        .next_code_boundary(offset, &self.config.editor.word_chars);
                     let edit_start = self.buffer.offset_of_position(
                         &edit.range.start,
                         self.config.editor.tab_width,
@@ -647,8 +854,12 @@ impl LapceEditorBufferData {
         }
 
         let offset = self.editor.cursor.offset();
-        let start_offset = self.buffer.prev_code_boundary(offset);
-        let end_offset = self.buffer.next_code_boundary(offset);
+        let start_offset = self
+            .buffer
+            .prev_code_boundary(offset, &self.config.editor.word_chars);
+        let end_offset = self
+            .buffer
+            .next_code_boundary(offset, &self.config.editor.word_chars);
         let selection = Selection::region(start_offset, end_offset);
 
         let delta = self.edit(
@@ -670,6 +881,58 @@ impl LapceEditorBufferData {
         Ok(())
     }
 
+    /// Put a caret right after the block on every line of a blockwise
+    /// visual selection and enter insert mode, so that the typed text is
+    /// appended to all of them. Lines shorter than the block are padded
+    /// with spaces, unless the block extends to the end of the lines.
+    fn append_block(&mut self, start: usize, end: usize) {
+        let tab_width = self.config.editor.tab_width;
+        let (start_line, start_col) =
+            self.buffer.offset_to_line_col(start.min(end), tab_width);
+        let (end_line, end_col) =
+            self.buffer.offset_to_line_col(start.max(end), tab_width);
+        let right = start_col.max(end_col) + 1;
+        let to_end = matches!(self.editor.cursor.horiz, Some(ColPosition::End));
+
+        self.buffer_mut().update_edit_type();
+        self.set_cursor(Cursor::new(
+            CursorMode::Insert(Selection::caret(start.min(end))),
+            None,
+        ));
+
+        if !to_end {
+            let paddings: Vec<(Selection, String)> = (start_line..end_line + 1)
+                .filter_map(|line| {
+                    let max_col = self.buffer.line_end_col(line, true, tab_width);
+                    if max_col < right {
+                        let offset = self.buffer.line_end_offset(line, true);
+                        Some((Selection::caret(offset), " ".repeat(right - max_col)))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            if !paddings.is_empty() {
+                let edits: Vec<(&Selection, &str)> = paddings
+                    .iter()
+                    .map(|(selection, content)| (selection, content.as_str()))
+                    .collect();
+                self.edit(&edits, true, EditType::InsertChars);
+            }
+        }
+
+        let mut selection = Selection::new();
+        for line in start_line..end_line + 1 {
+            let offset = if to_end {
+                self.buffer.line_end_offset(line, true)
+            } else {
+                self.buffer.offset_of_line_col(line, right, tab_width)
+            };
+            selection.add_region(SelRegion::caret(offset));
+        }
+        self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
+    }
+
     pub fn cancel_completion(&mut self) {
         let completion = Arc::make_mut(&mut self.completion);
         completion.cancel();
@@ -680,7 +943,53 @@ impl LapceEditorBufferData {
         hover.cancel();
     }
 
+    pub fn cancel_signature(&mut self) {
+        let signature = Arc::make_mut(&mut self.signature);
+        signature.cancel();
+    }
+
+    /// Request signature help for the function call the cursor is in
+    fn update_signature(&mut self, ctx: &mut EventCtx) {
+        if self.get_mode() != Mode::Insert {
+            return;
+        }
+        if !self.buffer.loaded || self.buffer.local {
+            return;
+        }
+        if !matches!(self.buffer.content, BufferContent::File(_)) {
+            return;
+        }
+
+        let offset = self.editor.cursor.offset();
+        let position = self
+            .buffer
+            .offset_to_position(offset, self.config.editor.tab_width);
+
+        let signature = Arc::make_mut(&mut self.signature);
+        signature.buffer_id = self.buffer.id;
+        signature.offset = offset;
+        signature.status = SignatureStatus::Started;
+        signature.request_id += 1;
+
+        let event_sink = ctx.get_external_handle();
+        signature.request(
+            self.proxy.clone(),
+            signature.request_id,
+            self.buffer.id,
+            position,
+            signature.id,
+            event_sink,
+        );
+    }
+
     fn update_completion(&mut self, ctx: &mut EventCtx) {
+        self.request_completion(ctx, false);
+    }
+
+    /// Request completion for the word at the cursor. Typing only requests
+    /// it after a word character or a trigger character, and keeps the items
+    /// of the word being typed, a `force`d request always starts over.
+    fn request_completion(&mut self, ctx: &mut EventCtx, force: bool) {
         if self.get_mode() != Mode::Insert {
             return;
         }
@@ -691,8 +1000,12 @@ impl LapceEditorBufferData {
             return;
         }
         let offset = self.editor.cursor.offset();
-        let start_offset = self.buffer.prev_code_boundary(offset);
-        let end_offset = self.buffer.next_code_boundary(offset);
+        let start_offset = self
+            .buffer
+            .prev_code_boundary(offset, &self.config.editor.word_chars);
+        let end_offset = self
+            .buffer
+            .next_code_boundary(offset, &self.config.editor.word_chars);
         let input = self
             .buffer
             .slice_to_cow(start_offset..end_offset)
@@ -705,7 +1018,9 @@ impl LapceEditorBufferData {
                 .to_string()
         };
         let completion = Arc::make_mut(&mut self.completion);
-        if input.is_empty() && char != "." && char != ":" {
+        if force {
+            completion.cancel();
+        } else if input.is_empty() && char != "." && char != ":" {
             completion.cancel();
             return;
         }
@@ -714,6 +1029,9 @@ impl LapceEditorBufferData {
             && completion.offset == start_offset
             && completion.buffer_id == self.buffer.id
         {
+            if completion.is_incomplete {
+                completion.input_items.remove(&input);
+            }
             completion.update_input(input.clone());
 
             if !completion.input_items.contains_key("") {
@@ -754,6 +1072,7 @@ impl LapceEditorBufferData {
         completion.input = input.clone();
         completion.status = CompletionStatus::Started;
         completion.input_items.clear();
+        completion.is_incomplete = false;
         completion.request_id += 1;
         let event_sink = ctx.get_external_handle();
         completion.request(
@@ -789,8 +1108,12 @@ impl LapceEditorBufferData {
             return;
         }
 
-        let start_offset = self.buffer.prev_code_boundary(offset);
-        let end_offset = self.buffer.next_code_boundary(offset);
+        let start_offset = self
+            .buffer
+            .prev_code_boundary(offset, &self.config.editor.word_chars);
+        let end_offset = self
+            .buffer
+            .next_code_boundary(offset, &self.config.editor.word_chars);
         let input = self.buffer.slice_to_cow(start_offset..end_offset);
         if input.trim().is_empty() {
             return;
@@ -828,41 +1151,9 @@ impl LapceEditorBufferData {
         let tab_id = *self.main_split.tab_id;
         ctx.submit_command(Command::new(
             LAPCE_UI_COMMAND,
-            LapceUICommand::UpdateSearch(pattern.to_string()),
+            LapceUICommand::UpdateSearch(pattern),
             Target::Widget(tab_id),
         ));
-        if pattern.is_empty() {
-            ctx.submit_command(Command::new(
-                LAPCE_UI_COMMAND,
-                LapceUICommand::GlobalSearchResult(
-                    pattern,
-                    Arc::new(HashMap::new()),
-                ),
-                Target::Widget(tab_id),
-            ));
-        } else {
-            let event_sink = ctx.get_external_handle();
-            self.proxy.global_search(
-                pattern.clone(),
-                Box::new(move |result| {
-                    if let Ok(matches) = result {
-                        if let Ok(matches) = serde_json::from_value::<
-                            HashMap<PathBuf, Vec<(usize, (usize, usize), String)>>,
-                        >(matches)
-                        {
-                            let _ = event_sink.submit_command(
-                                LAPCE_UI_COMMAND,
-                                LapceUICommand::GlobalSearchResult(
-                                    pattern,
-                                    Arc::new(matches),
-                                ),
-                                Target::Widget(tab_id),
-                            );
-                        }
-                    }
-                }),
-            )
-        }
     }
 
     fn insert_tab(&mut self) {
@@ -919,7 +1210,7 @@ impl LapceEditorBufferData {
 
     fn insert_new_line(&mut self, ctx: &mut EventCtx, selection: Selection) {
         match &self.buffer.content {
-            BufferContent::File(_) => {}
+            BufferContent::File(_) | BufferContent::Scratch(..) => {}
             BufferContent::Value(_name) => {
                 return;
             }
@@ -977,207 +1268,44 @@ impl LapceEditorBufferData {
             },
         }
 
-        let mut edits = Vec::new();
-        let mut extra_edits = Vec::new();
-        let mut shift = 0i32;
-        for region in selection.regions() {
-            let offset = region.max();
-            let line = self.buffer.line_of_offset(offset);
-            let line_start = self.buffer.offset_of_line(line);
-            let line_end = self.buffer.line_end_offset(line, true);
-            let line_indent = self.buffer.indent_on_line(line);
-            let first_half =
-                self.buffer.slice_to_cow(line_start..offset).to_string();
-            let second_half = self.buffer.slice_to_cow(offset..line_end).to_string();
-
-            let indent = if has_unmatched_pair(&first_half) {
-                format!("{}    ", line_indent)
-            } else {
-                let next_line_indent = self.buffer.indent_on_line(line + 1);
-                if next_line_indent.len() > line_indent.len() {
-                    next_line_indent
-                } else {
-                    line_indent.clone()
-                }
-            };
-
-            let selection = Selection::region(region.min(), region.max());
-            let content = format!("{}{}", "\n", indent);
-
-            shift -= (region.max() - region.min()) as i32;
-            shift += content.len() as i32;
-
-            edits.push((selection, content));
-
-            for c in first_half.chars().rev() {
-                if c != ' ' {
-                    if let Some(pair_start) = matching_pair_direction(c) {
-                        if pair_start {
-                            if let Some(c) = matching_char(c) {
-                                if second_half.trim().starts_with(&c.to_string()) {
-                                    let selection = Selection::caret(
-                                        (region.max() as i32 + shift) as usize,
-                                    );
-                                    let content = format!("{}{}", "\n", line_indent);
-                                    extra_edits.push((selection.clone(), content));
-                                }
-                            }
-                        }
-                    }
-                    break;
-                }
-            }
-        }
-
+        let (edits, new_selection) = new_line_edits(&self.buffer, &selection);
         let edits = edits
             .iter()
             .map(|(selection, s)| (selection, s.as_str()))
             .collect::<Vec<(&Selection, &str)>>();
-        let delta = self.edit(&edits, true, EditType::InsertNewline);
-        let mut selection =
-            selection.apply_delta(&delta, true, InsertDrift::Default);
-
-        if !extra_edits.is_empty() {
-            let edits = extra_edits
-                .iter()
-                .map(|(selection, s)| (selection, s.as_str()))
-                .collect::<Vec<(&Selection, &str)>>();
-            let delta = self.edit(&edits, true, EditType::InsertNewline);
-            selection = selection.apply_delta(&delta, false, InsertDrift::Default);
-        }
+        self.edit(&edits, true, EditType::InsertNewline);
 
         let editor = Arc::make_mut(&mut self.editor);
-        editor.cursor.mode = CursorMode::Insert(selection);
+        editor.cursor.mode = CursorMode::Insert(new_selection);
         editor.cursor.horiz = None;
     }
 
     fn set_cursor_after_change(&mut self, selection: Selection) {
-        match self.editor.cursor.mode {
-            CursorMode::Normal(_) | CursorMode::Visual { .. } => {
-                let offset = selection.min_offset();
-                let offset = self.buffer.offset_line_end(offset, false).min(offset);
-                self.set_cursor(Cursor::new(CursorMode::Normal(offset), None));
-            }
-            CursorMode::Insert(_) => {
-                self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
-            }
-        }
+        let cursor = self.editor.cursor.after_change(&self.buffer, selection);
+        self.set_cursor(cursor);
     }
 
     fn paste(&mut self, ctx: &mut EventCtx, data: &RegisterData) {
-        match data.mode {
-            VisualMode::Normal => {
-                Arc::make_mut(&mut self.editor).snippet = None;
-                let selection = match self.editor.cursor.mode {
-                    CursorMode::Normal(offset) => {
-                        let line_end = self.buffer.offset_line_end(offset, true);
-                        let offset = (offset + 1).min(line_end);
-                        Selection::caret(offset)
-                    }
-                    CursorMode::Insert { .. } | CursorMode::Visual { .. } => self
-                        .editor
-                        .cursor
-                        .edit_selection(&self.buffer, self.config.editor.tab_width),
-                };
-                let after =
-                    self.editor.cursor.is_insert() || !data.content.contains('\n');
-                let delta = self.edit(
-                    &[(&selection, &data.content)],
-                    after,
-                    EditType::InsertChars,
-                );
-                let selection =
-                    selection.apply_delta(&delta, after, InsertDrift::Default);
-                if !after {
-                    self.set_cursor_after_change(selection);
-                } else {
-                    match self.editor.cursor.mode {
-                        CursorMode::Normal(_) | CursorMode::Visual { .. } => {
-                            let offset = self.buffer.prev_grapheme_offset(
-                                selection.min_offset(),
-                                1,
-                                0,
-                            );
-                            self.set_cursor(Cursor::new(
-                                CursorMode::Normal(offset),
-                                None,
-                            ));
-                        }
-                        CursorMode::Insert { .. } => {
-                            self.set_cursor(Cursor::new(
-                                CursorMode::Insert(selection),
-                                None,
-                            ));
-                        }
-                    }
-                }
-            }
-            VisualMode::Linewise | VisualMode::Blockwise => {
-                let (selection, content) = match &self.editor.cursor.mode {
-                    CursorMode::Normal(offset) => {
-                        let line = self.buffer.line_of_offset(*offset);
-                        let offset = self.buffer.offset_of_line(line + 1);
-                        (Selection::caret(offset), data.content.clone())
-                    }
-                    CursorMode::Insert(selection) => {
-                        let mut selection = selection.clone();
-                        for region in selection.regions_mut() {
-                            if region.is_caret() {
-                                let line = self.buffer.line_of_offset(region.start);
-                                let start = self.buffer.offset_of_line(line);
-                                region.start = start;
-                                region.end = start;
-                            }
-                        }
-                        (selection, data.content.clone())
-                    }
-                    CursorMode::Visual { mode, .. } => {
-                        let selection = self.editor.cursor.edit_selection(
-                            &self.buffer,
-                            self.config.editor.tab_width,
-                        );
-                        let data = match mode {
-                            VisualMode::Linewise => data.content.clone(),
-                            _ => "\n".to_string() + &data.content,
-                        };
-                        (selection, data)
-                    }
-                };
-                let delta = self.edit(
-                    &[(&selection, &content)],
-                    self.editor.cursor.is_insert(),
-                    EditType::InsertChars,
-                );
-                let selection = selection.apply_delta(
-                    &delta,
-                    self.editor.cursor.is_insert(),
-                    InsertDrift::Default,
-                );
-                match self.editor.cursor.mode {
-                    CursorMode::Normal(_) | CursorMode::Visual { .. } => {
-                        let offset = selection.min_offset();
-                        let offset = if self.editor.cursor.is_visual() {
-                            offset + 1
-                        } else {
-                            offset
-                        };
-                        let line = self.buffer.line_of_offset(offset);
-                        let offset =
-                            self.buffer.first_non_blank_character_on_line(line);
-                        self.set_cursor(Cursor::new(
-                            CursorMode::Normal(offset),
-                            None,
-                        ));
-                    }
-                    CursorMode::Insert(_) => {
-                        self.set_cursor(Cursor::new(
-                            CursorMode::Insert(selection),
-                            None,
-                        ));
-                    }
-                }
-            }
+        if data.mode == VisualMode::Normal {
+            Arc::make_mut(&mut self.editor).snippet = None;
         }
+        let paste = PasteEdit::new(
+            &self.buffer,
+            &self.editor.cursor,
+            data,
+            self.config.editor.tab_width,
+        );
+        let delta = self.edit(
+            &[(&paste.selection, &paste.content)],
+            paste.after,
+            EditType::InsertChars,
+        );
+        let selection =
+            paste
+                .selection
+                .apply_delta(&delta, paste.after, InsertDrift::Default);
+        let cursor = paste.cursor(&self.buffer, &self.editor.cursor, selection);
+        self.set_cursor(cursor);
 
         self.update_completion(ctx);
     }
@@ -1296,6 +1424,14 @@ impl LapceEditorBufferData {
         _after: bool,
         edit_type: EditType,
     ) -> RopeDelta {
+        if self.buffer.read_only {
+            return Delta::simple_edit(
+                Interval::new(0, 0),
+                Rope::from(""),
+                self.buffer.len(),
+            );
+        }
+
         match &self.editor.cursor.mode {
             CursorMode::Normal(_) => {}
             #[allow(unused_variables)]
@@ -1311,11 +1447,23 @@ impl LapceEditorBufferData {
         }
 
         self.initiate_diagnositcs_offset();
+        self.save_last_visual();
 
+        let cursor = match &self.editor.cursor.mode {
+            CursorMode::Normal(offset) => Selection::caret(*offset),
+            CursorMode::Visual { start, end, .. } => Selection::region(*start, *end),
+            CursorMode::Insert(selection) => selection.clone(),
+        };
         let proxy = self.proxy.clone();
         let buffer = self.buffer_mut();
-        let delta = buffer.edit_multiple(edits, proxy, edit_type);
+        let delta = buffer.edit_multiple(edits, proxy, edit_type, Some(&cursor));
         self.inactive_apply_delta(&delta);
+        if let Some((selection, mode)) = self.editor.last_visual.clone() {
+            let selection =
+                selection.apply_delta(&delta, false, InsertDrift::Default);
+            Arc::make_mut(&mut self.editor).last_visual = Some((selection, mode));
+        }
+        Arc::make_mut(&mut self.editor).apply_delta_to_folds(&delta);
         if let Some(snippet) = self.editor.snippet.clone() {
             let mut transformer = Transformer::new(&delta);
             Arc::make_mut(&mut self.editor).snippet = Some(
@@ -1518,6 +1666,212 @@ impl LapceEditorBufferData {
         None
     }
 
+    /// The soft wrap layout of the buffer in this editor, `None` if it isn't
+    /// wrapped
+    pub fn wrap_layout(&self, text: &mut PietText) -> Option<Arc<WrapLayout>> {
+        self.editor.wrap_layout(&self.buffer, text, &self.config)
+    }
+
+    pub fn show_inlay_hints(&self) -> bool {
+        self.editor.show_inlay_hints(&self.config)
+    }
+
+    /// The column that `col` of `line` is drawn at, after the inlay hints
+    /// drawn before it
+    pub fn visual_col(&self, line: usize, col: usize) -> usize {
+        if !self.show_inlay_hints() {
+            return col;
+        }
+        self.buffer
+            .visual_col(line, col, self.config.editor.tab_width)
+    }
+
+    /// The column a caret at `col` of `line` is drawn at, before the inlay
+    /// hints drawn at `col`
+    pub fn caret_visual_col(&self, line: usize, col: usize) -> usize {
+        if col == 0 {
+            return 0;
+        }
+        self.visual_col(line, col - 1) + 1
+    }
+
+    /// Whether the caret is in the shown half of its blink, it's always
+    /// shown for an interval after it moved
+    pub fn caret_visible(&self) -> bool {
+        let interval = self.config.editor.cursor_blink_interval;
+        if !self.config.editor.blink_cursor || interval == 0 {
+            return true;
+        }
+        let elapsed = self.editor.last_cursor_instant.borrow().elapsed();
+        (elapsed.as_millis() / interval as u128) % 2 == 0
+    }
+
+    /// The horizontal span of the character under the cursor, after the
+    /// inlay hints drawn before it
+    pub fn current_char(&self, char_width: f64) -> (f64, f64) {
+        if !self.show_inlay_hints() {
+            return self.editor.cursor.current_char(
+                &self.buffer,
+                char_width,
+                &self.config,
+            );
+        }
+        let offset = self.editor.cursor.offset();
+        let next = self.buffer.next_grapheme_offset(
+            offset,
+            1,
+            self.buffer.offset_line_end(offset, true),
+        );
+        let tab_width = self.config.editor.tab_width;
+        let (line, col) = self.buffer.offset_to_line_col(offset, tab_width);
+        let (_, next_col) = self.buffer.offset_to_line_col(next, tab_width);
+        (
+            self.visual_col(line, col) as f64 * char_width,
+            self.caret_visual_col(line, next_col) as f64 * char_width,
+        )
+    }
+
+    /// The line that `H`, `M` or `L` moves to, counted from the first or
+    /// last line that is fully visible and kept `scroll_off` lines away from
+    /// the edges of the editor unless the buffer starts or ends there.
+    /// `None` for movements that don't depend on the viewport.
+    fn window_line(
+        &self,
+        movement: &Movement,
+        count: usize,
+        text: &mut PietText,
+    ) -> Option<usize> {
+        if !matches!(
+            movement,
+            Movement::TopOfWindow
+                | Movement::MiddleOfWindow
+                | Movement::BottomOfWindow
+        ) {
+            return None;
+        }
+
+        let line_height = self.config.editor.line_height as f64;
+        let height = self.editor.size.borrow().height;
+        let y = self.editor.scroll_offset.y;
+        let first_visual_line = (y / line_height).ceil() as usize;
+        let last_visual_line = (((y + height) / line_height).floor() as usize)
+            .saturating_sub(1)
+            .max(first_visual_line);
+        let (first_line, last_line) = match self.wrap_layout(text) {
+            Some(wrap) => (
+                wrap.line_of_visual_line(first_visual_line).0,
+                wrap.line_of_visual_line(last_visual_line).0,
+            ),
+            None => (first_visual_line, last_visual_line),
+        };
+        let last_line = last_line.min(self.buffer.last_line());
+        let first_line = first_line.min(last_line);
+
+        let scroll_off = self.config.editor.scroll_off;
+        let top = if first_line > 0 {
+            (first_line + scroll_off).min(last_line)
+        } else {
+            first_line
+        };
+        let bottom = if last_line < self.buffer.last_line() {
+            last_line.saturating_sub(scroll_off).max(top)
+        } else {
+            last_line
+        };
+
+        let line = match movement {
+            Movement::TopOfWindow => (top + count - 1).min(bottom),
+            Movement::BottomOfWindow => bottom.saturating_sub(count - 1).max(top),
+            _ => (first_line + last_line) / 2,
+        };
+        Some(line)
+    }
+
+    /// Move the cursor up or down by visual lines of a soft wrapped buffer,
+    /// keeping it at the same column within the visual line
+    fn move_visual_line(
+        &mut self,
+        wrap: &WrapLayout,
+        down: bool,
+        count: usize,
+        mods: Modifiers,
+    ) {
+        let editor = Arc::make_mut(&mut self.editor);
+        editor.last_movement = if down { Movement::Down } else { Movement::Up };
+
+        let tab_width = self.config.editor.tab_width;
+        let caret = self.editor.cursor.get_mode() != Mode::Normal;
+        let buffer = &self.buffer;
+        let move_offset = |offset: usize, horiz: Option<&ColPosition>| {
+            let (line, col) = buffer.offset_to_line_col(offset, tab_width);
+            let (visual_line, start_col) = wrap.visual_line_of_col(line, col);
+            let visual_line = if down {
+                (visual_line + count).min(wrap.num_visual_lines().saturating_sub(1))
+            } else {
+                visual_line.saturating_sub(count)
+            };
+            let (new_line, new_start_col, end_col) =
+                wrap.line_of_visual_line(visual_line);
+            let max_col = match end_col {
+                Some(end_col) => end_col.saturating_sub(1),
+                None => buffer.line_end_col(new_line, caret, tab_width),
+            };
+            let new_col = match horiz {
+                Some(ColPosition::End) => max_col,
+                Some(ColPosition::Col(horiz_col)) => {
+                    new_start_col + horiz_col.saturating_sub(start_col)
+                }
+                _ => new_start_col + col - start_col,
+            };
+            let horiz = match horiz {
+                Some(ColPosition::End) => ColPosition::End,
+                _ => ColPosition::Col(new_col),
+            };
+            let new_offset =
+                buffer.offset_of_line_col(new_line, new_col.min(max_col), tab_width);
+            (new_offset, horiz)
+        };
+
+        match &self.editor.cursor.mode {
+            CursorMode::Normal(offset) => {
+                let (new_offset, horiz) =
+                    move_offset(*offset, self.editor.cursor.horiz.as_ref());
+                let editor = Arc::make_mut(&mut self.editor);
+                editor.cursor.mode = CursorMode::Normal(new_offset);
+                editor.cursor.horiz = Some(horiz);
+            }
+            CursorMode::Visual { start, end, mode } => {
+                let (new_offset, horiz) =
+                    move_offset(*end, self.editor.cursor.horiz.as_ref());
+                let start = *start;
+                let mode = *mode;
+                let editor = Arc::make_mut(&mut self.editor);
+                editor.cursor.mode = CursorMode::Visual {
+                    start,
+                    end: new_offset,
+                    mode,
+                };
+                editor.cursor.horiz = Some(horiz);
+            }
+            CursorMode::Insert(selection) => {
+                let mut new_selection = Selection::new();
+                for region in selection.regions() {
+                    let (end, horiz) = move_offset(region.end(), region.horiz());
+                    let start = if mods.shift() { region.start() } else { end };
+                    new_selection.add_region(SelRegion::new(
+                        start,
+                        end,
+                        Some(horiz),
+                    ));
+                }
+                self.set_cursor(Cursor::new(
+                    CursorMode::Insert(new_selection),
+                    None,
+                ));
+            }
+        }
+    }
+
     fn page_move(
         &mut self,
         ctx: &mut EventCtx,
@@ -1595,11 +1949,45 @@ impl LapceEditorBufferData {
         ));
     }
 
+    /// Scroll so that the cursor line is at the top or the bottom of the
+    /// editor, `scroll_off` lines away from the edge, without moving the cursor
+    fn scroll_cursor_to_edge(&mut self, ctx: &mut EventCtx, top: bool) {
+        let line_height = self.config.editor.line_height as f64;
+        let height = self.editor.size.borrow().height;
+        let (line, _) = self.editor.visual_line_col(
+            &self.buffer,
+            self.editor.cursor.offset(),
+            ctx.text(),
+            &self.config,
+        );
+        let margin = (self.config.editor.scroll_off as f64 * line_height)
+            .min(((height - line_height) / 2.0).floor())
+            .max(0.0);
+        let line_top = line as f64 * line_height;
+        let y = if top {
+            line_top - margin
+        } else {
+            line_top + line_height + margin - height
+        };
+
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::ScrollTo((self.editor.scroll_offset.x, y.max(0.0))),
+            Target::Widget(self.editor.view_id),
+        ));
+    }
+
     fn toggle_visual(&mut self, visual_mode: VisualMode) {
         if !self.config.lapce.modal {
             return;
         }
 
+        if let CursorMode::Visual { mode, .. } = &self.editor.cursor.mode {
+            if mode == &visual_mode {
+                self.save_last_visual();
+            }
+        }
+
         let cursor = &mut Arc::make_mut(&mut self.editor).cursor;
 
         match &cursor.mode {
@@ -1625,75 +2013,399 @@ impl LapceEditorBufferData {
         }
     }
 
-    pub fn current_code_actions(&self) -> Option<&CodeActionResponse> {
-        let offset = self.editor.cursor.offset();
-        let prev_offset = self.buffer.prev_code_boundary(offset);
-        self.buffer.code_actions.get(&prev_offset)
+    /// Remember the current visual selection so that it can be selected
+    /// again after leaving visual mode
+    fn save_last_visual(&mut self) {
+        if let CursorMode::Visual { start, end, mode } = &self.editor.cursor.mode {
+            let selection = Selection::region(*start, *end);
+            Arc::make_mut(&mut self.editor).last_visual =
+                Some((selection, mode.clone()));
+        }
     }
 
-    pub fn diagnostics(&self) -> Option<&Arc<Vec<EditorDiagnostic>>> {
-        if let BufferContent::File(path) = &self.buffer.content {
-            self.main_split.diagnostics.get(path)
-        } else {
-            None
+    /// Unfold the block that the cursor is on, or fold the innermost block
+    /// that holds it
+    fn toggle_fold(&mut self) {
+        let line = self.buffer.line_of_offset(self.editor.cursor.offset());
+        let folded = self.editor.folded_lines(&self.buffer);
+        if let Some((start, end)) = folded
+            .iter()
+            .filter(|(start, end)| *start <= line && line <= *end)
+            .min_by_key(|(start, end)| end - start)
+        {
+            self.unfold_lines(*start, *end);
+            return;
         }
-    }
 
-    pub fn diagnostics_mut(&mut self) -> Option<&mut Vec<EditorDiagnostic>> {
-        if let BufferContent::File(path) = &self.buffer.content {
-            self.main_split.diagnostics.get_mut(path).map(Arc::make_mut)
-        } else {
-            None
+        let ranges = self.buffer.fold_ranges(self.config.editor.tab_width);
+        if let Some((start, end)) = fold_range_at(&ranges, line) {
+            Arc::make_mut(&mut self.editor).add_fold(&self.buffer, start, end);
+            self.move_cursor_out_of_folds();
         }
     }
 
-    pub fn offset_of_mouse(
-        &self,
-        text: &mut PietText,
-        pos: Point,
-        config: &Config,
-    ) -> usize {
-        let (line, char_width) = if self.editor.code_lens {
-            let (line, font_size) = if let Some(syntax) = self.buffer.syntax.as_ref()
-            {
-                let line = syntax.lens.line_of_height(pos.y.floor() as usize);
-                let line_height = syntax.lens.height_of_line(line + 1)
-                    - syntax.lens.height_of_line(line);
+    /// Remove the folds that start on `start` and hide up to `end`
+    fn unfold_lines(&mut self, start: usize, end: usize) {
+        let buffer = &self.buffer;
+        Arc::make_mut(&mut self.editor).folds.retain(|fold| {
+            buffer.line_of_offset(fold.start()) != start + 1
+                || buffer.line_of_offset(fold.end()) != end
+        });
+    }
 
-                let font_size = if line_height < config.editor.line_height {
-                    config.editor.code_lens_font_size
-                } else {
-                    config.editor.font_size
-                };
+    /// Unfold the blocks that hide the line the cursor moved to
+    fn unfold_cursor_line(&mut self) {
+        if self.editor.folds.is_empty() {
+            return;
+        }
+        let line = self.buffer.line_of_offset(self.editor.cursor.offset());
+        for (start, end) in self.editor.folded_lines(&self.buffer) {
+            if start < line && line <= end {
+                self.unfold_lines(start, end);
+            }
+        }
+    }
 
-                (line, font_size)
-            } else {
+    /// Put the cursor on the line of the outermost fold that hides it
+    fn move_cursor_out_of_folds(&mut self) {
+        let line = self.buffer.line_of_offset(self.editor.cursor.offset());
+        let start = self
+            .editor
+            .folded_lines(&self.buffer)
+            .into_iter()
+            .filter(|(start, end)| *start < line && line <= *end)
+            .map(|(start, _)| start)
+            .min();
+        if let Some(start) = start {
+            let offset = self.buffer.first_non_blank_character_on_line(start);
+            let mode = match self.editor.cursor.mode {
+                CursorMode::Insert(_) => {
+                    CursorMode::Insert(Selection::caret(offset))
+                }
+                _ => CursorMode::Normal(offset),
+            };
+            self.set_cursor(Cursor::new(
+                mode,
+                Some(ColPosition::Col(
+                    self.buffer
+                        .offset_to_line_col(offset, self.config.editor.tab_width)
+                        .1,
+                )),
+            ));
+        }
+    }
+
+    /// Rewrap the selected lines, or the paragraph around the cursor, to
+    /// the text width. Without a selection, lines of code are left alone and
+    /// only comments and plain text are rewrapped.
+    fn reflow_paragraph(&mut self) {
+        let (start_line, end_line) = match self.editor.cursor.mode {
+            CursorMode::Visual { .. } => {
+                let selection = self
+                    .editor
+                    .cursor
+                    .edit_selection(&self.buffer, self.config.editor.tab_width);
+                let start = selection.min_offset();
+                let end = selection.max_offset();
                 (
-                    (pos.y / config.editor.code_lens_font_size as f64).floor()
-                        as usize,
-                    config.editor.code_lens_font_size,
+                    self.buffer.line_of_offset(start),
+                    self.buffer.line_of_offset(end.saturating_sub(1).max(start)),
                 )
-            };
+            }
+            _ => {
+                let line = self.buffer.line_of_offset(self.editor.cursor.offset());
+                let content = self.buffer.line_content(line);
+                let key = match paragraph_key(&content) {
+                    Some(key) => key.to_string(),
+                    None => return,
+                };
+                let (_, comment) = line_prefix(&content);
+                if !comment && self.buffer.syntax.is_some() {
+                    return;
+                }
+                let in_paragraph = |line: usize| {
+                    paragraph_key(&self.buffer.line_content(line))
+                        == Some(key.as_str())
+                };
+                let mut start_line = line;
+                while start_line > 0 && in_paragraph(start_line - 1) {
+                    start_line -= 1;
+                }
+                let mut end_line = line;
+                while end_line < self.buffer.last_line()
+                    && in_paragraph(end_line + 1)
+                {
+                    end_line += 1;
+                }
+                (start_line, end_line)
+            }
+        };
 
-            (line, config.char_width(text, font_size as f64))
-        } else if let Some(compare) = self.editor.compare.as_ref() {
-            let line = (pos.y / config.editor.line_height as f64).floor() as usize;
-            let line = self.buffer.diff_actual_line_from_visual(compare, line);
-            (
-                line,
-                config.char_width(text, config.editor.font_size as f64),
-            )
-        } else {
-            let line = (pos.y / config.editor.line_height as f64).floor() as usize;
-            (
-                line,
-                config.char_width(text, config.editor.font_size as f64),
-            )
+        let start = self.buffer.offset_of_line(start_line);
+        let end = self.buffer.line_end_offset(end_line, true);
+        let text = self.buffer.slice_to_cow(start..end).to_string();
+        let reflowed = reflow(
+            &text,
+            self.config.editor.text_width,
+            self.config.editor.tab_width,
+            "\n",
+        );
+
+        if let CursorMode::Visual { .. } = self.editor.cursor.mode {
+            self.save_last_visual();
+            let horiz = self.editor.cursor.horiz.clone();
+            self.set_cursor(Cursor::new(CursorMode::Normal(start), horiz));
+        }
+        if reflowed != text {
+            self.edit(
+                &[(&Selection::region(start, end), reflowed.as_str())],
+                true,
+                EditType::Other,
+            );
+        }
+        let offset = self.buffer.first_non_blank_character_on_line(start_line);
+        let mode = match self.editor.cursor.mode {
+            CursorMode::Insert(_) => CursorMode::Insert(Selection::caret(offset)),
+            _ => CursorMode::Normal(offset),
         };
+        self.set_cursor(Cursor::new(mode, None));
+    }
 
-        let last_line = self.buffer.last_line();
-        let (line, col) = if line > last_line {
-            (last_line, 0)
+    /// Trim the trailing whitespace and fix up the final newline of the
+    /// buffer before it's saved, as far as the settings ask for it
+    fn clean_up_whitespace(&mut self) {
+        let trim = self.config.editor.trim_trailing_whitespace;
+        // The rope always uses `\n`, the line ending is applied on save
+        let final_newline = if self.config.editor.insert_final_newline {
+            Some("\n")
+        } else {
+            None
+        };
+        if !trim && final_newline.is_none() {
+            return;
+        }
+        let keep_line = if self.config.editor.trim_cursor_line {
+            None
+        } else {
+            Some(self.buffer.line_of_offset(self.editor.cursor.offset()))
+        };
+        let edits = whitespace_cleanup_edits(
+            &self.buffer.slice_to_cow(0..self.buffer.len()),
+            trim,
+            final_newline,
+            keep_line,
+        );
+        if edits.is_empty() {
+            return;
+        }
+
+        // Edits made in visual mode yank the selection, so leave it first
+        if let CursorMode::Visual { end, .. } = self.editor.cursor.mode {
+            self.save_last_visual();
+            let horiz = self.editor.cursor.horiz.clone();
+            self.set_cursor(Cursor::new(CursorMode::Normal(end), horiz));
+        }
+
+        let selections = edits
+            .iter()
+            .map(|(start, end, content)| (Selection::region(*start, *end), *content))
+            .collect::<Vec<_>>();
+        let delta = self.edit(
+            &selections
+                .iter()
+                .map(|(selection, content)| (selection, *content))
+                .collect::<Vec<_>>(),
+            true,
+            EditType::Other,
+        );
+        let editor = Arc::make_mut(&mut self.editor);
+        editor.cursor.apply_delta(&delta);
+        // A cursor that sat in the trimmed whitespace ends up after the end
+        // of its line, where it can't be in normal mode
+        if let CursorMode::Normal(offset) = editor.cursor.mode {
+            let line = self.buffer.line_of_offset(offset);
+            let offset = offset.min(self.buffer.line_end_offset(line, false));
+            editor.cursor.mode = CursorMode::Normal(offset);
+        }
+    }
+
+    /// Grow every selection, or the node under a caret, to the smallest
+    /// syntax node around it
+    fn expand_selection(&mut self) {
+        let syntax = match self.buffer.syntax.as_ref() {
+            Some(syntax) if syntax.rev() == self.buffer.rev => syntax,
+            _ => return,
+        };
+        let ranges: Vec<(usize, usize)> = match &self.editor.cursor.mode {
+            CursorMode::Normal(offset) => vec![(*offset, *offset)],
+            CursorMode::Visual { .. } => self
+                .editor
+                .cursor
+                .edit_selection(&self.buffer, self.config.editor.tab_width)
+                .regions()
+                .iter()
+                .map(|region| (region.min(), region.max()))
+                .collect(),
+            CursorMode::Insert(selection) => selection
+                .regions()
+                .iter()
+                .map(|region| (region.min(), region.max()))
+                .collect(),
+        };
+        let mut selection = Selection::new();
+        for (start, end) in ranges {
+            let range = syntax.expand_range(start..end).unwrap_or(start..end);
+            selection.add_region(SelRegion::new(range.start, range.end, None));
+        }
+        let mode = match self.editor.cursor.mode {
+            CursorMode::Insert(_) => CursorMode::Insert(selection),
+            _ => {
+                let start = selection.min_offset();
+                let end = selection.max_offset();
+                if start == end {
+                    return;
+                }
+                CursorMode::Visual {
+                    start,
+                    end: self.buffer.prev_grapheme_offset(end, 1, start),
+                    mode: VisualMode::Normal,
+                }
+            }
+        };
+        if mode == self.editor.cursor.mode {
+            return;
+        }
+
+        let editor = Arc::make_mut(&mut self.editor);
+        if editor.selection_expansions.last() != Some(&editor.cursor.mode) {
+            editor.selection_expansions = vec![editor.cursor.mode.clone()];
+        }
+        editor.selection_expansions.push(mode.clone());
+        self.set_cursor(Cursor::new(mode, None));
+    }
+
+    /// Select the last visual selection again, in the visual mode it had
+    fn select_last_visual(&mut self) {
+        if !self.config.lapce.modal {
+            return;
+        }
+        let (selection, mode) = match self.editor.last_visual.as_ref() {
+            Some(last_visual) => last_visual.clone(),
+            None => return,
+        };
+        let region = match selection.first() {
+            Some(region) => *region,
+            None => return,
+        };
+        let last_offset = self.buffer.len().saturating_sub(1);
+        let start = region.start.min(last_offset);
+        let end = region.end.min(last_offset);
+        let horiz = Some(ColPosition::Col(
+            self.buffer
+                .offset_to_line_col(end, self.config.editor.tab_width)
+                .1,
+        ));
+        self.set_cursor(Cursor::new(CursorMode::Visual { start, end, mode }, horiz));
+    }
+
+    pub fn current_code_actions(&self) -> Option<&CodeActionResponse> {
+        let offset = self.editor.cursor.offset();
+        let prev_offset = self.buffer.code_action_offset(offset);
+        self.buffer.code_actions.get(&prev_offset)
+    }
+
+    pub fn diagnostics(&self) -> Option<&Arc<Vec<EditorDiagnostic>>> {
+        if let BufferContent::File(path) = &self.buffer.content {
+            self.main_split.diagnostics.get(path)
+        } else {
+            None
+        }
+    }
+
+    pub fn diagnostics_mut(&mut self) -> Option<&mut Vec<EditorDiagnostic>> {
+        if let BufferContent::File(path) = &self.buffer.content {
+            self.main_split.diagnostics.get_mut(path).map(Arc::make_mut)
+        } else {
+            None
+        }
+    }
+
+    pub fn offset_of_mouse(
+        &self,
+        text: &mut PietText,
+        pos: Point,
+        config: &Config,
+    ) -> usize {
+        if let Some(wrap) = self.wrap_layout(text) {
+            let visual_line =
+                (pos.y / config.editor.line_height as f64).floor() as usize;
+            let (line, start_col, end_col) = wrap.line_of_visual_line(visual_line);
+            let char_width = config.char_width(text, config.editor.font_size as f64);
+            let col = start_col
+                + if self.editor.cursor.get_mode() == Mode::Insert {
+                    (pos.x / char_width).round() as usize
+                } else {
+                    (pos.x / char_width).floor() as usize
+                };
+            // Clicking past the end of a wrapped part of a line puts the
+            // cursor on its last column instead of on the next visual line
+            let col = match end_col {
+                Some(end_col) => col.min(end_col.saturating_sub(1)),
+                None => col,
+            };
+            let line_end = self.buffer.line_end_col(
+                line,
+                self.editor.cursor.get_mode() != Mode::Normal,
+                config.editor.tab_width,
+            );
+            return self.buffer.offset_of_line_col(
+                line,
+                col.min(line_end),
+                config.editor.tab_width,
+            );
+        }
+
+        let (line, char_width) = if self.editor.code_lens {
+            let (line, font_size) = if let Some(syntax) = self.buffer.syntax.as_ref()
+            {
+                let line = syntax.lens.line_of_height(pos.y.floor() as usize);
+                let line_height = syntax.lens.height_of_line(line + 1)
+                    - syntax.lens.height_of_line(line);
+
+                let font_size = if line_height < config.editor.line_height {
+                    config.editor.code_lens_font_size
+                } else {
+                    config.editor.font_size
+                };
+
+                (line, font_size)
+            } else {
+                (
+                    (pos.y / config.editor.code_lens_font_size as f64).floor()
+                        as usize,
+                    config.editor.code_lens_font_size,
+                )
+            };
+
+            (line, config.char_width(text, font_size as f64))
+        } else if let Some(compare) = self.editor.compare.as_ref() {
+            let line = (pos.y / config.editor.line_height as f64).floor() as usize;
+            let line = self.buffer.diff_actual_line_from_visual(compare, line);
+            (
+                line,
+                config.char_width(text, config.editor.font_size as f64),
+            )
+        } else {
+            let line = (pos.y / config.editor.line_height as f64).floor() as usize;
+            (
+                line,
+                config.char_width(text, config.editor.font_size as f64),
+            )
+        };
+
+        let last_line = self.buffer.last_line();
+        let (line, col) = if line > last_line {
+            (last_line, 0)
         } else {
             let line_end = self.buffer.line_end_col(
                 line,
@@ -1701,13 +2413,18 @@ impl LapceEditorBufferData {
                 config.editor.tab_width,
             );
 
-            let col = (if self.editor.cursor.get_mode() == Mode::Insert {
+            let col = if self.editor.cursor.get_mode() == Mode::Insert {
                 (pos.x / char_width).round() as usize
             } else {
                 (pos.x / char_width).floor() as usize
-            })
-            .min(line_end);
-            (line, col)
+            };
+            let col = if self.show_inlay_hints() {
+                self.buffer
+                    .col_of_visual_col(line, col, config.editor.tab_width)
+            } else {
+                col
+            };
+            (line, col.min(line_end))
         };
         self.buffer
             .offset_of_line_col(line, col, config.editor.tab_width)
@@ -1760,13 +2477,20 @@ impl LapceEditorBufferData {
     ) {
         ctx.set_active(true);
         let mouse_offset = self.offset_of_mouse(ctx.text(), mouse_event.pos, config);
-        let (start, end) = self.buffer.select_word(mouse_offset);
-        self.set_cursor(self.editor.cursor.add_region(
-            start,
-            end,
-            mouse_event.mods.shift(),
-            mouse_event.mods.alt(),
-        ));
+        let (start, end) = self.drag_unit(mouse_offset, DragSelect::Word);
+        let cursor = if mouse_event.mods.shift() {
+            self.editor
+                .cursor
+                .add_region(start, end, true, mouse_event.mods.alt())
+        } else {
+            self.editor.cursor.select_region(
+                start,
+                end,
+                VisualMode::Normal,
+                mouse_event.mods.alt(),
+            )
+        };
+        self.set_cursor(cursor);
     }
 
     pub fn triple_click(
@@ -1777,16 +2501,115 @@ impl LapceEditorBufferData {
     ) {
         ctx.set_active(true);
         let mouse_offset = self.offset_of_mouse(ctx.text(), mouse_event.pos, config);
-        let line = self.buffer.line_of_offset(mouse_offset);
-        let start = self.buffer.offset_of_line(line);
-        let end = self.buffer.offset_of_line(line + 1);
+        let (start, end) = self.drag_unit(mouse_offset, DragSelect::Line);
         let editor = Arc::make_mut(&mut self.editor);
-        editor.cursor = editor.cursor.add_region(
-            start,
-            end,
-            mouse_event.mods.shift(),
-            mouse_event.mods.alt(),
-        );
+        editor.cursor = if mouse_event.mods.shift() {
+            editor
+                .cursor
+                .add_region(start, end, true, mouse_event.mods.alt())
+        } else {
+            editor.cursor.select_region(
+                start,
+                end,
+                VisualMode::Linewise,
+                mouse_event.mods.alt(),
+            )
+        };
+    }
+
+    /// Extend the selection to the mouse while the button is held. A drag
+    /// that began with a double or triple click extends by whole words or
+    /// lines, keeping the one under `anchor` selected.
+    pub fn drag_select(
+        &mut self,
+        ctx: &mut EventCtx,
+        anchor: Point,
+        mouse_event: &MouseEvent,
+        kind: DragSelect,
+        config: &Config,
+    ) {
+        let offset = self.offset_of_mouse(ctx.text(), mouse_event.pos, config);
+        let new_cursor = mouse_event.mods.alt();
+        let cursor = match kind {
+            DragSelect::Char => {
+                self.editor.cursor.set_offset(offset, true, new_cursor)
+            }
+            DragSelect::Word | DragSelect::Line => {
+                let anchor = self.offset_of_mouse(ctx.text(), anchor, config);
+                let (anchor_start, anchor_end) = self.drag_unit(anchor, kind);
+                let (start, end) = self.drag_unit(offset, kind);
+                let (start, end) = if start < anchor_start {
+                    (anchor_end, start)
+                } else {
+                    (anchor_start, end.max(anchor_end))
+                };
+                let mode = if kind == DragSelect::Line {
+                    VisualMode::Linewise
+                } else {
+                    VisualMode::Normal
+                };
+                self.editor
+                    .cursor
+                    .select_region(start, end, mode, new_cursor)
+            }
+        };
+        Arc::make_mut(&mut self.editor).cursor = cursor;
+    }
+
+    /// Paste the primary selection at the mouse, like a charwise paste with
+    /// the cursor put there.
+    pub fn middle_click(
+        &mut self,
+        ctx: &mut EventCtx,
+        mouse_event: &MouseEvent,
+        config: &Config,
+    ) {
+        if self.is_read_only() {
+            return;
+        }
+        let content = match primary_selection::get() {
+            Some(content) => content,
+            None => return,
+        };
+        let offset = self.offset_of_mouse(ctx.text(), mouse_event.pos, config);
+        self.set_cursor(self.editor.cursor.set_offset(offset, false, false));
+        let data = RegisterData {
+            content,
+            mode: VisualMode::Normal,
+        };
+        self.paste(ctx, &data);
+    }
+
+    /// Make the selected text the primary selection, if there is any.
+    pub fn update_primary_selection(&self) {
+        let has_selection = match &self.editor.cursor.mode {
+            CursorMode::Normal(_) => false,
+            CursorMode::Visual { .. } => true,
+            CursorMode::Insert(selection) => !selection.is_caret(),
+        };
+        if has_selection {
+            let data = self
+                .editor
+                .cursor
+                .yank(&self.buffer, self.config.editor.tab_width);
+            primary_selection::put(data.content);
+        }
+    }
+
+    /// The word or line containing `offset`, as `start..end`.
+    fn drag_unit(&self, offset: usize, kind: DragSelect) -> (usize, usize) {
+        match kind {
+            DragSelect::Line => {
+                let line = self.buffer.line_of_offset(offset);
+                (
+                    self.buffer.offset_of_line(line),
+                    self.buffer.offset_of_line(line + 1),
+                )
+            }
+            DragSelect::Char | DragSelect::Word => self
+                .buffer
+                .select_word(offset, &self.config.editor.word_chars),
+        }
     }
 }
 
@@ -1796,7 +2619,7 @@ impl KeyPressFocus for LapceEditorBufferData {
     }
 
     fn expect_char(&self) -> bool {
-        self.editor.inline_find.is_some()
+        self.editor.inline_find.is_some() || self.editor.surround.is_some()
     }
 
     fn check_condition(&self, condition: &str) -> bool {
@@ -1806,6 +2629,7 @@ impl KeyPressFocus for LapceEditorBufferData {
             }
             "editor_focus" => match self.editor.content {
                 BufferContent::File(_) => true,
+                BufferContent::Scratch(..) => true,
                 BufferContent::Local(_) => false,
                 BufferContent::Value(_) => false,
             },
@@ -1819,6 +2643,13 @@ impl KeyPressFocus for LapceEditorBufferData {
             "hover_focus" => self.has_hover(),
             "list_focus" => self.has_completions(),
             "modal_focus" => self.has_completions() || self.has_hover(),
+            "motion_mode_delete" => {
+                self.editor.motion_mode == Some(MotionMode::Delete)
+            }
+            "motion_mode_change" => {
+                self.editor.motion_mode == Some(MotionMode::Change)
+            }
+            "motion_mode_yank" => self.editor.motion_mode == Some(MotionMode::Yank),
             _ => false,
         }
     }
@@ -1831,8 +2662,42 @@ impl KeyPressFocus for LapceEditorBufferData {
         mods: Modifiers,
         env: &Env,
     ) -> CommandExecuted {
+        if self.is_read_only() && cmd.is_edit_command() {
+            return CommandExecuted::Yes;
+        }
+        if self.editor.stats.is_some() && *cmd != LapceCommand::ShowStats {
+            Arc::make_mut(&mut self.editor).stats = None;
+        }
         if let Some(movement) = cmd.move_command(count) {
-            self.do_move(&movement, count.unwrap_or(1), mods);
+            let (movement, count) =
+                match self.window_line(&movement, count.unwrap_or(1), ctx.text()) {
+                    Some(line) => {
+                        (Movement::Line(LinePosition::Line(line + 1)), None)
+                    }
+                    None => (movement, count),
+                };
+            // Folded blocks are always stepped over as a single line
+            let wrap = match movement {
+                Movement::Up | Movement::Down
+                    if (self.config.editor.wrap_visual_line_movement
+                        || !self.editor.folds.is_empty())
+                        && self.editor.motion_mode.is_none() =>
+                {
+                    self.wrap_layout(ctx.text())
+                }
+                _ => None,
+            };
+            if let Some(wrap) = wrap {
+                self.move_visual_line(
+                    &wrap,
+                    movement == Movement::Down,
+                    count.unwrap_or(1),
+                    mods,
+                );
+            } else {
+                self.do_move(&movement, count.unwrap_or(1), mods);
+            }
+            self.unfold_cursor_line();
             if let Some(snippet) = self.editor.snippet.as_ref() {
                 let offset = self.editor.cursor.offset();
                 let mut within_region = false;
@@ -1848,11 +2713,12 @@ impl KeyPressFocus for LapceEditorBufferData {
             }
             self.cancel_completion();
             self.cancel_hover();
+            self.update_primary_selection();
             Arc::make_mut(&mut self.editor).motion_mode = None;
             return CommandExecuted::Yes;
         }
         if let Some(mode) = cmd.motion_mode_command() {
-            self.set_motion_mode(mode);
+            self.set_motion_mode(mode, count);
             return CommandExecuted::Yes;
         }
         Arc::make_mut(&mut self.editor).motion_mode = None;
@@ -1922,8 +2788,11 @@ impl KeyPressFocus for LapceEditorBufferData {
                 self.initiate_diagnositcs_offset();
                 let proxy = self.proxy.clone();
                 let buffer = self.buffer_mut();
-                if let Some(delta) = buffer.do_undo(proxy) {
-                    self.jump_to_nearest_delta(&delta);
+                if let Some((delta, cursor)) = buffer.do_undo(proxy) {
+                    match cursor {
+                        Some(selection) => self.set_cursor_after_change(selection),
+                        None => self.jump_to_nearest_delta(&delta),
+                    }
                     self.update_diagnositcs_offset(&delta);
                     self.update_completion(ctx);
                 }
@@ -1932,13 +2801,25 @@ impl KeyPressFocus for LapceEditorBufferData {
                 self.initiate_diagnositcs_offset();
                 let proxy = self.proxy.clone();
                 let buffer = self.buffer_mut();
-                if let Some(delta) = buffer.do_redo(proxy) {
-                    self.jump_to_nearest_delta(&delta);
+                if let Some((delta, cursor)) = buffer.do_redo(proxy) {
+                    match cursor {
+                        Some(selection) => self.set_cursor_after_change(selection),
+                        None => self.jump_to_nearest_delta(&delta),
+                    }
                     self.update_diagnositcs_offset(&delta);
                     self.update_completion(ctx);
                 }
             }
             LapceCommand::Append => {
+                if let CursorMode::Visual {
+                    start,
+                    end,
+                    mode: VisualMode::Blockwise,
+                } = self.editor.cursor.mode
+                {
+                    self.append_block(start, end);
+                    return CommandExecuted::Yes;
+                }
                 let offset = self
                     .buffer
                     .move_offset(
@@ -2037,6 +2918,42 @@ impl KeyPressFocus for LapceEditorBufferData {
                 let offset = self.buffer.offset_line_end(offset, true);
                 self.insert_new_line(ctx, Selection::caret(offset));
             }
+            LapceCommand::DeleteToEndOfLine | LapceCommand::ChangeToEndOfLine => {
+                let offset = match &self.editor.cursor.mode {
+                    CursorMode::Normal(offset) => *offset,
+                    CursorMode::Insert(selection)
+                        if cmd == &LapceCommand::DeleteToEndOfLine =>
+                    {
+                        let selection = selection.clone();
+                        self.kill_line(selection);
+                        return CommandExecuted::Yes;
+                    }
+                    _ => return CommandExecuted::Yes,
+                };
+                let end = self.line_end_after(offset, count.unwrap_or(1));
+                self.add_register(offset, end, false, RegisterKind::Delete);
+                let selection = Selection::region(offset, end);
+                let delta = self.edit(&[(&selection, "")], true, EditType::Delete);
+                let selection =
+                    selection.apply_delta(&delta, true, InsertDrift::Default);
+                if cmd == &LapceCommand::ChangeToEndOfLine {
+                    self.buffer_mut().update_edit_type();
+                    self.set_cursor(Cursor::new(
+                        CursorMode::Insert(selection),
+                        None,
+                    ));
+                } else {
+                    self.set_cursor_after_change(selection);
+                }
+            }
+            LapceCommand::YankLine => {
+                let line = self.buffer.line_of_offset(self.editor.cursor.offset());
+                self.execute_linewise_motion_mode(
+                    &MotionMode::Yank,
+                    line,
+                    count.unwrap_or(1),
+                );
+            }
             LapceCommand::DeleteToBeginningOfLine => {
                 let selection = match self.editor.cursor.mode {
                     CursorMode::Normal(_) | CursorMode::Visual { .. } => self
@@ -2089,6 +3006,7 @@ impl KeyPressFocus for LapceEditorBufferData {
                     .yank(&self.buffer, self.config.editor.tab_width);
                 let register = Arc::make_mut(&mut self.main_split.register);
                 register.add_yank(data);
+                self.save_last_visual();
                 match &self.editor.cursor.mode {
                     #[allow(unused_variables)]
                     CursorMode::Visual { start, end, mode } => {
@@ -2123,6 +3041,7 @@ impl KeyPressFocus for LapceEditorBufferData {
                             region.end = end;
                         }
                     }
+                    selection.merge_overlapping();
                     selection
                 } else {
                     self.editor
@@ -2190,60 +3109,24 @@ impl KeyPressFocus for LapceEditorBufferData {
                 let data = self.main_split.register.unamed.clone();
                 self.paste(ctx, &data);
             }
-            LapceCommand::DeleteWordForward => {
-                let selection = match self.editor.cursor.mode {
-                    CursorMode::Normal(_) | CursorMode::Visual { .. } => self
-                        .editor
-                        .cursor
-                        .edit_selection(&self.buffer, self.config.editor.tab_width),
-                    CursorMode::Insert(_) => {
-                        let selection = self.editor.cursor.edit_selection(
-                            &self.buffer,
-                            self.config.editor.tab_width,
-                        );
-
-                        self.buffer.update_selection(
-                            &selection,
-                            1,
-                            &Movement::WordForward,
-                            Mode::Insert,
-                            true,
-                            self.editor.code_lens,
-                            self.editor.compare.clone(),
-                            &self.config,
-                        )
-                    }
-                };
-                let delta = self.edit(&[(&selection, "")], true, EditType::Delete);
-                let selection =
-                    selection.apply_delta(&delta, true, InsertDrift::Default);
-                self.set_cursor_after_change(selection);
-                self.update_completion(ctx);
-            }
-            LapceCommand::DeleteWordBackward => {
-                let selection = match self.editor.cursor.mode {
-                    CursorMode::Normal(_) | CursorMode::Visual { .. } => self
-                        .editor
-                        .cursor
-                        .edit_selection(&self.buffer, self.config.editor.tab_width),
-                    CursorMode::Insert(_) => {
-                        let selection = self.editor.cursor.edit_selection(
-                            &self.buffer,
-                            self.config.editor.tab_width,
-                        );
-
-                        self.buffer.update_selection(
-                            &selection,
-                            1,
-                            &Movement::WordBackward,
-                            Mode::Insert,
-                            true,
-                            self.editor.code_lens,
-                            self.editor.compare.clone(),
-                            &self.config,
-                        )
-                    }
+            LapceCommand::DeleteWordForward
+            | LapceCommand::DeleteWordBackward
+            | LapceCommand::DeleteBigWordForward
+            | LapceCommand::DeleteBigWordBackward => {
+                let movement = match cmd {
+                    LapceCommand::DeleteWordForward => Movement::WordForward,
+                    LapceCommand::DeleteWordBackward => Movement::WordBackward,
+                    LapceCommand::DeleteBigWordForward => Movement::BigWordForward,
+                    _ => Movement::BigWordBackward,
                 };
+                let selection = delete_word_selection(
+                    &self.buffer,
+                    &self.editor.cursor,
+                    &movement,
+                    self.editor.code_lens,
+                    self.editor.compare.clone(),
+                    &self.config,
+                );
                 let delta = self.edit(&[(&selection, "")], true, EditType::Delete);
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
@@ -2251,104 +3134,13 @@ impl KeyPressFocus for LapceEditorBufferData {
                 self.update_completion(ctx);
             }
             LapceCommand::DeleteBackward => {
-                let selection = match self.editor.cursor.mode {
-                    CursorMode::Normal(_) | CursorMode::Visual { .. } => self
-                        .editor
-                        .cursor
-                        .edit_selection(&self.buffer, self.config.editor.tab_width),
-                    CursorMode::Insert(_) => {
-                        let indent = self.buffer.indent_unit();
-                        let selection = self.editor.cursor.edit_selection(
-                            &self.buffer,
-                            self.config.editor.tab_width,
-                        );
-                        let mut new_selection = Selection::new();
-                        for region in selection.regions() {
-                            let new_region = if region.is_caret() {
-                                if indent.starts_with('\t') {
-                                    self.buffer.update_region(
-                                        region,
-                                        1,
-                                        &Movement::Left,
-                                        Mode::Insert,
-                                        true,
-                                        self.editor.code_lens,
-                                        self.editor.compare.clone(),
-                                        &self.config,
-                                    )
-                                } else {
-                                    let line =
-                                        self.buffer.line_of_offset(region.start);
-                                    let nonblank = self
-                                        .buffer
-                                        .first_non_blank_character_on_line(line);
-                                    let (_, col) = self.buffer.offset_to_line_col(
-                                        region.start,
-                                        self.config.editor.tab_width,
-                                    );
-                                    let count =
-                                        if region.start <= nonblank && col > 0 {
-                                            let r = col % indent.len();
-                                            if r == 0 {
-                                                indent.len()
-                                            } else {
-                                                r
-                                            }
-                                        } else {
-                                            1
-                                        };
-                                    self.buffer.update_region(
-                                        region,
-                                        count,
-                                        &Movement::Left,
-                                        Mode::Insert,
-                                        true,
-                                        self.editor.code_lens,
-                                        self.editor.compare.clone(),
-                                        &self.config,
-                                    )
-                                }
-                            } else {
-                                *region
-                            };
-                            new_selection.add_region(new_region);
-                        }
-
-                        let mut selection = new_selection;
-                        if selection.regions().len() == 1 {
-                            let delete_str = self
-                                .buffer
-                                .slice_to_cow(
-                                    selection.min_offset()..selection.max_offset(),
-                                )
-                                .to_string();
-                            if str_is_pair_left(&delete_str) {
-                                if let Some(c) = str_matching_pair(&delete_str) {
-                                    let offset = selection.max_offset();
-                                    let line = self.buffer.line_of_offset(offset);
-                                    let line_end =
-                                        self.buffer.line_end_offset(line, true);
-                                    let content = self
-                                        .buffer
-                                        .slice_to_cow(offset..line_end)
-                                        .to_string();
-                                    if content.trim().starts_with(&c.to_string()) {
-                                        let index = content
-                                            .match_indices(c)
-                                            .next()
-                                            .unwrap()
-                                            .0;
-                                        selection = Selection::region(
-                                            selection.min_offset(),
-                                            offset + index + 1,
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                        selection
-                    }
-                };
+                let selection = delete_backward_selection(
+                    &self.buffer,
+                    &self.editor.cursor,
+                    self.editor.code_lens,
+                    self.editor.compare.clone(),
+                    &self.config,
+                );
                 let delta = self.edit(&[(&selection, "")], true, EditType::Delete);
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
@@ -2356,37 +3148,13 @@ impl KeyPressFocus for LapceEditorBufferData {
                 self.update_completion(ctx);
             }
             LapceCommand::DeleteForward => {
-                let selection = match self.editor.cursor.mode {
-                    CursorMode::Normal(_) | CursorMode::Visual { .. } => self
-                        .editor
-                        .cursor
-                        .edit_selection(&self.buffer, self.config.editor.tab_width),
-                    CursorMode::Insert(_) => {
-                        let selection = self.editor.cursor.edit_selection(
-                            &self.buffer,
-                            self.config.editor.tab_width,
-                        );
-                        let mut new_selection = Selection::new();
-                        for region in selection.regions() {
-                            let new_region = if region.is_caret() {
-                                self.buffer.update_region(
-                                    region,
-                                    1,
-                                    &Movement::Right,
-                                    Mode::Insert,
-                                    true,
-                                    self.editor.code_lens,
-                                    self.editor.compare.clone(),
-                                    &self.config,
-                                )
-                            } else {
-                                *region
-                            };
-                            new_selection.add_region(new_region);
-                        }
-                        new_selection
-                    }
-                };
+                let selection = delete_forward_selection(
+                    &self.buffer,
+                    &self.editor.cursor,
+                    self.editor.code_lens,
+                    self.editor.compare.clone(),
+                    &self.config,
+                );
                 let delta = self.edit(&[(&selection, "")], true, EditType::Delete);
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
@@ -2433,6 +3201,25 @@ impl KeyPressFocus for LapceEditorBufferData {
             LapceCommand::ToggleBlockwiseVisualMode => {
                 self.toggle_visual(VisualMode::Blockwise);
             }
+            LapceCommand::SelectLastVisual => {
+                self.select_last_visual();
+            }
+            LapceCommand::VisualSwapEnds => {
+                if let CursorMode::Visual { start, end, mode } =
+                    self.editor.cursor.mode.clone()
+                {
+                    let (_, col) = self
+                        .buffer
+                        .offset_to_line_col(start, self.config.editor.tab_width);
+                    let editor = Arc::make_mut(&mut self.editor);
+                    editor.cursor.mode = CursorMode::Visual {
+                        start: end,
+                        end: start,
+                        mode,
+                    };
+                    editor.cursor.horiz = Some(ColPosition::Col(col));
+                }
+            }
             LapceCommand::CenterOfWindow => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -2440,6 +3227,62 @@ impl KeyPressFocus for LapceEditorBufferData {
                     Target::Widget(self.editor.view_id),
                 ));
             }
+            LapceCommand::ShowStats => {
+                let total =
+                    TextStats::new(&self.buffer.slice_to_cow(0..self.buffer.len()));
+                let selection = match self.editor.cursor.mode {
+                    CursorMode::Visual { .. } => {
+                        let selection = self.editor.cursor.edit_selection(
+                            &self.buffer,
+                            self.config.editor.tab_width,
+                        );
+                        Some(TextStats::of_selection(&self.buffer, &selection))
+                    }
+                    CursorMode::Insert(ref selection) if !selection.is_caret() => {
+                        Some(TextStats::of_selection(&self.buffer, selection))
+                    }
+                    _ => None,
+                };
+                Arc::make_mut(&mut self.editor).stats = Some((total, selection));
+            }
+            LapceCommand::ExpandSelection => {
+                self.expand_selection();
+            }
+            LapceCommand::ShrinkSelection => {
+                let editor = Arc::make_mut(&mut self.editor);
+                if editor.selection_expansions.len() > 1
+                    && editor.selection_expansions.last()
+                        == Some(&editor.cursor.mode)
+                {
+                    editor.selection_expansions.pop();
+                    let mode = editor.selection_expansions.last().unwrap().clone();
+                    self.set_cursor(Cursor::new(mode, None));
+                }
+            }
+            LapceCommand::ReflowParagraph => {
+                self.reflow_paragraph();
+            }
+            LapceCommand::ToggleFold => {
+                self.toggle_fold();
+            }
+            LapceCommand::FoldAll => {
+                let ranges = self.buffer.fold_ranges(self.config.editor.tab_width);
+                let editor = Arc::make_mut(&mut self.editor);
+                editor.folds.clear();
+                for (start, end) in ranges {
+                    editor.add_fold(&self.buffer, start, end);
+                }
+                self.move_cursor_out_of_folds();
+            }
+            LapceCommand::UnfoldAll => {
+                Arc::make_mut(&mut self.editor).folds.clear();
+            }
+            LapceCommand::ScrollCursorTop => {
+                self.scroll_cursor_to_edge(ctx, true);
+            }
+            LapceCommand::ScrollCursorBottom => {
+                self.scroll_cursor_to_edge(ctx, false);
+            }
             LapceCommand::ScrollDown => {
                 self.scroll(ctx, true, count.unwrap_or(1), mods, env);
             }
@@ -2540,21 +3383,27 @@ impl KeyPressFocus for LapceEditorBufferData {
                 if let CursorMode::Insert(mut selection) =
                     self.editor.cursor.mode.clone()
                 {
-                    let offset = selection.first().map(|s| s.end()).unwrap_or(0);
-                    let (new_offset, _) = self.buffer.move_offset(
-                        offset,
-                        self.editor.cursor.horiz.as_ref(),
-                        1,
-                        &Movement::Up,
-                        Mode::Insert,
-                        self.editor.code_lens,
-                        self.editor.compare.clone(),
-                        &self.config,
-                    );
-                    if new_offset != offset {
-                        selection.add_region(SelRegion::new(
-                            new_offset, new_offset, None,
-                        ));
+                    // Every region gets a caret on the line above it,
+                    // overlapping ones are merged by add_region
+                    for region in selection.clone().regions() {
+                        let offset = region.end();
+                        let (new_offset, horiz) = self.buffer.move_offset(
+                            offset,
+                            region.horiz(),
+                            1,
+                            &Movement::Up,
+                            Mode::Insert,
+                            self.editor.code_lens,
+                            self.editor.compare.clone(),
+                            &self.config,
+                        );
+                        if new_offset != offset {
+                            selection.add_region(SelRegion::new(
+                                new_offset,
+                                new_offset,
+                                Some(horiz),
+                            ));
+                        }
                     }
                     self.set_cursor(Cursor::new(
                         CursorMode::Insert(selection),
@@ -2566,21 +3415,27 @@ impl KeyPressFocus for LapceEditorBufferData {
                 if let CursorMode::Insert(mut selection) =
                     self.editor.cursor.mode.clone()
                 {
-                    let offset = selection.last().map(|s| s.end()).unwrap_or(0);
-                    let (new_offset, _) = self.buffer.move_offset(
-                        offset,
-                        self.editor.cursor.horiz.as_ref(),
-                        1,
-                        &Movement::Down,
-                        Mode::Insert,
-                        self.editor.code_lens,
-                        self.editor.compare.clone(),
-                        &self.config,
-                    );
-                    if new_offset != offset {
-                        selection.add_region(SelRegion::new(
-                            new_offset, new_offset, None,
-                        ));
+                    // Every region gets a caret on the line below it,
+                    // overlapping ones are merged by add_region
+                    for region in selection.clone().regions() {
+                        let offset = region.end();
+                        let (new_offset, horiz) = self.buffer.move_offset(
+                            offset,
+                            region.horiz(),
+                            1,
+                            &Movement::Down,
+                            Mode::Insert,
+                            self.editor.code_lens,
+                            self.editor.compare.clone(),
+                            &self.config,
+                        );
+                        if new_offset != offset {
+                            selection.add_region(SelRegion::new(
+                                new_offset,
+                                new_offset,
+                                Some(horiz),
+                            ));
+                        }
                     }
                     self.set_cursor(Cursor::new(
                         CursorMode::Insert(selection),
@@ -2622,6 +3477,55 @@ impl KeyPressFocus for LapceEditorBufferData {
                     ));
                 }
             }
+            LapceCommand::InsertCursorNextMatch => {
+                if let CursorMode::Insert(mut selection) =
+                    self.editor.cursor.mode.clone()
+                {
+                    if let Some(r) = selection.last_inserted().cloned() {
+                        // Search for the word under the caret, or the selected
+                        // text, and put a new region at the same place in the
+                        // next occurrence
+                        let (start, end) = if r.is_caret() {
+                            self.buffer.select_word(
+                                r.start(),
+                                &self.config.editor.word_chars,
+                            )
+                        } else {
+                            (r.min(), r.max())
+                        };
+                        if start < end {
+                            let search_str = self.buffer.slice_to_cow(start..end);
+                            let mut find = Find::new(0);
+                            find.set_find(&search_str, true, false, r.is_caret());
+                            let mut offset = end;
+                            let mut seen = HashSet::new();
+                            while let Some((match_start, _)) =
+                                find.next(&self.buffer.rope, offset, false, true)
+                            {
+                                let new_start = match_start + (r.start - start);
+                                let new_end = match_start + (r.end - start);
+                                if !selection.regions().iter().any(|r| {
+                                    r.start == new_start && r.end == new_end
+                                }) {
+                                    selection.add_region(SelRegion::new(
+                                        new_start, new_end, None,
+                                    ));
+                                    break;
+                                }
+                                if seen.contains(&match_start) {
+                                    break;
+                                }
+                                seen.insert(match_start);
+                                offset = match_start + (end - start);
+                            }
+                        }
+                    }
+                    self.set_cursor(Cursor::new(
+                        CursorMode::Insert(selection),
+                        None,
+                    ));
+                }
+            }
             LapceCommand::SelectCurrentLine => {
                 if let CursorMode::Insert(selection) =
                     self.editor.cursor.mode.clone()
@@ -2648,7 +3552,10 @@ impl KeyPressFocus for LapceEditorBufferData {
                     if !selection.is_empty() {
                         let first = selection.first().unwrap();
                         let (start, end) = if first.is_caret() {
-                            self.buffer.select_word(first.start())
+                            self.buffer.select_word(
+                                first.start(),
+                                &self.config.editor.word_chars,
+                            )
                         } else {
                             (first.min(), first.max())
                         };
@@ -2679,12 +3586,15 @@ impl KeyPressFocus for LapceEditorBufferData {
                         for region in selection.regions_mut() {
                             if region.is_caret() {
                                 had_caret = true;
-                                let (start, end) =
-                                    self.buffer.select_word(region.start());
+                                let (start, end) = self.buffer.select_word(
+                                    region.start(),
+                                    &self.config.editor.word_chars,
+                                );
                                 region.start = start;
                                 region.end = end;
                             }
                         }
+                        selection.merge_overlapping();
                         if !had_caret {
                             let r = selection.last_inserted().unwrap();
                             let search_str =
@@ -2727,7 +3637,10 @@ impl KeyPressFocus for LapceEditorBufferData {
                     if !selection.is_empty() {
                         let r = selection.last_inserted().unwrap();
                         if r.is_caret() {
-                            let (start, end) = self.buffer.select_word(r.start());
+                            let (start, end) = self.buffer.select_word(
+                                r.start(),
+                                &self.config.editor.word_chars,
+                            );
                             selection.replace_last_inserted_region(SelRegion::new(
                                 start, end, None,
                             ));
@@ -2786,6 +3699,116 @@ impl KeyPressFocus for LapceEditorBufferData {
                 self.next_diff(ctx, env);
             }
             LapceCommand::PreviousDiff => {}
+            LapceCommand::NextChange | LapceCommand::PreviousChange => {
+                let line = self.editor.cursor.current_line(&self.buffer);
+                // In a diff view the changes are the ones against the other side
+                let hunks = match self.editor.compare.as_ref() {
+                    Some(history) => self.buffer.change_hunks_of(history),
+                    None => self.buffer.change_hunks(),
+                };
+                let hunk = if cmd == &LapceCommand::NextChange {
+                    hunks.iter().find(|hunk| hunk.lines.start > line)
+                } else {
+                    hunks.iter().rev().find(|hunk| hunk.lines.start < line)
+                };
+                if let Some(hunk) = hunk {
+                    let line = hunk.lines.start.min(self.buffer.last_line());
+                    self.do_move(
+                        &Movement::Line(LinePosition::Line(line + 1)),
+                        1,
+                        mods,
+                    );
+                }
+            }
+            LapceCommand::RevertChange => {
+                let line = self.editor.cursor.current_line(&self.buffer);
+                let edit = self
+                    .buffer
+                    .change_hunks()
+                    .iter()
+                    .find(|hunk| hunk.contains_line(line))
+                    .and_then(|hunk| self.buffer.revert_hunk_edit(hunk));
+                if let Some((selection, content)) = edit {
+                    let delta =
+                        self.edit(&[(&selection, &content)], true, EditType::Other);
+                    Arc::make_mut(&mut self.editor).cursor.apply_delta(&delta);
+                }
+            }
+            LapceCommand::DiffCopyToRight => {
+                let history = match self.editor.compare.clone() {
+                    Some(history) => history,
+                    None => return CommandExecuted::Yes,
+                };
+                let line = self.editor.cursor.current_line(&self.buffer);
+                let edit = self
+                    .buffer
+                    .change_hunks_of(&history)
+                    .iter()
+                    .find(|hunk| hunk.contains_line(line))
+                    .and_then(|hunk| {
+                        self.buffer.revert_hunk_edit_of(&history, hunk)
+                    });
+                if let Some((selection, content)) = edit {
+                    let delta =
+                        self.edit(&[(&selection, &content)], true, EditType::Other);
+                    Arc::make_mut(&mut self.editor).cursor.apply_delta(&delta);
+                }
+            }
+            LapceCommand::DiffCopyToLeft => {
+                // Only another file can be edited, not the git HEAD
+                let history = match self.editor.compare.clone() {
+                    Some(history) => history,
+                    None => return CommandExecuted::Yes,
+                };
+                let other_path = match history_file(&history) {
+                    Some(path) => path,
+                    None => return CommandExecuted::Yes,
+                };
+                let line = self.editor.cursor.current_line(&self.buffer);
+                let hunk = match self
+                    .buffer
+                    .change_hunks_of(&history)
+                    .into_iter()
+                    .find(|hunk| hunk.contains_line(line))
+                {
+                    Some(hunk) => hunk,
+                    None => return CommandExecuted::Yes,
+                };
+                let content = self
+                    .buffer
+                    .slice_to_cow(
+                        self.buffer.offset_of_line(hunk.lines.start)
+                            ..self.buffer.offset_of_line(hunk.lines.end),
+                    )
+                    .to_string();
+                let other = match self.main_split.open_files.get(&other_path) {
+                    Some(other) => other,
+                    None => return CommandExecuted::Yes,
+                };
+                // The hunk was found in the content the other file had when
+                // it was last compared, it's only copied if that's still it
+                let unchanged =
+                    self.buffer.history_revs.get(&history) == Some(&other.rev);
+                if unchanged && !other.read_only {
+                    let selection = Selection::region(
+                        other.offset_of_line(hunk.head.start),
+                        other.offset_of_line(hunk.head.end),
+                    );
+                    self.main_split.edit(
+                        &other_path,
+                        &[(&selection, &content)],
+                        EditType::Other,
+                        &self.config,
+                    );
+                }
+                let (rope, rev) = match self.main_split.open_files.get(&other_path) {
+                    Some(other) => (other.rope.clone(), other.rev),
+                    None => return CommandExecuted::Yes,
+                };
+                let buffer = self.buffer_mut();
+                buffer.load_history(&history, rope);
+                buffer.history_revs.insert(history, rev);
+            }
             LapceCommand::ListNext => {
                 let completion = Arc::make_mut(&mut self.completion);
                 completion.next();
@@ -2794,6 +3817,24 @@ impl KeyPressFocus for LapceEditorBufferData {
                 let completion = Arc::make_mut(&mut self.completion);
                 completion.previous();
             }
+            LapceCommand::AcceptCompletionPrefix => {
+                if let Some(prefix) = self.completion.common_prefix() {
+                    let start = self.completion.offset;
+                    let selection = Selection::region(
+                        start,
+                        start + self.completion.input.len(),
+                    );
+                    let delta = self.edit(
+                        &[(&selection, prefix.as_str())],
+                        true,
+                        EditType::InsertChars,
+                    );
+                    let selection =
+                        selection.apply_delta(&delta, true, InsertDrift::Default);
+                    self.set_cursor_after_change(selection);
+                    self.update_completion(ctx);
+                }
+            }
             LapceCommand::ModalClose => {
                 if self.has_completions() {
                     self.cancel_completion();
@@ -2802,6 +3843,8 @@ impl KeyPressFocus for LapceEditorBufferData {
                 if self.has_hover() {
                     self.cancel_hover();
                 }
+
+                self.cancel_signature();
             }
             LapceCommand::JumpToNextSnippetPlaceholder => {
                 if let Some(snippet) = self.editor.snippet.as_ref() {
@@ -2929,86 +3972,26 @@ impl KeyPressFocus for LapceEditorBufferData {
                     .edit_selection(&self.buffer, self.config.editor.tab_width);
                 self.outdent_line(selection);
             }
-            LapceCommand::ToggleLineComment => {
-                let mut lines = HashSet::new();
-                let selection = self
-                    .editor
-                    .cursor
-                    .edit_selection(&self.buffer, self.config.editor.tab_width);
-                let comment_token = self
-                    .buffer
-                    .syntax
-                    .as_ref()
-                    .map(|s| s.language.comment_token())
-                    .unwrap_or("//")
-                    .to_string();
-                let mut had_comment = true;
-                let mut smallest_indent = usize::MAX;
-                for region in selection.regions() {
-                    let mut line = self.buffer.line_of_offset(region.min());
-                    let end_line = self.buffer.line_of_offset(region.max());
-                    let end_line_offset = self.buffer.offset_of_line(end_line);
-                    let end = if end_line > line && region.max() == end_line_offset {
-                        end_line_offset
-                    } else {
-                        self.buffer.offset_of_line(end_line + 1)
-                    };
-                    let start = self.buffer.offset_of_line(line);
-                    for content in self.buffer.rope.lines(start..end) {
-                        let trimed_content = content.trim_start();
-                        if trimed_content.is_empty() {
-                            line += 1;
-                            continue;
-                        }
-                        let indent = content.len() - trimed_content.len();
-                        if indent < smallest_indent {
-                            smallest_indent = indent;
-                        }
-                        if !trimed_content.starts_with(&comment_token) {
-                            had_comment = false;
-                            lines.insert((line, indent, 0));
-                        } else {
-                            let had_space_after_comment =
-                                trimed_content.chars().nth(comment_token.len())
-                                    == Some(' ');
-                            lines.insert((
-                                line,
-                                indent,
-                                comment_token.len()
-                                    + if had_space_after_comment { 1 } else { 0 },
-                            ));
-                        }
-                        line += 1;
-                    }
-                }
-
-                let delta = if had_comment {
-                    let mut selection = Selection::new();
-                    for (line, indent, len) in lines.iter() {
-                        let start = self.buffer.offset_of_line(*line) + indent;
-                        selection.add_region(SelRegion::new(
-                            start,
-                            start + len,
-                            None,
-                        ))
-                    }
-                    self.edit(&[(&selection, "")], true, EditType::Delete)
+            LapceCommand::ToggleLineComment | LapceCommand::ToggleBlockComment => {
+                let toggle = if cmd == &LapceCommand::ToggleLineComment {
+                    toggle_line_comment
                 } else {
-                    let mut selection = Selection::new();
-                    for (line, _, _) in lines.iter() {
-                        let start =
-                            self.buffer.offset_of_line(*line) + smallest_indent;
-                        selection.add_region(SelRegion::new(start, start, None))
-                    }
-                    self.edit(
-                        &[(&selection, &(comment_token + " "))],
-                        true,
-                        EditType::InsertChars,
-                    )
+                    toggle_block_comment
                 };
-                Arc::make_mut(&mut self.editor).cursor.apply_delta(&delta);
-            }
-            LapceCommand::NormalMode => {
+                if let Some((edits, edit_type)) = toggle(
+                    &self.buffer,
+                    &self.editor.cursor,
+                    self.config.editor.tab_width,
+                ) {
+                    let edits: Vec<(&Selection, &str)> = edits
+                        .iter()
+                        .map(|(selection, content)| (selection, content.as_str()))
+                        .collect();
+                    let delta = self.edit(&edits, true, edit_type);
+                    Arc::make_mut(&mut self.editor).cursor.apply_delta(&delta);
+                }
+            }
+            LapceCommand::NormalMode => {
                 if !self.config.lapce.modal {
                     if let CursorMode::Insert(selection) = &self.editor.cursor.mode {
                         match selection.regions().len() {
@@ -3062,14 +4045,24 @@ impl KeyPressFocus for LapceEditorBufferData {
                     }
                     CursorMode::Normal(offset) => *offset,
                 };
+                // Only leaving insert mode moves the caret, outside of it the
+                // column to go back to on vertical moves stays the same
+                let horiz = if self.editor.cursor.is_insert() {
+                    None
+                } else {
+                    self.editor.cursor.horiz
+                };
                 self.buffer_mut().update_edit_type();
+                self.save_last_visual();
 
                 let editor = Arc::make_mut(&mut self.editor);
                 editor.cursor.mode = CursorMode::Normal(offset);
-                editor.cursor.horiz = None;
+                editor.cursor.horiz = horiz;
                 editor.snippet = None;
                 editor.inline_find = None;
+                editor.surround = None;
                 self.cancel_completion();
+                self.cancel_signature();
             }
             LapceCommand::ToggleCodeLens => {
                 let editor = Arc::make_mut(&mut self.editor);
@@ -3077,7 +4070,9 @@ impl KeyPressFocus for LapceEditorBufferData {
             }
             LapceCommand::GotoDefinition => {
                 let offset = self.editor.cursor.offset();
-                let start_offset = self.buffer.prev_code_boundary(offset);
+                let start_offset = self
+                    .buffer
+                    .prev_code_boundary(offset, &self.config.editor.word_chars);
                 let start_position = self
                     .buffer
                     .offset_to_position(start_offset, self.config.editor.tab_width);
@@ -3169,6 +4164,9 @@ impl KeyPressFocus for LapceEditorBufferData {
                     ));
                 }
             }
+            LapceCommand::TriggerCompletion => {
+                self.request_completion(ctx, true);
+            }
             LapceCommand::ShowCodeActions => {
                 if let Some(actions) = self.current_code_actions() {
                     if !actions.is_empty() {
@@ -3202,7 +4200,9 @@ impl KeyPressFocus for LapceEditorBufferData {
                     }
                 };
                 let pattern = if region.is_caret() {
-                    let (start, end) = self.buffer.select_word(region.start);
+                    let (start, end) = self
+                        .buffer
+                        .select_word(region.start, &self.config.editor.word_chars);
                     self.buffer.slice_to_cow(start..end).to_string()
                 } else {
                     self.buffer
@@ -3239,7 +4239,9 @@ impl KeyPressFocus for LapceEditorBufferData {
             LapceCommand::SearchWholeWordForward => {
                 Arc::make_mut(&mut self.find).visual = true;
                 let offset = self.editor.cursor.offset();
-                let (start, end) = self.buffer.select_word(offset);
+                let (start, end) = self
+                    .buffer
+                    .select_word(offset, &self.config.editor.word_chars);
                 let word = self.buffer.slice_to_cow(start..end).to_string();
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -3339,7 +4341,16 @@ impl KeyPressFocus for LapceEditorBufferData {
             }
             LapceCommand::RepeatLastInlineFind => {
                 if let Some((direction, c)) = self.editor.last_inline_find.clone() {
-                    self.inline_find(direction, &c);
+                    self.inline_find(direction, &c, count.unwrap_or(1));
+                }
+            }
+            LapceCommand::RepeatLastInlineFindReverse => {
+                if let Some((direction, c)) = self.editor.last_inline_find.clone() {
+                    let direction = match direction {
+                        InlineFindDirection::Left => InlineFindDirection::Right,
+                        InlineFindDirection::Right => InlineFindDirection::Left,
+                    };
+                    self.inline_find(direction, &c, count.unwrap_or(1));
                 }
             }
             LapceCommand::InlineFindLeft => {
@@ -3350,6 +4361,17 @@ impl KeyPressFocus for LapceEditorBufferData {
                 Arc::make_mut(&mut self.editor).inline_find =
                     Some(InlineFindDirection::Right);
             }
+            LapceCommand::AddSurround => {
+                Arc::make_mut(&mut self.editor).surround = Some(SurroundAction::Add);
+            }
+            LapceCommand::DeleteSurround => {
+                Arc::make_mut(&mut self.editor).surround =
+                    Some(SurroundAction::Delete);
+            }
+            LapceCommand::ChangeSurround => {
+                Arc::make_mut(&mut self.editor).surround =
+                    Some(SurroundAction::Change(None));
+            }
             LapceCommand::JoinLines => {
                 let offset = self.editor.cursor.offset();
                 let (line, _col) = self
@@ -3396,13 +4418,62 @@ impl KeyPressFocus for LapceEditorBufferData {
                     });
                 }
             }
+            LapceCommand::SetLineEndingLf | LapceCommand::SetLineEndingCrlf => {
+                let line_ending = if cmd == &LapceCommand::SetLineEndingLf {
+                    LineEnding::Lf
+                } else {
+                    LineEnding::CrLf
+                };
+                if self.buffer.line_ending != line_ending {
+                    let buffer = self.buffer_mut();
+                    buffer.line_ending = line_ending;
+                    // The content on disk differs now, even though the text
+                    // of the buffer is the same
+                    buffer.dirty = true;
+                }
+            }
+            LapceCommand::ReloadFromDisk => {
+                if let BufferContent::File(_) = &self.buffer.content {
+                    // The proxy bumps the revision and sends the content
+                    // back, which clears the dirty state of the buffer
+                    self.proxy.reload_buffer(self.buffer.id);
+                }
+            }
+            LapceCommand::ToggleReadOnly => {
+                if let BufferContent::File(_) = &self.buffer.content {
+                    if !self.buffer.binary {
+                        let buffer = self.buffer_mut();
+                        buffer.read_only = !buffer.read_only;
+                    }
+                }
+            }
+            LapceCommand::KeepUnsavedChanges => {
+                if self.buffer.changed_on_disk {
+                    self.buffer_mut().changed_on_disk = false;
+                }
+            }
             LapceCommand::Save => {
-                if !self.buffer.dirty {
+                // A scratch buffer can be saved as a file before any edits
+                let scratch =
+                    matches!(self.buffer.content, BufferContent::Scratch(..));
+                if (!self.buffer.dirty && !scratch) || self.buffer.binary {
                     return CommandExecuted::Yes;
                 }
 
-                if let BufferContent::File(path) = &self.buffer.content {
+                if let BufferContent::Scratch(id, name) = &self.buffer.content {
+                    let id = *id;
+                    // Nothing is changed until there is a path to save to
+                    if let Some(path) = self.main_split.pick_scratch_path(name) {
+                        self.clean_up_whitespace();
+                        ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::SaveScratchBuffer(id, path),
+                            Target::Widget(*self.main_split.tab_id),
+                        ));
+                    }
+                } else if let BufferContent::File(path) = &self.buffer.content {
                     let path = path.clone();
+                    self.clean_up_whitespace();
                     let proxy = self.proxy.clone();
                     let buffer_id = self.buffer.id;
                     let rev = self.buffer.rev;
@@ -3437,81 +4508,94 @@ impl KeyPressFocus for LapceEditorBufferData {
     }
 
     fn receive_char(&mut self, ctx: &mut EventCtx, c: &str) {
+        if self.get_mode() == Mode::Insert && self.is_read_only() {
+            return;
+        }
+        if self.editor.stats.is_some() {
+            Arc::make_mut(&mut self.editor).stats = None;
+        }
         if self.get_mode() == Mode::Insert {
-            let mut selection = self
+            let selection = self
                 .editor
                 .cursor
                 .edit_selection(&self.buffer, self.config.editor.tab_width);
-            let cursor_char =
-                self.buffer.char_at_offset(selection.get_cursor_offset());
 
-            let mut content = c.to_string();
+            let mut edits = vec![(selection.clone(), c.to_string())];
+            let mut auto_close = None;
             if c.chars().count() == 1 {
                 let c = c.chars().next().unwrap();
-                if !matching_pair_direction(c).unwrap_or(true) {
-                    if cursor_char == Some(c) {
+                match auto_pair_edit(
+                    &self.buffer,
+                    &selection,
+                    c,
+                    &self.config.editor,
+                ) {
+                    AutoPairEdit::StepOver => {
                         self.do_move(&Movement::Right, 1, Modifiers::empty());
                         return;
-                    } else {
-                        let offset = selection.get_cursor_offset();
-                        let line = self.buffer.line_of_offset(offset);
-                        let line_start = self.buffer.offset_of_line(line);
-                        if self.buffer.slice_to_cow(line_start..offset).trim() == ""
-                        {
-                            if let Some(c) = matching_char(c) {
-                                if let Some(previous_offset) =
-                                    self.buffer.previous_unmatched(c, offset)
-                                {
-                                    let previous_line =
-                                        self.buffer.line_of_offset(previous_offset);
-                                    let line_indent =
-                                        self.buffer.indent_on_line(previous_line);
-                                    content = line_indent + &content;
-                                    selection =
-                                        Selection::region(line_start, offset);
-                                }
-                            }
-                        };
                     }
+                    AutoPairEdit::Wrap(close) => {
+                        self.wrap_selection(&selection, c, close);
+                        return;
+                    }
+                    AutoPairEdit::Close(close) => auto_close = Some(close),
+                    AutoPairEdit::Insert => {}
+                }
+                if let Some(bracket_edits) =
+                    closing_bracket_edits(&self.buffer, &selection, c)
+                {
+                    edits = bracket_edits;
                 }
             }
 
-            let delta =
-                self.edit(&[(&selection, &content)], true, EditType::InsertChars);
+            let delta = self.edit(
+                &edits
+                    .iter()
+                    .map(|(selection, s)| (selection, s.as_str()))
+                    .collect::<Vec<(&Selection, &str)>>(),
+                true,
+                EditType::InsertChars,
+            );
             let selection =
                 selection.apply_delta(&delta, true, InsertDrift::Default);
             let editor = Arc::make_mut(&mut self.editor);
             editor.cursor.mode = CursorMode::Insert(selection.clone());
             editor.cursor.horiz = None;
-            if c.chars().count() == 1 {
-                let c = c.chars().next().unwrap();
-                let is_whitespace_or_punct = cursor_char
-                    .map(|c| {
-                        let prop = get_word_property(c);
-                        prop == WordProperty::Lf
-                            || prop == WordProperty::Space
-                            || prop == WordProperty::Punctuation
-                    })
-                    .unwrap_or(true);
-                if is_whitespace_or_punct
-                    && matching_pair_direction(c).unwrap_or(false)
-                {
-                    if let Some(c) = matching_char(c) {
-                        self.edit(
-                            &[(&selection, &c.to_string())],
-                            false,
-                            EditType::InsertChars,
-                        );
-                    }
-                }
+            if let Some(close) = auto_close {
+                self.edit(
+                    &[(&selection, &close.to_string())],
+                    false,
+                    EditType::InsertChars,
+                );
             }
             self.update_completion(ctx);
             self.cancel_hover();
+            if self
+                .buffer
+                .signature_trigger_characters
+                .iter()
+                .any(|trigger| trigger == c)
+            {
+                self.update_signature(ctx);
+            } else if c == ")" {
+                self.cancel_signature();
+            }
         } else if let Some(direction) = self.editor.inline_find.clone() {
-            self.inline_find(direction.clone(), c);
+            self.inline_find(direction.clone(), c, 1);
             let editor = Arc::make_mut(&mut self.editor);
             editor.last_inline_find = Some((direction, c.to_string()));
             editor.inline_find = None;
+        } else if let Some(action) = self.editor.surround.clone() {
+            Arc::make_mut(&mut self.editor).surround = None;
+            let mut chars = c.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                if action == SurroundAction::Change(None) {
+                    Arc::make_mut(&mut self.editor).surround =
+                        Some(SurroundAction::Change(Some(c)));
+                } else {
+                    self.surround(&action, c);
+                }
+            }
         }
     }
 }
@@ -3586,31 +4670,898 @@ fn next_in_file_errors_offset(
     ((*file_diagnostics[0].0).clone(), file_diagnostics[0].1[0])
 }
 
-fn str_is_pair_left(c: &str) -> bool {
-    if c.chars().count() == 1 {
-        let c = c.chars().next().unwrap();
-        if matching_pair_direction(c).unwrap_or(false) {
-            return true;
+/// The edits of the toggle line comment command for the cursor, with the
+/// comment token of the language of the buffer, and the type of edit they
+/// are. `None` if there's nothing to do.
+pub(crate) fn toggle_line_comment(
+    buffer: &Buffer,
+    cursor: &Cursor,
+    tab_width: usize,
+) -> Option<(Vec<(Selection, String)>, EditType)> {
+    let selection = cursor.edit_selection(buffer, tab_width);
+    let token = buffer
+        .syntax
+        .as_ref()
+        .map(|s| s.language.comment_token())
+        .unwrap_or("//");
+    let (selection, content) = toggle_line_comment_edit(buffer, &selection, token)?;
+    let edit_type = if content.is_empty() {
+        EditType::Delete
+    } else {
+        EditType::InsertChars
+    };
+    Some((vec![(selection, content)], edit_type))
+}
+
+/// The edits of the toggle block comment command for the cursor, which
+/// comments out the line of the cursor in normal mode, with the block
+/// comment tokens of the language of the buffer, and the type of edit they
+/// are. `None` if there's nothing to do.
+pub(crate) fn toggle_block_comment(
+    buffer: &Buffer,
+    cursor: &Cursor,
+    tab_width: usize,
+) -> Option<(Vec<(Selection, String)>, EditType)> {
+    let selection = match cursor.mode {
+        CursorMode::Normal(offset) => Selection::caret(offset),
+        _ => cursor.edit_selection(buffer, tab_width),
+    };
+    let (open, close) = match buffer.syntax.as_ref() {
+        Some(syntax) => syntax.language.block_comment_tokens()?,
+        None => ("/*", "*/"),
+    };
+    let edits = toggle_block_comment_edits(buffer, &selection, open, close);
+    let edit_type = if edits.first()?.1.is_empty() {
+        EditType::Delete
+    } else {
+        EditType::InsertChars
+    };
+    Some((edits, edit_type))
+}
+
+/// The edit that comments out the lines of the selection with `token`,
+/// lined up at their smallest indent, or uncomments them if all of them are
+/// commented already. Blank lines are left alone, and `None` is returned if
+/// there's nothing to do or the language has no line comments.
+fn toggle_line_comment_edit(
+    buffer: &Buffer,
+    selection: &Selection,
+    token: &str,
+) -> Option<(Selection, String)> {
+    if token.is_empty() {
+        return None;
+    }
+
+    let mut lines = BTreeMap::new();
+    let mut had_comment = true;
+    let mut smallest_indent = usize::MAX;
+    for region in selection.regions() {
+        let mut line = buffer.line_of_offset(region.min());
+        let end_line = buffer.line_of_offset(region.max());
+        let end_line_offset = buffer.offset_of_line(end_line);
+        let end = if end_line > line && region.max() == end_line_offset {
+            end_line_offset
+        } else {
+            buffer.offset_of_line(end_line + 1)
+        };
+        let start = buffer.offset_of_line(line);
+        for content in buffer.rope.lines(start..end) {
+            let trimed_content = content.trim_start();
+            if trimed_content.is_empty() {
+                line += 1;
+                continue;
+            }
+            let indent = content.len() - trimed_content.len();
+            if indent < smallest_indent {
+                smallest_indent = indent;
+            }
+            if let Some(rest) = trimed_content.strip_prefix(token) {
+                let len = token.len() + if rest.starts_with(' ') { 1 } else { 0 };
+                lines.insert(line, (indent, len));
+            } else {
+                had_comment = false;
+                lines.insert(line, (indent, 0));
+            }
+            line += 1;
+        }
+    }
+    if lines.is_empty() {
+        return None;
+    }
+
+    let mut edit_selection = Selection::new();
+    for (line, (indent, len)) in lines {
+        let start = buffer.offset_of_line(line);
+        let region = if had_comment {
+            SelRegion::new(start + indent, start + indent + len, None)
+        } else {
+            SelRegion::caret(start + smallest_indent)
+        };
+        edit_selection.add_region(region);
+    }
+    let content = if had_comment {
+        "".to_string()
+    } else {
+        format!("{token} ")
+    };
+    Some((edit_selection, content))
+}
+
+/// The edits that wrap the text of each region, or of the line of each
+/// caret, in `open` and `close`, or take them away again if all of them are
+/// wrapped already. Whitespace around the text stays outside of the comment.
+fn toggle_block_comment_edits(
+    buffer: &Buffer,
+    selection: &Selection,
+    open: &str,
+    close: &str,
+) -> Vec<(Selection, String)> {
+    let ranges: Vec<(usize, usize)> = selection
+        .regions()
+        .iter()
+        .filter_map(|region| {
+            let (start, end) = if region.is_caret() {
+                let line = buffer.line_of_offset(region.start);
+                (
+                    buffer.offset_of_line(line),
+                    buffer.line_end_offset(line, true),
+                )
+            } else {
+                (region.min(), region.max())
+            };
+            let text = buffer.slice_to_cow(start..end);
+            let trimmed = text.trim_start();
+            let start = start + (text.len() - trimmed.len());
+            let end = start + trimmed.trim_end().len();
+            if start < end {
+                Some((start, end))
+            } else {
+                None
+            }
+        })
+        .collect();
+    if ranges.is_empty() {
+        return Vec::new();
+    }
+
+    let is_commented = |(start, end): &(usize, usize)| {
+        let text = buffer.slice_to_cow(*start..*end);
+        text.len() >= open.len() + close.len()
+            && text.starts_with(open)
+            && text.ends_with(close)
+    };
+    if ranges.iter().all(is_commented) {
+        let mut selection = Selection::new();
+        for (start, end) in ranges {
+            let inner = buffer
+                .slice_to_cow(start + open.len()..end - close.len())
+                .to_string();
+            let open_len = open.len() + if inner.starts_with(' ') { 1 } else { 0 };
+            let inner = &inner[open_len - open.len()..];
+            let close_len = close.len()
+                + if !inner.is_empty() && inner.ends_with(' ') {
+                    1
+                } else {
+                    0
+                };
+            selection.add_region(SelRegion::new(start, start + open_len, None));
+            selection.add_region(SelRegion::new(end - close_len, end, None));
+        }
+        vec![(selection, "".to_string())]
+    } else {
+        let mut starts = Selection::new();
+        let mut ends = Selection::new();
+        for (start, end) in ranges {
+            starts.add_region(SelRegion::caret(start));
+            ends.add_region(SelRegion::caret(end));
+        }
+        vec![(starts, format!("{open} ")), (ends, format!(" {close}"))]
+    }
+}
+
+/// The edits that break the line at each region of the selection, with the
+/// indent the new line gets and the closing bracket right after the cursor
+/// moved to a line of its own, and the carets after them
+pub(crate) fn new_line_edits(
+    buffer: &Buffer,
+    selection: &Selection,
+) -> (Vec<(Selection, String)>, Selection) {
+    let mut edits = Vec::new();
+    let mut new_selection = Selection::new();
+    let mut shift = 0i32;
+    for region in selection.regions() {
+        let offset = region.max();
+        let line = buffer.line_of_offset(offset);
+        let line_start = buffer.offset_of_line(line);
+        let line_end = buffer.line_end_offset(line, true);
+        let line_indent = buffer.indent_on_line(line);
+        let first_half = buffer.slice_to_cow(line_start..offset).to_string();
+        let second_half = buffer.slice_to_cow(offset..line_end).to_string();
+
+        let indent_unit = buffer.indent_unit();
+        let indent = match buffer.syntax.as_ref() {
+            Some(syntax) => new_line_indent(
+                syntax.language,
+                &first_half,
+                &line_indent,
+                indent_unit,
+            ),
+            None if has_unmatched_pair(&first_half) => {
+                format!("{}{}", line_indent, indent_unit)
+            }
+            None => line_indent.clone(),
+        };
+        // Splitting a line that a more indented block follows puts the
+        // new line in that block
+        let indent = if indent == line_indent {
+            let next_line_indent = buffer.indent_on_line(line + 1);
+            if next_line_indent.len() > line_indent.len() {
+                next_line_indent
+            } else {
+                indent
+            }
+        } else {
+            indent
+        };
+
+        let selection = Selection::region(region.min(), region.max());
+        let mut content = format!("{}{}", "\n", indent);
+
+        shift -= (region.max() - region.min()) as i32;
+        shift += content.len() as i32;
+        new_selection
+            .add_region(SelRegion::caret((region.max() as i32 + shift) as usize));
+
+        // The closing bracket right after the cursor goes on a line of
+        // its own, in the same edit so that it's undone with the newline
+        for c in first_half.chars().rev() {
+            if c != ' ' {
+                if let Some(pair_start) = matching_pair_direction(c) {
+                    if pair_start {
+                        if let Some(c) = matching_char(c) {
+                            if second_half.trim().starts_with(&c.to_string()) {
+                                let extra = format!("{}{}", "\n", line_indent);
+                                shift += extra.len() as i32;
+                                content.push_str(&extra);
+                            }
+                        }
+                    }
+                }
+                break;
+            }
+        }
+
+        edits.push((selection, content));
+    }
+
+    (edits, new_selection)
+}
+
+/// The edits that dedent a closing bracket typed on a line that is blank up
+/// to each caret, lining it up with the line of the bracket it closes, or
+/// one indent unit less when that bracket isn't found
+pub(crate) fn closing_bracket_edits(
+    buffer: &Buffer,
+    selection: &Selection,
+    c: char,
+) -> Option<Vec<(Selection, String)>> {
+    if matching_pair_direction(c)? {
+        return None;
+    }
+    let open = matching_char(c)?;
+    let mut edits = Vec::new();
+    for region in selection.regions() {
+        if !region.is_caret() {
+            return None;
+        }
+        let offset = region.start;
+        let line = buffer.line_of_offset(offset);
+        let line_start = buffer.offset_of_line(line);
+        let before = buffer.slice_to_cow(line_start..offset);
+        if before.trim() != "" {
+            return None;
+        }
+        let indent = match buffer.previous_unmatched(open, offset) {
+            Some(open_offset) => {
+                buffer.indent_on_line(buffer.line_of_offset(open_offset))
+            }
+            None => dedent(&before, buffer.indent_unit()).to_string(),
+        };
+        edits.push((
+            Selection::region(line_start, offset),
+            format!("{indent}{c}"),
+        ));
+    }
+    Some(edits)
+}
+
+/// How typing a char in insert mode goes with the auto-closed pairs
+#[derive(Debug, PartialEq)]
+pub(crate) enum AutoPairEdit {
+    /// Move over the same closing char after the caret rather than adding
+    /// another one
+    StepOver,
+    /// Put the char and the closing char around the selections, instead of
+    /// replacing them
+    Wrap(char),
+    /// Insert the char followed by the closing char
+    Close(char),
+    /// Just insert the char
+    Insert,
+}
+
+/// Whether typing `c` at the selection steps over, wraps or closes one of
+/// the auto-closed pairs of the config. Nothing is closed in strings or
+/// comments, or right before a word, and quotes aren't closed right after
+/// a word either.
+pub(crate) fn auto_pair_edit(
+    buffer: &Buffer,
+    selection: &Selection,
+    c: char,
+    config: &EditorConfig,
+) -> AutoPairEdit {
+    let regions = selection.regions();
+    if regions.is_empty() {
+        return AutoPairEdit::Insert;
+    }
+    let offset = selection.get_cursor_offset();
+    let next_char = buffer.char_at_offset(offset);
+
+    if regions.iter().all(|region| !region.is_caret()) {
+        return match config.auto_closing_char(c) {
+            Some(close) => AutoPairEdit::Wrap(close),
+            None => AutoPairEdit::Insert,
+        };
+    }
+    if !regions.iter().all(|region| region.is_caret()) {
+        return AutoPairEdit::Insert;
+    }
+
+    if config.is_auto_closing_char(c) && next_char == Some(c) {
+        return AutoPairEdit::StepOver;
+    }
+
+    let close = match config.auto_closing_char(c) {
+        Some(close) => close,
+        None => return AutoPairEdit::Insert,
+    };
+    if buffer.in_string_or_comment(offset) {
+        return AutoPairEdit::Insert;
+    }
+    let is_word = |c: Option<char>| {
+        c.map(|c| get_word_property(c) == WordProperty::Other)
+            .unwrap_or(false)
+    };
+    if is_word(next_char) {
+        return AutoPairEdit::Insert;
+    }
+    if close == c {
+        let prev_char = if offset > 0 {
+            buffer.char_at_offset(buffer.prev_grapheme_offset(offset, 1, 0))
+        } else {
+            None
+        };
+        if is_word(prev_char) || prev_char == Some(c) {
+            return AutoPairEdit::Insert;
         }
     }
-    false
+    AutoPairEdit::Close(close)
 }
 
-#[allow(dead_code)]
-fn str_is_pair_right(c: &str) -> bool {
-    if c.chars().count() == 1 {
-        let c = c.chars().next().unwrap();
-        return !matching_pair_direction(c).unwrap_or(true);
+/// The edits that put `open` and `close` around each region of the selection
+pub(crate) fn wrap_selection_edits(
+    selection: &Selection,
+    open: char,
+    close: char,
+) -> Vec<(Selection, String)> {
+    let mut starts = Selection::new();
+    let mut ends = Selection::new();
+    for region in selection.regions() {
+        starts.add_region(SelRegion::caret(region.min()));
+        ends.add_region(SelRegion::caret(region.max()));
     }
-    false
+    vec![(starts, open.to_string()), (ends, close.to_string())]
 }
 
-fn str_matching_pair(c: &str) -> Option<char> {
-    if c.chars().count() == 1 {
-        let c = c.chars().next().unwrap();
-        return matching_char(c);
+/// The selection after wrapping it with `wrap_selection_edits`, with each
+/// region over the same text between the pair
+pub(crate) fn selection_in_wrap(
+    selection: &Selection,
+    open: char,
+    close: char,
+) -> Selection {
+    let pair_len = open.len_utf8() + close.len_utf8();
+    let mut new_selection = Selection::new();
+    for (i, region) in selection.regions().iter().enumerate() {
+        // Each region before this one got a pair around it
+        let shift = pair_len * i + open.len_utf8();
+        new_selection.add_region(SelRegion::new(
+            region.start + shift,
+            region.end + shift,
+            None,
+        ));
+    }
+    new_selection
+}
+
+/// The characters that open and close the pair of `c`, which is either half
+/// of a bracket pair or a quote
+pub(crate) fn surround_pair(c: char) -> Option<(char, char)> {
+    match c {
+        '"' | '\'' | '`' => Some((c, c)),
+        '<' | '>' => Some(('<', '>')),
+        _ => {
+            let other = matching_char(c)?;
+            if matching_pair_direction(c)? {
+                Some((c, other))
+            } else {
+                Some((other, c))
+            }
+        }
+    }
+}
+
+/// The offsets of the two halves of the pair of `c` around `offset`. Brackets
+/// are found with the bracket matching, quotes pair up from the start of
+/// the line.
+pub(crate) fn find_surround(
+    buffer: &Buffer,
+    offset: usize,
+    c: char,
+) -> Option<(usize, usize)> {
+    let (open, close) = surround_pair(c)?;
+    if open == close {
+        let line = buffer.line_of_offset(offset);
+        let line_start = buffer.offset_of_line(line);
+        let col = offset - line_start;
+        let quotes: Vec<usize> = buffer
+            .line_content(line)
+            .match_indices(open)
+            .map(|(i, _)| i)
+            .collect();
+        let pair = quotes
+            .chunks_exact(2)
+            .find(|pair| pair[0] <= col && col <= pair[1])?;
+        return Some((line_start + pair[0], line_start + pair[1]));
+    }
+
+    match buffer.char_at_offset(offset) {
+        Some(ch) if ch == open => {
+            let end = buffer.next_unmatched(close, offset + 1)?;
+            Some((offset, end))
+        }
+        Some(ch) if ch == close => {
+            let start = buffer.previous_unmatched(open, offset)?;
+            Some((start, offset))
+        }
+        _ => Some((
+            buffer.previous_unmatched(open, offset)?,
+            buffer.next_unmatched(close, offset)?,
+        )),
+    }
+}
+
+/// The edits of a surround action with the pair of `c`, and the offset of
+/// the opening half of the pair after them
+pub(crate) fn surround_edits(
+    buffer: &Buffer,
+    cursor: &Cursor,
+    action: &SurroundAction,
+    c: char,
+    tab_width: usize,
+    word_chars: &str,
+) -> Option<(Vec<(Selection, String)>, usize)> {
+    match action {
+        SurroundAction::Add => {
+            let (open, close) = surround_pair(c)?;
+            let selection = match cursor.mode {
+                CursorMode::Normal(offset) => {
+                    let (start, end) = buffer.select_word(offset, word_chars);
+                    Selection::region(start, end)
+                }
+                _ => cursor.edit_selection(buffer, tab_width),
+            };
+            let mut starts = Selection::new();
+            let mut ends = Selection::new();
+            for region in selection.regions() {
+                if !region.is_caret() {
+                    starts.add_region(SelRegion::caret(region.min()));
+                    ends.add_region(SelRegion::caret(region.max()));
+                }
+            }
+            if starts.is_empty() {
+                return None;
+            }
+            let offset = starts.min_offset();
+            Some((
+                vec![(starts, open.to_string()), (ends, close.to_string())],
+                offset,
+            ))
+        }
+        SurroundAction::Delete => {
+            let (start, end) = find_surround(buffer, cursor.offset(), c)?;
+            let mut selection = Selection::new();
+            selection.add_region(SelRegion::new(start, start + 1, None));
+            selection.add_region(SelRegion::new(end, end + 1, None));
+            Some((vec![(selection, "".to_string())], start))
+        }
+        SurroundAction::Change(old) => {
+            let (start, end) = find_surround(buffer, cursor.offset(), (*old)?)?;
+            let (open, close) = surround_pair(c)?;
+            Some((
+                vec![
+                    (Selection::region(start, start + 1), open.to_string()),
+                    (Selection::region(end, end + 1), close.to_string()),
+                ],
+                start,
+            ))
+        }
+    }
+}
+
+/// Pasting a register at the cursor: the selection it replaces and the text
+/// put in its place
+pub(crate) struct PasteEdit {
+    pub selection: Selection,
+    pub content: String,
+    /// Whether the selection moves after the pasted text rather than
+    /// staying before it
+    pub after: bool,
+    mode: VisualMode,
+    /// Whether the pasted lines start with a newline that was added to
+    /// separate them from a last line without one
+    leading_newline: bool,
+}
+
+impl PasteEdit {
+    pub fn new(
+        buffer: &Buffer,
+        cursor: &Cursor,
+        data: &RegisterData,
+        tab_width: usize,
+    ) -> Self {
+        match data.mode {
+            VisualMode::Normal => {
+                let selection = match cursor.mode {
+                    CursorMode::Normal(offset) => {
+                        let line_end = buffer.offset_line_end(offset, true);
+                        let offset =
+                            buffer.next_grapheme_offset(offset, 1, line_end);
+                        Selection::caret(offset)
+                    }
+                    CursorMode::Insert { .. } | CursorMode::Visual { .. } => {
+                        cursor.edit_selection(buffer, tab_width)
+                    }
+                };
+                Self {
+                    selection,
+                    content: data.content.clone(),
+                    after: cursor.is_insert() || !data.content.contains('\n'),
+                    mode: data.mode,
+                    leading_newline: false,
+                }
+            }
+            VisualMode::Linewise | VisualMode::Blockwise => {
+                let (selection, content, leading_newline) = match &cursor.mode {
+                    CursorMode::Normal(offset) => {
+                        let line = buffer.line_of_offset(*offset);
+                        let offset = buffer.offset_of_line(line + 1);
+                        if buffer.line_of_offset(offset) == line {
+                            // There's no line below to paste in front of, so
+                            // the last line gets the newline the lines are
+                            // pasted after instead
+                            let content = data
+                                .content
+                                .strip_suffix('\n')
+                                .unwrap_or(&data.content);
+                            (
+                                Selection::caret(offset),
+                                "\n".to_string() + content,
+                                true,
+                            )
+                        } else {
+                            (Selection::caret(offset), data.content.clone(), false)
+                        }
+                    }
+                    CursorMode::Insert(selection) => {
+                        let mut selection = selection.clone();
+                        for region in selection.regions_mut() {
+                            if region.is_caret() {
+                                let line = buffer.line_of_offset(region.start);
+                                let start = buffer.offset_of_line(line);
+                                region.start = start;
+                                region.end = start;
+                            }
+                        }
+                        selection.merge_overlapping();
+                        (selection, data.content.clone(), false)
+                    }
+                    CursorMode::Visual { mode, .. } => {
+                        let selection = cursor.edit_selection(buffer, tab_width);
+                        match mode {
+                            VisualMode::Linewise => {
+                                (selection, data.content.clone(), false)
+                            }
+                            _ => (selection, "\n".to_string() + &data.content, true),
+                        }
+                    }
+                };
+                Self {
+                    selection,
+                    content,
+                    after: cursor.is_insert(),
+                    mode: data.mode,
+                    leading_newline,
+                }
+            }
+        }
+    }
+
+    /// The cursor after the paste, given the selection of the edit moved
+    /// over the pasted text
+    pub fn cursor(
+        &self,
+        buffer: &Buffer,
+        cursor: &Cursor,
+        selection: Selection,
+    ) -> Cursor {
+        if cursor.is_insert() {
+            return Cursor::new(CursorMode::Insert(selection), None);
+        }
+        match self.mode {
+            VisualMode::Normal if !self.after => {
+                cursor.after_change(buffer, selection)
+            }
+            VisualMode::Normal => {
+                let offset =
+                    buffer.prev_grapheme_offset(selection.min_offset(), 1, 0);
+                Cursor::new(CursorMode::Normal(offset), None)
+            }
+            VisualMode::Linewise | VisualMode::Blockwise => {
+                let offset = selection.min_offset();
+                let offset = if self.leading_newline {
+                    (offset + 1).min(buffer.len())
+                } else {
+                    offset
+                };
+                let line = buffer.line_of_offset(offset);
+                let offset = buffer.first_non_blank_character_on_line(line);
+                Cursor::new(CursorMode::Normal(offset), None)
+            }
+        }
+    }
+}
+
+/// `dd`, `yy` or `cc` on `count` lines: the lines that go linewise into the
+/// register, and the region that is deleted unless they are yanked
+pub(crate) struct LinewiseEdit {
+    pub data: RegisterData,
+    pub selection: Option<Selection>,
+    mode: MotionMode,
+}
+
+impl LinewiseEdit {
+    /// `None` for the operators that don't go into the register
+    pub fn new(
+        buffer: &Buffer,
+        mode: &MotionMode,
+        line: usize,
+        count: usize,
+    ) -> Option<Self> {
+        let (start, end, content) = linewise_range(&buffer.rope, line, count);
+        let selection = match mode {
+            MotionMode::Yank => None,
+            MotionMode::Delete => Some(Selection::region(start, end)),
+            MotionMode::Change => {
+                // Keep the indentation of the first line
+                let last_line = (line + count - 1).min(buffer.last_line());
+                let end =
+                    buffer.offset_line_end(buffer.offset_of_line(last_line), true);
+                let start = buffer
+                    .first_non_blank_character_on_line(line)
+                    .min(buffer.offset_line_end(buffer.offset_of_line(line), true));
+                Some(Selection::region(start, end.max(start)))
+            }
+            MotionMode::Indent | MotionMode::Outdent => return None,
+        };
+        Some(Self {
+            data: RegisterData {
+                content,
+                mode: VisualMode::Linewise,
+            },
+            selection,
+            mode: mode.clone(),
+        })
+    }
+
+    /// The cursor after the edit, given the selection of the edit moved
+    /// over the deletion
+    pub fn cursor(&self, buffer: &Buffer, selection: Selection) -> Cursor {
+        if self.mode == MotionMode::Change {
+            return Cursor::new(CursorMode::Insert(selection), None);
+        }
+        let line = buffer.line_of_offset(selection.min_offset());
+        let offset = buffer.first_non_blank_character_on_line(line);
+        let offset = buffer.offset_line_end(offset, false).min(offset);
+        Cursor::new(CursorMode::Normal(offset), None)
+    }
+}
+
+/// What backspace deletes: the selections, or before each caret the
+/// character or the indent unit in the indentation, together with the
+/// closing half of an auto-closed pair right after it
+pub(crate) fn delete_backward_selection(
+    buffer: &Buffer,
+    cursor: &Cursor,
+    code_lens: bool,
+    compare: Option<String>,
+    config: &Config,
+) -> Selection {
+    match cursor.mode {
+        CursorMode::Normal(_) | CursorMode::Visual { .. } => {
+            cursor.edit_selection(buffer, config.editor.tab_width)
+        }
+        CursorMode::Insert(_) => {
+            let indent = buffer.indent_unit();
+            let selection = cursor.edit_selection(buffer, config.editor.tab_width);
+            let mut new_selection = Selection::new();
+            for region in selection.regions() {
+                let new_region = if region.is_caret() {
+                    if indent.starts_with('\t') {
+                        buffer.update_region(
+                            region,
+                            1,
+                            &Movement::Left,
+                            Mode::Insert,
+                            true,
+                            code_lens,
+                            compare.clone(),
+                            config,
+                        )
+                    } else {
+                        let line = buffer.line_of_offset(region.start);
+                        let nonblank =
+                            buffer.first_non_blank_character_on_line(line);
+                        let (_, col) = buffer.offset_to_line_col(
+                            region.start,
+                            config.editor.tab_width,
+                        );
+                        let count = if region.start <= nonblank && col > 0 {
+                            let r = col % indent.len();
+                            if r == 0 {
+                                indent.len()
+                            } else {
+                                r
+                            }
+                        } else {
+                            1
+                        };
+                        buffer.update_region(
+                            region,
+                            count,
+                            &Movement::Left,
+                            Mode::Insert,
+                            true,
+                            code_lens,
+                            compare.clone(),
+                            config,
+                        )
+                    }
+                } else {
+                    *region
+                };
+                new_selection.add_region(new_region);
+            }
+
+            let mut selection = new_selection;
+            if selection.regions().len() == 1 {
+                let delete_str = buffer
+                    .slice_to_cow(selection.min_offset()..selection.max_offset())
+                    .to_string();
+                let mut chars = delete_str.chars();
+                let close = match (chars.next(), chars.next()) {
+                    (Some(open), None) => config
+                        .editor
+                        .auto_closing_char(open)
+                        .map(|close| (open, close)),
+                    _ => None,
+                };
+                let offset = selection.max_offset();
+                match close {
+                    // An empty pair of quotes goes away together
+                    Some((open, close)) if open == close => {
+                        if buffer.char_at_offset(offset) == Some(close) {
+                            selection = Selection::region(
+                                selection.min_offset(),
+                                offset + 1,
+                            );
+                        }
+                    }
+                    // and so does a pair of brackets with only whitespace
+                    // in between on the line
+                    Some((_, close)) => {
+                        let line = buffer.line_of_offset(offset);
+                        let line_end = buffer.line_end_offset(line, true);
+                        let content =
+                            buffer.slice_to_cow(offset..line_end).to_string();
+                        if content.trim().starts_with(close) {
+                            let index =
+                                content.match_indices(close).next().unwrap().0;
+                            selection = Selection::region(
+                                selection.min_offset(),
+                                offset + index + 1,
+                            );
+                        }
+                    }
+                    None => {}
+                }
+            }
+            selection
+        }
+    }
+}
+
+/// What deleting by words deletes: the selections, or in insert mode from
+/// each caret or selection to where `movement` takes it
+pub(crate) fn delete_word_selection(
+    buffer: &Buffer,
+    cursor: &Cursor,
+    movement: &Movement,
+    code_lens: bool,
+    compare: Option<String>,
+    config: &Config,
+) -> Selection {
+    let selection = cursor.edit_selection(buffer, config.editor.tab_width);
+    match cursor.mode {
+        CursorMode::Normal(_) | CursorMode::Visual { .. } => selection,
+        CursorMode::Insert(_) => buffer.update_selection(
+            &selection,
+            1,
+            movement,
+            Mode::Insert,
+            true,
+            code_lens,
+            compare,
+            config,
+        ),
+    }
+}
+
+/// What delete deletes: the selections, or the character after each caret
+pub(crate) fn delete_forward_selection(
+    buffer: &Buffer,
+    cursor: &Cursor,
+    code_lens: bool,
+    compare: Option<String>,
+    config: &Config,
+) -> Selection {
+    match cursor.mode {
+        CursorMode::Normal(_) | CursorMode::Visual { .. } => {
+            cursor.edit_selection(buffer, config.editor.tab_width)
+        }
+        CursorMode::Insert(_) => {
+            let selection = cursor.edit_selection(buffer, config.editor.tab_width);
+            let mut new_selection = Selection::new();
+            for region in selection.regions() {
+                let new_region = if region.is_caret() {
+                    buffer.update_region(
+                        region,
+                        1,
+                        &Movement::Right,
+                        Mode::Insert,
+                        true,
+                        code_lens,
+                        compare.clone(),
+                        config,
+                    )
+                } else {
+                    *region
+                };
+                new_selection.add_region(new_region);
+            }
+            new_selection
+        }
     }
-    None
 }
 
 fn process_get_references(
@@ -3,26 +3,50 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use druid::ExtEventSink;
+use druid::{Command, Env, EventCtx, ExtEventSink, Modifiers};
 use druid::{Target, WidgetId};
 
 use include_dir::{include_dir, Dir};
 use lapce_rpc::file::FileNodeItem;
 
+use crate::command::{CommandExecuted, LapceCommand};
+use crate::keypress::KeyPressFocus;
+use crate::movement::Movement;
 use crate::proxy::LapceProxy;
-use crate::state::LapceWorkspace;
+use crate::state::{LapceWorkspace, Mode};
 
 use crate::{command::LapceUICommand, command::LAPCE_UI_COMMAND};
 
 #[allow(dead_code)]
 const ICONS_DIR: Dir = include_dir!("../icons");
 
+/// A file operation started from the explorer that waits for a name to be
+/// typed or for a confirmation
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileOperation {
+    /// Create the file `name` in the directory `dir`
+    NewFile {
+        dir: PathBuf,
+        name: String,
+    },
+    /// Rename `path` to `name` in the same directory
+    Rename {
+        path: PathBuf,
+        name: String,
+    },
+    Delete {
+        path: PathBuf,
+    },
+}
+
 #[derive(Clone)]
 pub struct FileExplorerData {
     pub tab_id: WidgetId,
     pub widget_id: WidgetId,
     pub workspace: Option<FileNodeItem>,
     pub active_selected: usize,
+    pub operation: Option<FileOperation>,
+    proxy: Arc<LapceProxy>,
 
     #[allow(dead_code)]
     count: usize,
@@ -46,29 +70,10 @@ impl FileExplorerData {
                 children: HashMap::new(),
                 children_open_count: 0,
             });
-            let index = 0;
             let path = path.clone();
+            let proxy = proxy.clone();
             std::thread::spawn(move || {
-                proxy.read_dir(
-                    &path.clone(),
-                    Box::new(move |result| {
-                        if let Ok(res) = result {
-                            let resp: Result<Vec<FileNodeItem>, serde_json::Error> =
-                                serde_json::from_value(res);
-                            if let Ok(items) = resp {
-                                let _ = event_sink.submit_command(
-                                    LAPCE_UI_COMMAND,
-                                    LapceUICommand::UpdateExplorerItems(
-                                        index,
-                                        path.clone(),
-                                        items,
-                                    ),
-                                    Target::Widget(tab_id),
-                                );
-                            }
-                        }
-                    }),
-                );
+                read_dir(proxy, tab_id, 0, path, event_sink);
             });
         }
         Self {
@@ -83,10 +88,152 @@ impl FileExplorerData {
                 children_open_count: 0,
             }),
             active_selected: 0,
+            operation: None,
+            proxy,
             count: 0,
         }
     }
 
+    /// Open the file at `index` in the tree, or expand or collapse the
+    /// directory, reading its entries the first time
+    pub fn activate(&mut self, ctx: &mut EventCtx, index: usize) {
+        let tab_id = self.tab_id;
+        let proxy = self.proxy.clone();
+        if let Some(node) = self.get_node_by_index(index) {
+            if node.is_dir {
+                if node.read {
+                    node.open = !node.open;
+                } else {
+                    read_dir(
+                        proxy,
+                        tab_id,
+                        index,
+                        node.path_buf.clone(),
+                        ctx.get_external_handle(),
+                    );
+                }
+                let path = node.path_buf.clone();
+                if let Some(paths) = self.node_tree(&path) {
+                    for path in paths.iter() {
+                        self.update_node_count(path);
+                    }
+                }
+            } else {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::OpenFile(node.path_buf.clone()),
+                    Target::Widget(tab_id),
+                ));
+            }
+            self.active_selected = index;
+        }
+    }
+
+    fn active_node(&self) -> Option<&FileNodeItem> {
+        let (_, node) =
+            get_item_children(0, self.active_selected, self.workspace.as_ref()?);
+        node
+    }
+
+    fn start_operation(&mut self, command: &LapceCommand) {
+        let node = match self.active_node() {
+            Some(node) => node,
+            None => return,
+        };
+        let is_root = self.active_selected == 0;
+        self.operation = match command {
+            LapceCommand::FileExplorerNewFile => {
+                let dir = if node.is_dir {
+                    Some(node.path_buf.as_path())
+                } else {
+                    node.path_buf.parent()
+                };
+                dir.map(|dir| FileOperation::NewFile {
+                    dir: dir.to_path_buf(),
+                    name: String::new(),
+                })
+            }
+            LapceCommand::FileExplorerRename if !is_root => {
+                Some(FileOperation::Rename {
+                    path: node.path_buf.clone(),
+                    name: node
+                        .path_buf
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("")
+                        .to_string(),
+                })
+            }
+            LapceCommand::FileExplorerDelete if !is_root => {
+                Some(FileOperation::Delete {
+                    path: node.path_buf.clone(),
+                })
+            }
+            _ => None,
+        };
+    }
+
+    /// Send the operation to the proxy and read the directory it changed
+    /// again once it's done
+    fn finish_operation(&self, ctx: &mut EventCtx, operation: FileOperation) {
+        let tab_id = self.tab_id;
+        let proxy = self.proxy.clone();
+        let event_sink = ctx.get_external_handle();
+        match operation {
+            FileOperation::NewFile { dir, name } => {
+                if name.is_empty() {
+                    return;
+                }
+                let path = dir.join(name);
+                self.proxy.create_file(
+                    &path.clone(),
+                    Box::new(move |result| match result {
+                        Ok(_) => {
+                            let _ = event_sink.submit_command(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::OpenFile(path),
+                                Target::Widget(tab_id),
+                            );
+                            read_dir(proxy, tab_id, 0, dir, event_sink);
+                        }
+                        Err(e) => log::error!("Failed to create file: {e}"),
+                    }),
+                );
+            }
+            FileOperation::Rename { path, name } => {
+                let dir = match path.parent() {
+                    Some(dir) => dir.to_path_buf(),
+                    None => return,
+                };
+                let to = dir.join(&name);
+                if name.is_empty() || to == path {
+                    return;
+                }
+                self.proxy.rename_path(
+                    &path,
+                    &to,
+                    Box::new(move |result| match result {
+                        Ok(_) => read_dir(proxy, tab_id, 0, dir, event_sink),
+                        Err(e) => log::error!("Failed to rename: {e}"),
+                    }),
+                );
+            }
+            FileOperation::Delete { path } => {
+                let dir = match path.parent() {
+                    Some(dir) => dir.to_path_buf(),
+                    None => return,
+                };
+                self.proxy.delete_path(
+                    &path,
+                    Box::new(move |result| match result {
+                        Ok(_) => read_dir(proxy, tab_id, 0, dir, event_sink),
+                        Err(e) => log::error!("Failed to delete: {e}"),
+                    }),
+                );
+            }
+        }
+    }
+
     pub fn update_node_count(&mut self, path: &Path) -> Option<()> {
         let node = self.get_node_mut(path)?;
         if node.is_dir {
@@ -135,6 +282,127 @@ impl FileExplorerData {
     }
 }
 
+impl KeyPressFocus for FileExplorerData {
+    fn get_mode(&self) -> Mode {
+        match &self.operation {
+            Some(FileOperation::NewFile { .. } | FileOperation::Rename { .. }) => {
+                Mode::Insert
+            }
+            _ => Mode::Normal,
+        }
+    }
+
+    fn check_condition(&self, condition: &str) -> bool {
+        matches!(condition, "list_focus" | "file_explorer_focus")
+    }
+
+    fn run_command(
+        &mut self,
+        ctx: &mut EventCtx,
+        command: &LapceCommand,
+        _count: Option<usize>,
+        _mods: Modifiers,
+        _env: &Env,
+    ) -> CommandExecuted {
+        let len = self
+            .workspace
+            .as_ref()
+            .map(|w| w.children_open_count)
+            .unwrap_or(0);
+        match command {
+            LapceCommand::Up | LapceCommand::ListPrevious
+                if self.operation.is_none() && len > 0 =>
+            {
+                self.active_selected = Movement::Up.update_index(
+                    self.active_selected.max(1) - 1,
+                    len,
+                    1,
+                    true,
+                ) + 1;
+            }
+            LapceCommand::Down | LapceCommand::ListNext
+                if self.operation.is_none() && len > 0 =>
+            {
+                self.active_selected = Movement::Down.update_index(
+                    self.active_selected.max(1) - 1,
+                    len,
+                    1,
+                    true,
+                ) + 1;
+            }
+            LapceCommand::ListSelect | LapceCommand::ListExpand => {
+                match self.operation.take() {
+                    Some(operation) => self.finish_operation(ctx, operation),
+                    None => self.activate(ctx, self.active_selected),
+                }
+            }
+            LapceCommand::FileExplorerNewFile
+            | LapceCommand::FileExplorerRename
+            | LapceCommand::FileExplorerDelete => {
+                self.start_operation(command);
+            }
+            LapceCommand::DeleteBackward => match &mut self.operation {
+                Some(
+                    FileOperation::NewFile { name, .. }
+                    | FileOperation::Rename { name, .. },
+                ) => {
+                    name.pop();
+                }
+                _ => return CommandExecuted::No,
+            },
+            LapceCommand::NormalMode if self.operation.is_some() => {
+                self.operation = None;
+            }
+            LapceCommand::FileExplorerCancel => {
+                if self.operation.take().is_none() {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::FocusEditor,
+                        Target::Auto,
+                    ));
+                }
+            }
+            _ => return CommandExecuted::No,
+        }
+        CommandExecuted::Yes
+    }
+
+    fn receive_char(&mut self, _ctx: &mut EventCtx, c: &str) {
+        if let Some(
+            FileOperation::NewFile { name, .. } | FileOperation::Rename { name, .. },
+        ) = &mut self.operation
+        {
+            name.push_str(c);
+        }
+    }
+}
+
+/// Read the entries of the directory `path` and update its node with them
+fn read_dir(
+    proxy: Arc<LapceProxy>,
+    tab_id: WidgetId,
+    index: usize,
+    path: PathBuf,
+    event_sink: ExtEventSink,
+) {
+    proxy.read_dir(
+        &path.clone(),
+        Box::new(move |result| {
+            if let Ok(res) = result {
+                let resp: Result<Vec<FileNodeItem>, serde_json::Error> =
+                    serde_json::from_value(res);
+                if let Ok(items) = resp {
+                    let _ = event_sink.submit_command(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::UpdateExplorerItems(index, path, items),
+                        Target::Widget(tab_id),
+                    );
+                }
+            }
+        }),
+    );
+}
+
 pub fn get_item_children(
     i: usize,
     index: usize,
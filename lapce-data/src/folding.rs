@@ -0,0 +1,97 @@
+/// The blocks that can be folded, found from the indentation of the lines.
+/// Each block is the line it starts on, which stays visible when folded,
+/// and the last line under it that's indented further. Blank lines don't
+/// end a block, but trailing ones aren't part of it, so the closing bracket
+/// of a block stays visible after the fold.
+pub fn indent_fold_ranges<S: AsRef<str>>(
+    lines: impl Iterator<Item = S>,
+    tab_width: usize,
+) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    // The lines that started a block which hasn't ended yet, with their indent
+    let mut starts: Vec<(usize, usize)> = Vec::new();
+    let mut last: Option<(usize, usize)> = None;
+    for (line, content) in lines.enumerate() {
+        let indent = match indent_width(content.as_ref(), tab_width) {
+            Some(indent) => indent,
+            None => continue,
+        };
+        if let Some((last_line, last_indent)) = last {
+            while let Some((start, start_indent)) = starts.last().copied() {
+                if start_indent < indent {
+                    break;
+                }
+                starts.pop();
+                ranges.push((start, last_line));
+            }
+            if last_indent < indent {
+                starts.push((last_line, last_indent));
+            }
+        }
+        last = Some((line, indent));
+    }
+    if let Some((last_line, _)) = last {
+        for (start, _) in starts {
+            ranges.push((start, last_line));
+        }
+    }
+    ranges.sort();
+    ranges
+}
+
+/// The innermost block that holds `line`, either starting on it or hiding it
+pub fn fold_range_at(
+    ranges: &[(usize, usize)],
+    line: usize,
+) -> Option<(usize, usize)> {
+    ranges
+        .iter()
+        .find(|(start, _)| *start == line)
+        .or_else(|| {
+            ranges
+                .iter()
+                .filter(|(start, end)| *start < line && line <= *end)
+                .min_by_key(|(start, end)| end - start)
+        })
+        .copied()
+}
+
+/// The width of the indentation of a line, `None` for blank lines
+fn indent_width(content: &str, tab_width: usize) -> Option<usize> {
+    let mut width = 0;
+    for c in content.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += tab_width - width % tab_width,
+            '\r' | '\n' => return None,
+            _ => return Some(width),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_indent_fold_ranges() {
+        let lines = [
+            "fn a() {\n",
+            "    b\n",
+            "\n",
+            "    if c {\n",
+            "    \td\n",
+            "    }\n",
+            "}\n",
+            "\n",
+            "e\n",
+        ];
+        let ranges = indent_fold_ranges(lines.into_iter(), 4);
+        assert_eq!(ranges, vec![(0, 5), (3, 4)]);
+        assert_eq!(fold_range_at(&ranges, 0), Some((0, 5)));
+        assert_eq!(fold_range_at(&ranges, 2), Some((0, 5)));
+        assert_eq!(fold_range_at(&ranges, 4), Some((3, 4)));
+        assert_eq!(fold_range_at(&ranges, 6), None);
+    }
+}
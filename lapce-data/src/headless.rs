@@ -0,0 +1,1017 @@
+use std::sync::Arc;
+
+use druid::{widget::Label, AppLauncher, WidgetId, WindowDesc};
+use xi_rope::RopeDelta;
+
+use crate::{
+    buffer::{Buffer, BufferContent, EditType, LocalBufferKind},
+    command::{CommandExecuted, LapceCommand},
+    config::Config,
+    data::{MotionMode, Register, RegisterData, SurroundAction},
+    editor::{
+        auto_pair_edit, closing_bracket_edits, delete_backward_selection,
+        delete_forward_selection, delete_word_selection, new_line_edits,
+        selection_in_wrap, surround_edits, toggle_block_comment,
+        toggle_line_comment, wrap_selection_edits, AutoPairEdit, LinewiseEdit,
+        PasteEdit,
+    },
+    movement::{Cursor, CursorMode, InsertDrift, Movement, Selection},
+    proxy::LapceProxy,
+};
+
+/// A buffer and a cursor that editing commands can be run on without a
+/// window or a proxy process, so that their effect on the text and the
+/// cursor can be checked in tests
+pub struct HeadlessEditor {
+    pub buffer: Buffer,
+    pub cursor: Cursor,
+    pub config: Config,
+    pub register: Register,
+    /// The operator waiting to be repeated, like the first `d` of `dd`,
+    /// with its count
+    motion_mode: Option<(MotionMode, Option<usize>)>,
+    proxy: Arc<LapceProxy>,
+}
+
+impl HeadlessEditor {
+    /// An editor with `text` and the cursor at its start, in normal mode
+    /// if `modal` and in insert mode otherwise
+    pub fn new(text: &str, modal: bool) -> Self {
+        let event_sink =
+            AppLauncher::with_window(WindowDesc::new(Label::<()>::new("")))
+                .get_external_handle();
+        let tab_id = WidgetId::next();
+        let mut buffer = Buffer::new(
+            BufferContent::Local(LocalBufferKind::Empty),
+            tab_id,
+            event_sink.clone(),
+        )
+        .set_local();
+        buffer.load_content(text);
+        let mode = if modal {
+            CursorMode::Normal(0)
+        } else {
+            CursorMode::Insert(Selection::caret(0))
+        };
+        Self {
+            buffer,
+            cursor: Cursor::new(mode, None),
+            config: Config::default_settings()
+                .expect("the default settings should parse"),
+            register: Register::default(),
+            motion_mode: None,
+            proxy: Arc::new(LapceProxy::disconnected(tab_id, event_sink)),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.buffer.rope.to_string()
+    }
+
+    pub fn offset(&self) -> usize {
+        self.cursor.offset()
+    }
+
+    /// Run a movement or an edit the way the editor does outside of
+    /// any operator, `CommandExecuted::No` for the commands not supported
+    pub fn run(
+        &mut self,
+        cmd: &LapceCommand,
+        count: Option<usize>,
+    ) -> CommandExecuted {
+        if let Some(movement) = cmd.move_command(count) {
+            self.cursor = self.cursor.moved(
+                &self.buffer,
+                &movement,
+                count.unwrap_or(1),
+                false,
+                false,
+                None,
+                &self.config,
+            );
+            return CommandExecuted::Yes;
+        }
+
+        if let Some(mode) = cmd.motion_mode_command() {
+            // Only the repeated operators, like `dd`, are run here
+            match self.motion_mode.take() {
+                Some((pending, pending_count)) if pending == mode => {
+                    let line = self.buffer.line_of_offset(self.offset());
+                    let count = count.unwrap_or(1) * pending_count.unwrap_or(1);
+                    self.run_linewise(&mode, line, count);
+                }
+                Some(_) => {}
+                None => self.motion_mode = Some((mode, count)),
+            }
+            return CommandExecuted::Yes;
+        }
+
+        match cmd {
+            LapceCommand::YankLine => {
+                let line = self.buffer.line_of_offset(self.offset());
+                self.run_linewise(&MotionMode::Yank, line, count.unwrap_or(1));
+            }
+            LapceCommand::DeleteBackward | LapceCommand::DeleteForward => {
+                let selection = if cmd == &LapceCommand::DeleteBackward {
+                    delete_backward_selection(
+                        &self.buffer,
+                        &self.cursor,
+                        false,
+                        None,
+                        &self.config,
+                    )
+                } else {
+                    delete_forward_selection(
+                        &self.buffer,
+                        &self.cursor,
+                        false,
+                        None,
+                        &self.config,
+                    )
+                };
+                let delta = self.edit(&[(&selection, "")], EditType::Delete);
+                let selection =
+                    selection.apply_delta(&delta, true, InsertDrift::Default);
+                self.cursor = self.cursor.after_change(&self.buffer, selection);
+            }
+            LapceCommand::DeleteWordForward
+            | LapceCommand::DeleteWordBackward
+            | LapceCommand::DeleteBigWordForward
+            | LapceCommand::DeleteBigWordBackward => {
+                let movement = match cmd {
+                    LapceCommand::DeleteWordForward => Movement::WordForward,
+                    LapceCommand::DeleteWordBackward => Movement::WordBackward,
+                    LapceCommand::DeleteBigWordForward => Movement::BigWordForward,
+                    _ => Movement::BigWordBackward,
+                };
+                let selection = delete_word_selection(
+                    &self.buffer,
+                    &self.cursor,
+                    &movement,
+                    false,
+                    None,
+                    &self.config,
+                );
+                let delta = self.edit(&[(&selection, "")], EditType::Delete);
+                let selection =
+                    selection.apply_delta(&delta, true, InsertDrift::Default);
+                self.cursor = self.cursor.after_change(&self.buffer, selection);
+            }
+            LapceCommand::ToggleLineComment | LapceCommand::ToggleBlockComment => {
+                let toggle = if cmd == &LapceCommand::ToggleLineComment {
+                    toggle_line_comment
+                } else {
+                    toggle_block_comment
+                };
+                if let Some((edits, edit_type)) =
+                    toggle(&self.buffer, &self.cursor, self.config.editor.tab_width)
+                {
+                    let edits: Vec<(&Selection, &str)> = edits
+                        .iter()
+                        .map(|(selection, content)| (selection, content.as_str()))
+                        .collect();
+                    let delta = self.edit(&edits, edit_type);
+                    self.cursor.apply_delta(&delta);
+                }
+            }
+            LapceCommand::InsertNewLine => {
+                let selection = match &self.cursor.mode {
+                    CursorMode::Normal(offset) => Selection::caret(*offset),
+                    CursorMode::Insert(selection) => selection.clone(),
+                    CursorMode::Visual { .. } => return CommandExecuted::Yes,
+                };
+                let (edits, selection) = new_line_edits(&self.buffer, &selection);
+                let edits: Vec<(&Selection, &str)> = edits
+                    .iter()
+                    .map(|(selection, content)| (selection, content.as_str()))
+                    .collect();
+                self.edit(&edits, EditType::InsertNewline);
+                self.cursor = Cursor::new(CursorMode::Insert(selection), None);
+            }
+            LapceCommand::Undo | LapceCommand::Redo => {
+                let result = if cmd == &LapceCommand::Undo {
+                    self.buffer.do_undo(self.proxy.clone())
+                } else {
+                    self.buffer.do_redo(self.proxy.clone())
+                };
+                if let Some((delta, cursor)) = result {
+                    match cursor {
+                        Some(selection) => {
+                            self.cursor =
+                                self.cursor.after_change(&self.buffer, selection)
+                        }
+                        None => self.cursor.apply_delta(&delta),
+                    }
+                }
+            }
+            _ => return CommandExecuted::No,
+        }
+        CommandExecuted::Yes
+    }
+
+    /// Type `text` at the cursor, replacing the selections, with a single
+    /// char stepping over, wrapping in or closing pairs and dedenting
+    /// closing brackets like it does in the editor
+    pub fn insert(&mut self, text: &str) {
+        let selection = self.selection();
+        let mut edits = vec![(selection.clone(), text.to_string())];
+        let mut auto_close = None;
+        let mut chars = text.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            match auto_pair_edit(&self.buffer, &selection, c, &self.config.editor) {
+                AutoPairEdit::StepOver => {
+                    self.run(&LapceCommand::Right, None);
+                    return;
+                }
+                AutoPairEdit::Wrap(close) => {
+                    let edits = wrap_selection_edits(&selection, c, close);
+                    let edits: Vec<(&Selection, &str)> = edits
+                        .iter()
+                        .map(|(selection, content)| (selection, content.as_str()))
+                        .collect();
+                    self.edit(&edits, EditType::InsertChars);
+                    self.cursor = Cursor::new(
+                        CursorMode::Insert(selection_in_wrap(&selection, c, close)),
+                        None,
+                    );
+                    return;
+                }
+                AutoPairEdit::Close(close) => auto_close = Some(close),
+                AutoPairEdit::Insert => {}
+            }
+            if let Some(bracket_edits) =
+                closing_bracket_edits(&self.buffer, &selection, c)
+            {
+                edits = bracket_edits;
+            }
+        }
+
+        let edits: Vec<(&Selection, &str)> = edits
+            .iter()
+            .map(|(selection, content)| (selection, content.as_str()))
+            .collect();
+        let delta = self.edit(&edits, EditType::InsertChars);
+        let selection = selection.apply_delta(&delta, true, InsertDrift::Default);
+        self.cursor = self.cursor.after_change(&self.buffer, selection.clone());
+        if let Some(close) = auto_close {
+            self.edit(&[(&selection, &close.to_string())], EditType::InsertChars);
+        }
+    }
+
+    /// Run a surround action with the pair of `c`, the char typed after the
+    /// surround command
+    pub fn surround(&mut self, action: &SurroundAction, c: char) {
+        if let Some((edits, offset)) = surround_edits(
+            &self.buffer,
+            &self.cursor,
+            action,
+            c,
+            self.config.editor.tab_width,
+            &self.config.editor.word_chars,
+        ) {
+            let edits: Vec<(&Selection, &str)> = edits
+                .iter()
+                .map(|(selection, content)| (selection, content.as_str()))
+                .collect();
+            self.edit(&edits, EditType::Other);
+            self.cursor = Cursor::new(CursorMode::Normal(offset), None);
+        }
+    }
+
+    /// Paste `data` at the cursor like `p` in normal and visual mode
+    pub fn paste(&mut self, data: &RegisterData) {
+        let paste = PasteEdit::new(
+            &self.buffer,
+            &self.cursor,
+            data,
+            self.config.editor.tab_width,
+        );
+        let delta =
+            self.edit(&[(&paste.selection, &paste.content)], EditType::InsertChars);
+        let selection =
+            paste
+                .selection
+                .apply_delta(&delta, paste.after, InsertDrift::Default);
+        self.cursor = paste.cursor(&self.buffer, &self.cursor, selection);
+    }
+
+    fn run_linewise(&mut self, mode: &MotionMode, line: usize, count: usize) {
+        let edit = match LinewiseEdit::new(&self.buffer, mode, line, count) {
+            Some(edit) => edit,
+            None => return,
+        };
+        let selection = match &edit.selection {
+            Some(selection) => {
+                self.register.add_delete(edit.data.clone());
+                selection
+            }
+            None => {
+                self.register.add_yank(edit.data.clone());
+                return;
+            }
+        };
+        let delta = self.edit(&[(selection, "")], EditType::Delete);
+        let selection = selection.apply_delta(&delta, true, InsertDrift::Default);
+        if mode == &MotionMode::Change {
+            self.buffer.update_edit_type();
+        }
+        self.cursor = edit.cursor(&self.buffer, selection);
+    }
+
+    fn selection(&self) -> Selection {
+        match &self.cursor.mode {
+            CursorMode::Normal(offset) => Selection::caret(*offset),
+            CursorMode::Visual { start, end, .. } => Selection::region(*start, *end),
+            CursorMode::Insert(selection) => selection.clone(),
+        }
+    }
+
+    fn edit(
+        &mut self,
+        edits: &[(&Selection, &str)],
+        edit_type: EditType,
+    ) -> RopeDelta {
+        let cursor = self.selection();
+        self.buffer.edit_multiple(
+            edits,
+            self.proxy.clone(),
+            edit_type,
+            Some(&cursor),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lapce_rpc::style::Style;
+    use strum::IntoEnumIterator;
+    use xi_rope::{spans::SpansBuilder, Interval, Rope};
+
+    use crate::{
+        buffer::{ChangeHunk, DiffLines, UndoHistory},
+        movement::SelRegion,
+        state::VisualMode,
+    };
+
+    use super::*;
+
+    /// The text and the cursor offset after pasting `content` as `mode`
+    /// with the cursor at `offset` in normal mode
+    fn paste(
+        text: &str,
+        offset: usize,
+        content: &str,
+        mode: VisualMode,
+    ) -> (String, usize) {
+        let mut editor = HeadlessEditor::new(text, true);
+        editor.cursor = Cursor::new(CursorMode::Normal(offset), None);
+        editor.paste(&RegisterData {
+            content: content.to_string(),
+            mode,
+        });
+        (editor.text(), editor.offset())
+    }
+
+    #[test]
+    fn test_modal_edits() {
+        let mut editor = HeadlessEditor::new("hello world\nfoo", true);
+        editor.run(&LapceCommand::WordForward, None);
+        assert_eq!(editor.offset(), 6);
+        editor.run(&LapceCommand::LineEnd, None);
+        assert_eq!(editor.offset(), 10);
+        editor.run(&LapceCommand::DeleteForward, None);
+        assert_eq!(editor.text(), "hello worl\nfoo");
+        assert_eq!(editor.offset(), 9);
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "hello world\nfoo");
+        assert_eq!(editor.offset(), 10);
+    }
+
+    #[test]
+    fn test_smart_line_start() {
+        let mut editor = HeadlessEditor::new("    foo bar", false);
+        editor.run(&LapceCommand::LineEnd, None);
+        editor.run(&LapceCommand::SmartLineStart, None);
+        assert_eq!(editor.offset(), 4);
+        editor.run(&LapceCommand::SmartLineStart, None);
+        assert_eq!(editor.offset(), 0);
+        editor.run(&LapceCommand::SmartLineStart, None);
+        assert_eq!(editor.offset(), 4);
+
+        let mut editor = HeadlessEditor::new("a\nfoo", true);
+        editor.run(&LapceCommand::Down, None);
+        editor.run(&LapceCommand::SmartLineStart, None);
+        assert_eq!(editor.offset(), 2);
+    }
+
+    #[test]
+    fn test_word_motions_with_word_chars() {
+        let mut editor = HeadlessEditor::new("margin-top: 0", true);
+        editor.run(&LapceCommand::WordForward, None);
+        assert_eq!(editor.offset(), 6);
+
+        let mut editor = HeadlessEditor::new("margin-top: 0", true);
+        editor.config.editor.word_chars = "-".to_string();
+        editor.run(&LapceCommand::WordForward, None);
+        assert_eq!(editor.offset(), 10);
+        editor.run(&LapceCommand::WordBackward, None);
+        assert_eq!(editor.offset(), 0);
+        assert_eq!(editor.buffer.select_word(3, "-"), (0, 10));
+        assert_eq!(editor.buffer.select_word(3, ""), (0, 6));
+    }
+
+    #[test]
+    fn test_vertical_moves_keep_column() {
+        let mut editor = HeadlessEditor::new("abcdefgh\nab\n\nabcdefgh", true);
+        editor.cursor = Cursor::new(CursorMode::Normal(6), None);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 10);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 12);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 19);
+        editor.run(&LapceCommand::Up, Some(3));
+        assert_eq!(editor.offset(), 6);
+
+        let mut editor = HeadlessEditor::new("abcdefgh\nab\nabcdefgh", false);
+        editor.cursor = Cursor::new(CursorMode::Insert(Selection::caret(6)), None);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 11);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 18);
+        editor.run(&LapceCommand::Up, Some(2));
+        assert_eq!(editor.offset(), 6);
+    }
+
+    #[test]
+    fn test_vertical_moves_keep_line_position() {
+        let mut editor = HeadlessEditor::new("abcdefgh\nab\nabcdefgh", true);
+        editor.run(&LapceCommand::LineEnd, None);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 10);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 19);
+
+        let mut editor = HeadlessEditor::new("a\n    bc\n  d", true);
+        editor.run(&LapceCommand::Down, None);
+        editor.run(&LapceCommand::LineStartNonBlank, None);
+        assert_eq!(editor.offset(), 6);
+        editor.run(&LapceCommand::Down, None);
+        assert_eq!(editor.offset(), 11);
+        editor.run(&LapceCommand::Up, None);
+        assert_eq!(editor.offset(), 6);
+    }
+
+    /// The text and the cursor offset after running `cmd` with the caret at
+    /// `offset` in insert mode
+    fn delete_word(text: &str, offset: usize, cmd: LapceCommand) -> (String, usize) {
+        let mut editor = HeadlessEditor::new(text, false);
+        editor.cursor =
+            Cursor::new(CursorMode::Insert(Selection::caret(offset)), None);
+        editor.run(&cmd, None);
+        (editor.text(), editor.offset())
+    }
+
+    #[test]
+    fn test_delete_word_at_line_end() {
+        assert_eq!(
+            delete_word("foo bar\nbaz", 7, LapceCommand::DeleteWordBackward),
+            ("foo \nbaz".to_string(), 4)
+        );
+        assert_eq!(
+            delete_word("foo bar\nbaz", 7, LapceCommand::DeleteWordForward),
+            ("foo barbaz".to_string(), 7)
+        );
+        assert_eq!(
+            delete_word("foo a.b\nbaz", 7, LapceCommand::DeleteBigWordBackward),
+            ("foo \nbaz".to_string(), 4)
+        );
+        assert_eq!(
+            delete_word("foo a.b\nbaz", 7, LapceCommand::DeleteBigWordForward),
+            ("foo a.bbaz".to_string(), 7)
+        );
+    }
+
+    #[test]
+    fn test_delete_word_across_punctuation() {
+        assert_eq!(
+            delete_word("foo.bar baz", 0, LapceCommand::DeleteWordForward),
+            (".bar baz".to_string(), 0)
+        );
+        assert_eq!(
+            delete_word("foo.bar baz", 0, LapceCommand::DeleteBigWordForward),
+            ("baz".to_string(), 0)
+        );
+        assert_eq!(
+            delete_word("foo.bar ", 8, LapceCommand::DeleteWordBackward),
+            ("foo.".to_string(), 4)
+        );
+        assert_eq!(
+            delete_word("foo.bar ", 8, LapceCommand::DeleteBigWordBackward),
+            ("".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn test_insert_edits() {
+        let mut editor = HeadlessEditor::new("abc", false);
+        editor.run(&LapceCommand::LineEnd, None);
+        assert_eq!(editor.offset(), 3);
+        editor.insert("d");
+        assert_eq!(editor.text(), "abcd");
+        assert_eq!(editor.offset(), 4);
+        editor.run(&LapceCommand::DeleteBackward, None);
+        assert_eq!(editor.text(), "abc");
+        assert_eq!(editor.offset(), 3);
+        editor.run(&LapceCommand::Left, None);
+        assert_eq!(editor.offset(), 2);
+        editor.run(&LapceCommand::DeleteForward, None);
+        assert_eq!(editor.text(), "ab");
+        assert_eq!(editor.offset(), 2);
+    }
+
+    #[test]
+    fn test_paste_charwise() {
+        let mode = VisualMode::Normal;
+        assert_eq!(paste("abc", 0, "xy", mode), ("axybc".to_string(), 2));
+        assert_eq!(paste("abc", 2, "xy", mode), ("abcxy".to_string(), 4));
+        assert_eq!(paste("", 0, "xy", mode), ("xy".to_string(), 1));
+        assert_eq!(paste("\n", 0, "xy", mode), ("xy\n".to_string(), 1));
+        assert_eq!(paste("éa", 0, "x", mode), ("éxa".to_string(), 2));
+        assert_eq!(paste("ab", 0, "x\ny", mode), ("ax\nyb".to_string(), 1));
+    }
+
+    #[test]
+    fn test_paste_linewise() {
+        for mode in [VisualMode::Linewise, VisualMode::Blockwise] {
+            assert_eq!(
+                paste("a\nb\n", 0, "  x\n", mode),
+                ("a\n  x\nb\n".to_string(), 4)
+            );
+            assert_eq!(
+                paste("a\nb\n", 2, "x\n", mode),
+                ("a\nb\nx\n".to_string(), 4)
+            );
+            assert_eq!(
+                paste("a\nb", 2, "  x\n", mode),
+                ("a\nb\n  x".to_string(), 6)
+            );
+            assert_eq!(paste("", 0, "x\n", mode), ("\nx".to_string(), 1));
+            assert_eq!(paste("a", 0, "x\ny\n", mode), ("a\nx\ny".to_string(), 2));
+        }
+    }
+
+    /// Delete the lines like `dd` and paste them back like `p` would,
+    /// below the line the cursor ends up on
+    /// The text after `dd` with `count` on `line` and `p` right after it
+    fn delete_and_paste(text: &str, line: usize, count: usize) -> String {
+        let mut editor = HeadlessEditor::new(text, true);
+        if line > 0 {
+            editor.run(&LapceCommand::Down, Some(line));
+        }
+        editor.run(&LapceCommand::MotionModeDelete, Some(count));
+        editor.run(&LapceCommand::MotionModeDelete, None);
+        let data = editor.register.unamed.clone();
+        editor.paste(&data);
+        editor.text()
+    }
+
+    #[test]
+    fn test_delete_lines_then_paste() {
+        assert_eq!(delete_and_paste("a\nb\nc\n", 0, 1), "b\na\nc\n");
+        assert_eq!(delete_and_paste("a\nb\nc\nd\n", 0, 2), "c\na\nb\nd\n");
+        assert_eq!(delete_and_paste("a\nb\nc\n", 1, 1), "a\nc\nb\n");
+    }
+
+    #[test]
+    fn test_linewise_operators() {
+        let mut editor = HeadlessEditor::new("a\n  b\nc\n", true);
+        editor.run(&LapceCommand::YankLine, Some(2));
+        assert_eq!(editor.register.unamed.content, "a\n  b\n");
+        assert_eq!(editor.text(), "a\n  b\nc\n");
+
+        editor.run(&LapceCommand::Down, None);
+        editor.run(&LapceCommand::MotionModeChange, None);
+        editor.run(&LapceCommand::MotionModeChange, None);
+        assert_eq!(editor.text(), "a\n  \nc\n");
+        assert_eq!(editor.register.unamed.content, "  b\n");
+        assert!(editor.cursor.is_insert());
+        assert_eq!(editor.offset(), 4);
+
+        let mut editor = HeadlessEditor::new("a\n  b\nc", true);
+        editor.run(&LapceCommand::Down, Some(2));
+        editor.run(&LapceCommand::MotionModeDelete, None);
+        editor.run(&LapceCommand::MotionModeDelete, None);
+        assert_eq!(editor.text(), "a\n  b");
+        assert_eq!(editor.register.unamed.content, "c\n");
+        assert_eq!(editor.offset(), 4);
+    }
+
+    #[test]
+    fn test_paste_in_visual_mode() {
+        let mut editor = HeadlessEditor::new("ab\ncd", true);
+        editor.cursor = Cursor::new(
+            CursorMode::Visual {
+                start: 3,
+                end: 4,
+                mode: VisualMode::Normal,
+            },
+            None,
+        );
+        editor.paste(&RegisterData {
+            content: "x\n".to_string(),
+            mode: VisualMode::Linewise,
+        });
+        assert_eq!(editor.text(), "ab\n\nx\n");
+        assert_eq!(editor.offset(), 4);
+
+        let mut editor = HeadlessEditor::new("ab\ncd", true);
+        editor.cursor = Cursor::new(
+            CursorMode::Visual {
+                start: 3,
+                end: 3,
+                mode: VisualMode::Linewise,
+            },
+            None,
+        );
+        editor.paste(&RegisterData {
+            content: "\n  x\n".to_string(),
+            mode: VisualMode::Linewise,
+        });
+        assert_eq!(editor.text(), "ab\n\n  x\n");
+        assert_eq!(editor.offset(), 3);
+    }
+
+    #[test]
+    fn test_toggle_line_comment() {
+        let mut editor =
+            HeadlessEditor::new("fn a() {\n    b();\n\n  c();\n}", true);
+        editor.cursor = Cursor::new(
+            CursorMode::Visual {
+                start: 9,
+                end: 22,
+                mode: VisualMode::Linewise,
+            },
+            None,
+        );
+        editor.run(&LapceCommand::ToggleLineComment, None);
+        assert_eq!(editor.text(), "fn a() {\n  //   b();\n\n  // c();\n}");
+        editor.run(&LapceCommand::ToggleLineComment, None);
+        assert_eq!(editor.text(), "fn a() {\n    b();\n\n  c();\n}");
+
+        // Only some of the lines being commented comments all of them
+        let mut editor = HeadlessEditor::new("// a\nb", true);
+        editor.cursor = Cursor::new(
+            CursorMode::Visual {
+                start: 0,
+                end: 5,
+                mode: VisualMode::Linewise,
+            },
+            None,
+        );
+        editor.run(&LapceCommand::ToggleLineComment, None);
+        assert_eq!(editor.text(), "// // a\n// b");
+    }
+
+    #[test]
+    fn test_toggle_block_comment() {
+        let mut editor = HeadlessEditor::new("  let a = 1;\n", true);
+        editor.run(&LapceCommand::ToggleBlockComment, None);
+        assert_eq!(editor.text(), "  /* let a = 1; */\n");
+        editor.run(&LapceCommand::ToggleBlockComment, None);
+        assert_eq!(editor.text(), "  let a = 1;\n");
+
+        let mut editor = HeadlessEditor::new("f(a, b)", true);
+        editor.cursor = Cursor::new(
+            CursorMode::Visual {
+                start: 5,
+                end: 5,
+                mode: VisualMode::Normal,
+            },
+            None,
+        );
+        editor.run(&LapceCommand::ToggleBlockComment, None);
+        assert_eq!(editor.text(), "f(a, /* b */)");
+
+        let mut editor = HeadlessEditor::new("/**/ /* */", true);
+        editor.cursor = Cursor::new(
+            CursorMode::Visual {
+                start: 5,
+                end: 9,
+                mode: VisualMode::Normal,
+            },
+            None,
+        );
+        editor.run(&LapceCommand::ToggleBlockComment, None);
+        assert_eq!(editor.text(), "/**/ ");
+    }
+
+    #[test]
+    fn test_add_surround() {
+        let mut editor = HeadlessEditor::new("let value = 1;", true);
+        editor.cursor = Cursor::new(CursorMode::Normal(6), None);
+        editor.surround(&SurroundAction::Add, ')');
+        assert_eq!(editor.text(), "let (value) = 1;");
+        assert_eq!(editor.offset(), 4);
+
+        let mut editor = HeadlessEditor::new("a b c", true);
+        editor.cursor = Cursor::new(
+            CursorMode::Visual {
+                start: 2,
+                end: 4,
+                mode: VisualMode::Normal,
+            },
+            None,
+        );
+        editor.surround(&SurroundAction::Add, '"');
+        assert_eq!(editor.text(), "a \"b c\"");
+        assert_eq!(editor.offset(), 2);
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "a b c");
+    }
+
+    #[test]
+    fn test_delete_and_change_surround() {
+        let mut editor = HeadlessEditor::new("f(a, [b], c)", true);
+        editor.cursor = Cursor::new(CursorMode::Normal(3), None);
+        editor.surround(&SurroundAction::Delete, '(');
+        assert_eq!(editor.text(), "fa, [b], c");
+        assert_eq!(editor.offset(), 1);
+
+        editor.cursor = Cursor::new(CursorMode::Normal(4), None);
+        editor.surround(&SurroundAction::Change(Some(']')), '{');
+        assert_eq!(editor.text(), "fa, {b}, c");
+
+        let mut editor = HeadlessEditor::new("x = \"a\" + 'b'", true);
+        editor.cursor = Cursor::new(CursorMode::Normal(6), None);
+        editor.surround(&SurroundAction::Change(Some('"')), '\'');
+        assert_eq!(editor.text(), "x = 'a' + 'b'");
+        editor.cursor = Cursor::new(CursorMode::Normal(12), None);
+        editor.surround(&SurroundAction::Delete, '\'');
+        assert_eq!(editor.text(), "x = 'a' + b");
+
+        // Nothing happens without a pair around the cursor
+        editor.surround(&SurroundAction::Delete, '(');
+        assert_eq!(editor.text(), "x = 'a' + b");
+    }
+
+    /// An editor in insert mode with the caret at `offset`
+    fn insert_at(text: &str, offset: usize) -> HeadlessEditor {
+        let mut editor = HeadlessEditor::new(text, false);
+        editor.cursor =
+            Cursor::new(CursorMode::Insert(Selection::caret(offset)), None);
+        editor
+    }
+
+    #[test]
+    fn test_auto_close_pairs() {
+        let mut editor = insert_at("", 0);
+        editor.insert("(");
+        assert_eq!(editor.text(), "()");
+        assert_eq!(editor.offset(), 1);
+        editor.insert(")");
+        assert_eq!(editor.text(), "()");
+        assert_eq!(editor.offset(), 2);
+
+        let mut editor = insert_at("f()", 2);
+        editor.insert("\"");
+        assert_eq!(editor.text(), "f(\"\")");
+        assert_eq!(editor.offset(), 3);
+        editor.run(&LapceCommand::DeleteBackward, None);
+        assert_eq!(editor.text(), "f()");
+        assert_eq!(editor.offset(), 2);
+
+        // Not before or, for quotes, after a word
+        let mut editor = insert_at("ab", 0);
+        editor.insert("(");
+        assert_eq!(editor.text(), "(ab");
+        let mut editor = insert_at("ab", 2);
+        editor.insert("\"");
+        assert_eq!(editor.text(), "ab\"");
+    }
+
+    #[test]
+    fn test_dedent_closing_bracket() {
+        let text = "fn a() {\n    if b {\n        c();\n        ";
+        let mut editor = insert_at(text, text.len());
+        editor.insert("}");
+        assert_eq!(editor.text(), "fn a() {\n    if b {\n        c();\n    }");
+        assert_eq!(editor.offset(), editor.text().len());
+        editor.insert("\n");
+        editor.insert("}");
+        assert_eq!(
+            editor.text(),
+            "fn a() {\n    if b {\n        c();\n    }\n}"
+        );
+
+        // One level less without a bracket to line up with
+        let mut editor = insert_at("a\n    b\n        ", 16);
+        editor.insert(")");
+        assert_eq!(editor.text(), "a\n    b\n    )");
+
+        // Not after other text on the line
+        let mut editor = insert_at("{\n    a", 7);
+        editor.insert("}");
+        assert_eq!(editor.text(), "{\n    a}");
+    }
+
+    #[test]
+    fn test_auto_close_configured_pairs_only() {
+        let mut editor = insert_at("", 0);
+        editor.config.editor.auto_closing_pairs = vec!["[]".to_string()];
+        editor.insert("(");
+        editor.insert("[");
+        assert_eq!(editor.text(), "([]");
+        editor.run(&LapceCommand::DeleteBackward, None);
+        editor.run(&LapceCommand::DeleteBackward, None);
+        assert_eq!(editor.text(), "");
+    }
+
+    #[test]
+    fn test_no_auto_close_in_strings() {
+        let mut editor = insert_at("\"ab\"", 2);
+        let mut styles = SpansBuilder::new(4);
+        styles.add_span(
+            Interval::new(0, 4),
+            Style {
+                fg_color: Some("string".to_string()),
+            },
+        );
+        editor.buffer.semantic_styles = Some(Arc::new(styles.build()));
+        editor.insert("(");
+        assert_eq!(editor.text(), "\"a(b\"");
+    }
+
+    #[test]
+    fn test_wrap_selection_in_pair() {
+        let mut editor = HeadlessEditor::new("ab c", false);
+        let mut selection = Selection::region(0, 2);
+        selection.add_region(SelRegion::new(3, 4, None));
+        editor.cursor = Cursor::new(CursorMode::Insert(selection), None);
+        editor.insert("[");
+        assert_eq!(editor.text(), "[ab] [c]");
+        let regions: Vec<(usize, usize)> = editor
+            .selection()
+            .regions()
+            .iter()
+            .map(|r| (r.start, r.end))
+            .collect();
+        assert_eq!(regions, vec![(1, 3), (6, 7)]);
+    }
+
+    #[test]
+    fn test_wrap_selection_in_multibyte_pair() {
+        let mut editor = HeadlessEditor::new("ab c", false);
+        editor.config.editor.auto_closing_pairs = vec!["«»".to_string()];
+        let mut selection = Selection::region(0, 2);
+        selection.add_region(SelRegion::new(3, 4, None));
+        editor.cursor = Cursor::new(CursorMode::Insert(selection), None);
+        editor.insert("«");
+        assert_eq!(editor.text(), "«ab» «c»");
+        let regions: Vec<(usize, usize)> = editor
+            .selection()
+            .regions()
+            .iter()
+            .map(|r| (r.start, r.end))
+            .collect();
+        assert_eq!(regions, vec![(2, 4), (9, 10)]);
+    }
+
+    #[test]
+    fn test_restore_undo_history() {
+        let mut editor = insert_at("ab", 2);
+        editor.insert("c");
+        let history = editor.buffer.undo_history();
+        let history: UndoHistory =
+            serde_json::from_str(&serde_json::to_string(&history).unwrap()).unwrap();
+
+        // Not for a different text
+        editor.buffer.load_content("ab");
+        assert!(!editor.buffer.restore_undo_history(history.clone()));
+
+        editor.buffer.load_content("abc");
+        assert!(editor.buffer.restore_undo_history(history));
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "ab");
+        editor.run(&LapceCommand::Redo, None);
+        assert_eq!(editor.text(), "abc");
+    }
+
+    #[test]
+    fn test_reload_keeps_undo_history() {
+        let mut editor = insert_at("ab\n", 2);
+        editor.insert("c");
+        let rev = editor.buffer.rev + 1;
+        editor.buffer.reload_content("xabc\nd\n", rev);
+        assert_eq!(editor.text(), "xabc\nd\n");
+        assert_eq!(editor.buffer.rev, rev);
+
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "abc\n");
+        editor.run(&LapceCommand::Undo, None);
+        assert_eq!(editor.text(), "ab\n");
+        editor.run(&LapceCommand::Redo, None);
+        editor.run(&LapceCommand::Redo, None);
+        assert_eq!(editor.text(), "xabc\nd\n");
+    }
+
+    #[test]
+    fn test_change_hunks() {
+        // "b" changed to "B", "d" deleted and "e" added
+        let mut editor = HeadlessEditor::new("a\nB\nc\ne\n", true);
+        editor
+            .buffer
+            .histories
+            .insert("head".to_string(), Rope::from("a\nb\nc\nd\n"));
+        editor.buffer.history_changes.insert(
+            "head".to_string(),
+            Arc::new(vec![
+                DiffLines::Both(0..1, 0..1),
+                DiffLines::Left(1..2),
+                DiffLines::Right(1..2),
+                DiffLines::Both(2..3, 2..3),
+                DiffLines::Left(3..4),
+                DiffLines::Right(3..4),
+            ]),
+        );
+        let hunks = editor.buffer.change_hunks();
+        assert_eq!(
+            hunks,
+            vec![
+                ChangeHunk {
+                    head: 1..2,
+                    lines: 1..2,
+                },
+                ChangeHunk {
+                    head: 3..4,
+                    lines: 3..4,
+                },
+            ]
+        );
+
+        let (selection, content) =
+            editor.buffer.revert_hunk_edit(&hunks[0]).unwrap();
+        editor.edit(&[(&selection, &content)], EditType::Other);
+        assert_eq!(editor.text(), "a\nb\nc\ne\n");
+    }
+
+    #[test]
+    fn test_change_hunks_of_added_and_deleted_lines() {
+        let mut editor = HeadlessEditor::new("a\nc\nd\n", true);
+        editor
+            .buffer
+            .histories
+            .insert("head".to_string(), Rope::from("a\nb\nc\n"));
+        editor.buffer.history_changes.insert(
+            "head".to_string(),
+            Arc::new(vec![
+                DiffLines::Both(0..1, 0..1),
+                DiffLines::Left(1..2),
+                DiffLines::Both(2..3, 1..2),
+                DiffLines::Right(2..3),
+            ]),
+        );
+        let hunks = editor.buffer.change_hunks();
+        assert_eq!(
+            hunks,
+            vec![
+                ChangeHunk {
+                    head: 1..2,
+                    lines: 1..1,
+                },
+                ChangeHunk {
+                    head: 3..3,
+                    lines: 2..3,
+                },
+            ]
+        );
+        assert!(hunks[0].contains_line(1));
+
+        for hunk in hunks.iter().rev() {
+            let (selection, content) = editor.buffer.revert_hunk_edit(hunk).unwrap();
+            editor.edit(&[(&selection, &content)], EditType::Other);
+        }
+        assert_eq!(editor.text(), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn test_edit_commands_are_read_only_guarded() {
+        // Every command that changes the text here has to be one that a
+        // read only buffer rejects
+        for cmd in LapceCommand::iter() {
+            for modal in [true, false] {
+                let text = "fn foo(bar) {\n    baz qux\n}\n";
+                let mut editor = HeadlessEditor::new(text, modal);
+                editor.run(&LapceCommand::Down, None);
+                editor.run(&LapceCommand::WordForward, None);
+                editor.run(&cmd, Some(1));
+                if editor.text() != text {
+                    assert!(
+                        cmd.is_edit_command(),
+                        "{cmd} edits the buffer but isn't an edit command"
+                    );
+                }
+            }
+        }
+    }
+}
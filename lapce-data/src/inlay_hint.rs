@@ -0,0 +1,56 @@
+use lsp_types::Position;
+use serde::Deserialize;
+
+/// An inlay hint sent by a language server, `lsp-types` doesn't have them
+/// yet so only the fields that are drawn are read
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LspInlayHint {
+    pub position: Position,
+    pub label: LspInlayHintLabel,
+    #[serde(default)]
+    pub padding_left: bool,
+    #[serde(default)]
+    pub padding_right: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum LspInlayHintLabel {
+    String(String),
+    Parts(Vec<LspInlayHintLabelPart>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LspInlayHintLabelPart {
+    pub value: String,
+}
+
+impl LspInlayHint {
+    /// The text drawn for the hint, with its padding
+    pub fn text(&self) -> String {
+        let label = match &self.label {
+            LspInlayHintLabel::String(label) => label.clone(),
+            LspInlayHintLabel::Parts(parts) => {
+                parts.iter().map(|part| part.value.as_str()).collect()
+            }
+        };
+        // the hint is drawn on the same line as the text, so it can't
+        // break it
+        let label = label.replace(['\n', '\r'], " ");
+        format!(
+            "{}{}{}",
+            if self.padding_left { " " } else { "" },
+            label,
+            if self.padding_right { " " } else { "" },
+        )
+    }
+}
+
+/// An inlay hint of a buffer, drawn before the character at `offset`
+/// without being part of the text
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlayHint {
+    pub offset: usize,
+    pub label: String,
+}
@@ -1,7 +1,10 @@
+use std::str::FromStr;
+
 use anyhow::{anyhow, Result};
 use indexmap::IndexMap;
 
 use crate::{
+    command::{LapceCommand, LapceWorkbenchCommand},
     keypress::{get_modes, keypress::KeyPress, KeyMap},
     state::Modes,
 };
@@ -98,6 +101,23 @@ impl KeyMapLoader {
             .and_then(|v| v.as_str())
             .ok_or_else(|| anyhow!("no key in keymap"))?;
 
+        let keypresses = KeyPress::parse(key);
+        if keypresses.is_empty() || keypresses.len() != key.split(' ').count() {
+            return Err(anyhow!("invalid key {key:?} in keymap"));
+        }
+
+        let command = toml_keymap
+            .get("command")
+            .and_then(|c| c.as_str())
+            .map(|c| c.trim().to_string())
+            .ok_or_else(|| anyhow!("no command in keymap for {key:?}"))?;
+        let name = command.strip_prefix('-').unwrap_or(&command);
+        if LapceCommand::from_str(name).is_err()
+            && LapceWorkbenchCommand::from_str(name).is_err()
+        {
+            return Err(anyhow!("unknown command {name:?} in keymap for {key:?}"));
+        }
+
         let modes = get_modes(toml_keymap);
         // If not using modal editing, remove keymaps that only make sense in modal.
         if !modal && !modes.is_empty() && !modes.contains(Modes::INSERT) {
@@ -106,17 +126,13 @@ impl KeyMapLoader {
         }
 
         Ok(Some(KeyMap {
-            key: KeyPress::parse(key),
+            key: keypresses,
             modes,
             when: toml_keymap
                 .get("when")
                 .and_then(|w| w.as_str())
                 .map(|w| w.to_string()),
-            command: toml_keymap
-                .get("command")
-                .and_then(|c| c.as_str())
-                .map(|w| w.trim().to_string())
-                .unwrap_or_else(|| "".to_string()),
+            command,
         }))
     }
 }
@@ -178,4 +194,59 @@ keymaps = [
         let keypress = KeyPress::parse("I");
         assert_eq!(keymaps.get(&keypress).unwrap().len(), 1);
     }
+
+    #[test]
+    fn test_user_keymap() {
+        let defaults = r###"
+keymaps = [
+    { key = "ctrl+w h", command = "left", mode = "n" },
+    { key = "ctrl+w l", command = "right", mode = "n" },
+]
+        "###;
+        let user = r###"
+keymaps = [
+    { key = "ctrl+w l", command = "-right", mode = "n" },
+    { key = "ctrl+w l", command = "line_end", mode = "n" },
+    { key = "ctrl+w j", command = "no_such_command", mode = "n" },
+    { key = "ctrl+nosuchkey j", command = "down", mode = "n" },
+    { key = "ctrl+w k" },
+]
+        "###;
+        let mut loader = KeyMapLoader::new();
+        loader.load_from_str(defaults, true).unwrap();
+        loader.load_from_str(user, true).unwrap();
+
+        let (keymaps, command_keymaps) = loader.finalize();
+
+        let keymap = keymaps.get(&KeyPress::parse("ctrl+w l")).unwrap();
+        assert_eq!(keymap.len(), 1);
+        assert_eq!(keymap[0].command, "line_end");
+        assert!(command_keymaps.get("right").unwrap().is_empty());
+        assert_eq!(keymaps.get(&KeyPress::parse("ctrl+w")).unwrap().len(), 2);
+        assert!(keymaps.get(&KeyPress::parse("ctrl+w j")).is_none());
+        assert!(keymaps.get(&KeyPress::parse("j")).is_none());
+        assert!(keymaps.get(&KeyPress::parse("ctrl+w k")).is_none());
+    }
+
+    #[test]
+    fn test_emacs_preset() {
+        let mut loader = KeyMapLoader::new();
+        loader
+            .load_from_str(crate::keypress::DEFAULT_KEYMAPS_NONMACOS, false)
+            .unwrap();
+        loader
+            .load_from_str(crate::keypress::KEYMAPS_EMACS, false)
+            .unwrap();
+
+        let (keymaps, _) = loader.finalize();
+
+        // Kill line isn't the start of a key sequence anymore
+        let keymap = keymaps.get(&KeyPress::parse("ctrl+k")).unwrap();
+        assert_eq!(keymap.len(), 1);
+        assert_eq!(keymap[0].command, "delete_to_end_of_line");
+
+        // The preset is tried before the defaults bound to the same key
+        let keymap = keymaps.get(&KeyPress::parse("ctrl+a")).unwrap();
+        assert_eq!(keymap.last().unwrap().command, "line_start");
+    }
 }
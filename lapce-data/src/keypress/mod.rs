@@ -3,13 +3,14 @@
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use druid::piet::{PietTextLayout, Text, TextLayout, TextLayoutBuilder};
 use druid::{Command, KbKey};
 use druid::{
     Env, EventCtx, ExtEventSink, FontFamily, KeyEvent, Modifiers, PaintCtx, Point,
-    Rect, RenderContext, Size, Target,
+    Rect, RenderContext, Size, Target, TimerToken,
 };
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
@@ -37,6 +38,7 @@ const DEFAULT_KEYMAPS_MACOS: &str =
     include_str!("../../../defaults/keymaps-macos.toml");
 const DEFAULT_KEYMAPS_NONMACOS: &str =
     include_str!("../../../defaults/keymaps-nonmacos.toml");
+const KEYMAPS_EMACS: &str = include_str!("../../../defaults/keymaps-emacs.toml");
 
 #[derive(PartialEq)]
 enum KeymapMatch {
@@ -156,6 +158,17 @@ pub struct KeyPressData {
     pub filter_pattern: String,
 
     count: Option<usize>,
+    /// The timer of the pending keys, after which they run the command they
+    /// are bound to on their own, if any, and are dropped
+    pending_timer: TimerToken,
+    /// How long to wait for the next key of a sequence, in milliseconds
+    key_sequence_timeout: u64,
+    /// Whether to show the keys that can follow a sequence paused on
+    /// instead of dropping it
+    show_which_key: bool,
+    /// The keys that can follow the pending keys and the commands they run,
+    /// shown while the pending keys wait for the next one
+    pub which_key: Vec<(String, String)>,
 
     event_sink: ExtEventSink,
 }
@@ -175,6 +188,10 @@ impl KeyPressData {
             filtered_commands_with_keymap: Arc::new(Vec::new()),
             filtered_commands_without_keymap: Arc::new(Vec::new()),
             count: None,
+            pending_timer: TimerToken::INVALID,
+            key_sequence_timeout: config.lapce.key_sequence_timeout,
+            show_which_key: config.lapce.which_key,
+            which_key: Vec::new(),
             event_sink,
         };
         keypress.load_commands();
@@ -182,6 +199,8 @@ impl KeyPressData {
     }
 
     pub fn update_keymaps(&mut self, config: &Config) {
+        self.key_sequence_timeout = config.lapce.key_sequence_timeout;
+        self.show_which_key = config.lapce.which_key;
         if let Ok((new_keymaps, new_command_keymaps)) = Self::get_keymaps(config) {
             self.keymaps = Arc::new(new_keymaps);
             self.command_keymaps = Arc::new(new_command_keymaps);
@@ -321,9 +340,20 @@ impl KeyPressData {
             return false;
         }
 
+        if !self.which_key.is_empty() && keypress.key == KbKey::Escape {
+            self.pending_keypress.clear();
+            self.count = None;
+            self.which_key.clear();
+            return true;
+        }
+
         self.pending_keypress.push(keypress.clone());
+        self.pending_timer = TimerToken::INVALID;
 
         let keymatch = self.match_keymap(&self.pending_keypress, focus);
+        if keymatch != KeymapMatch::Prefix {
+            self.which_key.clear();
+        }
         match keymatch {
             KeymapMatch::Full(command) => {
                 self.pending_keypress.clear();
@@ -347,6 +377,14 @@ impl KeyPressData {
             KeymapMatch::Prefix => {
                 // Here pending_keypress contains only a prefix of some keymap, so let's keep
                 // collecting key presses.
+                if self.which_key.is_empty() {
+                    self.pending_timer = ctx.request_timer(Duration::from_millis(
+                        self.key_sequence_timeout,
+                    ));
+                } else {
+                    self.which_key =
+                        self.which_key_items(&self.pending_keypress, focus);
+                }
                 return false;
             }
             KeymapMatch::None => {
@@ -386,13 +424,122 @@ impl KeyPressData {
         false
     }
 
+    /// Handle the timer of the pending keys: run the command bound to the
+    /// keys typed so far if there's one, since no longer sequence was typed,
+    /// and start over. Without one, the keys that can follow are shown
+    /// instead if enabled, and the keys keep waiting for the next one.
+    /// Returns whether the timer was the pending keys' one.
+    pub fn timer<T: KeyPressFocus>(
+        &mut self,
+        ctx: &mut EventCtx,
+        token: TimerToken,
+        focus: &mut T,
+        env: &Env,
+    ) -> bool {
+        if token != self.pending_timer {
+            return false;
+        }
+        self.pending_timer = TimerToken::INVALID;
+        let keypresses = std::mem::take(&mut self.pending_keypress);
+        let count = self.count.take();
+        let mods = match keypresses.last() {
+            Some(keypress) => keypress.mods,
+            None => return true,
+        };
+        let commands: Vec<String> = self
+            .matching_keymaps(&keypresses, focus)
+            .iter()
+            .filter(|keymap| keymap.key == keypresses)
+            .map(|keymap| keymap.command.clone())
+            .collect();
+        if commands.is_empty() && self.show_which_key {
+            self.which_key = self.which_key_items(&keypresses, focus);
+            self.pending_keypress = keypresses;
+            self.count = count;
+            return true;
+        }
+        for command in commands.iter().rev() {
+            if self.run_command(ctx, command, count, mods, focus, env)
+                == CommandExecuted::Yes
+            {
+                break;
+            }
+        }
+        true
+    }
+
+    pub fn pending_timer(&self) -> TimerToken {
+        self.pending_timer
+    }
+
+    /// The count and the keys of a sequence being typed, to show them
+    pub fn pending_keys(&self) -> String {
+        let count = self.count.map(|c| c.to_string()).unwrap_or_default();
+        let keys = self
+            .pending_keypress
+            .iter()
+            .map(|k| k.to_string())
+            .join(" ");
+        format!("{count}{keys}")
+    }
+
     fn match_keymap<T: KeyPressFocus>(
         &self,
         keypresses: &[KeyPress],
         check: &T,
     ) -> KeymapMatch {
-        let matches = self
-            .keymaps
+        let matches = self.matching_keymaps(keypresses, check);
+
+        if matches.is_empty() {
+            KeymapMatch::None
+        } else if matches.len() == 1 && matches[0].key == keypresses {
+            KeymapMatch::Full(matches[0].command.clone())
+        } else if matches.len() > 1
+            && matches.iter().filter(|m| m.key != keypresses).count() == 0
+        {
+            KeymapMatch::Multiple(
+                matches.iter().rev().map(|m| m.command.clone()).collect(),
+            )
+        } else {
+            KeymapMatch::Prefix
+        }
+    }
+
+    /// The keys that can follow the keys typed so far, each with the
+    /// description of the command it runs, sorted by key
+    fn which_key_items<T: KeyPressFocus>(
+        &self,
+        keypresses: &[KeyPress],
+        focus: &T,
+    ) -> Vec<(String, String)> {
+        let mut items = IndexMap::new();
+        // Later keymaps win, like when they are run
+        for keymap in self.matching_keymaps(keypresses, focus).iter().rev() {
+            if keymap.key.len() <= keypresses.len() {
+                continue;
+            }
+            let keys = keymap.key[keypresses.len()..]
+                .iter()
+                .map(|k| k.to_string())
+                .join(" ");
+            let desc = self
+                .commands
+                .get(&keymap.command)
+                .and_then(|cmd| cmd.palette_desc.clone())
+                .unwrap_or_else(|| keymap.command.clone());
+            items.entry(keys).or_insert(desc);
+        }
+        items.sort_keys();
+        items.into_iter().collect()
+    }
+
+    /// The keymaps that start with the keys and apply to the focus
+    fn matching_keymaps<T: KeyPressFocus>(
+        &self,
+        keypresses: &[KeyPress],
+        check: &T,
+    ) -> Vec<&KeyMap> {
+        self.keymaps
             .get(keypresses)
             .map(|keymaps| {
                 keymaps
@@ -418,21 +565,7 @@ impl KeyPressData {
                     })
                     .collect()
             })
-            .unwrap_or_else(Vec::new);
-
-        if matches.is_empty() {
-            KeymapMatch::None
-        } else if matches.len() == 1 && matches[0].key == keypresses {
-            KeymapMatch::Full(matches[0].command.clone())
-        } else if matches.len() > 1
-            && matches.iter().filter(|m| m.key != keypresses).count() == 0
-        {
-            KeymapMatch::Multiple(
-                matches.iter().rev().map(|m| m.command.clone()).collect(),
-            )
-        } else {
-            KeymapMatch::Prefix
-        }
+            .unwrap_or_else(Vec::new)
     }
 
     fn check_condition<T: KeyPressFocus>(condition: &str, check: &T) -> bool {
@@ -653,6 +786,16 @@ impl KeyPressData {
             log::error!("Failed to load OS defaults: {err}");
         }
 
+        let preset = match config.lapce.keymap_preset.as_str() {
+            "emacs" => Some(KEYMAPS_EMACS),
+            _ => None,
+        };
+        if let Some(preset) = preset {
+            if let Err(err) = loader.load_from_str(preset, is_modal) {
+                log::error!("Failed to load the keymap preset: {err}");
+            }
+        }
+
         if let Some(path) = Self::file() {
             if let Ok(content) = std::fs::read_to_string(&path) {
                 if let Err(err) = loader.load_from_str(&content, is_modal) {
@@ -1,4 +1,5 @@
 pub mod buffer;
+pub mod cli;
 pub mod code_action;
 pub mod command;
 pub mod completion;
@@ -9,8 +10,12 @@ pub mod db;
 pub mod editor;
 pub mod explorer;
 pub mod find;
+pub mod folding;
+pub mod headless;
 pub mod hover;
+pub mod inlay_hint;
 pub mod keypress;
+pub mod location_list;
 pub mod lsp;
 pub mod menu;
 pub mod movement;
@@ -19,8 +24,10 @@ pub mod palette;
 pub mod panel;
 pub mod picker;
 pub mod plugin;
+pub mod primary_selection;
 pub mod problem;
 pub mod proxy;
+pub mod reflow;
 pub mod scroll;
 pub mod search;
 pub mod settings;
@@ -31,3 +38,4 @@ pub mod state;
 pub mod svg;
 pub mod terminal;
 pub mod window;
+pub mod wrap;
@@ -0,0 +1,254 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use druid::WidgetId;
+use itertools::Itertools;
+use lsp_types::Position;
+
+use crate::{data::EditorDiagnostic, editor::EditorLocationNew, search::Match};
+
+/// The feature that filled the location list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocationListSource {
+    Diagnostics,
+    References,
+    /// The matches of the workspace search with this id
+    Search(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocationListItem {
+    pub path: PathBuf,
+    pub start: Position,
+    pub end: Position,
+    /// The diagnostic message, or the text of the line for the other sources
+    pub message: String,
+}
+
+impl LocationListItem {
+    pub fn location(&self) -> EditorLocationNew {
+        EditorLocationNew {
+            path: self.path.clone(),
+            position: Some(self.start),
+            scroll_offset: None,
+            history: None,
+        }
+    }
+}
+
+/// A list of locations shared by the diagnostics, the references and the
+/// workspace search, which can be stepped through from anywhere
+#[derive(Clone)]
+pub struct LocationListData {
+    pub widget_id: WidgetId,
+    pub split_id: WidgetId,
+    pub content_widget_id: WidgetId,
+    pub source: Option<LocationListSource>,
+    pub items: Arc<Vec<LocationListItem>>,
+    /// The item that was jumped to last
+    pub current: Option<usize>,
+}
+
+impl LocationListData {
+    pub fn new() -> Self {
+        Self {
+            widget_id: WidgetId::next(),
+            split_id: WidgetId::next(),
+            content_widget_id: WidgetId::next(),
+            source: None,
+            items: Arc::new(Vec::new()),
+            current: None,
+        }
+    }
+
+    /// Replace the items of the list. When they come from the same source
+    /// as before, the current item is kept if it's still in the list.
+    pub fn set_items(
+        &mut self,
+        source: LocationListSource,
+        items: Vec<LocationListItem>,
+    ) {
+        let current = if self.source == Some(source) {
+            self.current
+                .and_then(|i| self.items.get(i))
+                .and_then(|current| items.iter().position(|item| item == current))
+        } else {
+            None
+        };
+        self.source = Some(source);
+        self.items = Arc::new(items);
+        self.current = current;
+    }
+
+    /// Add items from `source`, starting a new list if the list was filled
+    /// from somewhere else
+    pub fn add_items(
+        &mut self,
+        source: LocationListSource,
+        items: Vec<LocationListItem>,
+    ) {
+        if self.source != Some(source) {
+            self.set_items(source, items);
+        } else {
+            Arc::make_mut(&mut self.items).extend(items);
+        }
+    }
+
+    pub fn select(&mut self, index: usize) -> Option<&LocationListItem> {
+        if index >= self.items.len() {
+            return None;
+        }
+        self.current = Some(index);
+        self.items.get(index)
+    }
+
+    /// Move to the item after the current one, wrapping around at the end
+    pub fn next(&mut self) -> Option<&LocationListItem> {
+        let len = self.items.len();
+        if len == 0 {
+            return None;
+        }
+        let index = match self.current {
+            Some(i) => (i + 1) % len,
+            None => 0,
+        };
+        self.select(index)
+    }
+
+    /// Move to the item before the current one, wrapping around at the start
+    pub fn previous(&mut self) -> Option<&LocationListItem> {
+        let len = self.items.len();
+        if len == 0 {
+            return None;
+        }
+        let index = match self.current {
+            Some(i) => (i + len - 1) % len,
+            None => len - 1,
+        };
+        self.select(index)
+    }
+}
+
+impl Default for LocationListData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The diagnostics of all files, sorted by path and then by position
+pub fn diagnostic_items(
+    diagnostics: &im::HashMap<PathBuf, Arc<Vec<EditorDiagnostic>>>,
+) -> Vec<LocationListItem> {
+    diagnostics
+        .iter()
+        .sorted_by_key(|(path, _)| (*path).clone())
+        .flat_map(|(path, diagnostics)| {
+            diagnostics
+                .iter()
+                .map(|d| {
+                    // The range of a diagnostic that was moved by edits is
+                    // only known by its start
+                    let (start, end) = match d.range {
+                        Some((line, col)) => {
+                            let start = Position {
+                                line: line as u32,
+                                character: col as u32,
+                            };
+                            (start, start)
+                        }
+                        None => (d.diagnositc.range.start, d.diagnositc.range.end),
+                    };
+                    LocationListItem {
+                        path: path.clone(),
+                        start,
+                        end,
+                        message: d.diagnositc.message.clone(),
+                    }
+                })
+                .sorted_by_key(|item| (item.start.line, item.start.character))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The matches of a workspace search in a file
+pub fn search_items(path: &Path, matches: &[Match]) -> Vec<LocationListItem> {
+    matches
+        .iter()
+        .map(|(line, (start, end), content)| {
+            let line = line.saturating_sub(1) as u32;
+            LocationListItem {
+                path: path.to_path_buf(),
+                start: Position {
+                    line,
+                    character: *start as u32,
+                },
+                end: Position {
+                    line,
+                    character: *end as u32,
+                },
+                message: content.trim().to_string(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(line: u32) -> LocationListItem {
+        LocationListItem {
+            path: PathBuf::from("/a.rs"),
+            start: Position { line, character: 0 },
+            end: Position { line, character: 1 },
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_next_and_previous_wrap_around() {
+        let mut list = LocationListData::new();
+        assert_eq!(list.next(), None);
+
+        list.set_items(LocationListSource::References, vec![item(1), item(2)]);
+        assert_eq!(list.next().map(|i| i.start.line), Some(1));
+        assert_eq!(list.next().map(|i| i.start.line), Some(2));
+        assert_eq!(list.next().map(|i| i.start.line), Some(1));
+        assert_eq!(list.previous().map(|i| i.start.line), Some(2));
+
+        let mut list = LocationListData::new();
+        list.set_items(LocationListSource::References, vec![item(1), item(2)]);
+        assert_eq!(list.previous().map(|i| i.start.line), Some(2));
+    }
+
+    #[test]
+    fn test_set_items_keeps_current_of_same_source() {
+        let mut list = LocationListData::new();
+        list.set_items(LocationListSource::Diagnostics, vec![item(1), item(2)]);
+        list.select(1);
+
+        list.set_items(LocationListSource::Diagnostics, vec![item(0), item(2)]);
+        assert_eq!(list.current, Some(1));
+
+        list.set_items(LocationListSource::Diagnostics, vec![item(0)]);
+        assert_eq!(list.current, None);
+
+        list.select(0);
+        list.set_items(LocationListSource::References, vec![item(0)]);
+        assert_eq!(list.current, None);
+    }
+
+    #[test]
+    fn test_add_items_starts_a_new_list_for_a_new_search() {
+        let mut list = LocationListData::new();
+        list.add_items(LocationListSource::Search(1), vec![item(1)]);
+        list.add_items(LocationListSource::Search(1), vec![item(2)]);
+        assert_eq!(list.items.len(), 2);
+
+        list.add_items(LocationListSource::Search(2), vec![item(3)]);
+        assert_eq!(list.items.as_ref(), &vec![item(3)]);
+    }
+}
@@ -50,6 +50,85 @@ impl Cursor {
         }
     }
 
+    /// The cursor moved `count` times by `movement`, like it is outside of
+    /// an operator like `d`. With `modify` the selections in insert mode are
+    /// extended instead of moved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn moved(
+        &self,
+        buffer: &Buffer,
+        movement: &Movement,
+        count: usize,
+        modify: bool,
+        code_lens: bool,
+        compare: Option<String>,
+        config: &Config,
+    ) -> Cursor {
+        match &self.mode {
+            CursorMode::Normal(offset) => {
+                let (new_offset, horiz) = buffer.move_offset(
+                    *offset,
+                    self.horiz.as_ref(),
+                    count,
+                    movement,
+                    Mode::Normal,
+                    code_lens,
+                    compare,
+                    config,
+                );
+                Cursor::new(CursorMode::Normal(new_offset), Some(horiz))
+            }
+            CursorMode::Visual { start, end, mode } => {
+                let (new_offset, horiz) = buffer.move_offset(
+                    *end,
+                    self.horiz.as_ref(),
+                    count,
+                    movement,
+                    Mode::Visual,
+                    code_lens,
+                    compare,
+                    config,
+                );
+                Cursor::new(
+                    CursorMode::Visual {
+                        start: *start,
+                        end: new_offset,
+                        mode: *mode,
+                    },
+                    Some(horiz),
+                )
+            }
+            CursorMode::Insert(selection) => {
+                let selection = buffer.update_selection(
+                    selection,
+                    count,
+                    movement,
+                    Mode::Insert,
+                    modify,
+                    code_lens,
+                    compare,
+                    config,
+                );
+                Cursor::new(CursorMode::Insert(selection), None)
+            }
+        }
+    }
+
+    /// The cursor after an edit that left `selection` behind. Outside of
+    /// insert mode that's the start of the selection, kept on a character.
+    pub fn after_change(&self, buffer: &Buffer, selection: Selection) -> Cursor {
+        match self.mode {
+            CursorMode::Normal(_) | CursorMode::Visual { .. } => {
+                let offset = selection.min_offset();
+                let offset = buffer.offset_line_end(offset, false).min(offset);
+                Cursor::new(CursorMode::Normal(offset), None)
+            }
+            CursorMode::Insert(_) => {
+                Cursor::new(CursorMode::Insert(selection), None)
+            }
+        }
+    }
+
     pub fn is_normal(&self) -> bool {
         matches!(&self.mode, CursorMode::Normal(_))
     }
@@ -207,6 +286,39 @@ impl Cursor {
         }
     }
 
+    /// Select `start..end`, replacing the current selection, with `end` being
+    /// the active side. Modal cursors become a visual selection of `mode`, so
+    /// the operators work on a mouse selection like on a keyboard one.
+    pub fn select_region(
+        &self,
+        start: usize,
+        end: usize,
+        mode: VisualMode,
+        new_cursor: bool,
+    ) -> Self {
+        match &self.mode {
+            CursorMode::Normal(_) | CursorMode::Visual { .. } => {
+                let (start, end) = if end >= start {
+                    (start, end.saturating_sub(1).max(start))
+                } else {
+                    (start.saturating_sub(1).max(end), end)
+                };
+                Cursor::new(CursorMode::Visual { start, end, mode }, None)
+            }
+            CursorMode::Insert(selection) => {
+                let region = SelRegion::new(start, end, None);
+                let new_selection = if new_cursor {
+                    let mut new_selection = selection.clone();
+                    new_selection.replace_last_inserted_region(region);
+                    new_selection
+                } else {
+                    Selection::region(start, end)
+                };
+                Cursor::new(CursorMode::Insert(new_selection), None)
+            }
+        }
+    }
+
     pub fn current_char(
         &self,
         buffer: &Buffer,
@@ -651,11 +763,26 @@ impl Selection {
         }
         if ix == end_ix {
             self.regions.insert(ix, region);
-            self.last_inserted = ix;
         } else {
             self.regions[ix] = region;
             remove_n_at(&mut self.regions, ix + 1, end_ix - ix - 1);
         }
+        self.last_inserted = ix;
+    }
+
+    /// Merge the regions that overlap after being changed in place with
+    /// `regions_mut`, the last inserted region stays the last inserted one
+    pub fn merge_overlapping(&mut self) {
+        let mut regions = std::mem::take(&mut self.regions);
+        if regions.is_empty() {
+            return;
+        }
+        let last_inserted =
+            regions.remove(self.last_inserted.min(regions.len() - 1));
+        for region in regions {
+            self.add_region(region);
+        }
+        self.add_region(last_inserted);
     }
 
     pub fn get_cursor_offset(&self) -> usize {
@@ -736,6 +863,8 @@ impl Selection {
             last += 1;
         }
         remove_n_at(&mut self.regions, first, last - first);
+        self.last_inserted =
+            self.last_inserted.min(self.regions.len().saturating_sub(1));
     }
 
     pub fn regions_in_range(&self, start: usize, end: usize) -> &[SelRegion] {
@@ -825,15 +954,26 @@ pub enum Movement {
     DocumentEnd,
     FirstNonBlank,
     StartOfLine,
+    /// The first non-blank character of the line, or the start of the line
+    /// when already on it, so that repeating it toggles between the two
+    SmartLineStart,
     EndOfLine,
     Line(LinePosition),
     Offset(usize),
     WordEndForward,
     WordForward,
     WordBackward,
+    /// Like the word movements, but words are only delimited by whitespace
+    BigWordForward,
+    BigWordBackward,
     NextUnmatched(char),
     PreviousUnmatched(char),
     MatchPairs,
+    /// The top, middle or bottom line shown in the editor, these depend on
+    /// the viewport and are resolved to a line by the editor
+    TopOfWindow,
+    MiddleOfWindow,
+    BottomOfWindow,
 }
 
 impl PartialEq for Movement {
@@ -851,6 +991,9 @@ impl Movement {
                 | Movement::Line(_)
                 | Movement::DocumentStart
                 | Movement::DocumentEnd
+                | Movement::TopOfWindow
+                | Movement::MiddleOfWindow
+                | Movement::BottomOfWindow
         )
     }
 
@@ -913,7 +1056,96 @@ pub fn remove_n_at<T>(v: &mut Vec<T>, index: usize, n: usize) {
 
 #[cfg(test)]
 mod test {
-    use crate::movement::Movement;
+    use xi_rope::{Delta, Interval, Rope};
+
+    use crate::{
+        movement::{
+            Cursor, CursorMode, InsertDrift, Movement, SelRegion, Selection,
+        },
+        state::VisualMode,
+    };
+
+    fn carets(offsets: &[usize]) -> Selection {
+        let mut selection = Selection::new();
+        for offset in offsets {
+            selection.add_region(SelRegion::caret(*offset));
+        }
+        selection
+    }
+
+    #[test]
+    fn test_carets_moved_onto_each_other_merge() {
+        let mut selection = carets(&[2, 6]);
+        for region in selection.regions_mut() {
+            region.start = 4;
+            region.end = 4;
+        }
+        selection.merge_overlapping();
+        assert_eq!(selection.regions(), &[SelRegion::caret(4)]);
+        assert_eq!(selection.get_cursor_offset(), 4);
+    }
+
+    #[test]
+    fn test_carets_merge_after_delete_between_them() {
+        let selection = carets(&[2, 6]);
+        let delta = Delta::simple_edit(Interval::new(2, 6), Rope::from(""), 10);
+        let selection = selection.apply_delta(&delta, true, InsertDrift::Default);
+        assert_eq!(selection.regions(), &[SelRegion::caret(2)]);
+    }
+
+    #[test]
+    fn test_merged_regions_keep_cursor() {
+        let mut selection = carets(&[1, 5, 9]);
+        selection.add_region(SelRegion::new(0, 6, None));
+        assert_eq!(selection.len(), 2);
+        assert_eq!(selection.get_cursor_offset(), 6);
+    }
+
+    #[test]
+    fn test_delete_last_inserted_region() {
+        let mut selection = carets(&[1, 5, 9]);
+        assert_eq!(selection.get_cursor_offset(), 9);
+        selection.delete_range(8, 10, true);
+        assert_eq!(selection.len(), 2);
+        assert_eq!(selection.get_cursor_offset(), 5);
+    }
+
+    #[test]
+    fn test_select_region_becomes_visual() {
+        let cursor = Cursor::new(CursorMode::Normal(3), None);
+        let cursor = cursor.select_region(4, 9, VisualMode::Normal, false);
+        assert_eq!(
+            cursor.mode,
+            CursorMode::Visual {
+                start: 4,
+                end: 8,
+                mode: VisualMode::Normal,
+            }
+        );
+
+        let cursor = cursor.select_region(9, 4, VisualMode::Linewise, false);
+        assert_eq!(
+            cursor.mode,
+            CursorMode::Visual {
+                start: 8,
+                end: 4,
+                mode: VisualMode::Linewise,
+            }
+        );
+    }
+
+    #[test]
+    fn test_select_region_replaces_insert_selection() {
+        let cursor = Cursor::new(CursorMode::Insert(carets(&[1, 5])), None);
+        let cursor = cursor.select_region(4, 9, VisualMode::Normal, false);
+        assert_eq!(cursor.mode, CursorMode::Insert(Selection::region(4, 9)));
+
+        let cursor = Cursor::new(CursorMode::Insert(carets(&[1, 5])), None);
+        let cursor = cursor.select_region(4, 9, VisualMode::Normal, true);
+        let mut expected = carets(&[1]);
+        expected.add_region(SelRegion::new(4, 9, None));
+        assert_eq!(cursor.mode, CursorMode::Insert(expected));
+    }
 
     #[test]
     fn test_wrapping() {
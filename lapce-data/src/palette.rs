@@ -13,7 +13,9 @@ use druid::{
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 use itertools::Itertools;
-use lsp_types::{DocumentSymbolResponse, Range, SymbolKind};
+use lapce_core::language::LapceLanguage;
+use lapce_rpc::source_control::BlameHunk;
+use lsp_types::{DocumentSymbol, DocumentSymbolResponse, Range, SymbolKind};
 use serde_json;
 use std::collections::HashSet;
 use std::path::PathBuf;
@@ -23,7 +25,7 @@ use usvg;
 use uuid::Uuid;
 
 use crate::{
-    buffer::BufferContent,
+    buffer::{blame_text, BufferContent},
     command::LAPCE_UI_COMMAND,
     command::{CommandExecuted, LapceCommand, LAPCE_NEW_COMMAND},
     command::{LapceCommandNew, LapceUICommand},
@@ -52,6 +54,10 @@ pub enum PaletteType {
     Reference,
     Theme,
     SshHost,
+    Buffer,
+    Language,
+    RecentFile,
+    Blame,
 }
 
 impl PaletteType {
@@ -66,6 +72,10 @@ impl PaletteType {
             PaletteType::Reference => "".to_string(),
             PaletteType::Theme => "".to_string(),
             PaletteType::SshHost => "".to_string(),
+            PaletteType::Buffer => "".to_string(),
+            PaletteType::Language => "".to_string(),
+            PaletteType::RecentFile => "".to_string(),
+            PaletteType::Blame => "".to_string(),
         }
     }
 
@@ -76,6 +86,7 @@ impl PaletteType {
                 | PaletteType::DocumentSymbol
                 | PaletteType::GlobalSearch
                 | PaletteType::Reference
+                | PaletteType::Blame
         )
     }
 }
@@ -104,12 +115,23 @@ pub enum PaletteItemContent {
         name: String,
         range: Range,
         container_name: Option<String>,
+        /// How deep the symbol is nested in other symbols
+        depth: usize,
     },
     ReferenceLocation(PathBuf, EditorLocationNew),
     Workspace(LapceWorkspace),
     SshHost(String, String),
-    Command(LapceCommandNew),
+    /// A command, together with the key binding that runs it, if any
+    Command(LapceCommandNew, String),
+    /// An open file, with the editor that already shows it, if any
+    Buffer {
+        path: PathBuf,
+        full_path: PathBuf,
+        view_id: Option<WidgetId>,
+        dirty: bool,
+    },
     Theme(String),
+    Language(LapceLanguage),
 }
 
 impl PaletteItemContent {
@@ -135,6 +157,7 @@ impl PaletteItemContent {
                 name,
                 range,
                 container_name,
+                depth,
             } => {
                 let editor_id = if preview {
                     Some(preview_editor_id)
@@ -180,6 +203,24 @@ impl PaletteItemContent {
                     ));
                 }
             }
+            PaletteItemContent::Buffer {
+                full_path, view_id, ..
+            } => {
+                if !preview {
+                    match view_id {
+                        Some(view_id) => ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::Focus,
+                            Target::Widget(*view_id),
+                        )),
+                        None => ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::OpenFile(full_path.clone()),
+                            Target::Auto,
+                        )),
+                    }
+                }
+            }
             PaletteItemContent::Theme(theme) => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -187,7 +228,16 @@ impl PaletteItemContent {
                     Target::Auto,
                 ));
             }
-            PaletteItemContent::Command(command) => {
+            PaletteItemContent::Language(language) => {
+                if !preview {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::SetLanguage(*language),
+                        Target::Auto,
+                    ));
+                }
+            }
+            PaletteItemContent::Command(command, _) => {
                 if !preview {
                     ctx.submit_command(Command::new(
                         LAPCE_NEW_COMMAND,
@@ -235,12 +285,28 @@ impl PaletteItemContent {
     ) {
         let (svg, text, text_indices, hint, hint_indices) = match &self {
             PaletteItemContent::File(path, _) => file_paint_items(path, indices),
+            PaletteItemContent::Buffer { path, dirty, .. } => {
+                let (svg, text, text_indices, hint, hint_indices) =
+                    file_paint_items(path, indices);
+                if *dirty {
+                    (
+                        svg,
+                        "*".to_string() + &text,
+                        text_indices.iter().map(|i| i + 1).collect(),
+                        hint,
+                        hint_indices,
+                    )
+                } else {
+                    (svg, text, text_indices, hint, hint_indices)
+                }
+            }
             #[allow(unused_variables)]
             PaletteItemContent::DocumentSymbol {
                 kind,
                 name,
                 range,
                 container_name,
+                depth,
             } => {
                 let text = name.to_string();
                 let hint = container_name.clone().unwrap_or_else(|| "".to_string());
@@ -287,7 +353,7 @@ impl PaletteItemContent {
                 };
                 (None, text, indices.to_vec(), "".to_string(), vec![])
             }
-            PaletteItemContent::Command(command) => (
+            PaletteItemContent::Command(command, keymap) => (
                 None,
                 command
                     .palette_desc
@@ -295,7 +361,7 @@ impl PaletteItemContent {
                     .map(|m| m.to_string())
                     .unwrap_or_else(|| "".to_string()),
                 indices.to_vec(),
-                "".to_string(),
+                keymap.to_string(),
                 vec![],
             ),
             PaletteItemContent::Theme(theme) => (
@@ -305,6 +371,13 @@ impl PaletteItemContent {
                 "".to_string(),
                 vec![],
             ),
+            PaletteItemContent::Language(language) => (
+                None,
+                language.name().to_string(),
+                indices.to_vec(),
+                "".to_string(),
+                vec![],
+            ),
             PaletteItemContent::TerminalLine(_line, content) => (
                 None,
                 content.clone(),
@@ -321,11 +394,18 @@ impl PaletteItemContent {
             ),
         };
 
+        let indent = match &self {
+            PaletteItemContent::DocumentSymbol { depth, .. } => {
+                *depth as f64 * line_height / 2.0
+            }
+            _ => 0.0,
+        };
+
         if let Some(svg) = svg.as_ref() {
             let width = 14.0;
             let height = 14.0;
             let rect = Size::new(width, height).to_rect().with_origin(Point::new(
-                (line_height - width) / 2.0 + 5.0,
+                indent + (line_height - width) / 2.0 + 5.0,
                 (line_height - height) / 2.0 + line_height * line as f64,
             ));
             ctx.draw_svg(svg, rect, None);
@@ -336,7 +416,7 @@ impl PaletteItemContent {
                 0.0
             }
             _ => line_height,
-        };
+        } + indent;
 
         let focus_color = config.get_color_unchecked(LapceTheme::EDITOR_FOCUS);
 
@@ -568,6 +648,10 @@ impl PaletteData {
             PaletteType::Reference => &self.input,
             PaletteType::Theme => &self.input,
             PaletteType::SshHost => &self.input,
+            PaletteType::Buffer => &self.input,
+            PaletteType::Language => &self.input,
+            PaletteType::RecentFile => &self.input,
+            PaletteType::Blame => &self.input,
             PaletteType::Line => &self.input[1..],
             PaletteType::DocumentSymbol => &self.input[1..],
             PaletteType::Workspace => &self.input[1..],
@@ -674,6 +758,18 @@ impl PaletteViewData {
                 let config = self.config.clone();
                 self.get_themes(ctx, &config);
             }
+            PaletteType::Buffer => {
+                self.get_buffers();
+            }
+            PaletteType::Language => {
+                self.get_languages();
+            }
+            PaletteType::RecentFile => {
+                self.get_recent_files();
+            }
+            PaletteType::Blame => {
+                self.get_blame(ctx);
+            }
         }
     }
 
@@ -699,6 +795,10 @@ impl PaletteViewData {
             PaletteType::Reference => 0,
             PaletteType::Theme => 0,
             PaletteType::SshHost => 0,
+            PaletteType::Buffer => 0,
+            PaletteType::Language => 0,
+            PaletteType::RecentFile => 0,
+            PaletteType::Blame => 0,
             PaletteType::Line => 1,
             PaletteType::DocumentSymbol => 1,
             PaletteType::Workspace => 1,
@@ -731,7 +831,10 @@ impl PaletteViewData {
     }
 
     pub fn select(&mut self, ctx: &mut EventCtx) {
-        if self.palette.palette_type == PaletteType::Line {
+        if self.palette.palette_type == PaletteType::Line
+            && self.palette.get_input().parse::<usize>().is_err()
+        {
+            // Going to a line number doesn't search for it
             let pattern = self.palette.get_input().to_string();
             let find = Arc::make_mut(&mut self.find);
             find.visual = true;
@@ -784,6 +887,23 @@ impl PaletteViewData {
             self.run(ctx, Some(palette_type));
             return;
         }
+        if self.palette.palette_type == PaletteType::Line {
+            if let Ok(line) = self.palette.get_input().parse::<usize>() {
+                // A plain number goes to that line instead of searching for it
+                let palette = Arc::make_mut(&mut self.palette);
+                let line = line.max(1).min(palette.items.len());
+                palette.filtered_items = palette
+                    .items
+                    .iter()
+                    .filter(|item| {
+                        matches!(item.content, PaletteItemContent::Line(l, _) if l == line)
+                    })
+                    .cloned()
+                    .collect();
+                palette.preview(ctx);
+                return;
+            }
+        }
         if self.palette.get_input() != "" {
             let _ = self.palette.sender.send((
                 self.palette.run_id.clone(),
@@ -797,7 +917,12 @@ impl PaletteViewData {
 
     fn get_palette_type(&self) -> PaletteType {
         match self.palette.palette_type {
-            PaletteType::Reference | PaletteType::SshHost => {
+            PaletteType::Reference
+            | PaletteType::SshHost
+            | PaletteType::Buffer
+            | PaletteType::Language
+            | PaletteType::RecentFile
+            | PaletteType::Blame => {
                 return self.palette.palette_type.clone();
             }
             _ => (),
@@ -818,12 +943,17 @@ impl PaletteViewData {
         let run_id = self.palette.run_id.clone();
         let widget_id = self.palette.widget_id;
         let workspace = self.workspace.clone();
+        let open_files: HashSet<PathBuf> =
+            self.main_split.open_files.keys().cloned().collect();
         let event_sink = ctx.get_external_handle();
         self.palette.proxy.get_files(Box::new(move |result| {
             if let Ok(res) = result {
                 let resp: Result<Vec<PathBuf>, serde_json::Error> =
                     serde_json::from_value(res);
-                if let Ok(resp) = resp {
+                if let Ok(mut resp) = resp {
+                    // Files that are already open go first, and because the
+                    // filter uses a stable sort they also win ties on score
+                    resp.sort_by_key(|path| !open_files.contains(path));
                     let items: Vec<NewPaletteItem> = resp
                         .iter()
                         .enumerate()
@@ -857,6 +987,72 @@ impl PaletteViewData {
         }));
     }
 
+    fn get_buffers(&mut self) {
+        let main_split = &self.main_split;
+        let mut paths: Vec<PathBuf> =
+            main_split.recent_buffers.iter().cloned().collect();
+        // Files which haven't been focused yet go last
+        for path in main_split.open_files.keys() {
+            if !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+
+        let items: Vec<NewPaletteItem> = paths
+            .into_iter()
+            .filter_map(|full_path| {
+                let buffer = main_split.open_files.get(&full_path)?;
+                // Prefer the editor which is the visible one in its tab
+                let view_id = main_split
+                    .editors
+                    .values()
+                    .filter(|editor| {
+                        editor.view_id != *main_split.palette_preview_editor
+                            && editor.content
+                                == BufferContent::File(full_path.clone())
+                    })
+                    .max_by_key(|editor| {
+                        editor
+                            .tab_id
+                            .and_then(|tab_id| main_split.editor_tabs.get(&tab_id))
+                            .map(|tab| {
+                                tab.children.get(tab.active).map(|c| c.widget_id())
+                                    == Some(editor.view_id)
+                            })
+                            .unwrap_or(false)
+                    })
+                    .map(|editor| editor.view_id);
+                let mut path = full_path.clone();
+                if let Some(workspace_path) = self.workspace.path.as_ref() {
+                    path = path
+                        .strip_prefix(workspace_path)
+                        .unwrap_or(&full_path)
+                        .to_path_buf();
+                }
+                let filter_text = path.to_str().unwrap_or("").to_string();
+                Some(NewPaletteItem {
+                    content: PaletteItemContent::Buffer {
+                        path,
+                        full_path,
+                        view_id,
+                        dirty: buffer.dirty,
+                    },
+                    filter_text,
+                    score: 0,
+                    indices: vec![],
+                })
+            })
+            .collect();
+
+        let palette = Arc::make_mut(&mut self.palette);
+        // The first one is the current file, so going back to the previous
+        // file only needs a confirm
+        if items.len() > 1 {
+            palette.index = 1;
+        }
+        palette.items = items;
+    }
+
     #[allow(unused_variables)]
     fn get_ssh_hosts(&mut self, ctx: &mut EventCtx) {
         let workspaces = Config::recent_workspaces().unwrap_or_default();
@@ -919,8 +1115,8 @@ impl PaletteViewData {
     fn get_themes(&mut self, ctx: &mut EventCtx, config: &Config) {
         let palette = Arc::make_mut(&mut self.palette);
         palette.items = config
-            .themes
-            .keys()
+            .available_themes()
+            .iter()
             .map(|n| NewPaletteItem {
                 content: PaletteItemContent::Theme(n.to_string()),
                 filter_text: n.to_string(),
@@ -930,6 +1126,49 @@ impl PaletteViewData {
             .collect();
     }
 
+    fn get_recent_files(&mut self) {
+        // The recent files are local ones, which a remote workspace can't open
+        let files = match self.workspace.kind {
+            LapceWorkspaceType::Local => Config::recent_files().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let workspace_path = self.workspace.path.clone();
+        let palette = Arc::make_mut(&mut self.palette);
+        palette.items = files
+            .into_iter()
+            .filter(|full_path| full_path.exists())
+            .map(|full_path| {
+                let path = workspace_path
+                    .as_ref()
+                    .and_then(|workspace_path| {
+                        full_path.strip_prefix(workspace_path).ok()
+                    })
+                    .unwrap_or(&full_path)
+                    .to_path_buf();
+                let filter_text = path.to_str().unwrap_or("").to_string();
+                NewPaletteItem {
+                    content: PaletteItemContent::File(path, full_path),
+                    filter_text,
+                    score: 0,
+                    indices: vec![],
+                }
+            })
+            .collect();
+    }
+
+    fn get_languages(&mut self) {
+        let palette = Arc::make_mut(&mut self.palette);
+        palette.items = LapceLanguage::ALL
+            .iter()
+            .map(|language| NewPaletteItem {
+                content: PaletteItemContent::Language(*language),
+                filter_text: language.name().to_string(),
+                score: 0,
+                indices: vec![],
+            })
+            .collect();
+    }
+
     #[allow(unused_variables)]
     fn get_commands(&mut self, ctx: &mut EventCtx) {
         let palette = Arc::make_mut(&mut self.palette);
@@ -938,8 +1177,15 @@ impl PaletteViewData {
             .commands
             .iter()
             .filter_map(|(_, c)| {
+                let keymap = self
+                    .keypress
+                    .command_keymaps
+                    .get(&c.cmd)
+                    .and_then(|keymaps| keymaps.first())
+                    .map(|keymap| keymap.key.iter().map(|k| k.to_string()).join(" "))
+                    .unwrap_or_default();
                 c.palette_desc.as_ref().map(|m| NewPaletteItem {
-                    content: PaletteItemContent::Command(c.clone()),
+                    content: PaletteItemContent::Command(c.clone(), keymap),
                     filter_text: m.to_string(),
                     score: 0,
                     indices: vec![],
@@ -1001,7 +1247,10 @@ impl PaletteViewData {
             None => return,
         };
 
-        let buffer = self.main_split.editor_buffer(editor.view_id);
+        let buffer = match self.main_split.editor_buffer(editor.view_id) {
+            Some(buffer) => buffer,
+            None => return,
+        };
         let last_line_number = buffer.last_line() + 1;
         let last_line_number_len = last_line_number.to_string().len();
         let palette = Arc::make_mut(&mut self.palette);
@@ -1028,6 +1277,61 @@ impl PaletteViewData {
             .collect();
     }
 
+    /// Lists the blame hunks of the file in the active editor, with the
+    /// commit that last changed each of them
+    fn get_blame(&mut self, ctx: &mut EventCtx) {
+        let path = match self
+            .main_split
+            .active_editor()
+            .map(|editor| editor.content.clone())
+        {
+            Some(BufferContent::File(path)) => path,
+            _ => return,
+        };
+
+        let run_id = self.palette.run_id.clone();
+        let widget_id = self.palette.widget_id;
+        let event_sink = ctx.get_external_handle();
+        self.palette.proxy.git_blame(
+            path,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(hunks) = serde_json::from_value::<Vec<BlameHunk>>(res)
+                    {
+                        let items: Vec<NewPaletteItem> = hunks
+                            .iter()
+                            .map(|hunk| {
+                                let line = hunk.start_line + 1;
+                                let commit =
+                                    hunk.commit.as_deref().unwrap_or("0000000");
+                                let text = format!(
+                                    "L{} {} {}",
+                                    line,
+                                    commit,
+                                    blame_text(hunk)
+                                );
+                                NewPaletteItem {
+                                    content: PaletteItemContent::Line(
+                                        line,
+                                        text.clone(),
+                                    ),
+                                    filter_text: text,
+                                    score: 0,
+                                    indices: vec![],
+                                }
+                            })
+                            .collect();
+                        let _ = event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdatePaletteItems(run_id, items),
+                            Target::Widget(widget_id),
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
     #[allow(unused_variables)]
     fn get_global_search(&mut self, ctx: &mut EventCtx) {}
 
@@ -1072,6 +1376,7 @@ impl PaletteViewData {
                                                     container_name: s
                                                         .container_name
                                                         .clone(),
+                                                    depth: 0,
                                                 },
                                             filter_text,
                                             score: 0,
@@ -1079,21 +1384,13 @@ impl PaletteViewData {
                                         }
                                     })
                                     .collect(),
-                                DocumentSymbolResponse::Nested(symbols) => symbols
-                                    .iter()
-                                    .map(|s| NewPaletteItem {
-                                        content:
-                                            PaletteItemContent::DocumentSymbol {
-                                                kind: s.kind,
-                                                name: s.name.clone(),
-                                                range: s.range,
-                                                container_name: None,
-                                            },
-                                        filter_text: s.name.clone(),
-                                        score: 0,
-                                        indices: Vec::new(),
-                                    })
-                                    .collect(),
+                                DocumentSymbolResponse::Nested(symbols) => {
+                                    let mut items = Vec::new();
+                                    nested_symbol_items(
+                                        &symbols, None, 0, &mut items,
+                                    );
+                                    items
+                                }
                             };
                             let _ = event_sink.submit_command(
                                 LAPCE_UI_COMMAND,
@@ -1179,6 +1476,37 @@ impl PaletteViewData {
     }
 }
 
+/// Flatten the nested document symbols in document order, keeping track of
+/// the parent symbol and the depth so that they can be shown indented
+fn nested_symbol_items(
+    symbols: &[DocumentSymbol],
+    container_name: Option<&str>,
+    depth: usize,
+    items: &mut Vec<NewPaletteItem>,
+) {
+    for s in symbols {
+        let mut filter_text = s.name.clone();
+        if let Some(container_name) = container_name {
+            filter_text += container_name;
+        }
+        items.push(NewPaletteItem {
+            content: PaletteItemContent::DocumentSymbol {
+                kind: s.kind,
+                name: s.name.clone(),
+                range: s.range,
+                container_name: container_name.map(|c| c.to_string()),
+                depth,
+            },
+            filter_text,
+            score: 0,
+            indices: Vec::new(),
+        });
+        if let Some(children) = s.children.as_ref() {
+            nested_symbol_items(children, Some(&s.name), depth + 1, items);
+        }
+    }
+}
+
 fn file_paint_items(
     path: &Path,
     indices: &[usize],
@@ -0,0 +1,53 @@
+//! The primary selection of X11, which selecting text sets and a middle click
+//! pastes. Other platforms don't have one, so there it is always empty.
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use std::cell::RefCell;
+
+    use copypasta::{
+        x11_clipboard::{Primary, X11ClipboardContext},
+        ClipboardProvider,
+    };
+
+    thread_local! {
+        /// The connection is kept open because the selection is set on
+        /// every change of a selection
+        static PRIMARY: RefCell<Option<X11ClipboardContext<Primary>>> =
+            RefCell::new(X11ClipboardContext::<Primary>::new().ok());
+    }
+
+    pub fn get() -> Option<String> {
+        PRIMARY.with(|primary| primary.borrow_mut().as_mut()?.get_contents().ok())
+    }
+
+    pub fn put(content: String) {
+        PRIMARY.with(|primary| {
+            if let Some(primary) = primary.borrow_mut().as_mut() {
+                if let Err(e) = primary.set_contents(content) {
+                    log::error!("failed to set the primary selection: {e}");
+                }
+            }
+        });
+    }
+}
+
+/// The text of the primary selection, from this or any other application.
+pub fn get() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        x11::get().filter(|s| !s.is_empty())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Make `content` the primary selection.
+pub fn put(content: String) {
+    #[cfg(target_os = "linux")]
+    x11::put(content);
+    #[cfg(not(target_os = "linux"))]
+    let _ = content;
+}
@@ -1,9 +1,12 @@
+use std::collections::HashMap;
 use std::io::BufReader;
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 use std::{path::PathBuf, sync::Arc};
 
 use anyhow::{anyhow, Result};
@@ -13,7 +16,7 @@ use druid::Target;
 use druid::{ExtEventSink, WidgetId};
 use flate2::read::GzDecoder;
 use lapce_proxy::dispatch::Dispatcher;
-use lapce_rpc::buffer::BufferId;
+use lapce_rpc::buffer::{BufferId, LineEnding};
 use lapce_rpc::core::{CoreNotification, CoreRequest};
 use lapce_rpc::plugin::PluginDescription;
 use lapce_rpc::source_control::FileDiff;
@@ -23,12 +26,13 @@ use lapce_rpc::{stdio_transport, Callback};
 use lapce_rpc::{ControlFlow, Handler};
 use lsp_types::CompletionItem;
 use lsp_types::Position;
+use lsp_types::Range;
 use lsp_types::Url;
 use parking_lot::Mutex;
 use serde_json::json;
 use serde_json::Value;
 use xi_rope::spans::SpansBuilder;
-use xi_rope::{Interval, RopeDelta};
+use xi_rope::{Interval, Rope, RopeDelta};
 
 use crate::command::LapceUICommand;
 use crate::command::LAPCE_UI_COMMAND;
@@ -39,16 +43,29 @@ use crate::terminal::RawTerminal;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How long to wait before restarting a proxy that stopped, doubled after
+/// each restart up to `RESTART_DELAY_MAX`
+const RESTART_DELAY_MIN: Duration = Duration::from_secs(1);
+/// The longest wait before restarting a proxy, a proxy that ran for longer
+/// than this is restarted after `RESTART_DELAY_MIN` again
+const RESTART_DELAY_MAX: Duration = Duration::from_secs(30);
+/// How long to wait for the response of a request answered by a language
+/// server before giving up on it
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub enum TermEvent {
     NewTerminal(Arc<Mutex<RawTerminal>>),
     UpdateContent(String),
     CloseTerminal,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ProxyStatus {
     Connecting,
     Connected,
+    /// The proxy stopped, or the connection to a remote proxy was lost,
+    /// and it's being started again
+    Reconnecting,
     Disconnected,
 }
 
@@ -59,6 +76,11 @@ pub struct LapceProxy {
     proxy_receiver: Arc<Receiver<Value>>,
     term_tx: Sender<(TermId, TermEvent)>,
     event_sink: ExtEventSink,
+    /// Set once the proxy is shut down, so that it isn't started again
+    stopped: Arc<AtomicBool>,
+    /// The completion requests in flight by their rpc id, with the
+    /// completion request id they were made for
+    completion_requests: Arc<Mutex<HashMap<u64, usize>>>,
 }
 
 impl Handler for LapceProxy {
@@ -74,14 +96,20 @@ impl Handler for LapceProxy {
                 path,
                 styles,
                 len,
+                range,
             } => {
                 let event_sink = self.event_sink.clone();
                 let tab_id = self.tab_id;
                 rayon::spawn(move || {
+                    // the styles of a range are built relative to its start
+                    let (start, len) = match range.as_ref() {
+                        Some(range) => (range.start, range.end - range.start),
+                        None => (0, len),
+                    };
                     let mut styles_span = SpansBuilder::new(len);
                     for style in styles {
                         styles_span.add_span(
-                            Interval::new(style.start, style.end),
+                            Interval::new(style.start - start, style.end - start),
                             style.style,
                         );
                     }
@@ -92,6 +120,7 @@ impl Handler for LapceProxy {
                             buffer_id,
                             path,
                             rev,
+                            range,
                             styles_span,
                         ),
                         Target::Widget(tab_id),
@@ -109,6 +138,28 @@ impl Handler for LapceProxy {
                     Target::Widget(self.tab_id),
                 );
             }
+            BufferChangedOnDisk { buffer_id, content } => {
+                let _ = self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::BufferChangedOnDisk(
+                        buffer_id,
+                        Rope::from(content),
+                    ),
+                    Target::Widget(self.tab_id),
+                );
+            }
+            SignatureTriggerCharacters {
+                buffer_id,
+                characters,
+            } => {
+                let _ = self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdateSignatureTriggerCharacters(
+                        buffer_id, characters,
+                    ),
+                    Target::Widget(self.tab_id),
+                );
+            }
             PublishDiagnostics { diagnostics } => {
                 let _ = self.event_sink.submit_command(
                     LAPCE_UI_COMMAND,
@@ -154,6 +205,17 @@ impl Handler for LapceProxy {
                     Target::Widget(self.tab_id),
                 );
             }
+            GlobalSearchMatches {
+                search_id,
+                path,
+                matches,
+            } => {
+                let _ = self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::GlobalSearchMatches(search_id, path, matches),
+                    Target::Widget(self.tab_id),
+                );
+            }
             ProxyConnected {} => {
                 let _ = self.event_sink.submit_command(
                     LAPCE_UI_COMMAND,
@@ -161,6 +223,7 @@ impl Handler for LapceProxy {
                     Target::Widget(self.tab_id),
                 );
             }
+            ProxyExited {} => return ControlFlow::Exit,
             HomeDir { path } => {
                 let _ = self.event_sink.submit_command(
                     LAPCE_UI_COMMAND,
@@ -168,6 +231,16 @@ impl Handler for LapceProxy {
                     Target::Widget(self.tab_id),
                 );
             }
+            SettingsError { path, errors } => {
+                for error in errors.iter() {
+                    log::error!("{}: {error}", path.display());
+                }
+                let _ = self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::SettingsError(path, errors),
+                    Target::Widget(self.tab_id),
+                );
+            }
         }
         ControlFlow::Continue
     }
@@ -192,6 +265,8 @@ impl LapceProxy {
             proxy_receiver: Arc::new(proxy_receiver),
             term_tx,
             event_sink: event_sink.clone(),
+            stopped: Arc::new(AtomicBool::new(false)),
+            completion_requests: Arc::new(Mutex::new(HashMap::new())),
         };
 
         let local_proxy = proxy.clone();
@@ -201,7 +276,26 @@ impl LapceProxy {
                 LapceUICommand::ProxyUpdateStatus(ProxyStatus::Connecting),
                 Target::Widget(tab_id),
             );
-            let _ = local_proxy.start(workspace.clone());
+            let mut delay = RESTART_DELAY_MIN;
+            loop {
+                let started = Instant::now();
+                if let Err(e) = local_proxy.start(workspace.clone()) {
+                    log::error!("Failed to start the proxy: {e}");
+                }
+                if local_proxy.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if started.elapsed() > RESTART_DELAY_MAX {
+                    delay = RESTART_DELAY_MIN;
+                }
+                let _ = event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::ProxyUpdateStatus(ProxyStatus::Reconnecting),
+                    Target::Widget(tab_id),
+                );
+                thread::sleep(delay);
+                delay = (delay * 2).min(RESTART_DELAY_MAX);
+            }
             let _ = event_sink.submit_command(
                 LAPCE_UI_COMMAND,
                 LapceUICommand::ProxyUpdateStatus(ProxyStatus::Disconnected),
@@ -212,21 +306,49 @@ impl LapceProxy {
         proxy
     }
 
+    /// A proxy that isn't connected to a proxy process, for buffers that
+    /// never send it anything, like the ones edited in tests
+    pub fn disconnected(tab_id: WidgetId, event_sink: ExtEventSink) -> Self {
+        let (proxy_sender, proxy_receiver) = crossbeam_channel::unbounded();
+        let (term_tx, _) = crossbeam_channel::unbounded();
+        Self {
+            tab_id,
+            rpc: RpcHandler::new(proxy_sender),
+            proxy_receiver: Arc::new(proxy_receiver),
+            term_tx,
+            event_sink,
+            stopped: Arc::new(AtomicBool::new(false)),
+            completion_requests: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
     fn start(&self, workspace: LapceWorkspace) -> Result<()> {
         if let Some(path) = workspace.path.as_ref() {
             self.initialize(path.clone());
         }
         let (core_sender, core_receiver) = crossbeam_channel::unbounded();
-        match workspace.kind {
+        let remote_child = match workspace.kind {
             LapceWorkspaceType::Local => {
                 let proxy_receiver = (*self.proxy_receiver).clone();
-                thread::spawn(move || {
+                let exit_sender = core_sender.clone();
+                let dispatcher = thread::spawn(move || {
                     let dispatcher = Dispatcher::new(core_sender);
                     let _ = dispatcher.mainloop(proxy_receiver);
                 });
+                // the threads started by the dispatcher keep the core sender
+                // alive, so the end of the proxy, by a shutdown or a panic,
+                // has to be sent
+                thread::spawn(move || {
+                    let _ = dispatcher.join();
+                    let _ = exit_sender.send(json!({
+                        "method": "proxy_exited",
+                        "params": {},
+                    }));
+                });
+                None
             }
             LapceWorkspaceType::RemoteSSH(user, host) => {
-                self.start_remote(SshRemote { user, host }, core_sender)?;
+                Some(self.start_remote(SshRemote { user, host }, core_sender)?)
             }
             LapceWorkspaceType::RemoteWSL => {
                 let distro = WslDistro::all()?
@@ -234,22 +356,31 @@ impl LapceProxy {
                     .find(|distro| distro.default)
                     .ok_or_else(|| anyhow!("no default distro found"))?
                     .name;
-                self.start_remote(WslRemote { distro }, core_sender)?;
+                Some(self.start_remote(WslRemote { distro }, core_sender)?)
             }
-        }
+        };
 
         let mut proxy = self.clone();
         let mut handler = self.clone();
         proxy.rpc.mainloop(core_receiver, &mut handler);
+        self.rpc.fail_pending_requests();
+
+        // the connection is gone, make sure the remote command is too
+        if let Some(mut child) = remote_child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
 
         Ok(())
     }
 
+    /// Start the proxy on the remote, uploading it first if needed, and
+    /// return the command running it
     fn start_remote(
         &self,
         remote: impl Remote,
         core_sender: Sender<Value>,
-    ) -> Result<()> {
+    ) -> Result<Child> {
         let proxy_filename = format!("lapce-proxy-{VERSION}");
         let remote_proxy_file = format!("~/.lapce/{}", proxy_filename);
 
@@ -265,14 +396,10 @@ impl LapceProxy {
                 .join(&proxy_filename);
             if !local_proxy_file.exists() {
                 let url = format!("https://github.com/lapce/lapce/releases/download/v{VERSION}/lapce-proxy-linux.gz");
-                let mut data = ureq::get(&url)
-                    .call()
-                    .expect("request failed")
-                    .into_reader();
-                let mut out = std::fs::File::create(&local_proxy_file)
-                    .expect("failed to create file");
+                let mut data = ureq::get(&url).call()?.into_reader();
+                let mut out = std::fs::File::create(&local_proxy_file)?;
                 let mut gz = GzDecoder::new(&mut data);
-                std::io::copy(&mut gz, &mut out).expect("failed to copy content");
+                std::io::copy(&mut gz, &mut out)?;
             }
 
             remote
@@ -311,7 +438,7 @@ impl LapceProxy {
         let proxy_receiver = (*self.proxy_receiver).clone();
         stdio_transport(stdin, proxy_receiver, stdout, core_sender);
 
-        Ok(())
+        Ok(child)
     }
 
     pub fn initialize(&self, workspace: PathBuf) {
@@ -399,14 +526,32 @@ impl LapceProxy {
         );
     }
 
-    pub fn global_search(&self, pattern: String, f: Box<dyn Callback>) {
+    /// Search the workspace for `pattern`, the matches are sent back file by
+    /// file and submitted to the tab as `GlobalSearchMatches`
+    pub fn search_workspace(
+        &self,
+        search_id: u64,
+        pattern: &str,
+        case_sensitive: bool,
+        whole_word: bool,
+    ) {
         self.rpc.send_rpc_request_async(
             "global_search",
-            &json!({ "pattern": pattern }),
-            f,
+            &json!({
+                "search_id": search_id,
+                "pattern": pattern,
+                "case_sensitive": case_sensitive,
+                "whole_word": whole_word,
+            }),
+            Box::new(|_| {}),
         );
     }
 
+    pub fn cancel_search_workspace(&self) {
+        self.rpc
+            .send_rpc_notification("cancel_global_search", &json!({}));
+    }
+
     pub fn new_buffer(
         &self,
         buffer_id: BufferId,
@@ -431,12 +576,55 @@ impl LapceProxy {
         )
     }
 
-    pub fn save(&self, rev: u64, buffer_id: BufferId, f: Box<dyn Callback>) {
+    pub fn reopen_buffer(
+        &self,
+        buffer_id: BufferId,
+        path: &Path,
+        content: &str,
+        rev: u64,
+    ) {
+        self.rpc.send_rpc_notification(
+            "reopen_buffer",
+            &json!({
+                "buffer_id": buffer_id,
+                "path": path,
+                "content": content,
+                "rev": rev,
+            }),
+        )
+    }
+
+    pub fn reload_buffer(&self, buffer_id: BufferId) {
+        self.rpc.send_rpc_notification(
+            "reload_buffer",
+            &json!({
+                "buffer_id": buffer_id,
+            }),
+        )
+    }
+
+    pub fn refresh_semantic_tokens(&self, buffer_id: BufferId) {
+        self.rpc.send_rpc_notification(
+            "refresh_semantic_tokens",
+            &json!({
+                "buffer_id": buffer_id,
+            }),
+        )
+    }
+
+    pub fn save(
+        &self,
+        rev: u64,
+        buffer_id: BufferId,
+        line_ending: LineEnding,
+        f: Box<dyn Callback>,
+    ) {
         self.rpc.send_rpc_request_async(
             "save",
             &json!({
                 "rev": rev,
                 "buffer_id": buffer_id,
+                "line_ending": line_ending,
             }),
             f,
         );
@@ -449,7 +637,22 @@ impl LapceProxy {
         position: Position,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        // the completion moved on from the requests made for older ids
+        let superseded: Vec<u64> = {
+            let mut completion_requests = self.completion_requests.lock();
+            let superseded = completion_requests
+                .iter()
+                .filter(|(_, id)| **id < request_id)
+                .map(|(rpc_id, _)| *rpc_id)
+                .collect();
+            completion_requests.retain(|_, id| *id >= request_id);
+            superseded
+        };
+        for rpc_id in superseded {
+            self.cancel_request(rpc_id);
+        }
+
+        let rpc_id = self.send_lsp_request(
             "get_completion",
             &json!({
                 "request_id": request_id,
@@ -458,6 +661,7 @@ impl LapceProxy {
             }),
             f,
         );
+        self.completion_requests.lock().insert(rpc_id, request_id);
     }
 
     pub fn completion_resolve(
@@ -466,7 +670,7 @@ impl LapceProxy {
         completion_item: CompletionItem,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "completion_resolve",
             &json!({
                 "buffer_id": buffer_id,
@@ -483,7 +687,7 @@ impl LapceProxy {
         position: Position,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "get_hover",
             &json!({
                 "request_id": request_id,
@@ -494,13 +698,29 @@ impl LapceProxy {
         );
     }
 
-    pub fn get_signature(
+    pub fn get_inlay_hints(
+        &self,
+        buffer_id: BufferId,
+        range: Range,
+        f: Box<dyn Callback>,
+    ) {
+        self.send_lsp_request(
+            "get_inlay_hints",
+            &json!({
+                "buffer_id": buffer_id,
+                "range": range,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_signature_help(
         &self,
         buffer_id: BufferId,
         position: Position,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "get_signature",
             &json!({
                 "buffer_id": buffer_id,
@@ -516,7 +736,7 @@ impl LapceProxy {
         position: Position,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "get_references",
             &json!({
                 "buffer_id": buffer_id,
@@ -526,6 +746,11 @@ impl LapceProxy {
         );
     }
 
+    pub fn git_blame(&self, path: PathBuf, f: Box<dyn Callback>) {
+        self.rpc
+            .send_rpc_request_async("git_blame", &json!({ "path": path }), f);
+    }
+
     pub fn get_files(&self, f: Box<dyn Callback>) {
         self.rpc.send_rpc_request_async(
             "get_files",
@@ -546,6 +771,37 @@ impl LapceProxy {
         );
     }
 
+    pub fn create_file(&self, path: &Path, f: Box<dyn Callback>) {
+        self.rpc.send_rpc_request_async(
+            "create_file",
+            &json!({
+                "path": path,
+            }),
+            f,
+        );
+    }
+
+    pub fn rename_path(&self, from: &Path, to: &Path, f: Box<dyn Callback>) {
+        self.rpc.send_rpc_request_async(
+            "rename_path",
+            &json!({
+                "from": from,
+                "to": to,
+            }),
+            f,
+        );
+    }
+
+    pub fn delete_path(&self, path: &Path, f: Box<dyn Callback>) {
+        self.rpc.send_rpc_request_async(
+            "delete_path",
+            &json!({
+                "path": path,
+            }),
+            f,
+        );
+    }
+
     pub fn get_definition(
         &self,
         request_id: usize,
@@ -553,7 +809,7 @@ impl LapceProxy {
         position: Position,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "get_definition",
             &json!({
                 "request_id": request_id,
@@ -565,7 +821,7 @@ impl LapceProxy {
     }
 
     pub fn get_document_symbols(&self, buffer_id: BufferId, f: Box<dyn Callback>) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "get_document_symbols",
             &json!({
                 "buffer_id": buffer_id,
@@ -580,7 +836,7 @@ impl LapceProxy {
         position: Position,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "get_code_actions",
             &json!({
                 "buffer_id": buffer_id,
@@ -595,7 +851,7 @@ impl LapceProxy {
         buffer_id: BufferId,
         f: Box<dyn Callback>,
     ) {
-        self.rpc.send_rpc_request_async(
+        self.send_lsp_request(
             "get_document_formatting",
             &json!({
                 "buffer_id": buffer_id,
@@ -604,7 +860,29 @@ impl LapceProxy {
         );
     }
 
+    /// Send a request answered by a language server, which fails if there's
+    /// no response in time
+    fn send_lsp_request(
+        &self,
+        method: &str,
+        params: &Value,
+        f: Box<dyn Callback>,
+    ) -> u64 {
+        self.rpc
+            .send_rpc_request_async_timeout(method, params, REQUEST_TIMEOUT, f)
+    }
+
+    /// Give up on a request, and have the proxy cancel it if it's still
+    /// waiting for it
+    pub fn cancel_request(&self, id: u64) {
+        if self.rpc.cancel_rpc_request(id) {
+            self.rpc
+                .send_rpc_notification("cancel_request", &json!({ "id": id }));
+        }
+    }
+
     pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
         self.rpc.send_rpc_notification("shutdown", &json!({}));
         // self.core_sender.send(json!({
         //     "method": "shutdown",
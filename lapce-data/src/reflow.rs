@@ -0,0 +1,146 @@
+use crate::buffer::char_width;
+
+/// The comment markers kept at the start of every line of a paragraph,
+/// longer ones first so that `///` isn't taken for `//`
+const COMMENT_MARKERS: &[&str] = &["///", "//!", "//", "#", "*"];
+
+/// The start of a line that a reflowed paragraph repeats on each of its
+/// lines: the indentation, then the comment marker and the whitespace after
+/// it if there is one. Also returns whether there was a comment marker.
+pub fn line_prefix(line: &str) -> (&str, bool) {
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let rest = &line[indent..];
+    for marker in COMMENT_MARKERS {
+        if let Some(after) = rest.strip_prefix(marker) {
+            let spaces = after.len() - after.trim_start_matches([' ', '\t']).len();
+            return (&line[..indent + marker.len() + spaces], true);
+        }
+    }
+    (&line[..indent], false)
+}
+
+/// What lines of the same paragraph have in common, `None` for a line that
+/// separates paragraphs because it has nothing after its prefix
+pub fn paragraph_key(line: &str) -> Option<&str> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let (prefix, _) = line_prefix(line);
+    if line[prefix.len()..].trim().is_empty() {
+        None
+    } else {
+        Some(prefix.trim_end())
+    }
+}
+
+/// Rewrap the lines of a text to `width` columns, one paragraph at a time.
+/// Each paragraph keeps the prefix of its first line, and the lines between
+/// paragraphs are left as they are. The lines are joined with `line_ending`.
+pub fn reflow(
+    text: &str,
+    width: usize,
+    tab_width: usize,
+    line_ending: &str,
+) -> String {
+    let mut lines = Vec::new();
+    // The prefix and the words of the paragraph being collected
+    let mut paragraph: Option<(&str, Vec<&str>)> = None;
+    for line in text.lines() {
+        let key = paragraph_key(line);
+        if let Some((prefix, words)) = paragraph.as_ref() {
+            if key != Some(prefix.trim_end()) {
+                wrap_words(prefix, words, width, tab_width, &mut lines);
+                paragraph = None;
+            }
+        }
+        if key.is_none() {
+            lines.push(line.to_string());
+            continue;
+        }
+        let (prefix, _) = line_prefix(line);
+        paragraph
+            .get_or_insert_with(|| (prefix, Vec::new()))
+            .1
+            .extend(line[prefix.len()..].split_whitespace());
+    }
+    if let Some((prefix, words)) = paragraph.as_ref() {
+        wrap_words(prefix, words, width, tab_width, &mut lines);
+    }
+    lines.join(line_ending)
+}
+
+/// Fill lines with the words, starting each one with the prefix. A word
+/// longer than the width gets a line of its own.
+fn wrap_words(
+    prefix: &str,
+    words: &[&str],
+    width: usize,
+    tab_width: usize,
+    lines: &mut Vec<String>,
+) {
+    let prefix_width = text_width(prefix, tab_width);
+    let mut line = String::new();
+    let mut line_width = 0;
+    for word in words {
+        let word_width = text_width(word, tab_width);
+        if !line.is_empty() {
+            if prefix_width + line_width + 1 + word_width > width {
+                lines.push(format!("{}{}", prefix, line));
+                line.clear();
+                line_width = 0;
+            } else {
+                line.push(' ');
+                line_width += 1;
+            }
+        }
+        line.push_str(word);
+        line_width += word_width;
+    }
+    if !line.is_empty() {
+        lines.push(format!("{}{}", prefix, line));
+    }
+}
+
+fn text_width(text: &str, tab_width: usize) -> usize {
+    text.chars().fold(0, |width, c| {
+        if c == '\t' {
+            width + tab_width - width % tab_width
+        } else {
+            width + char_width(c)
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_prefix() {
+        assert_eq!(line_prefix("    /// doc"), ("    /// ", true));
+        assert_eq!(line_prefix("\t# comment"), ("\t# ", true));
+        assert_eq!(line_prefix(" * block"), (" * ", true));
+        assert_eq!(line_prefix("  plain text"), ("  ", false));
+        assert_eq!(paragraph_key("  //  \n"), None);
+        assert_eq!(paragraph_key("  // a\n"), Some("  //"));
+    }
+
+    #[test]
+    fn test_reflow() {
+        assert_eq!(
+            reflow("one two three\nfour five six", 10, 4, "\n"),
+            "one two\nthree four\nfive six"
+        );
+        assert_eq!(
+            reflow("    // aaa bbb\n    // ccc\n\n    // ddd", 15, 4, "\n"),
+            "    // aaa bbb\n    // ccc\n\n    // ddd"
+        );
+        assert_eq!(
+            reflow("// aaa\n// bbb ccc\n//\n// ddd\n# eee", 80, 4, "\r\n"),
+            "// aaa bbb ccc\r\n//\r\n// ddd\r\n# eee"
+        );
+        assert_eq!(reflow("\t# a b c", 6, 4, "\n"), "\t# a\n\t# b\n\t# c");
+        assert_eq!(
+            reflow("a averyveryverylongword b", 8, 4, "\n"),
+            "a\naveryveryverylongword\nb"
+        );
+    }
+}
@@ -1,7 +1,9 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::path::{Path, PathBuf};
 
 use druid::WidgetId;
 
+use crate::proxy::LapceProxy;
+
 pub type Match = (usize, (usize, usize), String);
 #[derive(Clone)]
 pub struct SearchData {
@@ -9,7 +11,15 @@ pub struct SearchData {
     pub widget_id: WidgetId,
     pub split_id: WidgetId,
     pub editor_view_id: WidgetId,
-    pub matches: Arc<HashMap<PathBuf, Vec<Match>>>,
+    /// The matches grouped by file, in the order the files were searched.
+    /// The files are added one at a time while the search runs, which a
+    /// persistent vector does without copying the ones before.
+    pub matches: im::Vector<(PathBuf, Vec<Match>)>,
+    /// The id of the current search, the matches of older searches still
+    /// coming from the proxy are dropped
+    pub search_id: u64,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
 }
 
 impl SearchData {
@@ -20,7 +30,36 @@ impl SearchData {
             widget_id: WidgetId::next(),
             split_id: WidgetId::next(),
             editor_view_id,
-            matches: Arc::new(HashMap::new()),
+            matches: im::Vector::new(),
+            search_id: 0,
+            case_sensitive: false,
+            whole_word: false,
+        }
+    }
+
+    /// Clear the matches and search the workspace for `pattern`
+    pub fn search(&mut self, pattern: &str, proxy: &LapceProxy) {
+        self.search_id += 1;
+        self.matches = im::Vector::new();
+        proxy.search_workspace(
+            self.search_id,
+            pattern,
+            self.case_sensitive,
+            self.whole_word,
+        );
+    }
+
+    /// Clear the matches and stop the running search
+    pub fn cancel(&mut self, proxy: &LapceProxy) {
+        self.search_id += 1;
+        self.matches = im::Vector::new();
+        proxy.cancel_search_workspace();
+    }
+
+    pub fn add_matches(&mut self, search_id: u64, path: &Path, matches: &[Match]) {
+        if search_id == self.search_id {
+            self.matches
+                .push_back((path.to_path_buf(), matches.to_vec()));
         }
     }
 }
@@ -1,65 +1,151 @@
-use lsp_types::ParameterLabel;
-use lsp_types::SignatureHelp;
+use std::sync::Arc;
+
+use druid::{ExtEventSink, Target, WidgetId};
+use lapce_rpc::buffer::BufferId;
+use lsp_types::{ParameterLabel, Position, SignatureHelp, SignatureInformation};
+
+use crate::{
+    command::{LapceUICommand, LAPCE_UI_COMMAND},
+    proxy::LapceProxy,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureStatus {
+    Inactive,
+    Started,
+}
 
 #[derive(Clone)]
-pub struct SignatureState {
-    pub offset: Option<usize>,
-    pub signature: Option<SignatureHelp>,
-    pub active: Option<(usize, usize)>,
+pub struct SignatureData {
+    pub id: WidgetId,
+    /// The current request status
+    pub status: SignatureStatus,
+    /// The offset of the cursor when the request was sent
+    pub offset: usize,
+    /// The buffer that this signature help is for
+    pub buffer_id: BufferId,
+    /// A counter to keep track of the active requests
+    pub request_id: usize,
+    /// The latest response from the language server
+    pub signature: Option<Arc<SignatureHelp>>,
 }
 
-impl SignatureState {
+impl SignatureData {
     pub fn new() -> Self {
         Self {
-            offset: None,
+            id: WidgetId::next(),
+            status: SignatureStatus::Inactive,
+            offset: 0,
+            buffer_id: BufferId(0),
+            request_id: 0,
             signature: None,
-            active: None,
         }
     }
 
-    pub fn update(&mut self, offset: usize, commas: Vec<usize>) -> Option<bool> {
+    pub fn is_active(&self) -> bool {
+        self.status != SignatureStatus::Inactive && self.signature.is_some()
+    }
+
+    /// The signature that the language server marked as active
+    pub fn active_signature(&self) -> Option<&SignatureInformation> {
         let signature = self.signature.as_ref()?;
-        let params = signature.signatures[0].parameters.as_ref()?;
-        if params.is_empty() {
-            return None;
-        }
-        let mut index = commas.len();
-        for (i, c) in commas.iter().enumerate() {
-            if offset <= *c {
-                index = i;
-                break;
+        let index = signature.active_signature.unwrap_or(0) as usize;
+        signature
+            .signatures
+            .get(index)
+            .or_else(|| signature.signatures.first())
+    }
+
+    /// The byte range of the active parameter within the label of the active signature
+    pub fn active_parameter_range(&self) -> Option<(usize, usize)> {
+        let signature = self.signature.as_ref()?;
+        let info = self.active_signature()?;
+        let index = info
+            .active_parameter
+            .or(signature.active_parameter)
+            .unwrap_or(0) as usize;
+        let param = info.parameters.as_ref()?.get(index)?;
+        match &param.label {
+            ParameterLabel::Simple(s) => {
+                let start = info.label.find(s.as_str())?;
+                Some((start, start + s.len()))
+            }
+            ParameterLabel::LabelOffsets(offsets) => {
+                // The offsets are in utf-16 code units
+                let start = utf16_to_utf8_offset(&info.label, offsets[0] as usize);
+                let end = utf16_to_utf8_offset(&info.label, offsets[1] as usize);
+                Some((start, end))
             }
         }
+    }
 
-        let label = signature.signatures[0].label.clone();
-        let active = if index >= params.len() {
-            None
-        } else {
-            match &params[index].label {
-                ParameterLabel::Simple(s) => {
-                    let start = label.find(s)?;
-                    Some((start, start + s.len()))
-                }
-                ParameterLabel::LabelOffsets(offsets) => {
-                    Some((offsets[0] as usize, offsets[1] as usize))
-                }
-            }
-        };
+    /// Cancel the current signature help, clearing out held data
+    pub fn cancel(&mut self) {
+        if self.status == SignatureStatus::Inactive {
+            return;
+        }
 
-        let changed = self.active != active;
-        self.active = active;
-        Some(changed)
+        self.status = SignatureStatus::Inactive;
+        self.signature = None;
     }
 
-    pub fn clear(&mut self) {
-        self.offset = None;
-        self.signature = None;
-        self.active = None;
+    /// Send a request to update the signature help at the given position and file
+    pub fn request(
+        &self,
+        proxy: Arc<LapceProxy>,
+        request_id: usize,
+        buffer_id: BufferId,
+        position: Position,
+        signature_widget_id: WidgetId,
+        event_sink: ExtEventSink,
+    ) {
+        proxy.get_signature_help(
+            buffer_id,
+            position,
+            Box::new(move |result| {
+                if let Ok(resp) = result {
+                    if let Ok(resp) = serde_json::from_value::<SignatureHelp>(resp) {
+                        let _ = event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateSignature(request_id, resp),
+                            Target::Widget(signature_widget_id),
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Receive the result of a signature help request
+    pub fn receive(&mut self, request_id: usize, resp: SignatureHelp) {
+        // Drop responses for requests that are no longer the latest one,
+        // so that a slow server doesn't flash outdated signatures
+        if self.status == SignatureStatus::Inactive || self.request_id != request_id
+        {
+            return;
+        }
+
+        if resp.signatures.is_empty() {
+            self.cancel();
+            return;
+        }
+        self.signature = Some(Arc::new(resp));
     }
 }
 
-impl Default for SignatureState {
+impl Default for SignatureData {
     fn default() -> Self {
         Self::new()
     }
 }
+
+fn utf16_to_utf8_offset(s: &str, utf16_offset: usize) -> usize {
+    let mut utf16 = 0;
+    for (i, c) in s.char_indices() {
+        if utf16 >= utf16_offset {
+            return i;
+        }
+        utf16 += c.len_utf16();
+    }
+    s.len()
+}
@@ -387,6 +387,13 @@ impl KeyPressFocus for LapceTerminalViewData {
                     Target::Widget(self.terminal.split_id),
                 ));
             }
+            LapceCommand::SplitClose => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::CloseTerminal(self.terminal.term_id),
+                    Target::Auto,
+                ));
+            }
             LapceCommand::ClipboardCopy => {
                 if self.terminal.mode == Mode::Visual {
                     self.terminal_mut().mode = Mode::Normal;
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use crate::buffer::char_width;
+
+/// How the logical lines of a buffer are broken into visual lines when it's
+/// soft wrapped at a column or has folded lines. The lines hidden by a fold
+/// take up no visual lines at all.
+#[derive(Clone, Debug)]
+pub struct WrapLayout {
+    /// The column that lines are wrapped at, `usize::MAX` if the lines
+    /// aren't wrapped and the layout is only there for the folds
+    pub wrap_col: usize,
+    /// The folds the layout was computed with
+    pub folds: Vec<(usize, usize)>,
+    /// The first visual line of every logical line, followed by the total
+    /// number of visual lines
+    first_visual_lines: Vec<usize>,
+    /// The columns where the wrapped parts of a logical line start, only
+    /// holds the lines that are longer than the wrap column
+    breaks: HashMap<usize, Vec<usize>>,
+}
+
+impl WrapLayout {
+    /// `folds` are the folded blocks, as the line that stays visible and
+    /// the last line that's hidden under it
+    pub fn new<S: AsRef<str>>(
+        lines: impl Iterator<Item = S>,
+        wrap_col: usize,
+        tab_width: usize,
+        folds: &[(usize, usize)],
+    ) -> Self {
+        let wrap_col = wrap_col.max(1);
+        let mut first_visual_lines = vec![0];
+        let mut breaks = HashMap::new();
+        let mut visual_lines = 0;
+        let mut hidden_until = None;
+        for (line, content) in lines.enumerate() {
+            if hidden_until.map(|end| line <= end).unwrap_or(false) {
+                first_visual_lines.push(visual_lines);
+                continue;
+            }
+            for (_, end) in folds.iter().filter(|(start, _)| *start == line) {
+                hidden_until = Some(hidden_until.unwrap_or(0).max(*end));
+            }
+            let line_breaks = wrap_line(content.as_ref(), wrap_col, tab_width);
+            visual_lines += line_breaks.len() + 1;
+            first_visual_lines.push(visual_lines);
+            if !line_breaks.is_empty() {
+                breaks.insert(line, line_breaks);
+            }
+        }
+        Self {
+            wrap_col,
+            folds: folds.to_vec(),
+            first_visual_lines,
+            breaks,
+        }
+    }
+
+    /// Whether the lines are wrapped, rather than the layout only being
+    /// there for the folds
+    pub fn wraps(&self) -> bool {
+        self.wrap_col != usize::MAX
+    }
+
+    pub fn num_visual_lines(&self) -> usize {
+        *self.first_visual_lines.last().unwrap_or(&0)
+    }
+
+    /// The first visual line of a logical line, lines past the end of the
+    /// buffer give the total number of visual lines
+    pub fn visual_line(&self, line: usize) -> usize {
+        let last = self.first_visual_lines.len() - 1;
+        self.first_visual_lines[line.min(last)]
+    }
+
+    /// Whether a line is hidden inside a fold
+    pub fn is_hidden(&self, line: usize) -> bool {
+        line + 1 < self.first_visual_lines.len()
+            && self.first_visual_lines[line] == self.first_visual_lines[line + 1]
+    }
+
+    /// Whether a line is shown with the lines under it folded away
+    pub fn is_folded(&self, line: usize) -> bool {
+        !self.is_hidden(line) && self.is_hidden(line + 1)
+    }
+
+    /// The columns where the wrapped parts of a line start
+    pub fn line_breaks(&self, line: usize) -> &[usize] {
+        self.breaks.get(&line).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+
+    /// The visual line that a column of a logical line falls in, together
+    /// with the column that visual line starts at
+    pub fn visual_line_of_col(&self, line: usize, col: usize) -> (usize, usize) {
+        let breaks = self.line_breaks(line);
+        let index = breaks.iter().take_while(|b| **b <= col).count();
+        let start_col = if index == 0 { 0 } else { breaks[index - 1] };
+        (self.visual_line(line) + index, start_col)
+    }
+
+    /// The logical line shown on a visual line, with the columns the visual
+    /// line starts and ends at, `None` for the end means it's the last part
+    /// of the logical line. Visual lines past the end give the last line
+    /// that isn't hidden.
+    pub fn line_of_visual_line(
+        &self,
+        visual_line: usize,
+    ) -> (usize, usize, Option<usize>) {
+        let num_lines = self.first_visual_lines.len() - 1;
+        if num_lines == 0 {
+            return (0, 0, None);
+        }
+        // Hidden lines share their first visual line with the line after
+        // them, so this always lands on a line that's shown
+        let mut line = (self
+            .first_visual_lines
+            .partition_point(|first| *first <= visual_line)
+            - 1)
+        .min(num_lines - 1);
+        while line > 0 && self.is_hidden(line) {
+            line -= 1;
+        }
+        let breaks = self.line_breaks(line);
+        let index = (visual_line - self.first_visual_lines[line]).min(breaks.len());
+        let start_col = if index == 0 { 0 } else { breaks[index - 1] };
+        (line, start_col, breaks.get(index).copied())
+    }
+}
+
+/// The columns to break a line at so that no part is wider than `wrap_col`,
+/// preferring to break after whitespace and only splitting words that
+/// don't fit on a visual line of their own
+fn wrap_line(content: &str, wrap_col: usize, tab_width: usize) -> Vec<usize> {
+    let mut breaks = Vec::new();
+    let mut start = 0;
+    let mut col = 0;
+    let mut last_space = None;
+    for c in content.chars() {
+        if c == '\n' || c == '\r' {
+            break;
+        }
+        let width = if c == '\t' {
+            tab_width - col % tab_width
+        } else {
+            char_width(c)
+        };
+        if col + width - start > wrap_col && col > start {
+            start = match last_space {
+                Some(space) if space > start => space,
+                _ => col,
+            };
+            breaks.push(start);
+            last_space = None;
+        }
+        col += width;
+        if c.is_whitespace() {
+            last_space = Some(col);
+        }
+    }
+    breaks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wrap_line() {
+        assert!(wrap_line("short line\n", 20, 4).is_empty());
+        assert_eq!(wrap_line("hello world foo\n", 8, 4), vec![6, 12]);
+        assert_eq!(wrap_line("abcdefghij", 4, 4), vec![4, 8]);
+        assert_eq!(wrap_line("ab abcdefgh", 4, 4), vec![3, 7]);
+    }
+
+    #[test]
+    fn test_visual_lines() {
+        let layout = WrapLayout::new(
+            ["hello world foo\n", "short\n", "abcdefghij"].into_iter(),
+            8,
+            4,
+            &[],
+        );
+        assert_eq!(layout.num_visual_lines(), 6);
+        assert_eq!(layout.visual_line(1), 3);
+        assert_eq!(layout.visual_line(2), 4);
+        assert_eq!(layout.visual_line_of_col(0, 7), (1, 6));
+        assert_eq!(layout.visual_line_of_col(2, 9), (5, 8));
+        assert_eq!(layout.line_of_visual_line(0), (0, 0, Some(6)));
+        assert_eq!(layout.line_of_visual_line(2), (0, 12, None));
+        assert_eq!(layout.line_of_visual_line(3), (1, 0, None));
+        assert_eq!(layout.line_of_visual_line(10), (2, 8, None));
+    }
+
+    #[test]
+    fn test_folded_lines() {
+        let lines = ["fn a() {\n", "    b\n", "    c\n", "}\n", "d"];
+        let layout = WrapLayout::new(lines.into_iter(), usize::MAX, 4, &[(0, 2)]);
+        assert!(!layout.wraps());
+        assert_eq!(layout.num_visual_lines(), 3);
+        assert!(layout.is_folded(0));
+        assert!(layout.is_hidden(1));
+        assert!(layout.is_hidden(2));
+        assert!(!layout.is_hidden(3));
+        assert_eq!(layout.visual_line(3), 1);
+        assert_eq!(layout.line_of_visual_line(0), (0, 0, None));
+        assert_eq!(layout.line_of_visual_line(1), (3, 0, None));
+        assert_eq!(layout.line_of_visual_line(2), (4, 0, None));
+
+        // A fold at the end never gives back a hidden line
+        let layout = WrapLayout::new(lines.into_iter(), usize::MAX, 4, &[(3, 4)]);
+        assert_eq!(layout.num_visual_lines(), 4);
+        assert_eq!(layout.line_of_visual_line(3), (3, 0, None));
+        assert_eq!(layout.line_of_visual_line(10), (3, 0, None));
+    }
+}
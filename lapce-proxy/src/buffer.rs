@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Result};
 use crossbeam_channel::Sender;
-use lapce_rpc::buffer::BufferId;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use lapce_rpc::buffer::{BufferId, LineEnding};
 use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
@@ -16,11 +17,23 @@ pub struct Buffer {
     pub language_id: String,
     pub id: BufferId,
     pub rope: Rope,
+    /// The line ending the file was loaded with, if it has any line breaks
+    pub line_ending: Option<LineEnding>,
+    /// The text encoding of the file on disk
+    pub encoding: &'static Encoding,
+    /// Whether the file on disk starts with a byte order mark
+    pub bom: bool,
+    /// The file isn't text we could decode, so the buffer only holds a
+    /// placeholder and must never be written back
+    pub binary: bool,
     pub path: PathBuf,
     pub rev: u64,
     pub dirty: bool,
     sender: Sender<(BufferId, u64)>,
     pub mod_time: Option<SystemTime>,
+    /// The last semantic tokens of the language server, which the next ones
+    /// are requested as a delta of
+    pub semantic_tokens: Option<SemanticTokens>,
 }
 
 impl Buffer {
@@ -29,29 +42,49 @@ impl Buffer {
         path: PathBuf,
         sender: Sender<(BufferId, u64)>,
     ) -> Buffer {
-        let rope = if let Ok(rope) = load_file(&path) {
-            rope
-        } else {
-            Rope::from("")
-        };
+        let file = load_file(&path).unwrap_or_default();
         let language_id = language_id_from_path(&path).unwrap_or("").to_string();
         let mod_time = get_mod_time(&path);
         Buffer {
             id,
-            rope,
+            rope: file.rope,
+            line_ending: file.line_ending,
+            encoding: file.encoding,
+            bom: file.bom,
+            binary: file.binary,
             path,
             language_id,
             rev: 0,
             sender,
             dirty: false,
             mod_time,
+            semantic_tokens: None,
+        }
+    }
+
+    /// Replace the content loaded from disk with the one of the core at `rev`
+    pub fn restore(&mut self, content: &str, rev: u64) {
+        if self.rope.to_string() != content {
+            self.rope = Rope::from(content);
+            self.dirty = true;
         }
+        self.rev = rev;
     }
 
-    pub fn save(&mut self, rev: u64) -> Result<()> {
+    pub fn save(&mut self, rev: u64, line_ending: LineEnding) -> Result<()> {
         if self.rev != rev {
             return Err(anyhow!("not the right rev"));
         }
+        if self.binary {
+            return Err(anyhow!("can't save a file that isn't text"));
+        }
+
+        let mut content = self.rope.to_string();
+        if line_ending == LineEnding::CrLf {
+            content = content.replace('\n', "\r\n");
+        }
+        let bytes = encode(&content, self.encoding, self.bom)?;
+
         self.dirty = false;
         let tmp_extension = self.path.extension().map_or_else(
             || OsString::from("swp"),
@@ -63,27 +96,40 @@ impl Buffer {
         );
         let tmp_path = &self.path.with_extension(tmp_extension);
 
-        let mut f = File::create(tmp_path)?;
-        for chunk in self.rope.iter_chunks(..self.rope.len()) {
-            f.write_all(chunk.as_bytes())?;
+        // A file opened before it existed is created on its first save
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
         }
+        let mut f = File::create(tmp_path)?;
+        f.write_all(&bytes)?;
         fs::rename(tmp_path, &self.path)?;
+        self.line_ending = Some(line_ending);
         self.mod_time = get_mod_time(&self.path);
         Ok(())
     }
 
     pub fn reload(&mut self) {
-        let rope = if let Ok(rope) = load_file(&self.path) {
-            rope
-        } else {
-            Rope::from("")
-        };
+        let file = load_file(&self.path).unwrap_or_default();
 
-        self.rope = rope;
+        self.rope = file.rope;
+        self.line_ending = file.line_ending;
+        self.encoding = file.encoding;
+        self.bom = file.bom;
+        self.binary = file.binary;
+        self.dirty = false;
+        self.mod_time = get_mod_time(&self.path);
         self.rev += 1;
         let _ = self.sender.send((self.id, self.rev));
     }
 
+    /// The content of the file as it is on disk, which differs from the
+    /// buffer's after another program changed the file
+    pub fn disk_content(&self) -> String {
+        load_file(&self.path)
+            .map(|file| file.rope.to_string())
+            .unwrap_or_default()
+    }
+
     pub fn update(
         &mut self,
         delta: &RopeDelta,
@@ -146,11 +192,127 @@ impl Buffer {
     }
 }
 
-fn load_file(path: &Path) -> Result<Rope> {
+/// The placeholder shown instead of the content of files that aren't text
+const BINARY_PLACEHOLDER: &str =
+    "This file is binary or uses an unsupported text encoding.\n";
+
+struct LoadedFile {
+    /// The decoded text with its line endings normalized to `\n`
+    rope: Rope,
+    line_ending: Option<LineEnding>,
+    encoding: &'static Encoding,
+    bom: bool,
+    binary: bool,
+}
+
+impl Default for LoadedFile {
+    fn default() -> Self {
+        Self {
+            rope: Rope::from(""),
+            line_ending: None,
+            encoding: UTF_8,
+            bom: false,
+            binary: false,
+        }
+    }
+}
+
+fn load_file(path: &Path) -> Result<LoadedFile> {
     let mut f = File::open(path)?;
     let mut bytes = Vec::new();
     f.read_to_end(&mut bytes)?;
-    Ok(Rope::from(std::str::from_utf8(&bytes)?))
+
+    let (content, encoding, bom) = match decode(&bytes) {
+        Some(decoded) => decoded,
+        None => {
+            return Ok(LoadedFile {
+                rope: Rope::from(BINARY_PLACEHOLDER),
+                binary: true,
+                ..Default::default()
+            })
+        }
+    };
+    let line_ending = LineEnding::detect(&content);
+    let rope = if content.contains("\r\n") {
+        Rope::from(content.replace("\r\n", "\n"))
+    } else {
+        Rope::from(content)
+    };
+    Ok(LoadedFile {
+        rope,
+        line_ending,
+        encoding,
+        bom,
+        binary: false,
+    })
+}
+
+/// Decode the bytes of a file, returning the text, its encoding and whether
+/// it had a byte order mark, or `None` if it doesn't look like text.
+///
+/// The encoding is taken from the byte order mark if there is one. Otherwise
+/// the file is UTF-8 if it is valid UTF-8, and Windows-1252 (a superset of
+/// Latin-1) if not.
+fn decode(bytes: &[u8]) -> Option<(Cow<str>, &'static Encoding, bool)> {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        let (content, had_errors) =
+            encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        return if had_errors {
+            None
+        } else {
+            Some((content, encoding, true))
+        };
+    }
+
+    // Nul bytes don't appear in text files
+    if bytes.contains(&0) {
+        return None;
+    }
+
+    if let Ok(content) = std::str::from_utf8(bytes) {
+        return Some((Cow::Borrowed(content), UTF_8, false));
+    }
+
+    let (content, had_errors) = WINDOWS_1252.decode_without_bom_handling(bytes);
+    if had_errors {
+        None
+    } else {
+        Some((content, WINDOWS_1252, false))
+    }
+}
+
+/// Encode the text back into the encoding it was loaded with, failing if it
+/// has characters the encoding can't represent
+fn encode(content: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>> {
+    // encoding_rs only decodes UTF-16, so that is encoded by hand
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let mut bytes = Vec::with_capacity(content.len() * 2 + 2);
+        let bom = if bom { Some(0xFEFF) } else { None };
+        for unit in bom.into_iter().chain(content.encode_utf16()) {
+            if encoding == UTF_16LE {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            } else {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            }
+        }
+        return Ok(bytes);
+    }
+
+    let mut bytes = Vec::with_capacity(content.len() + 3);
+    if bom && encoding == UTF_8 {
+        bytes.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    // encoding_rs writes the characters it can't map as HTML entities, which
+    // would silently change the file
+    let (encoded, _, unmappable) = encoding.encode(content);
+    if unmappable {
+        return Err(anyhow!(
+            "the file has characters that can't be saved as {}",
+            encoding.name()
+        ));
+    }
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
 }
 
 fn language_id_from_path(path: &Path) -> Option<&str> {
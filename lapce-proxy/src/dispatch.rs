@@ -1,11 +1,12 @@
 use crate::buffer::{get_mod_time, Buffer};
 use crate::lsp::LspCatalog;
 use crate::plugin::PluginCatalog;
+use crate::settings::WorkspaceSettings;
 use crate::terminal::Terminal;
 use alacritty_terminal::event_loop::Msg;
 use alacritty_terminal::term::SizeInfo;
 use anyhow::{anyhow, Context, Result};
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::{unbounded, Receiver, RecvError, Sender, TryRecvError};
 use directories::BaseDirs;
 use git2::{DiffOptions, Repository};
 use grep_matcher::Matcher;
@@ -15,7 +16,7 @@ use grep_searcher::SearcherBuilder;
 use lapce_rpc::buffer::{BufferHeadResponse, BufferId, NewBufferResponse};
 use lapce_rpc::file::FileNodeItem;
 use lapce_rpc::proxy::{ProxyNotification, ProxyRequest};
-use lapce_rpc::source_control::{DiffInfo, FileDiff};
+use lapce_rpc::source_control::{BlameHunk, DiffInfo, FileDiff};
 use lapce_rpc::terminal::TermId;
 use lapce_rpc::{self, Call, RequestId, RpcObject};
 use lsp_types::TextDocumentContentChangeEvent;
@@ -26,10 +27,15 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::{collections::HashSet, io::BufRead};
 
+/// The maximum number of paths returned when listing the workspace files,
+/// so that huge workspaces don't flood the file palette
+const MAX_WORKSPACE_FILES: usize = 100_000;
+
 #[derive(Clone)]
 pub struct Dispatcher {
     pub sender: Arc<Sender<Value>>,
@@ -45,6 +51,9 @@ pub struct Dispatcher {
     pub lsp: Arc<Mutex<LspCatalog>>,
     pub watcher: Arc<Mutex<Option<notify::RecommendedWatcher>>>,
     last_diff: Arc<Mutex<DiffInfo>>,
+    /// The id of the running workspace search, a search stops as soon as
+    /// it's no longer the current one
+    global_search_id: Arc<AtomicU64>,
 }
 
 impl notify::EventHandler for Dispatcher {
@@ -64,24 +73,18 @@ impl notify::EventHandler for Dispatcher {
                                         return;
                                     }
                                     if !buffer.dirty {
-                                        buffer.reload();
-                                        self.lsp.lock().update(
-                                            buffer,
-                                            &TextDocumentContentChangeEvent {
-                                                range: None,
-                                                range_length: None,
-                                                text: buffer.get_document(),
-                                            },
-                                            buffer.rev,
-                                        );
-                                        let _ = self.sender.send(json!({
-                                            "method": "reload_buffer",
-                                            "params": {
+                                        self.reload_buffer(buffer);
+                                    } else {
+                                        // Don't throw away the unsaved changes,
+                                        // but let the user decide what to keep
+                                        buffer.mod_time = get_mod_time(&buffer.path);
+                                        self.send_notification(
+                                            "buffer_changed_on_disk",
+                                            json!({
                                                 "buffer_id": buffer_id,
-                                                "rev": buffer.rev,
-                                                "new_content": buffer.get_document(),
-                                            },
-                                        }));
+                                                "content": buffer.disk_content(),
+                                            }),
+                                        );
                                     }
                                 }
                             }
@@ -129,6 +132,7 @@ impl Dispatcher {
             lsp: Arc::new(Mutex::new(LspCatalog::new())),
             watcher: Arc::new(Mutex::new(None)),
             last_diff: Arc::new(Mutex::new(DiffInfo::default())),
+            global_search_id: Arc::new(AtomicU64::new(0)),
         };
         *dispatcher.watcher.lock() =
             Some(notify::recommended_watcher(dispatcher.clone()).unwrap());
@@ -203,27 +207,42 @@ impl Dispatcher {
     pub fn start_update_process(&self, receiver: Receiver<(BufferId, u64)>) {
         let buffers = self.buffers.clone();
         let lsp = self.lsp.clone();
-        thread::spawn(move || loop {
-            match receiver.recv() {
-                Ok((buffer_id, rev)) => {
-                    let buffers = buffers.lock();
-                    let buffer = buffers.get(&buffer_id).unwrap();
-                    let (_path, _content) = if buffer.rev != rev {
-                        continue;
-                    } else {
-                        (
-                            buffer.path.clone(),
-                            buffer.slice_to_cow(..buffer.len()).to_string(),
-                        )
+        thread::spawn(move || {
+            // The senders are dropped with the buffers and the dispatcher,
+            // which ends the loop and lets the thread exit
+            while let Ok(updates) = receive_batch(&receiver) {
+                let buffers = buffers.lock();
+                for (buffer_id, rev) in updates {
+                    let buffer = match buffers.get(&buffer_id) {
+                        Some(buffer) if buffer.rev == rev => buffer,
+                        _ => continue,
                     };
-
                     lsp.lock().get_semantic_tokens(buffer);
                 }
-                Err(_) => {
-                    return;
-                }
             }
         });
+
+        /// Waits for the next update and collects the ones already queued
+        /// behind it, keeping only the latest revision of each buffer.
+        fn receive_batch(
+            receiver: &Receiver<(BufferId, u64)>,
+        ) -> Result<HashMap<BufferId, u64>, RecvError> {
+            let (buffer_id, rev) = receiver.recv()?;
+            let mut updates = HashMap::new();
+            updates.insert(buffer_id, rev);
+            loop {
+                match receiver.try_recv() {
+                    Ok((buffer_id, rev)) => {
+                        updates.insert(buffer_id, rev);
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    // Handle what was received, the next `recv` reports
+                    // the disconnect
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            }
+            Ok(updates)
+        }
     }
 
     pub fn next<R: BufRead>(
@@ -263,6 +282,28 @@ impl Dispatcher {
         let _ = self.sender.send(resp);
     }
 
+    /// Reload the buffer from disk, and send the new content to the editor
+    fn reload_buffer(&self, buffer: &mut Buffer) {
+        buffer.reload();
+        self.lsp.lock().update(
+            buffer,
+            &TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: buffer.get_document(),
+            },
+            buffer.rev,
+        );
+        self.send_notification(
+            "reload_buffer",
+            json!({
+                "buffer_id": buffer.id,
+                "rev": buffer.rev,
+                "new_content": buffer.get_document(),
+            }),
+        );
+    }
+
     pub fn send_notification(&self, method: &str, params: Value) {
         let _ = self.sender.send(json!({
             "method": method,
@@ -270,6 +311,20 @@ impl Dispatcher {
         }));
     }
 
+    /// Load the file of a buffer and watch it for changes
+    fn open_buffer(&self, buffer_id: BufferId, path: PathBuf) -> Buffer {
+        let _ = self
+            .watcher
+            .lock()
+            .as_mut()
+            .unwrap()
+            .watch(&path, notify::RecursiveMode::Recursive);
+        self.open_files
+            .lock()
+            .insert(path.to_str().unwrap().to_string(), buffer_id);
+        Buffer::new(buffer_id, path, self.git_sender.clone())
+    }
+
     fn handle_notification(&self, rpc: ProxyNotification) {
         use ProxyNotification::*;
         match rpc {
@@ -290,6 +345,21 @@ impl Dispatcher {
                     );
                     *self.last_diff.lock() = diff;
                 }
+
+                let (settings, errors) = WorkspaceSettings::load(&workspace);
+                if !errors.is_empty() {
+                    self.send_notification(
+                        "settings_error",
+                        json!({
+                            "path": WorkspaceSettings::path(&workspace),
+                            "errors": errors,
+                        }),
+                    );
+                }
+                let lsp = self.lsp.clone();
+                thread::spawn(move || {
+                    lsp.lock().set_servers(settings.lsp);
+                });
             }
             Shutdown {} => {}
             Update {
@@ -298,9 +368,37 @@ impl Dispatcher {
                 rev,
             } => {
                 let mut buffers = self.buffers.lock();
-                let buffer = buffers.get_mut(&buffer_id).unwrap();
-                if let Some(content_change) = buffer.update(&delta, rev) {
-                    self.lsp.lock().update(buffer, &content_change, buffer.rev);
+                // updates sent while a restarted proxy was down are for
+                // buffers it doesn't have yet
+                if let Some(buffer) = buffers.get_mut(&buffer_id) {
+                    if let Some(content_change) = buffer.update(&delta, rev) {
+                        self.lsp.lock().update(buffer, &content_change, buffer.rev);
+                    }
+                }
+            }
+            ReopenBuffer {
+                buffer_id,
+                path,
+                content,
+                rev,
+            } => {
+                let mut buffer = self.open_buffer(buffer_id, path);
+                buffer.restore(&content, rev);
+                self.buffers.lock().insert(buffer_id, buffer);
+                let _ = self.git_sender.send((buffer_id, rev));
+            }
+            ReloadBuffer { buffer_id } => {
+                let mut buffers = self.buffers.lock();
+                if let Some(buffer) = buffers.get_mut(&buffer_id) {
+                    self.reload_buffer(buffer);
+                }
+            }
+            RefreshSemanticTokens { buffer_id } => {
+                let mut buffers = self.buffers.lock();
+                if let Some(buffer) = buffers.get_mut(&buffer_id) {
+                    // without previous tokens the next ones aren't a delta
+                    buffer.semantic_tokens = None;
+                    self.lsp.lock().get_semantic_tokens(buffer);
                 }
             }
             InstallPlugin { plugin } => {
@@ -368,6 +466,13 @@ impl Dispatcher {
                     let _ = tx.send(Msg::Resize(size));
                 }
             }
+            CancelGlobalSearch {} => {
+                // the searches sent by the core have ids starting from 1
+                self.global_search_id.store(0, Ordering::SeqCst);
+            }
+            CancelRequest { id } => {
+                self.lsp.lock().cancel_request(id);
+            }
             GitCommit { message, diffs } => {
                 if let Some(workspace) = self.workspace.lock().clone() {
                     if let Err(_e) = git_commit(&workspace, &message, diffs) {}
@@ -380,20 +485,23 @@ impl Dispatcher {
         use ProxyRequest::*;
         match rpc {
             NewBuffer { buffer_id, path } => {
-                let _ = self
-                    .watcher
-                    .lock()
-                    .as_mut()
-                    .unwrap()
-                    .watch(&path, notify::RecursiveMode::Recursive);
-                self.open_files
-                    .lock()
-                    .insert(path.to_str().unwrap().to_string(), buffer_id);
-                let buffer = Buffer::new(buffer_id, path, self.git_sender.clone());
+                let buffer = self.open_buffer(buffer_id, path);
                 let content = buffer.rope.to_string();
+                let line_ending = buffer.line_ending;
+                let encoding = buffer.encoding.name().to_string();
+                let binary = buffer.binary;
+                let read_only = fs::metadata(&buffer.path)
+                    .map(|m| m.permissions().readonly())
+                    .unwrap_or(false);
                 self.buffers.lock().insert(buffer_id, buffer);
                 let _ = self.git_sender.send((buffer_id, 0));
-                let resp = NewBufferResponse { content };
+                let resp = NewBufferResponse {
+                    content,
+                    line_ending,
+                    encoding,
+                    binary,
+                    read_only,
+                };
                 let _ = self.sender.send(json!({
                     "id": id,
                     "result": resp,
@@ -415,6 +523,18 @@ impl Dispatcher {
                     }
                 }
             }
+            GitBlame { path } => {
+                if let Some(workspace) = self.workspace.lock().clone() {
+                    let local_dispatcher = self.clone();
+                    thread::spawn(move || {
+                        let result = file_git_blame(&workspace, &path)
+                            .map(|hunks| serde_json::to_value(hunks).unwrap());
+                        local_dispatcher.respond(id, result);
+                    });
+                } else {
+                    self.respond(id, Err(anyhow!("no workspace")));
+                }
+            }
             GetCompletion {
                 buffer_id,
                 position,
@@ -445,7 +565,12 @@ impl Dispatcher {
                 let buffer = buffers.get(&buffer_id).unwrap();
                 self.lsp.lock().get_hover(id, request_id, buffer, position);
             }
-            GetSignature {                
+            GetInlayHints { buffer_id, range } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_inlay_hints(id, buffer, range);
+            }
+            GetSignature {
                 buffer_id,
                 position,
             } => {
@@ -516,6 +641,35 @@ impl Dispatcher {
                     local_dispatcher.respond(id, result);
                 });
             }
+            CreateFile { path } => {
+                let resp = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(path)
+                    .map(|_| json!({}))
+                    .map_err(|e| anyhow!(e));
+                self.respond(id, resp);
+            }
+            RenamePath { from, to } => {
+                let resp = if to.exists() {
+                    Err(anyhow!("{} already exists", to.display()))
+                } else {
+                    fs::rename(from, to)
+                        .map(|_| json!({}))
+                        .map_err(|e| anyhow!(e))
+                };
+                self.respond(id, resp);
+            }
+            DeletePath { path } => {
+                let resp = if path.is_dir() {
+                    fs::remove_dir_all(path)
+                } else {
+                    fs::remove_file(path)
+                }
+                .map(|_| json!({}))
+                .map_err(|e| anyhow!(e));
+                self.respond(id, resp);
+            }
             #[allow(unused_variables)]
             GetFiles { path } => {
                 if let Some(workspace) = self.workspace.lock().clone() {
@@ -526,6 +680,9 @@ impl Dispatcher {
                             if let Some(file_type) = path.file_type() {
                                 if file_type.is_file() {
                                     items.push(path.into_path());
+                                    if items.len() >= MAX_WORKSPACE_FILES {
+                                        break;
+                                    }
                                 }
                             }
                         }
@@ -534,25 +691,47 @@ impl Dispatcher {
                     });
                 }
             }
-            Save { rev, buffer_id } => {
+            Save {
+                rev,
+                buffer_id,
+                line_ending,
+            } => {
                 let mut buffers = self.buffers.lock();
-                let buffer = buffers.get_mut(&buffer_id).unwrap();
-                let resp = buffer.save(rev).map(|_r| json!({}));
-                self.lsp.lock().save_buffer(buffer);
+                let resp = match buffers.get_mut(&buffer_id) {
+                    Some(buffer) => {
+                        let resp = buffer.save(rev, line_ending).map(|_r| json!({}));
+                        self.lsp.lock().save_buffer(buffer);
+                        resp
+                    }
+                    None => Err(anyhow!("buffer not found")),
+                };
                 self.respond(id, resp);
             }
-            GlobalSearch { pattern } => {
+            GlobalSearch {
+                search_id,
+                pattern,
+                case_sensitive,
+                whole_word,
+            } => {
+                self.global_search_id.store(search_id, Ordering::SeqCst);
                 if let Some(workspace) = self.workspace.lock().clone() {
                     let local_dispatcher = self.clone();
                     thread::spawn(move || {
-                        let mut matches = HashMap::new();
                         let pattern = regex::escape(&pattern);
                         if let Ok(matcher) = RegexMatcherBuilder::new()
-                            .case_insensitive(true)
+                            .case_insensitive(!case_sensitive)
+                            .word(whole_word)
                             .build_literals(&[&pattern])
                         {
                             let mut searcher = SearcherBuilder::new().build();
                             for path in ignore::Walk::new(workspace).flatten() {
+                                if local_dispatcher
+                                    .global_search_id
+                                    .load(Ordering::SeqCst)
+                                    != search_id
+                                {
+                                    break;
+                                }
                                 if let Some(file_type) = path.file_type() {
                                     if file_type.is_file() {
                                         let path = path.into_path();
@@ -573,15 +752,20 @@ impl Dispatcher {
                                             }),
                                         );
                                         if !line_matches.is_empty() {
-                                            matches
-                                                .insert(path.clone(), line_matches);
+                                            local_dispatcher.send_notification(
+                                                "global_search_matches",
+                                                json!({
+                                                    "search_id": search_id,
+                                                    "path": path,
+                                                    "matches": line_matches,
+                                                }),
+                                            );
                                         }
                                     }
                                 }
                             }
                         }
-                        local_dispatcher
-                            .respond(id, Ok(serde_json::to_value(matches).unwrap()));
+                        local_dispatcher.respond(id, Ok(json!({})));
                     });
                 }
             }
@@ -762,6 +946,37 @@ fn file_get_head(workspace_path: &Path, path: &Path) -> Result<(String, String)>
     Ok((id, content))
 }
 
+fn file_git_blame(workspace_path: &Path, path: &Path) -> Result<Vec<BlameHunk>> {
+    let repo = Repository::open(
+        workspace_path
+            .to_str()
+            .ok_or_else(|| anyhow!("can't to str"))?,
+    )?;
+    let blame = repo.blame_file(path.strip_prefix(workspace_path)?, None)?;
+    let mut hunks = Vec::new();
+    for hunk in blame.iter() {
+        let id = hunk.final_commit_id();
+        let commit = if id.is_zero() {
+            None
+        } else {
+            repo.find_commit(id).ok()
+        };
+        let signature = hunk.final_signature();
+        hunks.push(BlameHunk {
+            start_line: hunk.final_start_line().saturating_sub(1),
+            line_count: hunk.lines_in_hunk(),
+            commit: commit.as_ref().map(|_| id.to_string()[..7].to_string()),
+            author: signature.name().unwrap_or("").to_string(),
+            time: signature.when().seconds(),
+            summary: commit
+                .as_ref()
+                .and_then(|commit| commit.summary().map(|s| s.to_string()))
+                .unwrap_or_default(),
+        });
+    }
+    Ok(hunks)
+}
+
 #[allow(dead_code)]
 fn file_git_diff(
     workspace_path: &Path,
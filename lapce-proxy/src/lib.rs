@@ -2,6 +2,7 @@ pub mod buffer;
 pub mod dispatch;
 pub mod lsp;
 pub mod plugin;
+pub mod settings;
 pub mod terminal;
 
 use dispatch::Dispatcher;
@@ -24,6 +24,7 @@ use serde_json::{json, to_value, Value};
 
 use crate::buffer::Buffer;
 use crate::dispatch::Dispatcher;
+use crate::settings::LspServerSettings;
 
 pub type Callback = Box<dyn Callable>;
 const HEADER_CONTENT_LENGTH: &str = "content-length";
@@ -42,6 +43,9 @@ impl<F: Send + FnOnce(&LspClient, Result<Value>)> Callable for F {
 pub struct LspCatalog {
     pub dispatcher: Option<Dispatcher>,
     clients: HashMap<String, Arc<LspClient>>,
+    /// The servers set in the workspace settings by language id, which
+    /// the servers started by plugins don't replace
+    servers: HashMap<String, LspServerSettings>,
 }
 
 pub struct LspState {
@@ -49,9 +53,15 @@ pub struct LspState {
     writer: Box<dyn Write + Send>,
     process: Child,
     pending: HashMap<u64, Callback>,
+    /// The ids of the requests sent for requests of the core that can be
+    /// cancelled, by the id of the core request
+    cancellable: HashMap<RequestId, u64>,
     pub server_capabilities: Option<ServerCapabilities>,
     pub opened_documents: HashMap<BufferId, Url>,
     pub is_initialized: bool,
+    /// Set when the server is stopped on purpose, so that it isn't
+    /// started again
+    is_shutdown: bool,
 }
 
 #[derive(Clone)]
@@ -59,6 +69,8 @@ pub struct LspClient {
     #[allow(dead_code)]
     language_id: String,
     exec_path: String,
+    args: Vec<String>,
+    env: HashMap<String, String>,
     options: Option<Value>,
     state: Arc<Mutex<LspState>>,
     dispatcher: Dispatcher,
@@ -69,12 +81,13 @@ impl LspCatalog {
         LspCatalog {
             dispatcher: None,
             clients: HashMap::new(),
+            servers: HashMap::new(),
         }
     }
 
     pub fn stop(&mut self) {
         for (_, client) in self.clients.iter() {
-            client.stop();
+            client.shutdown();
         }
         self.clients.clear();
         self.dispatcher.take();
@@ -86,15 +99,39 @@ impl LspCatalog {
         language_id: &str,
         options: Option<Value>,
     ) {
+        if self.servers.contains_key(language_id) {
+            return;
+        }
         let client = LspClient::new(
             language_id.to_string(),
             exec_path,
+            Vec::new(),
+            HashMap::new(),
             options,
             self.dispatcher.clone().unwrap(),
         );
         self.clients.insert(language_id.to_string(), client);
     }
 
+    /// Start the servers set in the workspace settings, in place of the
+    /// ones started for the same languages
+    pub fn set_servers(&mut self, servers: HashMap<String, LspServerSettings>) {
+        for (language_id, server) in servers.iter() {
+            let client = LspClient::new(
+                language_id.to_string(),
+                &server.command,
+                server.args.clone(),
+                server.env.clone(),
+                server.initialization_options.clone(),
+                self.dispatcher.clone().unwrap(),
+            );
+            if let Some(old) = self.clients.insert(language_id.to_string(), client) {
+                old.shutdown();
+            }
+        }
+        self.servers = servers;
+    }
+
     pub fn new_buffer(
         &self,
         buffer_id: &BufferId,
@@ -108,6 +145,12 @@ impl LspCatalog {
         }
     }
 
+    pub fn cancel_request(&self, id: RequestId) {
+        for (_, client) in self.clients.iter() {
+            client.cancel_request(id);
+        }
+    }
+
     pub fn save_buffer(&self, buffer: &Buffer) {
         if let Some(client) = self.clients.get(&buffer.language_id) {
             let uri = client.get_uri(buffer);
@@ -115,43 +158,62 @@ impl LspCatalog {
         }
     }
 
+    /// Request the semantic tokens of a buffer, as a delta of the previous
+    /// ones when the server supports it. A delta the server can't provide
+    /// falls back to requesting all the tokens.
     pub fn get_semantic_tokens(&self, buffer: &Buffer) {
         let buffer_id = buffer.id;
-        let path = buffer.path.clone();
         let rev = buffer.rev;
-        let len = buffer.len();
         if let Some(client) = self.clients.get(&buffer.language_id) {
             let uri = client.get_uri(buffer);
             let local_dispatcher = self.dispatcher.clone().unwrap();
-            client.request_semantic_tokens(uri, move |lsp_client, result| {
-                if let Ok(res) = result {
-                    let buffers = local_dispatcher.buffers.lock();
-                    let buffer = buffers.get(&buffer_id).unwrap();
-                    if buffer.rev != rev {
-                        return;
-                    }
-                    let lsp_state = lsp_client.state.lock();
-                    let semantic_tokens_provider = &lsp_state
-                        .server_capabilities
-                        .as_ref()
-                        .unwrap()
-                        .semantic_tokens_provider;
-                    if let Some(styles) =
-                        format_semantic_styles(buffer, semantic_tokens_provider, res)
-                    {
-                        local_dispatcher.send_notification(
-                            "semantic_styles",
-                            json!({
-                                "rev": rev,
-                                "buffer_id": buffer_id,
-                                "path": path,
-                                "styles": styles,
-                                "len": len,
-                            }),
+            let previous_result_id = buffer
+                .semantic_tokens
+                .as_ref()
+                .and_then(|tokens| tokens.result_id.clone())
+                .filter(|_| client.supports_semantic_tokens_delta());
+            let previous_result_id = match previous_result_id {
+                Some(previous_result_id) => previous_result_id,
+                None => {
+                    request_full_semantic_tokens(
+                        client,
+                        local_dispatcher,
+                        uri,
+                        buffer_id,
+                        rev,
+                    );
+                    return;
+                }
+            };
+            client.request_semantic_tokens_delta(
+                uri.clone(),
+                previous_result_id.clone(),
+                move |lsp_client, result| {
+                    let delta = result.ok().and_then(|res| {
+                        serde_json::from_value::<SemanticTokensFullDeltaResult>(res)
+                            .ok()
+                    });
+                    let applied = delta.map(|delta| {
+                        apply_semantic_tokens_delta(
+                            &local_dispatcher,
+                            lsp_client,
+                            buffer_id,
+                            rev,
+                            &previous_result_id,
+                            delta,
                         )
+                    });
+                    if applied != Some(true) {
+                        request_full_semantic_tokens(
+                            lsp_client,
+                            local_dispatcher,
+                            uri,
+                            buffer_id,
+                            rev,
+                        );
                     }
-                }
-            });
+                },
+            );
         }
     }
 
@@ -188,37 +250,85 @@ impl LspCatalog {
     ) {
         if let Some(client) = self.clients.get(&buffer.language_id) {
             let uri = client.get_uri(buffer);
-            client.request_completion(uri, position, move |lsp_client, result| {
-                let mut resp = json!({ "id": id });
-                match result {
-                    Ok(v) => resp["result"] = v,
-                    Err(e) => {
-                        resp["error"] = json!({
-                            "code": 0,
-                            "message": format!("{}",e),
-                        })
+            let lsp_id = client.request_completion(
+                uri,
+                position,
+                move |lsp_client, result| {
+                    lsp_client.state.lock().cancellable.remove(&id);
+                    let mut resp = json!({ "id": id });
+                    match result {
+                        Ok(v) => resp["result"] = v,
+                        Err(e) => {
+                            resp["error"] = json!({
+                                "code": 0,
+                                "message": format!("{}",e),
+                            })
+                        }
                     }
-                }
-                let _ = lsp_client.dispatcher.sender.send(resp);
-            });
+                    let _ = lsp_client.dispatcher.sender.send(resp);
+                },
+            );
+            let mut state = client.state.lock();
+            // a response handled already has nothing left to cancel
+            if state.pending.contains_key(&lsp_id) {
+                state.cancellable.insert(id, lsp_id);
+            }
         }
     }
 
+    /// Resolve the lazy fields of a completion item, an item the server
+    /// can't resolve is sent back as it is
     pub fn completion_resolve(
         &self,
         id: RequestId,
         buffer: &Buffer,
         completion_item: &CompletionItem,
+    ) {
+        match self.clients.get(&buffer.language_id) {
+            Some(client) if client.supports_completion_resolve() => {
+                client.completion_resolve(
+                    completion_item,
+                    move |lsp_client, result| {
+                        let mut resp = json!({ "id": id });
+                        match result {
+                            Ok(v) => resp["result"] = v,
+                            Err(e) => {
+                                resp["error"] = json!({
+                                    "code": 0,
+                                    "message": format!("{}",e),
+                                })
+                            }
+                        }
+                        let _ = lsp_client.dispatcher.sender.send(resp);
+                    },
+                );
+            }
+            _ => {
+                self.dispatcher
+                    .as_ref()
+                    .unwrap()
+                    .respond(id, to_value(completion_item).map_err(|e| anyhow!(e)));
+            }
+        }
+    }
+
+    pub fn get_hover(
+        &self,
+        id: RequestId,
+        _request_id: usize,
+        buffer: &Buffer,
+        position: Position,
     ) {
         if let Some(client) = self.clients.get(&buffer.language_id) {
-            client.completion_resolve(completion_item, move |lsp_client, result| {
+            let uri = client.get_uri(buffer);
+            client.request_hover(uri, position, move |lsp_client, result| {
                 let mut resp = json!({ "id": id });
                 match result {
                     Ok(v) => resp["result"] = v,
                     Err(e) => {
                         resp["error"] = json!({
                             "code": 0,
-                            "message": format!("{}",e),
+                            "message": format!("{}", e),
                         })
                     }
                 }
@@ -227,16 +337,10 @@ impl LspCatalog {
         }
     }
 
-    pub fn get_hover(
-        &self,
-        id: RequestId,
-        _request_id: usize,
-        buffer: &Buffer,
-        position: Position,
-    ) {
+    pub fn get_inlay_hints(&self, id: RequestId, buffer: &Buffer, range: Range) {
         if let Some(client) = self.clients.get(&buffer.language_id) {
             let uri = client.get_uri(buffer);
-            client.request_hover(uri, position, move |lsp_client, result| {
+            client.request_inlay_hints(uri, range, move |lsp_client, result| {
                 let mut resp = json!({ "id": id });
                 match result {
                     Ok(v) => resp["result"] = v,
@@ -249,6 +353,11 @@ impl LspCatalog {
                 }
                 let _ = lsp_client.dispatcher.sender.send(resp);
             });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no inlay hints")));
         }
     }
 
@@ -371,16 +480,20 @@ impl LspClient {
     pub fn new(
         language_id: String,
         exec_path: &str,
+        args: Vec<String>,
+        env: HashMap<String, String>,
         options: Option<Value>,
         dispatcher: Dispatcher,
     ) -> Arc<LspClient> {
-        let mut process = Self::process(exec_path);
+        let mut process = Self::process(exec_path, &args, &env);
         let writer = Box::new(BufWriter::new(process.stdin.take().unwrap()));
         let stdout = process.stdout.take().unwrap();
 
         let lsp_client = Arc::new(LspClient {
             dispatcher,
             exec_path: exec_path.to_string(),
+            args,
+            env,
             language_id,
             options,
             state: Arc::new(Mutex::new(LspState {
@@ -388,9 +501,11 @@ impl LspClient {
                 writer,
                 process,
                 pending: HashMap::new(),
+                cancellable: HashMap::new(),
                 server_capabilities: None,
                 opened_documents: HashMap::new(),
                 is_initialized: false,
+                is_shutdown: false,
             })),
         });
 
@@ -410,6 +525,9 @@ impl LspClient {
                         local_lsp_client.handle_message(message_str.as_ref());
                     }
                     Err(_err) => {
+                        if local_lsp_client.state.lock().is_shutdown {
+                            return;
+                        }
                         local_lsp_client.stop();
                         local_lsp_client.reload();
                         return;
@@ -419,11 +537,17 @@ impl LspClient {
         });
     }
 
-    fn process(exec_path: &str) -> Child {
+    fn process(
+        exec_path: &str,
+        args: &[String],
+        env: &HashMap<String, String>,
+    ) -> Child {
         let mut process = Command::new(exec_path);
         #[cfg(target_os = "windows")]
         let process = process.creation_flags(0x08000000);
         process
+            .args(args)
+            .envs(env)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
@@ -431,13 +555,14 @@ impl LspClient {
     }
 
     fn reload(&self) {
-        let mut process = Self::process(&self.exec_path);
+        let mut process = Self::process(&self.exec_path, &self.args, &self.env);
         let writer = Box::new(BufWriter::new(process.stdin.take().unwrap()));
         let stdout = process.stdout.take().unwrap();
 
         let mut state = self.state.lock();
         state.next_id = 0;
         state.pending.clear();
+        state.cancellable.clear();
         state.opened_documents.clear();
         state.server_capabilities = None;
         state.is_initialized = false;
@@ -452,6 +577,13 @@ impl LspClient {
         let _ = self.state.lock().process.kill();
     }
 
+    /// Stop the server for good, unlike a server that crashed
+    fn shutdown(&self) {
+        let mut state = self.state.lock();
+        state.is_shutdown = true;
+        let _ = state.process.kill();
+    }
+
     pub fn get_uri(&self, buffer: &Buffer) -> Url {
         let exists = {
             let state = self.state.lock();
@@ -556,17 +688,47 @@ impl LspClient {
         self.send_rpc(&res);
     }
 
-    pub fn send_request(&self, method: &str, params: Params, completion: Callback) {
-        let request = {
+    pub fn send_request(
+        &self,
+        method: &str,
+        params: Params,
+        completion: Callback,
+    ) -> u64 {
+        let (id, request) = {
             let mut state = self.state.lock();
             let next_id = state.next_id;
             state.pending.insert(next_id, completion);
             state.next_id += 1;
 
-            JsonRpc::request_with_params(Id::Num(next_id as i64), method, params)
+            (
+                next_id,
+                JsonRpc::request_with_params(
+                    Id::Num(next_id as i64),
+                    method,
+                    params,
+                ),
+            )
         };
 
         self.send_rpc(&to_value(&request).unwrap());
+        id
+    }
+
+    /// Ask the server to stop working on the request sent for the core
+    /// request `id`, its response is still handled when it comes
+    pub fn cancel_request(&self, id: RequestId) {
+        let lsp_id = {
+            let mut state = self.state.lock();
+            match state.cancellable.remove(&id) {
+                Some(lsp_id) if state.pending.contains_key(&lsp_id) => lsp_id,
+                _ => return,
+            }
+        };
+        let params = CancelParams {
+            id: NumberOrString::Number(lsp_id as i32),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_notification("$/cancelRequest", params);
     }
 
     fn initialize(&self) {
@@ -583,6 +745,14 @@ impl LspClient {
                         state.is_initialized = true;
                     }
                     lsp_client.send_initialized();
+                    let buffer_ids: Vec<BufferId> = lsp_client
+                        .state
+                        .lock()
+                        .opened_documents
+                        .keys()
+                        .copied()
+                        .collect();
+                    lsp_client.send_signature_trigger_characters(&buffer_ids);
                 }
                 let _ = sender.send(true);
             });
@@ -607,6 +777,8 @@ impl LspClient {
 
         if !is_initialized {
             self.initialize();
+        } else {
+            self.send_signature_trigger_characters(&[*buffer_id]);
         }
 
         let text_document_did_open_params = DidOpenTextDocumentParams {
@@ -632,6 +804,28 @@ impl LspClient {
         self.send_notification("textDocument/didSave", params);
     }
 
+    /// Tell the core which characters trigger signature help in the
+    /// buffers, going by the server's `signatureHelpProvider`
+    fn send_signature_trigger_characters(&self, buffer_ids: &[BufferId]) {
+        let characters = self
+            .state
+            .lock()
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.signature_help_provider.as_ref())
+            .and_then(|provider| provider.trigger_characters.clone())
+            .unwrap_or_default();
+        for buffer_id in buffer_ids {
+            self.dispatcher.send_notification(
+                "signature_trigger_characters",
+                json!({
+                    "buffer_id": buffer_id,
+                    "characters": characters,
+                }),
+            );
+        }
+    }
+
     pub fn send_initialized(&self) {
         self.send_notification("initialized", Params::from(json!({})));
     }
@@ -654,19 +848,19 @@ impl LspClient {
                     }),
                     ..Default::default()
                 }),
-                // signature_help: Some(SignatureHelpCapability {
-                //     signature_information: Some(SignatureInformationSettings {
-                //         parameter_information: Some(ParameterInformationSettings {
-                //             label_offset_support: Some(true),
-                //         }),
-                //         active_parameter_support: Some(true),
-                //         documentation_format: Some(vec![
-                //             MarkupKind::Markdown,
-                //             MarkupKind::PlainText,
-                //         ]),
-                //     }),
-                //     ..Default::default()
-                // }),
+                signature_help: Some(SignatureHelpClientCapabilities {
+                    signature_information: Some(SignatureInformationSettings {
+                        parameter_information: Some(ParameterInformationSettings {
+                            label_offset_support: Some(true),
+                        }),
+                        active_parameter_support: Some(true),
+                        documentation_format: Some(vec![
+                            MarkupKind::Markdown,
+                            MarkupKind::PlainText,
+                        ]),
+                    }),
+                    ..Default::default()
+                }),
                 // We could set content_format to specify our preferences but RA seems to only
                 // check if the preferences contains markdown, rather than paying attention to our
                 // given priority ordering
@@ -695,6 +889,12 @@ impl LspClient {
                     ..Default::default()
                 }),
                 semantic_tokens: Some(SemanticTokensClientCapabilities {
+                    requests: SemanticTokensClientCapabilitiesRequests {
+                        full: Some(SemanticTokensFullOptions::Delta {
+                            delta: Some(true),
+                        }),
+                        ..Default::default()
+                    },
                     ..Default::default()
                 }),
 
@@ -775,6 +975,63 @@ impl LspClient {
         self.send_request("textDocument/semanticTokens/full", params, Box::new(cb));
     }
 
+    pub fn request_semantic_tokens_delta<CB>(
+        &self,
+        document_uri: Url,
+        previous_result_id: String,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = SemanticTokensDeltaParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            previous_result_id,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request(
+            "textDocument/semanticTokens/full/delta",
+            params,
+            Box::new(cb),
+        );
+    }
+
+    pub fn supports_completion_resolve(&self) -> bool {
+        self.state
+            .lock()
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.completion_provider.as_ref())
+            .and_then(|provider| provider.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    /// Whether the server can send the semantic tokens of a document as a
+    /// delta of the previous ones
+    pub fn supports_semantic_tokens_delta(&self) -> bool {
+        let state = self.state.lock();
+        let full = match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.semantic_tokens_provider.as_ref())
+        {
+            Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+                options,
+            )) => options.full.as_ref(),
+            Some(
+                SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
+                    options,
+                ),
+            ) => options.semantic_tokens_options.full.as_ref(),
+            None => None,
+        };
+        matches!(
+            full,
+            Some(SemanticTokensFullOptions::Delta { delta: Some(true) })
+        )
+    }
+
     pub fn request_code_actions<CB>(&self, document_uri: Url, range: Range, cb: CB)
     where
         CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
@@ -838,7 +1095,8 @@ impl LspClient {
         document_uri: Url,
         position: Position,
         on_completion: CB,
-    ) where
+    ) -> u64
+    where
         CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
     {
         let completion_params = CompletionParams {
@@ -851,11 +1109,7 @@ impl LspClient {
             context: None,
         };
         let params = Params::from(serde_json::to_value(completion_params).unwrap());
-        self.send_request(
-            "textDocument/completion",
-            params,
-            Box::new(on_completion),
-        );
+        self.send_request("textDocument/completion", params, Box::new(on_completion))
     }
 
     pub fn completion_resolve<CB>(
@@ -869,6 +1123,19 @@ impl LspClient {
         self.send_request("completionItem/resolve", params, Box::new(on_result));
     }
 
+    /// Request the inlay hints of a range, `lsp-types` doesn't have the
+    /// types of the request yet so its params are written out
+    pub fn request_inlay_hints<CB>(&self, document_uri: Url, range: Range, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = Params::from(json!({
+            "textDocument": TextDocumentIdentifier { uri: document_uri },
+            "range": range,
+        }));
+        self.send_request("textDocument/inlayHint", params, Box::new(cb));
+    }
+
     pub fn request_hover<CB>(&self, document_uri: Url, position: Position, cb: CB)
     where
         CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
@@ -1039,33 +1306,220 @@ pub fn get_change_for_sync_kind(
     }
 }
 
+fn request_full_semantic_tokens(
+    client: &LspClient,
+    dispatcher: Dispatcher,
+    uri: Url,
+    buffer_id: BufferId,
+    rev: u64,
+) {
+    client.request_semantic_tokens(uri, move |lsp_client, result| {
+        if let Ok(res) = result {
+            if let Ok(tokens) = serde_json::from_value::<SemanticTokens>(res) {
+                send_semantic_styles(
+                    &dispatcher,
+                    lsp_client,
+                    buffer_id,
+                    rev,
+                    tokens,
+                    None,
+                );
+            }
+        }
+    });
+}
+
+/// Apply a semantic tokens delta to the tokens it was computed from, `false`
+/// if the buffer doesn't have them anymore or the delta doesn't fit them
+fn apply_semantic_tokens_delta(
+    dispatcher: &Dispatcher,
+    lsp_client: &LspClient,
+    buffer_id: BufferId,
+    rev: u64,
+    previous_result_id: &str,
+    delta: SemanticTokensFullDeltaResult,
+) -> bool {
+    let (result_id, edits) = match delta {
+        SemanticTokensFullDeltaResult::Tokens(tokens) => {
+            send_semantic_styles(
+                dispatcher, lsp_client, buffer_id, rev, tokens, None,
+            );
+            return true;
+        }
+        SemanticTokensFullDeltaResult::TokensDelta(delta) => {
+            (delta.result_id, delta.edits)
+        }
+        SemanticTokensFullDeltaResult::PartialTokensDelta { edits } => (None, edits),
+    };
+
+    let (data, changed) = {
+        let buffers = dispatcher.buffers.lock();
+        let previous = match buffers
+            .get(&buffer_id)
+            .and_then(|buffer| buffer.semantic_tokens.as_ref())
+        {
+            Some(previous) => previous,
+            None => return false,
+        };
+        if previous.result_id.as_deref() != Some(previous_result_id) {
+            return false;
+        }
+        match apply_semantic_tokens_edits(&previous.data, edits) {
+            Some(applied) => applied,
+            None => return false,
+        }
+    };
+    send_semantic_styles(
+        dispatcher,
+        lsp_client,
+        buffer_id,
+        rev,
+        SemanticTokens { result_id, data },
+        Some(changed),
+    );
+    true
+}
+
+/// The tokens after the edits of a delta, and the range of them that the
+/// edits changed. The edits refer to the tokens before any of them is
+/// applied, in numbers of integers of the encoded tokens.
+fn apply_semantic_tokens_edits(
+    tokens: &[SemanticToken],
+    mut edits: Vec<SemanticTokensEdit>,
+) -> Option<(Vec<SemanticToken>, std::ops::Range<usize>)> {
+    // each token is encoded as 5 integers
+    const TOKEN_LEN: u32 = 5;
+
+    edits.sort_by_key(|edit| edit.start);
+    let mut new_tokens = Vec::with_capacity(tokens.len());
+    let mut changed: Option<std::ops::Range<usize>> = None;
+    let mut last = 0;
+    for edit in edits {
+        if edit.start % TOKEN_LEN != 0 || edit.delete_count % TOKEN_LEN != 0 {
+            return None;
+        }
+        let start = (edit.start / TOKEN_LEN) as usize;
+        let end = start + (edit.delete_count / TOKEN_LEN) as usize;
+        if start < last || end > tokens.len() {
+            return None;
+        }
+        new_tokens.extend_from_slice(&tokens[last..start]);
+        let changed_start = new_tokens.len();
+        new_tokens.extend(edit.data.unwrap_or_default());
+        changed = Some(
+            changed.map(|c| c.start).unwrap_or(changed_start)..new_tokens.len(),
+        );
+        last = end;
+    }
+    new_tokens.extend_from_slice(&tokens[last..]);
+    let changed = changed.unwrap_or(new_tokens.len()..new_tokens.len());
+    Some((new_tokens, changed))
+}
+
+/// Send the styles of the semantic tokens of a buffer to the core, only
+/// the ones of the `changed` tokens when the others are the ones it
+/// already has, and keep the tokens for the next delta
+fn send_semantic_styles(
+    dispatcher: &Dispatcher,
+    lsp_client: &LspClient,
+    buffer_id: BufferId,
+    rev: u64,
+    tokens: SemanticTokens,
+    changed: Option<std::ops::Range<usize>>,
+) {
+    let mut buffers = dispatcher.buffers.lock();
+    let buffer = match buffers.get_mut(&buffer_id) {
+        Some(buffer) => buffer,
+        None => return,
+    };
+    if buffer.rev != rev {
+        return;
+    }
+    if changed.as_ref().map(|c| c.is_empty()) == Some(true) {
+        buffer.semantic_tokens = Some(tokens);
+        return;
+    }
+    let lsp_state = lsp_client.state.lock();
+    let semantic_tokens_provider = &lsp_state
+        .server_capabilities
+        .as_ref()
+        .unwrap()
+        .semantic_tokens_provider;
+    if let Some((styles, range)) =
+        format_semantic_styles(buffer, semantic_tokens_provider, &tokens, changed)
+    {
+        dispatcher.send_notification(
+            "semantic_styles",
+            json!({
+                "rev": rev,
+                "buffer_id": buffer_id,
+                "path": buffer.path,
+                "styles": styles,
+                "len": buffer.len(),
+                "range": range,
+            }),
+        );
+    }
+    buffer.semantic_tokens = Some(tokens);
+}
+
+/// The styles of the semantic tokens, or of the `changed` ones only with
+/// the range of the buffer between the tokens around them
 fn format_semantic_styles(
     buffer: &Buffer,
     semantic_tokens_provider: &Option<SemanticTokensServerCapabilities>,
-    value: Value,
-) -> Option<Vec<LineStyle>> {
-    let semantic_tokens: SemanticTokens = serde_json::from_value(value).ok()?;
+    semantic_tokens: &SemanticTokens,
+    changed: Option<std::ops::Range<usize>>,
+) -> Option<(Vec<LineStyle>, Option<std::ops::Range<usize>>)> {
     let semantic_tokens_provider = semantic_tokens_provider.as_ref()?;
     let semantic_lengends = semantic_tokens_lengend(semantic_tokens_provider);
 
-    let mut highlights = Vec::new();
+    let mut offsets = Vec::with_capacity(semantic_tokens.data.len());
     let mut line = 0;
     let mut start = 0;
-    let mut last_start = 0;
     for semantic_token in &semantic_tokens.data {
         if semantic_token.delta_line > 0 {
             line += semantic_token.delta_line as usize;
             start = buffer.offset_of_line(line);
         }
         start += semantic_token.delta_start as usize;
-        let end = start + semantic_token.length as usize;
-        let kind = semantic_lengends.token_types[semantic_token.token_type as usize]
-            .as_str()
-            .to_string();
+        offsets.push((start, start + semantic_token.length as usize));
+    }
+
+    let (changed, range) = match changed {
+        Some(changed) => {
+            let start = changed
+                .start
+                .checked_sub(1)
+                .map(|i| offsets[i].1)
+                .unwrap_or(0);
+            let end = offsets
+                .get(changed.end)
+                .map(|(start, _)| *start)
+                .unwrap_or_else(|| buffer.len())
+                .max(start);
+            (changed, Some(start..end))
+        }
+        None => (0..offsets.len(), None),
+    };
+
+    let mut highlights = Vec::new();
+    let mut last_start = range.as_ref().map(|r| r.start).unwrap_or(0);
+    for i in changed {
+        let (start, end) = offsets[i];
         if start < last_start {
             continue;
         }
+        if let Some(range) = range.as_ref() {
+            if end > range.end {
+                continue;
+            }
+        }
         last_start = start;
+        let kind = semantic_lengends.token_types
+            [semantic_tokens.data[i].token_type as usize]
+            .as_str()
+            .to_string();
         highlights.push(LineStyle {
             start,
             end,
@@ -1075,7 +1529,7 @@ fn format_semantic_styles(
         });
     }
 
-    Some(highlights)
+    Some((highlights, range))
 }
 
 fn semantic_tokens_lengend(
@@ -1090,3 +1544,77 @@ fn semantic_tokens_lengend(
         ) => options.semantic_tokens_options.legend.clone(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(delta_line: u32, delta_start: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length: 1,
+            token_type: 0,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    fn edit(
+        start: u32,
+        delete_count: u32,
+        data: Option<Vec<SemanticToken>>,
+    ) -> SemanticTokensEdit {
+        SemanticTokensEdit {
+            start,
+            delete_count,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_apply_semantic_tokens_edits() {
+        let tokens = vec![token(0, 0), token(1, 2), token(0, 4)];
+
+        // no edits leave the tokens as they are, with nothing changed
+        assert_eq!(
+            apply_semantic_tokens_edits(&tokens, vec![]),
+            Some((tokens.clone(), 3..3))
+        );
+
+        // replace the second token
+        assert_eq!(
+            apply_semantic_tokens_edits(
+                &tokens,
+                vec![edit(5, 5, Some(vec![token(2, 3)]))]
+            ),
+            Some((vec![token(0, 0), token(2, 3), token(0, 4)], 1..2))
+        );
+
+        // insert at the start and delete the last token, the edits refer to
+        // the old tokens whatever order they come in
+        assert_eq!(
+            apply_semantic_tokens_edits(
+                &tokens,
+                vec![edit(10, 5, None), edit(0, 0, Some(vec![token(0, 1)]))]
+            ),
+            Some((vec![token(0, 1), token(0, 0), token(1, 2)], 0..3))
+        );
+
+        // edits that don't fall on token boundaries or past the end don't fit
+        assert_eq!(
+            apply_semantic_tokens_edits(&tokens, vec![edit(3, 5, None)]),
+            None
+        );
+        assert_eq!(
+            apply_semantic_tokens_edits(&tokens, vec![edit(10, 10, None)]),
+            None
+        );
+        assert_eq!(
+            apply_semantic_tokens_edits(
+                &tokens,
+                vec![edit(0, 10, None), edit(5, 5, None)]
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// The settings of a workspace, read from `.lapce/settings.toml` in it
+///
+/// ```toml
+/// [lsp.rust]
+/// command = "rust-analyzer"
+/// env = { RA_LOG = "error" }
+///
+/// [lsp.rust.initialization_options]
+/// checkOnSave = { command = "clippy" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct WorkspaceSettings {
+    /// The language servers to use instead of the ones started by plugins,
+    /// by language id
+    #[serde(default)]
+    pub lsp: HashMap<String, LspServerSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LspServerSettings {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub initialization_options: Option<Value>,
+}
+
+impl WorkspaceSettings {
+    pub fn path(workspace: &Path) -> PathBuf {
+        workspace.join(".lapce").join("settings.toml")
+    }
+
+    /// Read the settings of the workspace, without the language servers
+    /// that can't be started. A workspace without a settings file has the
+    /// default settings.
+    pub fn load(workspace: &Path) -> (WorkspaceSettings, Vec<String>) {
+        let path = Self::path(workspace);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) => return (WorkspaceSettings::default(), Vec::new()),
+        };
+        let mut settings: WorkspaceSettings = match toml::from_str(&content) {
+            Ok(settings) => settings,
+            Err(e) => return (WorkspaceSettings::default(), vec![e.to_string()]),
+        };
+
+        let mut errors = Vec::new();
+        settings.lsp.retain(|language_id, server| {
+            match server.resolve_command(workspace) {
+                Ok(command) => {
+                    server.command = command;
+                    true
+                }
+                Err(e) => {
+                    errors.push(format!("lsp.{language_id}: {e}"));
+                    false
+                }
+            }
+        });
+        (settings, errors)
+    }
+}
+
+impl LspServerSettings {
+    /// The path of the server executable, a relative path is in the
+    /// workspace
+    fn resolve_command(&self, workspace: &Path) -> Result<String> {
+        if self.command.is_empty() {
+            return Err(anyhow!("the command is empty"));
+        }
+        let path =
+            which::which_in(&self.command, std::env::var_os("PATH"), workspace)
+                .map_err(|_| anyhow!("command \"{}\" not found", self.command))?;
+        path.to_str()
+            .map(|p| p.to_string())
+            .ok_or_else(|| anyhow!("path can't to string"))
+    }
+}
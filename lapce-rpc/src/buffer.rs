@@ -12,9 +12,56 @@ impl BufferId {
     }
 }
 
+/// The line ending style of a file
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    /// The line ending used by the majority of the lines in the text,
+    /// or `None` if the text has no line breaks at all
+    pub fn detect(text: &str) -> Option<Self> {
+        let lf = text.matches('\n').count();
+        if lf == 0 {
+            return None;
+        }
+        let crlf = text.matches("\r\n").count();
+        if crlf * 2 > lf {
+            Some(LineEnding::CrLf)
+        } else {
+            Some(LineEnding::Lf)
+        }
+    }
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        LineEnding::Lf
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewBufferResponse {
+    /// The content of the file with every line ending normalized to `\n`
     pub content: String,
+    /// The line ending the file was written with
+    pub line_ending: Option<LineEnding>,
+    /// The name of the text encoding of the file
+    pub encoding: String,
+    /// The file isn't text, so the content is only a placeholder
+    pub binary: bool,
+    /// The file can't be written to with the current permissions
+    #[serde(default)]
+    pub read_only: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,18 +12,36 @@ use crate::{
 #[serde(tag = "method", content = "params")]
 pub enum CoreNotification {
     ProxyConnected {},
+    /// The thread running a local proxy ended, sent by the core to itself
+    ProxyExited {},
     SemanticStyles {
         rev: u64,
         buffer_id: BufferId,
         path: PathBuf,
         len: usize,
         styles: Vec<LineStyle>,
+        /// The styles only replace the ones of this range when it's set, the
+        /// others haven't changed since the previous styles
+        #[serde(default)]
+        range: Option<std::ops::Range<usize>>,
     },
     ReloadBuffer {
         buffer_id: BufferId,
         new_content: String,
         rev: u64,
     },
+    /// The file of a buffer with unsaved changes was modified by someone
+    /// else, `content` is what the file has now
+    BufferChangedOnDisk {
+        buffer_id: BufferId,
+        content: String,
+    },
+    /// The characters that trigger signature help when typed in a buffer,
+    /// from the capabilities of its language server
+    SignatureTriggerCharacters {
+        buffer_id: BufferId,
+        characters: Vec<String>,
+    },
     PublishDiagnostics {
         diagnostics: PublishDiagnosticsParams,
     },
@@ -33,6 +51,12 @@ pub enum CoreNotification {
     HomeDir {
         path: PathBuf,
     },
+    /// The workspace settings file has errors, the settings with errors
+    /// aren't used
+    SettingsError {
+        path: PathBuf,
+        errors: Vec<String>,
+    },
     InstalledPlugins {
         plugins: HashMap<String, PluginDescription>,
     },
@@ -52,6 +76,13 @@ pub enum CoreNotification {
     CloseTerminal {
         term_id: TermId,
     },
+    /// The matches of a file found by a workspace search, as the line number,
+    /// the start and end of the match in the line, and the line
+    GlobalSearchMatches {
+        search_id: u64,
+        path: PathBuf,
+        matches: Vec<(usize, (usize, usize), String)>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +10,8 @@ mod stdio;
 pub mod style;
 pub mod terminal;
 
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::io::stdin;
 use std::io::stdout;
@@ -17,6 +19,9 @@ use std::io::BufReader;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender};
@@ -84,14 +89,23 @@ pub struct RpcHandler {
     sender: Sender<Value>,
     id: Arc<AtomicU64>,
     pending: Arc<Mutex<HashMap<u64, ResponseHandler>>>,
+    /// The deadlines of the requests sent with a timeout
+    deadlines: Sender<(Instant, u64)>,
 }
 
 impl RpcHandler {
     pub fn new(sender: Sender<Value>) -> Self {
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let (deadlines, deadline_receiver) = crossbeam_channel::unbounded();
+        let timeout_pending = pending.clone();
+        thread::spawn(move || {
+            fail_timed_out_requests(timeout_pending, deadline_receiver);
+        });
         Self {
             sender,
             id: Arc::new(AtomicU64::new(0)),
-            pending: Arc::new(Mutex::new(HashMap::new())),
+            pending,
+            deadlines,
         }
     }
 
@@ -130,6 +144,16 @@ impl RpcHandler {
         }
     }
 
+    /// Fail the requests still waiting for a response, when the other end
+    /// has stopped and will never send it
+    pub fn fail_pending_requests(&self) {
+        let pending: Vec<ResponseHandler> =
+            self.pending.lock().drain().map(|(_, rh)| rh).collect();
+        for rh in pending {
+            rh.invoke(Err(json!("disconnected")));
+        }
+    }
+
     pub fn send_rpc_notification(&self, method: &str, params: &Value) {
         if let Err(_e) = self.sender.send(json!({
             "method": method,
@@ -142,7 +166,7 @@ impl RpcHandler {
         method: &str,
         params: &Value,
         rh: ResponseHandler,
-    ) {
+    ) -> u64 {
         let id = self.id.fetch_add(1, Ordering::Relaxed);
         {
             let mut pending = self.pending.lock();
@@ -158,6 +182,7 @@ impl RpcHandler {
                 rh.invoke(Err(json!("io error")));
             }
         }
+        id
     }
 
     pub fn send_rpc_request(
@@ -175,8 +200,35 @@ impl RpcHandler {
         method: &str,
         params: &Value,
         f: Box<dyn Callback>,
-    ) {
-        self.send_rpc_request_common(method, params, ResponseHandler::Callback(f));
+    ) -> u64 {
+        self.send_rpc_request_common(method, params, ResponseHandler::Callback(f))
+    }
+
+    /// Send a request whose callback is invoked with an error if there's no
+    /// response after `timeout`
+    pub fn send_rpc_request_async_timeout(
+        &self,
+        method: &str,
+        params: &Value,
+        timeout: Duration,
+        f: Box<dyn Callback>,
+    ) -> u64 {
+        let id = self.send_rpc_request_async(method, params, f);
+        let _ = self.deadlines.send((Instant::now() + timeout, id));
+        id
+    }
+
+    /// Stop waiting for the response of a request, its callback is invoked
+    /// with an error. Returns false if the request wasn't pending anymore.
+    pub fn cancel_rpc_request(&self, id: u64) -> bool {
+        let handler = self.pending.lock().remove(&id);
+        match handler {
+            Some(rh) => {
+                rh.invoke(Err(json!("cancelled")));
+                true
+            }
+            None => false,
+        }
     }
 
     fn handle_response(&self, id: u64, resp: Result<Value, Value>) {
@@ -200,3 +252,37 @@ impl RpcHandler {
         let _ = self.sender.send(response);
     }
 }
+
+/// Fail the requests still pending at their deadline, until every
+/// `RpcHandler` sending deadlines is dropped
+fn fail_timed_out_requests(
+    pending: Arc<Mutex<HashMap<u64, ResponseHandler>>>,
+    receiver: Receiver<(Instant, u64)>,
+) {
+    let mut deadlines = BinaryHeap::new();
+    loop {
+        let received = match deadlines.peek() {
+            Some(Reverse((deadline, _))) => receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .map_err(|e| e.is_disconnected()),
+            None => receiver.recv().map_err(|_| true),
+        };
+        match received {
+            Ok(deadline) => deadlines.push(Reverse(deadline)),
+            Err(true) => return,
+            Err(false) => {}
+        }
+
+        let now = Instant::now();
+        while let Some(Reverse((deadline, id))) = deadlines.peek().copied() {
+            if deadline > now {
+                break;
+            }
+            deadlines.pop();
+            let handler = pending.lock().remove(&id);
+            if let Some(rh) = handler {
+                rh.invoke(Err(json!("timeout")));
+            }
+        }
+    }
+}
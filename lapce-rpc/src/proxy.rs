@@ -1,12 +1,15 @@
 use std::path::PathBuf;
 
-use lsp_types::{CompletionItem, Position};
+use lsp_types::{CompletionItem, Position, Range};
 use serde::{Deserialize, Serialize};
 use xi_rope::RopeDelta;
 
 use crate::{
-    buffer::BufferId, plugin::PluginDescription, source_control::FileDiff,
+    buffer::{BufferId, LineEnding},
+    plugin::PluginDescription,
+    source_control::FileDiff,
     terminal::TermId,
+    RequestId,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +25,23 @@ pub enum ProxyNotification {
         delta: RopeDelta,
         rev: u64,
     },
+    /// Discard the changes of the buffer and load it from disk again
+    ReloadBuffer {
+        buffer_id: BufferId,
+    },
+    /// Send all the semantic styles of the buffer again, because the core
+    /// couldn't apply the last ones the next delta would build on
+    RefreshSemanticTokens {
+        buffer_id: BufferId,
+    },
+    /// Open a buffer the core already has, with its content and revision,
+    /// after the proxy was restarted
+    ReopenBuffer {
+        buffer_id: BufferId,
+        path: PathBuf,
+        content: String,
+        rev: u64,
+    },
     NewTerminal {
         term_id: TermId,
         cwd: Option<PathBuf>,
@@ -46,6 +66,12 @@ pub enum ProxyNotification {
     TerminalClose {
         term_id: TermId,
     },
+    /// Stop the running workspace search, if any
+    CancelGlobalSearch {},
+    /// Stop working on a request the core doesn't wait for anymore
+    CancelRequest {
+        id: RequestId,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,13 +86,24 @@ pub enum ProxyRequest {
         buffer_id: BufferId,
         path: PathBuf,
     },
+    /// The git blame of the file as it's on disk, as a list of `BlameHunk`
+    GitBlame {
+        path: PathBuf,
+    },
     GetCompletion {
         request_id: usize,
         buffer_id: BufferId,
         position: Position,
     },
+    /// Search the workspace files for `pattern`, sending the matches of each
+    /// file with a `global_search_matches` notification
     GlobalSearch {
+        search_id: u64,
         pattern: String,
+        #[serde(default)]
+        case_sensitive: bool,
+        #[serde(default)]
+        whole_word: bool,
     },
     CompletionResolve {
         buffer_id: BufferId,
@@ -81,6 +118,10 @@ pub enum ProxyRequest {
         buffer_id: BufferId,
         position: Position,
     },
+    GetInlayHints {
+        buffer_id: BufferId,
+        range: Range,
+    },
     GetReferences {
         buffer_id: BufferId,
         position: Position,
@@ -106,8 +147,23 @@ pub enum ProxyRequest {
     ReadDir {
         path: PathBuf,
     },
+    /// Create an empty file, failing if it already exists
+    CreateFile {
+        path: PathBuf,
+    },
+    /// Rename a file or a directory, failing if `to` already exists
+    RenamePath {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// Delete a file, or a directory with everything in it
+    DeletePath {
+        path: PathBuf,
+    },
     Save {
         rev: u64,
         buffer_id: BufferId,
+        #[serde(default)]
+        line_ending: LineEnding,
     },
 }
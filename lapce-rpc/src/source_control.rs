@@ -27,3 +27,17 @@ impl FileDiff {
         }
     }
 }
+
+/// Lines of a file that were last changed by the same commit
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlameHunk {
+    /// The first line of the hunk, counted from 0
+    pub start_line: usize,
+    pub line_count: usize,
+    /// The short commit id, `None` for lines that aren't committed yet
+    pub commit: Option<String>,
+    pub author: String,
+    /// The commit time in seconds since the unix epoch
+    pub time: i64,
+    pub summary: String,
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{select, Receiver, Sender};
 use serde_json::Value;
 use std::{
     io::{self, BufRead, Write},
@@ -15,13 +15,19 @@ pub fn stdio_transport<W, R>(
     W: 'static + Write + Send,
     R: 'static + BufRead + Send,
 {
+    // dropped when the reader stops, so that the writer stops as well
+    // instead of taking the messages meant for the next transport
+    let (reader_done_sender, reader_done) = crossbeam_channel::bounded::<()>(0);
     thread::spawn(move || -> Result<()> {
-        writer_receiver
-            .into_iter()
-            .try_for_each(|it| write_msg(&mut writer, &it))?;
-        Ok(())
+        loop {
+            select! {
+                recv(writer_receiver) -> msg => write_msg(&mut writer, &msg?)?,
+                recv(reader_done) -> _ => return Ok(()),
+            }
+        }
     });
     thread::spawn(move || -> Result<()> {
+        let _reader_done_sender = reader_done_sender;
         loop {
             let msg = read_msg(&mut reader)?;
             reader_sender.send(msg)?;
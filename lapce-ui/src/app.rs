@@ -3,9 +3,14 @@ use druid::{
     Widget, WidgetExt, WindowDesc, WindowId,
 };
 use lapce_data::{
+    buffer::BufferContent,
+    cli::parse_path_args,
     command::{LapceUICommand, LAPCE_UI_COMMAND},
     config::Config,
-    data::{LapceData, LapceWindowData, LapceWindowLens},
+    data::{
+        confirm_unsaved_files, LapceData, LapceWindowData, LapceWindowLens,
+        UnsavedChoice,
+    },
     db::{TabsInfo, WindowInfo},
 };
 
@@ -36,7 +41,11 @@ pub fn launch() {
     let _ = log_dispatch.apply();
 
     let mut launcher = AppLauncher::new().delegate(LapceAppDelegate::new());
-    let data = LapceData::load(launcher.get_external_handle());
+    let mut data = LapceData::load(launcher.get_external_handle());
+    if let Ok(cwd) = std::env::current_dir() {
+        let paths = parse_path_args(std::env::args().skip(1), &cwd);
+        data.open_paths(&paths, launcher.get_external_handle());
+    }
     for (_window_id, window_data) in data.windows.iter() {
         let root = build_window(window_data);
         let window = WindowDesc::new_with_id(window_data.window_id, root)
@@ -69,7 +78,7 @@ impl Default for LapceAppDelegate {
 impl AppDelegate<LapceData> for LapceAppDelegate {
     fn event(
         &mut self,
-        _ctx: &mut druid::DelegateCtx,
+        ctx: &mut druid::DelegateCtx,
         window_id: WindowId,
         event: druid::Event,
         data: &mut LapceData,
@@ -77,6 +86,13 @@ impl AppDelegate<LapceData> for LapceAppDelegate {
     ) -> Option<Event> {
         match event {
             Event::WindowCloseRequested => {
+                if let Some(window) = data.windows.get(&window_id) {
+                    // Pass the request on to the window, which handles it so
+                    // that the window stays open
+                    if !confirm_close_windows(ctx, &[window]) {
+                        return Some(event);
+                    }
+                }
                 if let Some(window) = data.windows.remove(&window_id) {
                     for (_, tab) in window.tabs.iter() {
                         let _ = data.db.save_workspace(tab);
@@ -86,6 +102,14 @@ impl AppDelegate<LapceData> for LapceAppDelegate {
                 return None;
             }
             Event::ApplicationQuit => {
+                // Passed on like a cancelled window close, so that the
+                // windows handle it and the app keeps running
+                if !confirm_close_windows(
+                    ctx,
+                    &data.windows.values().collect::<Vec<_>>(),
+                ) {
+                    return Some(event);
+                }
                 let _ = data.db.save_app(data);
                 return None;
             }
@@ -158,3 +182,33 @@ impl AppDelegate<LapceData> for LapceAppDelegate {
     ) {
     }
 }
+
+/// Ask what to do with the unsaved files of the windows before they are
+/// closed and save them if asked to, returns false if the close should be
+/// cancelled, which it also is when saving them fails
+fn confirm_close_windows(
+    ctx: &mut druid::DelegateCtx,
+    windows: &[&LapceWindowData],
+) -> bool {
+    let unsaved: Vec<BufferContent> = windows
+        .iter()
+        .flat_map(|window| window.tabs.values())
+        .flat_map(|tab| tab.main_split.unsaved_buffers())
+        .collect();
+    match confirm_unsaved_files(&unsaved, None) {
+        // Every tab is saved even when one of them fails, so that as much
+        // as possible is on disk
+        UnsavedChoice::SaveAll => windows
+            .iter()
+            .flat_map(|window| window.tabs.values())
+            .map(|tab| {
+                tab.main_split.save_buffers(
+                    &tab.main_split.unsaved_buffers(),
+                    ctx.get_external_handle(),
+                )
+            })
+            .fold(true, |saved, tab_saved| saved && tab_saved),
+        UnsavedChoice::Discard => true,
+        UnsavedChoice::Cancel => false,
+    }
+}
@@ -388,7 +388,11 @@ impl Widget<LapceTabData> for CompletionContainer {
             ));
         }
 
-        if old_completion.index != completion.index {
+        if old_completion.index != completion.index
+            || !old_completion
+                .filtered_items
+                .same(&completion.filtered_items)
+        {
             self.ensure_item_visble(ctx, data, env);
             ctx.request_paint();
         }
@@ -3,7 +3,7 @@ use std::{iter::Iterator, sync::Arc, time::Instant};
 
 use druid::TimerToken;
 use druid::{
-    kurbo::{BezPath, Line},
+    kurbo::{Affine, BezPath, Line},
     piet::{PietText, PietTextLayout, Text, TextLayout as _, TextLayoutBuilder},
     BoxConstraints, Color, Command, Env, Event, EventCtx, FontFamily,
     InternalLifeCycle, LayoutCtx, LifeCycle, LifeCycleCtx, MouseButton, MouseEvent,
@@ -11,18 +11,23 @@ use druid::{
     Widget, WidgetId,
 };
 use lapce_data::{
-    buffer::{matching_pair_direction, BufferContent, DiffLines, LocalBufferKind},
+    buffer::{
+        blame_text, matching_pair_direction, str_col, BufferContent, DiffLines,
+        LocalBufferKind,
+    },
     command::{
         CommandTarget, LapceCommand, LapceCommandNew, LapceUICommand,
         LapceWorkbenchCommand, LAPCE_UI_COMMAND,
     },
-    config::{Config, LapceTheme},
-    data::{LapceTabData, PanelData, PanelKind},
-    editor::{EditorLocation, LapceEditorBufferData, Syntax},
+    config::{Config, CursorShape, LapceTheme},
+    data::{DiffSide, EditorDiagnostic, LapceTabData, PanelData, PanelKind},
+    editor::{DragSelect, EditorLocation, LapceEditorBufferData, Syntax},
+    inlay_hint::InlayHint,
     menu::MenuItem,
     movement::{ColPosition, CursorMode, Movement, Selection},
     panel::PanelPosition,
     state::{Mode, VisualMode},
+    wrap::WrapLayout,
 };
 use lapce_rpc::buffer::BufferId;
 use lsp_types::{DiagnosticSeverity, DocumentChanges, TextEdit, Url, WorkspaceEdit};
@@ -90,6 +95,14 @@ pub struct EditorState {
 //     DiffSplit(LapceSplitNew),
 // }
 
+/// How often the editor scrolls while a selection is dragged past its top or
+/// bottom
+const AUTOSCROLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// The scroll speed (in px per second) for each px the pointer is past the
+/// top or bottom of the editor
+const AUTOSCROLL_ACCELERATION: f64 = 10.0;
+
 #[derive(Clone, Copy)]
 enum ClickKind {
     Single,
@@ -110,6 +123,15 @@ pub struct LapceEditor {
     /// A timer for listening for when the user has hovered for long enough to trigger showing
     /// of hover info (if there is any)
     mouse_hover_timer: TimerToken,
+    /// A timer to repaint the caret when it blinks, restarted whenever the
+    /// caret moves
+    cursor_blink_timer: TimerToken,
+    /// The last mouse move of a selection drag, with its position relative to
+    /// the viewport, to keep extending the selection while auto-scrolling
+    drag_event: Option<MouseEvent>,
+    /// A timer to scroll while a selection is dragged past the top or the
+    /// bottom of the editor
+    autoscroll_timer: TimerToken,
 }
 
 impl LapceEditor {
@@ -121,7 +143,65 @@ impl LapceEditor {
             last_left_click: None,
             mouse_pos: Point::ZERO,
             mouse_hover_timer: TimerToken::INVALID,
+            cursor_blink_timer: TimerToken::INVALID,
+            drag_event: None,
+            autoscroll_timer: TimerToken::INVALID,
+        }
+    }
+
+    /// Extend the selection being dragged to the mouse, by characters, words
+    /// or lines depending on the click that started the drag.
+    fn drag_select(
+        &self,
+        ctx: &mut EventCtx,
+        mouse_event: &MouseEvent,
+        data: &mut LapceTabData,
+    ) {
+        let buffer = match data.main_split.editor_buffer(self.view_id) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let editor = data.main_split.editors.get(&self.view_id).unwrap().clone();
+        let (kind, anchor) = match self.last_left_click.as_ref() {
+            Some((_, ClickKind::Double, pos)) => (DragSelect::Word, *pos),
+            Some((_, ClickKind::Triple | ClickKind::Quadruple, pos)) => {
+                (DragSelect::Line, *pos)
+            }
+            _ => (DragSelect::Char, mouse_event.pos),
+        };
+        let mut editor_data = data.editor_view_content(self.view_id);
+        editor_data.drag_select(ctx, anchor, mouse_event, kind, &data.config);
+        data.update_from_editor_buffer_data(editor_data, &editor, &buffer);
+    }
+
+    /// Scroll towards the pointer when a selection is dragged past the top or
+    /// the bottom of the editor, extending the selection as the text moves
+    /// under the pointer, and keep going until the pointer is back inside.
+    fn autoscroll(&mut self, ctx: &mut EventCtx, data: &mut LapceTabData) {
+        let mut mouse_event = match self.drag_event.clone() {
+            Some(mouse_event) => mouse_event,
+            None => return,
+        };
+        let editor = data.main_split.editors.get(&self.view_id).unwrap();
+        let speed = autoscroll_speed(
+            mouse_event.pos.y,
+            editor.size.borrow().height,
+            &data.config,
+        );
+        if speed == 0.0 {
+            return;
         }
+        let scroll_offset = editor.scroll_offset;
+        let y =
+            (scroll_offset.y + speed * AUTOSCROLL_INTERVAL.as_secs_f64()).max(0.0);
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::ScrollTo((scroll_offset.x, y)),
+            Target::Widget(self.view_id),
+        ));
+        mouse_event.pos += Vec2::new(scroll_offset.x, y);
+        self.drag_select(ctx, &mouse_event, data);
+        self.autoscroll_timer = ctx.request_timer(AUTOSCROLL_INTERVAL);
     }
 
     fn mouse_down(
@@ -145,7 +225,11 @@ impl LapceEditor {
                 editor_data.cancel_completion();
                 editor_data.cancel_hover();
             }
-            MouseButton::Middle => {}
+            MouseButton::Middle => {
+                editor_data.middle_click(ctx, mouse_event, config);
+                editor_data.cancel_completion();
+                editor_data.cancel_hover();
+            }
             _ => (),
         }
     }
@@ -190,6 +274,7 @@ impl LapceEditor {
         mouse_event: &MouseEvent,
         config: &Config,
     ) {
+        self.last_left_click = None;
         editor_data.single_click(ctx, mouse_event, config);
         let menu_items = vec![
             MenuItem {
@@ -230,9 +315,11 @@ impl LapceEditor {
         env: &Env,
     ) -> Size {
         let line_height = data.config.editor.line_height as f64;
-        let width = data.config.editor_text_width(text, "W");
+        let width = data
+            .config
+            .char_width(text, data.config.editor.font_size as f64);
         match &data.editor.content {
-            BufferContent::File(_) => {
+            BufferContent::File(_) | BufferContent::Scratch(..) => {
                 if data.editor.code_lens {
                     if let Some(syntax) = data.buffer.syntax.as_ref() {
                         let height =
@@ -240,8 +327,9 @@ impl LapceEditor {
                         Size::new(
                             (width * data.buffer.max_len as f64)
                                 .max(editor_size.width),
-                            (height as f64 - line_height).max(0.0)
-                                + editor_size.height,
+                            data.config
+                                .editor
+                                .scroll_height(height as f64, editor_size.height),
                         )
                     } else {
                         let height = data.buffer.num_lines
@@ -249,8 +337,9 @@ impl LapceEditor {
                         Size::new(
                             (width * data.buffer.max_len as f64)
                                 .max(editor_size.width),
-                            (height as f64 - line_height).max(0.0)
-                                + editor_size.height,
+                            data.config
+                                .editor
+                                .scroll_height(height as f64, editor_size.height),
                         )
                     }
                 } else if let Some(compare) = data.editor.compare.as_ref() {
@@ -267,15 +356,31 @@ impl LapceEditor {
                     }
                     Size::new(
                         (width * data.buffer.max_len as f64).max(editor_size.width),
-                        (line_height * lines as f64 - line_height).max(0.0)
-                            + editor_size.height,
+                        data.config.editor.scroll_height(
+                            line_height * lines as f64,
+                            editor_size.height,
+                        ),
+                    )
+                } else if let Some(wrap) = data.wrap_layout(text) {
+                    let width = if wrap.wraps() {
+                        editor_size.width
+                    } else {
+                        (width * data.buffer.max_len as f64).max(editor_size.width)
+                    };
+                    Size::new(
+                        width,
+                        data.config.editor.scroll_height(
+                            line_height * wrap.num_visual_lines() as f64,
+                            editor_size.height,
+                        ),
                     )
                 } else {
                     Size::new(
                         (width * data.buffer.max_len as f64).max(editor_size.width),
-                        (line_height * data.buffer.num_lines as f64 - line_height)
-                            .max(0.0)
-                            + editor_size.height,
+                        data.config.editor.scroll_height(
+                            line_height * data.buffer.num_lines as f64,
+                            editor_size.height,
+                        ),
                     )
                 }
             }
@@ -326,6 +431,29 @@ impl LapceEditor {
         }
     }
 
+    /// Paint the rows a side of a diff view leaves empty for the lines that
+    /// only the other side has
+    fn paint_diff_filler(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        start: usize,
+        len: usize,
+        color: &str,
+    ) {
+        let line_height = data.config.editor.line_height as f64;
+        let rect = Size::new(ctx.size().width, line_height * len as f64)
+            .to_rect()
+            .with_origin(Point::new(0.0, line_height * start as f64));
+        ctx.fill(
+            rect,
+            &data
+                .config
+                .get_color_unchecked(color)
+                .clone()
+                .with_alpha(0.3),
+        );
+    }
+
     pub fn paint_code_lens_content(
         data: &LapceEditorBufferData,
         ctx: &mut PaintCtx,
@@ -399,6 +527,7 @@ impl LapceEditor {
                     is_focused,
                     cursor_line,
                     line,
+                    data.config.editor.highlight_current_line,
                     x,
                     y,
                     if is_small {
@@ -412,6 +541,7 @@ impl LapceEditor {
                     ctx,
                     line,
                     &line_content,
+                    &[],
                     None,
                     if is_small {
                         data.config.editor.code_lens_font_size
@@ -455,16 +585,49 @@ impl LapceEditor {
         let char_width = text_layout.size().width;
         let y_shift = (line_height - text_layout.size().height) / 2.0;
 
+        if !data.editor.content.is_input() {
+            Self::paint_rulers(data, ctx, char_width);
+        }
+
+        if let Some(wrap) = data.wrap_layout(ctx.text()) {
+            Self::paint_wrapped_content(
+                data, ctx, is_focused, &wrap, font_size, char_width, y_shift, env,
+            );
+            return;
+        }
+
         if data.editor.content.is_input()
             || (data.editor.compare.is_none() && !data.editor.code_lens)
         {
             Self::paint_cursor(data, ctx, is_focused, placeholder, char_width, env);
-            Self::paint_find(data, ctx, char_width, env);
+            Self::paint_find(
+                data,
+                ctx,
+                Self::visible_lines(data, line_height),
+                char_width,
+                env,
+            );
+        }
+        if !data.editor.content.is_input()
+            && data.editor.compare.is_none()
+            && !data.editor.code_lens
+            && is_focused
+        {
+            Self::paint_bracket_pair(data, ctx, char_width, env);
         }
         let self_size = ctx.size();
         let rect = ctx.region().bounding_box();
         let start_line = (rect.y0 / line_height).floor() as usize;
         let end_line = (rect.y1 / line_height).ceil() as usize;
+        data.buffer.update_highlights(start_line, end_line);
+        if data.show_inlay_hints() {
+            data.buffer.update_inlay_hints(
+                &data.proxy,
+                start_line,
+                end_line,
+                data.config.editor.tab_width,
+            );
+        }
 
         if !data.editor.content.is_input() && data.editor.code_lens {
             Self::paint_code_lens_content(data, ctx, is_focused);
@@ -472,9 +635,34 @@ impl LapceEditor {
             if let Some(changes) = data.buffer.history_changes.get(compare) {
                 let cursor_line =
                     data.buffer.line_of_offset(data.editor.cursor.offset());
+                // The two sides of a diff view keep the lines of the other
+                // side empty, so that their lines stay aligned
+                let side = data.editor.diff_view.as_ref().map(|d| d.side);
                 let mut line = 0;
                 for change in changes.iter() {
                     match change {
+                        DiffLines::Left(range) if side == Some(DiffSide::Right) => {
+                            let len = range.len();
+                            line += len;
+                            Self::paint_diff_filler(
+                                data,
+                                ctx,
+                                line - len,
+                                len,
+                                LapceTheme::SOURCE_CONTROL_REMOVED,
+                            );
+                        }
+                        DiffLines::Right(range) if side == Some(DiffSide::Left) => {
+                            let len = range.len();
+                            line += len;
+                            Self::paint_diff_filler(
+                                data,
+                                ctx,
+                                line - len,
+                                len,
+                                LapceTheme::SOURCE_CONTROL_ADDED,
+                            );
+                        }
                         DiffLines::Left(range) => {
                             let len = range.len();
                             line += len;
@@ -587,6 +775,7 @@ impl LapceEditor {
                                     is_focused,
                                     cursor_line,
                                     rope_line,
+                                    data.config.editor.highlight_current_line,
                                     0.0,
                                     l as f64 * line_height,
                                     char_width,
@@ -596,6 +785,7 @@ impl LapceEditor {
                                     ctx,
                                     rope_line,
                                     &data.buffer.line_content(rope_line),
+                                    &[],
                                     None,
                                     font_size,
                                     [rect.x0, rect.x1],
@@ -649,6 +839,7 @@ impl LapceEditor {
                                     is_focused,
                                     cursor_line,
                                     rope_line,
+                                    data.config.editor.highlight_current_line,
                                     0.0,
                                     l as f64 * line_height,
                                     char_width,
@@ -658,6 +849,7 @@ impl LapceEditor {
                                     ctx,
                                     rope_line,
                                     &data.buffer.line_content(rope_line),
+                                    &[],
                                     None,
                                     font_size,
                                     [rect.x0, rect.x1],
@@ -706,15 +898,40 @@ impl LapceEditor {
                     } else {
                         None
                     };
+                let inlay_hints = if data.show_inlay_hints() {
+                    data.buffer.inlay_hints_on_line(line)
+                } else {
+                    &[]
+                };
                 let text_layout = data.buffer.new_text_layout(
                     ctx,
                     line,
                     line_content,
+                    inlay_hints,
                     cursor_index,
                     font_size,
                     [rect.x0, rect.x1],
                     &data.config,
                 );
+                Self::paint_inlay_hints_background(
+                    data,
+                    ctx,
+                    inlay_hints,
+                    char_width,
+                    line_height * line as f64 + y_shift,
+                    line_height,
+                );
+                if !data.editor.content.is_input() {
+                    Self::paint_whitespace(
+                        data,
+                        ctx,
+                        line,
+                        line_content,
+                        font_size,
+                        char_width,
+                        line_height * line as f64 + y_shift,
+                    );
+                }
                 ctx.draw_text(
                     &text_layout,
                     Point::new(
@@ -722,10 +939,20 @@ impl LapceEditor {
                         line_height * line as f64 + y_shift + line_padding,
                     ),
                 );
+                if is_focused && line == cursor_line {
+                    Self::paint_line_blame(
+                        data,
+                        ctx,
+                        line,
+                        font_size,
+                        char_width,
+                        line_height * line as f64 + y_shift + line_padding,
+                    );
+                }
             }
         }
 
-        Self::paint_snippet(data, ctx);
+        Self::paint_snippet(data, ctx, Self::visible_lines(data, line_height));
         Self::paint_diagnostics(data, ctx);
         if data.buffer.len() == 0 {
             if let Some(placeholder) = placeholder {
@@ -745,6 +972,356 @@ impl LapceEditor {
         }
     }
 
+    /// Paint a soft wrapped buffer. Every visual line draws its whole logical
+    /// line shifted left to the column the visual line starts at, clipped to
+    /// the part of the line that belongs to it.
+    #[allow(clippy::too_many_arguments)]
+    fn paint_wrapped_content(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        is_focused: bool,
+        wrap: &WrapLayout,
+        font_size: usize,
+        char_width: f64,
+        y_shift: f64,
+        env: &Env,
+    ) {
+        let line_height = Self::line_height(data, env);
+        let width = ctx.size().width;
+        let rect = ctx.region().bounding_box();
+        let start_visual_line = (rect.y0 / line_height).floor() as usize;
+        let end_visual_line =
+            ((rect.y1 / line_height).ceil() as usize).min(wrap.num_visual_lines());
+        if start_visual_line >= end_visual_line {
+            return;
+        }
+        let (start_line, _, _) = wrap.line_of_visual_line(start_visual_line);
+        let (end_line, _, _) = wrap.line_of_visual_line(end_visual_line - 1);
+        data.buffer.update_highlights(start_line, end_line);
+
+        let cursor_offset = data.editor.cursor.offset();
+        let cursor_line = data.buffer.line_of_offset(cursor_offset);
+        let mode = data.editor.cursor.get_mode();
+        let mut current_diagnostic = None;
+        for line in start_line..end_line + 1 {
+            if wrap.is_hidden(line) {
+                continue;
+            }
+            let breaks = wrap.line_breaks(line);
+            let cursor_index =
+                if is_focused && mode != Mode::Insert && line == cursor_line {
+                    let cursor_line_start = data
+                        .buffer
+                        .offset_of_line(cursor_line)
+                        .min(data.buffer.len());
+                    let index = data
+                        .buffer
+                        .slice_to_cow(cursor_line_start..cursor_offset)
+                        .len();
+                    Some(index)
+                } else {
+                    None
+                };
+            let last_start_col = breaks.last().copied().unwrap_or(0);
+            let line_content = data.buffer.line_content(line);
+            let text_layout = data.buffer.new_text_layout(
+                ctx,
+                line,
+                &line_content,
+                &[],
+                cursor_index,
+                font_size,
+                [rect.x0, rect.x1 + last_start_col as f64 * char_width],
+                &data.config,
+            );
+
+            let highlight = data.config.editor.highlight_current_line
+                && Self::is_current_line(data, cursor_line, line);
+            let first_visual_line = wrap.visual_line(line);
+            for (i, start_col) in
+                std::iter::once(0).chain(breaks.iter().copied()).enumerate()
+            {
+                let visual_line = first_visual_line + i;
+                if visual_line < start_visual_line {
+                    continue;
+                }
+                if visual_line >= end_visual_line {
+                    break;
+                }
+                let x_shift = -(start_col as f64) * char_width;
+                let y = visual_line as f64 * line_height;
+                let line_y = line as f64 * line_height;
+                // Only the last part of a line reaches the right edge, the
+                // others stop where the next part of the line starts
+                let clip_width = match breaks.get(i) {
+                    Some(end_col) => (end_col - start_col) as f64 * char_width,
+                    None => width,
+                };
+
+                if highlight {
+                    ctx.fill(
+                        Rect::new(0.0, y, width, y + line_height),
+                        data.config
+                            .get_color_unchecked(LapceTheme::EDITOR_CURRENT_LINE),
+                    );
+                }
+                ctx.with_save(|ctx| {
+                    ctx.clip(Rect::new(0.0, y, clip_width, y + line_height));
+                    Self::paint_cursor_on_line(
+                        data,
+                        ctx,
+                        is_focused,
+                        cursor_line,
+                        line,
+                        false,
+                        x_shift,
+                        y,
+                        char_width,
+                        line_height,
+                    );
+
+                    // Everything else is painted where it would be without
+                    // wrapping, and moved to the visual line
+                    ctx.transform(Affine::translate((x_shift, y - line_y)));
+                    Self::paint_find(data, ctx, (line, line), char_width, env);
+                    if is_focused {
+                        Self::paint_bracket_pair(data, ctx, char_width, env);
+                    }
+                    Self::paint_whitespace(
+                        data,
+                        ctx,
+                        line,
+                        &line_content,
+                        font_size,
+                        char_width,
+                        line_y + y_shift,
+                    );
+                    ctx.draw_text(&text_layout, Point::new(0.0, line_y + y_shift));
+                    if wrap.is_folded(line) && i == breaks.len() {
+                        Self::paint_fold_marker(
+                            data, ctx, line, font_size, char_width, line_y, y_shift,
+                        );
+                    }
+                    if is_focused && line == cursor_line && i == breaks.len() {
+                        Self::paint_line_blame(
+                            data,
+                            ctx,
+                            line,
+                            font_size,
+                            char_width,
+                            line_y + y_shift,
+                        );
+                    }
+                    Self::paint_snippet(data, ctx, (line, line));
+                    if let Some(diagnostic) =
+                        Self::paint_diagnostic_lines(data, ctx, (line, line))
+                    {
+                        current_diagnostic = Some(diagnostic);
+                    }
+                });
+            }
+        }
+
+        if let Some(diagnostic) = current_diagnostic {
+            let line = diagnostic.diagnositc.range.start.line as usize;
+            let y = wrap.visual_line(line + 1) as f64 * line_height;
+            Self::paint_diagnostic_message(data, ctx, diagnostic, y);
+        }
+    }
+
+    /// Paint the vertical rulers at the columns of the `rulers` setting
+    fn paint_rulers(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        char_width: f64,
+    ) {
+        if data.config.editor.rulers.is_empty() {
+            return;
+        }
+        let rect = ctx.region().bounding_box();
+        let color = data.config.get_color_unchecked(LapceTheme::EDITOR_RULER);
+        for col in data.config.editor.rulers.iter() {
+            // Half a pixel in, so that the line is sharp
+            let x = (*col as f64 * char_width).round() + 0.5;
+            if x < rect.x0 || x > rect.x1 {
+                continue;
+            }
+            ctx.stroke(Line::new((x, rect.y0), (x, rect.y1)), color, 1.0);
+        }
+    }
+
+    /// Paint a middot on the spaces and an arrow on the tabs of a line that
+    /// the `render-whitespace` setting makes visible, behind the text
+    fn paint_whitespace(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        line: usize,
+        line_content: &str,
+        font_size: usize,
+        char_width: f64,
+        y: f64,
+    ) {
+        let whitespace = data.config.editor.visible_whitespace(line_content);
+        if whitespace.is_empty() {
+            return;
+        }
+        let color = data
+            .config
+            .get_color_unchecked(LapceTheme::EDITOR_VISIBLE_WHITESPACE)
+            .clone();
+        let mut glyph = |text: &str| {
+            ctx.text()
+                .new_text_layout(text.to_string())
+                .font(data.config.editor.font_family(), font_size as f64)
+                .text_color(color.clone())
+                .build()
+                .unwrap()
+        };
+        let space = glyph("·");
+        let tab = glyph("→");
+        for (col, is_tab) in whitespace {
+            ctx.draw_text(
+                if is_tab { &tab } else { &space },
+                Point::new(data.visual_col(line, col) as f64 * char_width, y),
+            );
+        }
+    }
+
+    /// Paint the background of the inlay hints drawn on a line
+    fn paint_inlay_hints_background(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        inlay_hints: &[InlayHint],
+        char_width: f64,
+        y: f64,
+        line_height: f64,
+    ) {
+        let tab_width = data.config.editor.tab_width;
+        let color = data
+            .config
+            .get_color_unchecked(LapceTheme::INLAY_HINT_BACKGROUND)
+            .clone();
+        let mut shift = 0;
+        for hint in inlay_hints {
+            let (_, col) = data.buffer.offset_to_line_col(hint.offset, tab_width);
+            let width = str_col(&hint.label, tab_width);
+            let x0 = (col + shift) as f64 * char_width;
+            ctx.fill(
+                Rect::new(x0, y, x0 + width as f64 * char_width, y + line_height),
+                &color,
+            );
+            shift += width;
+        }
+    }
+
+    /// Paint the marker after the end of a line whose block is folded
+    #[allow(clippy::too_many_arguments)]
+    fn paint_fold_marker(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        line: usize,
+        font_size: usize,
+        char_width: f64,
+        line_y: f64,
+        y_shift: f64,
+    ) {
+        let end_col =
+            data.buffer
+                .line_end_col(line, true, data.config.editor.tab_width);
+        let text_layout = ctx
+            .text()
+            .new_text_layout("⋯")
+            .font(data.config.editor.font_family(), font_size as f64)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(
+            &text_layout,
+            Point::new((end_col + 1) as f64 * char_width, line_y + y_shift),
+        );
+    }
+
+    /// Paint who last changed the line after its end, if the blame is
+    /// turned on and known for the current content of the buffer
+    fn paint_line_blame(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        line: usize,
+        font_size: usize,
+        char_width: f64,
+        y: f64,
+    ) {
+        if !data.config.editor.show_blame {
+            return;
+        }
+        let hunk = match data.buffer.blame_of_line(line) {
+            Some(hunk) => hunk,
+            None => return,
+        };
+        let tab_width = data.config.editor.tab_width;
+        let mut end_col = data.buffer.line_end_col(line, true, tab_width);
+        if data.show_inlay_hints() {
+            end_col += data
+                .buffer
+                .inlay_hints_on_line(line)
+                .iter()
+                .map(|hint| str_col(&hint.label, tab_width))
+                .sum::<usize>();
+        }
+        let text_layout = ctx
+            .text()
+            .new_text_layout(blame_text(hunk))
+            .font(data.config.editor.font_family(), font_size as f64)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(
+            &text_layout,
+            Point::new((end_col + 4) as f64 * char_width, y),
+        );
+    }
+
+    /// Whether `line` has a cursor on it, and so gets the current line
+    /// highlight
+    fn is_current_line(
+        data: &LapceEditorBufferData,
+        cursor_line: usize,
+        line: usize,
+    ) -> bool {
+        match &data.editor.cursor.mode {
+            CursorMode::Insert(selection) => {
+                selection.regions().iter().any(|region| {
+                    region.is_caret()
+                        && data.buffer.line_of_offset(region.end()) == line
+                })
+            }
+            _ => cursor_line == line,
+        }
+    }
+
+    /// The first and last line in the viewport of an editor that isn't
+    /// soft wrapped
+    fn visible_lines(
+        data: &LapceEditorBufferData,
+        line_height: f64,
+    ) -> (usize, usize) {
+        let start_line =
+            (data.editor.scroll_offset.y / line_height).floor() as usize;
+        let end_line = ((data.editor.size.borrow().height
+            + data.editor.scroll_offset.y)
+            / line_height)
+            .ceil() as usize;
+        (start_line, end_line)
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn paint_cursor_on_line(
         data: &LapceEditorBufferData,
@@ -752,14 +1329,28 @@ impl LapceEditor {
         is_focused: bool,
         cursor_line: usize,
         actual_line: usize,
+        paint_current_line: bool,
         x_shift: f64,
         y: f64,
         char_width: f64,
         line_height: f64,
     ) {
+        let highlight_current_line =
+            paint_current_line && cursor_line == actual_line;
         match &data.editor.cursor.mode {
             CursorMode::Normal(_) => {}
             CursorMode::Visual { start, end, mode } => {
+                // Painted first so that the selection stays visible on top
+                if highlight_current_line {
+                    let size = ctx.size();
+                    ctx.fill(
+                        Rect::ZERO
+                            .with_origin(Point::new(0.0, y))
+                            .with_size(Size::new(size.width, line_height)),
+                        data.config
+                            .get_color_unchecked(LapceTheme::EDITOR_CURRENT_LINE),
+                    );
+                }
                 let (start_line, start_col) = data.buffer.offset_to_line_col(
                     *start.min(end),
                     data.config.editor.tab_width,
@@ -833,8 +1424,11 @@ impl LapceEditor {
                     }
                 };
 
-                let x0 = left_col as f64 * char_width + x_shift;
-                let x1 = right_col as f64 * char_width + x_shift;
+                let x0 = data.visual_col(actual_line, left_col) as f64 * char_width
+                    + x_shift;
+                let x1 = data.caret_visual_col(actual_line, right_col) as f64
+                    * char_width
+                    + x_shift;
                 let y0 = y;
                 let y1 = y0 + line_height;
                 ctx.fill(
@@ -851,7 +1445,7 @@ impl LapceEditor {
                     if region.is_caret() {
                         let caret_actual_line =
                             data.buffer.line_of_offset(region.end());
-                        if caret_actual_line == actual_line {
+                        if caret_actual_line == actual_line && paint_current_line {
                             let size = ctx.size();
                             ctx.fill(
                                 Rect::ZERO
@@ -893,8 +1487,13 @@ impl LapceEditor {
                                 data.config.editor.tab_width,
                             ),
                         };
-                        let x0 = left_col as f64 * char_width + x_shift;
-                        let x1 = right_col as f64 * char_width + x_shift;
+                        let x0 = data.visual_col(actual_line, left_col) as f64
+                            * char_width
+                            + x_shift;
+                        let x1 = data.caret_visual_col(actual_line, right_col)
+                            as f64
+                            * char_width
+                            + x_shift;
                         let y0 = y;
                         let y1 = y0 + line_height;
                         ctx.fill(
@@ -905,30 +1504,33 @@ impl LapceEditor {
                     }
                 }
                 for region in regions {
-                    if is_focused {
+                    if is_focused && data.caret_visible() {
                         let (caret_actual_line, col) =
                             data.buffer.offset_to_line_col(
                                 region.end(),
                                 data.config.editor.tab_width,
                             );
                         if caret_actual_line == actual_line {
-                            let x = col as f64 * char_width + x_shift;
-                            ctx.stroke(
-                                Line::new(
-                                    Point::new(x, y),
-                                    Point::new(x, y + line_height),
-                                ),
-                                data.config
-                                    .get_color_unchecked(LapceTheme::EDITOR_CARET),
-                                2.0,
-                            )
+                            let x = data.caret_visual_col(actual_line, col) as f64
+                                * char_width
+                                + x_shift;
+                            Self::paint_caret(
+                                data,
+                                ctx,
+                                x,
+                                x + char_width,
+                                y,
+                                line_height,
+                            );
                         }
                     }
                 }
             }
         }
         if cursor_line == actual_line {
-            if let CursorMode::Normal(_) = &data.editor.cursor.mode {
+            if highlight_current_line
+                && matches!(data.editor.cursor.mode, CursorMode::Normal(_))
+            {
                 let size = ctx.size();
                 ctx.fill(
                     Rect::ZERO
@@ -940,20 +1542,17 @@ impl LapceEditor {
             }
             match &data.editor.cursor.mode {
                 CursorMode::Normal(_) | CursorMode::Visual { .. } => {
-                    if is_focused {
-                        let (x0, x1) = data.editor.cursor.current_char(
-                            &data.buffer,
-                            char_width,
-                            &data.config,
-                        );
+                    if is_focused && data.caret_visible() {
+                        let (x0, x1) = data.current_char(char_width);
                         let cursor_width =
                             if x1 > x0 { x1 - x0 } else { char_width };
-                        ctx.fill(
-                            Rect::ZERO
-                                .with_origin(Point::new(x0 + x_shift, y))
-                                .with_size(Size::new(cursor_width, line_height)),
-                            data.config
-                                .get_color_unchecked(LapceTheme::EDITOR_CARET),
+                        Self::paint_caret(
+                            data,
+                            ctx,
+                            x0 + x_shift,
+                            x0 + x_shift + cursor_width,
+                            y,
+                            line_height,
                         );
                     }
                 }
@@ -983,25 +1582,23 @@ impl LapceEditor {
                 let line = data.buffer.line_of_offset(*offset);
                 Self::paint_cursor_line(data, ctx, line, is_focused, placeholder);
 
-                if is_focused {
-                    let (x0, x1) = data.editor.cursor.current_char(
-                        &data.buffer,
-                        width,
-                        &data.config,
-                    );
+                if is_focused && data.caret_visible() {
+                    let (x0, x1) = data.current_char(width);
                     let char_width = if x1 > x0 { x1 - x0 } else { width };
-                    ctx.fill(
-                        Rect::ZERO
-                            .with_origin(Point::new(
-                                x0,
-                                line as f64 * line_height + line_padding,
-                            ))
-                            .with_size(Size::new(char_width, line_height)),
-                        data.config.get_color_unchecked(LapceTheme::EDITOR_CARET),
+                    Self::paint_caret(
+                        data,
+                        ctx,
+                        x0,
+                        x0 + char_width,
+                        line as f64 * line_height + line_padding,
+                        line_height,
                     );
                 }
             }
             CursorMode::Visual { start, end, mode } => {
+                let line = data.buffer.line_of_offset(*end);
+                Self::paint_cursor_line(data, ctx, line, is_focused, placeholder);
+
                 let paint_start_line = start_line;
                 let paint_end_line = end_line;
                 let (start_line, start_col) = data.buffer.offset_to_line_col(
@@ -1036,7 +1633,7 @@ impl LapceEditor {
                             left
                         }
                     };
-                    let x0 = left_col as f64 * width;
+                    let x0 = data.visual_col(line, left_col) as f64 * width;
 
                     let right_col = match mode {
                         VisualMode::Normal => match line {
@@ -1077,7 +1674,8 @@ impl LapceEditor {
                         }
                     };
                     if !line_content.is_empty() {
-                        let x1 = right_col as f64 * width;
+                        let x1 =
+                            data.caret_visual_col(line, right_col) as f64 * width;
 
                         let y0 = line as f64 * line_height + line_padding;
                         let y1 = y0 + line_height;
@@ -1088,24 +1686,18 @@ impl LapceEditor {
                         );
                     }
 
-                    if is_focused {
+                    if is_focused && data.caret_visible() {
                         let line = data.buffer.line_of_offset(*end);
 
-                        let (x0, x1) = data.editor.cursor.current_char(
-                            &data.buffer,
-                            width,
-                            &data.config,
-                        );
+                        let (x0, x1) = data.current_char(width);
                         let char_width = if x1 > x0 { x1 - x0 } else { width };
-                        ctx.fill(
-                            Rect::ZERO
-                                .with_origin(Point::new(
-                                    x0,
-                                    line as f64 * line_height + line_padding,
-                                ))
-                                .with_size(Size::new(char_width, line_height)),
-                            data.config
-                                .get_color_unchecked(LapceTheme::EDITOR_CARET),
+                        Self::paint_caret(
+                            data,
+                            ctx,
+                            x0,
+                            x0 + char_width,
+                            line as f64 * line_height + line_padding,
+                            line_height,
                         );
                     }
                 }
@@ -1156,7 +1748,7 @@ impl LapceEditor {
                                 _ if line == start_line => start_col,
                                 _ => 0,
                             };
-                            let x0 = left_col as f64 * width;
+                            let x0 = data.visual_col(line, left_col) as f64 * width;
 
                             let right_col = match line {
                                 _ if line == end_line => {
@@ -1175,7 +1767,9 @@ impl LapceEditor {
                             };
 
                             if !line_content.is_empty() {
-                                let x1 = right_col as f64 * width;
+                                let x1 = data.caret_visual_col(line, right_col)
+                                    as f64
+                                    * width;
                                 let y0 = line as f64 * line_height + line_padding;
                                 let y1 = y0 + line_height;
                                 ctx.fill(
@@ -1190,28 +1784,55 @@ impl LapceEditor {
                 }
 
                 for region in regions {
-                    if is_focused {
+                    if is_focused && data.caret_visible() {
                         let (line, col) = data.buffer.offset_to_line_col(
                             region.end(),
                             data.config.editor.tab_width,
                         );
-                        let x = col as f64 * width;
+                        let x = data.caret_visual_col(line, col) as f64 * width;
                         let y = line as f64 * line_height + line_padding;
-                        ctx.stroke(
-                            Line::new(
-                                Point::new(x, y),
-                                Point::new(x, y + line_height),
-                            ),
-                            data.config
-                                .get_color_unchecked(LapceTheme::EDITOR_CARET),
-                            2.0,
-                        )
+                        Self::paint_caret(data, ctx, x, x + width, y, line_height);
                     }
                 }
             }
         }
     }
 
+    /// Draw the caret over the character between `x0` and `x1`, in the shape
+    /// set for the current mode
+    fn paint_caret(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        x0: f64,
+        x1: f64,
+        y: f64,
+        line_height: f64,
+    ) {
+        let color = data.config.get_color_unchecked(LapceTheme::EDITOR_CARET);
+        match data
+            .config
+            .editor
+            .cursor_shape(data.editor.cursor.get_mode())
+        {
+            CursorShape::Block => {
+                ctx.fill(Rect::new(x0, y, x1, y + line_height), color);
+            }
+            CursorShape::Bar => {
+                ctx.stroke(
+                    Line::new(Point::new(x0, y), Point::new(x0, y + line_height)),
+                    color,
+                    2.0,
+                );
+            }
+            CursorShape::Underline => {
+                ctx.fill(
+                    Rect::new(x0, y + line_height - 2.0, x1, y + line_height),
+                    color,
+                );
+            }
+        }
+    }
+
     fn paint_cursor_line(
         data: &LapceEditorBufferData,
         ctx: &mut PaintCtx,
@@ -1225,6 +1846,9 @@ impl LapceEditor {
         if data.editor.content.is_input() {
             return;
         }
+        if !data.config.editor.highlight_current_line {
+            return;
+        }
         let line_height = data.config.editor.line_height as f64;
         let size = ctx.size();
         ctx.fill(
@@ -1236,9 +1860,45 @@ impl LapceEditor {
         );
     }
 
+    /// Draw a box around the bracket at the cursor and the one matching it,
+    /// or around the bracket alone in the error color if it's unmatched
+    fn paint_bracket_pair(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        char_width: f64,
+        env: &Env,
+    ) {
+        let offset = data.editor.cursor.offset();
+        let (bracket, matching) = match data.buffer.bracket_pair_at(offset) {
+            Some(pair) => pair,
+            None => return,
+        };
+        let color = if matching.is_some() {
+            data.config
+                .get_color_unchecked(LapceTheme::EDITOR_MATCHING_BRACKET)
+        } else {
+            data.config.get_color_unchecked(LapceTheme::LAPCE_ERROR)
+        };
+        let line_height = Self::line_height(data, env);
+        for offset in std::iter::once(bracket).chain(matching) {
+            let (line, col) = data
+                .buffer
+                .offset_to_line_col(offset, data.config.editor.tab_width);
+            let rect = Size::new(char_width, line_height)
+                .to_rect()
+                .with_origin(Point::new(
+                    data.visual_col(line, col) as f64 * char_width,
+                    line as f64 * line_height,
+                ))
+                .inflate(-0.5, -0.5);
+            ctx.stroke(rect, color, 1.0);
+        }
+    }
+
     fn paint_find(
         data: &LapceEditorBufferData,
         ctx: &mut PaintCtx,
+        (start_line, end_line): (usize, usize),
         char_width: f64,
         env: &Env,
     ) {
@@ -1249,12 +1909,6 @@ impl LapceEditor {
             return;
         }
         let line_height = Self::line_height(data, env);
-        let start_line =
-            (data.editor.scroll_offset.y / line_height).floor() as usize;
-        let end_line = ((data.editor.size.borrow().height
-            + data.editor.scroll_offset.y)
-            / line_height)
-            .ceil() as usize;
         let start_offset = data.buffer.offset_of_line(start_line);
         let end_offset = data.buffer.offset_of_line(end_line + 1);
         let cursor_offset = data.editor.cursor.offset();
@@ -1288,8 +1942,9 @@ impl LapceEditor {
                             data.config.editor.tab_width,
                         ) + 1
                     };
-                    let x0 = left_col as f64 * char_width;
-                    let x1 = right_col as f64 * char_width;
+                    let x0 = data.visual_col(line, left_col) as f64 * char_width;
+                    let x1 =
+                        data.caret_visual_col(line, right_col) as f64 * char_width;
                     let y0 = line as f64 * line_height;
                     let y1 = y0 + line_height;
                     let rect = Rect::new(x0, y0, x1, y1);
@@ -1314,15 +1969,15 @@ impl LapceEditor {
         }
     }
 
-    fn paint_snippet(data: &LapceEditorBufferData, ctx: &mut PaintCtx) {
+    fn paint_snippet(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        (start_line, end_line): (usize, usize),
+    ) {
         let line_height = data.config.editor.line_height as f64;
-        let start_line =
-            (data.editor.scroll_offset.y / line_height).floor() as usize;
-        let end_line = ((data.editor.size.borrow().height
-            + data.editor.scroll_offset.y)
-            / line_height)
-            .ceil() as usize;
-        let width = data.config.editor_text_width(ctx.text(), "W");
+        let width = data
+            .config
+            .char_width(ctx.text(), data.config.editor.font_size as f64);
         if let Some(snippet) = data.editor.snippet.as_ref() {
             for (_, (start, end)) in snippet {
                 let paint_start_line = start_line;
@@ -1335,7 +1990,7 @@ impl LapceEditor {
                     *start.max(end),
                     data.config.editor.tab_width,
                 );
-                for line in paint_start_line..paint_end_line {
+                for line in paint_start_line..paint_end_line + 1 {
                     if line < start_line || line > end_line {
                         continue;
                     }
@@ -1344,7 +1999,7 @@ impl LapceEditor {
                         _ if line == start_line => start_col,
                         _ => 0,
                     };
-                    let x0 = left_col as f64 * width;
+                    let x0 = data.visual_col(line, left_col) as f64 * width;
 
                     let right_col = match line {
                         _ if line == end_line => {
@@ -1362,7 +2017,8 @@ impl LapceEditor {
                         ),
                     };
                     if !line_content.is_empty() {
-                        let x1 = right_col as f64 * width;
+                        let x1 =
+                            data.caret_visual_col(line, right_col) as f64 * width;
                         let y0 = line as f64 * line_height;
                         let y1 = y0 + line_height;
                         ctx.stroke(
@@ -1379,14 +2035,25 @@ impl LapceEditor {
 
     fn paint_diagnostics(data: &LapceEditorBufferData, ctx: &mut PaintCtx) {
         let line_height = data.config.editor.line_height as f64;
-        let start_line =
-            (data.editor.scroll_offset.y / line_height).floor() as usize;
-        let end_line = ((data.editor.size.borrow().height
-            + data.editor.scroll_offset.y)
-            / line_height)
-            .ceil() as usize;
+        let lines = Self::visible_lines(data, line_height);
+        if let Some(diagnostic) = Self::paint_diagnostic_lines(data, ctx, lines) {
+            let y =
+                (diagnostic.diagnositc.range.start.line + 1) as f64 * line_height;
+            Self::paint_diagnostic_message(data, ctx, diagnostic, y);
+        }
+    }
 
-        let width = data.config.editor_text_width(ctx.text(), "W");
+    /// Underline the diagnostics on the lines from `start_line` to
+    /// `end_line`, returning the one that starts at the cursor
+    fn paint_diagnostic_lines(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        (start_line, end_line): (usize, usize),
+    ) -> Option<EditorDiagnostic> {
+        let line_height = data.config.editor.line_height as f64;
+        let width = data
+            .config
+            .char_width(ctx.text(), data.config.editor.font_size as f64);
         let mut current = None;
         let cursor_offset = data.editor.cursor.offset();
         if let Some(diagnostics) = data.diagnostics() {
@@ -1414,22 +2081,28 @@ impl LapceEditor {
                         }
 
                         let x0 = if line == start.line as usize {
-                            start.character as f64 * width
+                            data.visual_col(line, start.character as usize) as f64
+                                * width
                         } else {
                             let (_, col) = data.buffer.offset_to_line_col(
                                 data.buffer.first_non_blank_character_on_line(line),
                                 data.config.editor.tab_width,
                             );
-                            col as f64 * width
+                            data.visual_col(line, col) as f64 * width
                         };
                         let x1 = if line == end.line as usize {
-                            end.character as f64 * width
+                            data.caret_visual_col(line, end.character as usize)
+                                as f64
+                                * width
                         } else {
-                            (data.buffer.line_end_col(
+                            data.caret_visual_col(
                                 line,
-                                false,
-                                data.config.editor.tab_width,
-                            ) + 1) as f64
+                                data.buffer.line_end_col(
+                                    line,
+                                    false,
+                                    data.config.editor.tab_width,
+                                ) + 1,
+                            ) as f64
                                 * width
                         };
                         let _y1 = (line + 1) as f64 * line_height;
@@ -1461,108 +2134,106 @@ impl LapceEditor {
                 }
             }
         }
+        current
+    }
 
-        if let Some(diagnostic) = current {
-            if data.editor.cursor.is_normal() {
-                let text_layout = ctx
-                    .text()
-                    .new_text_layout(diagnostic.diagnositc.message.clone())
-                    .font(FontFamily::SYSTEM_UI, 14.0)
-                    .text_color(
-                        data.config
-                            .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
-                            .clone(),
-                    )
-                    .max_width(data.editor.size.borrow().width - 20.0)
-                    .build()
-                    .unwrap();
-                let text_size = text_layout.size();
-                let mut text_height = text_size.height;
-
-                let related = diagnostic
-                    .diagnositc
-                    .related_information
-                    .map(|related| {
-                        related
-                            .iter()
-                            .map(|i| {
-                                let text_layout = ctx
-                                    .text()
-                                    .new_text_layout(i.message.clone())
-                                    .font(FontFamily::SYSTEM_UI, 14.0)
-                                    .text_color(
-                                        data.config
-                                            .get_color_unchecked(
-                                                LapceTheme::EDITOR_FOREGROUND,
-                                            )
-                                            .clone(),
-                                    )
-                                    .max_width(
-                                        data.editor.size.borrow().width - 20.0,
+    /// Show the message of the diagnostic at the cursor in a box at `y`
+    fn paint_diagnostic_message(
+        data: &LapceEditorBufferData,
+        ctx: &mut PaintCtx,
+        diagnostic: EditorDiagnostic,
+        y: f64,
+    ) {
+        if !data.editor.cursor.is_normal() {
+            return;
+        }
+
+        let text_layout = ctx
+            .text()
+            .new_text_layout(diagnostic.diagnositc.message.clone())
+            .font(FontFamily::SYSTEM_UI, 14.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .max_width(data.editor.size.borrow().width - 20.0)
+            .build()
+            .unwrap();
+        let text_size = text_layout.size();
+        let mut text_height = text_size.height;
+
+        let related = diagnostic
+            .diagnositc
+            .related_information
+            .map(|related| {
+                related
+                    .iter()
+                    .map(|i| {
+                        let text_layout = ctx
+                            .text()
+                            .new_text_layout(i.message.clone())
+                            .font(FontFamily::SYSTEM_UI, 14.0)
+                            .text_color(
+                                data.config
+                                    .get_color_unchecked(
+                                        LapceTheme::EDITOR_FOREGROUND,
                                     )
-                                    .build()
-                                    .unwrap();
-                                text_height += 10.0 + text_layout.size().height;
-                                text_layout
-                            })
-                            .collect::<Vec<PietTextLayout>>()
+                                    .clone(),
+                            )
+                            .max_width(data.editor.size.borrow().width - 20.0)
+                            .build()
+                            .unwrap();
+                        text_height += 10.0 + text_layout.size().height;
+                        text_layout
                     })
-                    .unwrap_or_else(Vec::new);
-
-                let start = diagnostic.diagnositc.range.start;
-                let rect = Rect::ZERO
-                    .with_origin(Point::new(
-                        0.0,
-                        (start.line + 1) as f64 * line_height,
-                    ))
-                    .with_size(Size::new(
-                        data.editor.size.borrow().width,
-                        text_height + 20.0,
-                    ));
-                ctx.fill(
-                    rect,
-                    data.config
-                        .get_color_unchecked(LapceTheme::EDITOR_SELECTION),
-                );
+                    .collect::<Vec<PietTextLayout>>()
+            })
+            .unwrap_or_else(Vec::new);
+
+        let rect = Rect::ZERO
+            .with_origin(Point::new(0.0, y))
+            .with_size(Size::new(
+                data.editor.size.borrow().width,
+                text_height + 20.0,
+            ));
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::EDITOR_SELECTION),
+        );
 
-                let severity = diagnostic
-                    .diagnositc
-                    .severity
-                    .as_ref()
-                    .unwrap_or(&DiagnosticSeverity::Information);
-                let color = match severity {
-                    DiagnosticSeverity::Error => {
-                        data.config.get_color_unchecked(LapceTheme::LAPCE_ERROR)
-                    }
-                    DiagnosticSeverity::Warning => {
-                        data.config.get_color_unchecked(LapceTheme::LAPCE_WARN)
-                    }
-                    _ => data.config.get_color_unchecked(LapceTheme::LAPCE_WARN),
-                };
-                ctx.stroke(rect, color, 1.0);
-                ctx.draw_text(
-                    &text_layout,
-                    Point::new(
-                        10.0 + data.editor.scroll_offset.x,
-                        (start.line + 1) as f64 * line_height + 10.0,
-                    ),
-                );
-                let mut text_height = text_size.height;
-
-                for text in related {
-                    text_height += 10.0;
-                    ctx.draw_text(
-                        &text,
-                        Point::new(
-                            10.0 + data.editor.scroll_offset.x,
-                            (start.line + 1) as f64 * line_height
-                                + 10.0
-                                + text_height,
-                        ),
-                    );
-                    text_height += text.size().height;
-                }
+        let severity = diagnostic
+            .diagnositc
+            .severity
+            .as_ref()
+            .unwrap_or(&DiagnosticSeverity::Information);
+        let color = match severity {
+            DiagnosticSeverity::Error => {
+                data.config.get_color_unchecked(LapceTheme::LAPCE_ERROR)
             }
+            DiagnosticSeverity::Warning => {
+                data.config.get_color_unchecked(LapceTheme::LAPCE_WARN)
+            }
+            _ => data.config.get_color_unchecked(LapceTheme::LAPCE_WARN),
+        };
+        ctx.stroke(rect, color, 1.0);
+        ctx.draw_text(
+            &text_layout,
+            Point::new(10.0 + data.editor.scroll_offset.x, y + 10.0),
+        );
+        let mut text_height = text_size.height;
+
+        for text in related {
+            text_height += 10.0;
+            ctx.draw_text(
+                &text,
+                Point::new(
+                    10.0 + data.editor.scroll_offset.x,
+                    y + 10.0 + text_height,
+                ),
+            );
+            text_height += text.size().height;
         }
     }
 
@@ -1625,28 +2296,39 @@ impl Widget<LapceTabData> for LapceEditor {
                         Duration::from_millis(data.config.editor.hover_delay),
                     );
                     if ctx.is_active() {
-                        let editor_data = data.editor_view_content(self.view_id);
-                        let new_offset = editor_data.offset_of_mouse(
-                            ctx.text(),
-                            mouse_event.pos,
-                            &data.config,
-                        );
+                        self.drag_select(ctx, mouse_event, data);
                         let editor =
-                            data.main_split.editors.get_mut(&self.view_id).unwrap();
-                        let editor = Arc::make_mut(editor);
-                        editor.cursor = editor.cursor.set_offset(
-                            new_offset,
-                            true,
-                            mouse_event.mods.alt(),
+                            data.main_split.editors.get(&self.view_id).unwrap();
+                        let mut drag_event = mouse_event.clone();
+                        drag_event.pos -= editor.scroll_offset;
+                        let speed = autoscroll_speed(
+                            drag_event.pos.y,
+                            editor.size.borrow().height,
+                            &data.config,
                         );
+                        self.drag_event = Some(drag_event);
+                        if speed != 0.0
+                            && self.autoscroll_timer == TimerToken::INVALID
+                        {
+                            self.autoscroll_timer =
+                                ctx.request_timer(AUTOSCROLL_INTERVAL);
+                        }
                     }
                 }
             }
             Event::MouseUp(_mouse_event) => {
+                if ctx.is_active() {
+                    data.editor_view_content(self.view_id)
+                        .update_primary_selection();
+                }
                 ctx.set_active(false);
+                self.drag_event = None;
             }
             Event::MouseDown(mouse_event) => {
-                let buffer = data.main_split.editor_buffer(self.view_id);
+                let buffer = match data.main_split.editor_buffer(self.view_id) {
+                    Some(buffer) => buffer,
+                    None => return,
+                };
                 let editor =
                     data.main_split.editors.get(&self.view_id).unwrap().clone();
                 let mut editor_data = data.editor_view_content(self.view_id);
@@ -1688,6 +2370,22 @@ impl Widget<LapceTabData> for LapceEditor {
                 //     _ => {}
                 // }
             }
+            Event::Timer(id) if *id == self.autoscroll_timer => {
+                self.autoscroll_timer = TimerToken::INVALID;
+                if ctx.is_active() {
+                    self.autoscroll(ctx, data);
+                }
+            }
+            Event::Timer(id) if *id == self.cursor_blink_timer => {
+                self.cursor_blink_timer = TimerToken::INVALID;
+                if data.focus == self.view_id && data.config.editor.blink_cursor {
+                    ctx.request_paint();
+                    self.cursor_blink_timer =
+                        ctx.request_timer(Duration::from_millis(
+                            data.config.editor.cursor_blink_interval.max(1),
+                        ));
+                }
+            }
             Event::Timer(id) => {
                 if self.mouse_hover_timer == *id {
                     let editor =
@@ -1743,11 +2441,35 @@ impl Widget<LapceTabData> for LapceEditor {
 
     fn update(
         &mut self,
-        _ctx: &mut UpdateCtx,
-        _old_data: &LapceTabData,
-        _data: &LapceTabData,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
         _env: &Env,
     ) {
+        // Show the caret solid again after it moved, an edit or a change of
+        // focus, and blink it from there
+        let editor = data.main_split.editors.get(&self.view_id);
+        let old_editor = old_data.main_split.editors.get(&self.view_id);
+        if let (Some(editor), Some(old_editor)) = (editor, old_editor) {
+            let rev = |data: &LapceTabData| {
+                data.main_split.editor_buffer(self.view_id).map(|b| b.rev)
+            };
+            let gained_focus =
+                data.focus == self.view_id && old_data.focus != self.view_id;
+            if gained_focus
+                || editor.cursor != old_editor.cursor
+                || rev(data) != rev(old_data)
+            {
+                *editor.last_cursor_instant.borrow_mut() = Instant::now();
+                if data.focus == self.view_id && data.config.editor.blink_cursor {
+                    self.cursor_blink_timer =
+                        ctx.request_timer(Duration::from_millis(
+                            data.config.editor.cursor_blink_interval.max(1),
+                        ));
+                }
+            }
+        }
+
         // let buffer = &data.buffer;
         // let old_buffer = &old_data.buffer;
 
@@ -1843,6 +2565,22 @@ pub struct HighlightTextLayout {
     pub highlights: Vec<(usize, usize, String)>,
 }
 
+/// The speed (in px per second, negative upwards) to scroll at while a
+/// selection is dragged with the pointer at `y` relative to a viewport of
+/// `height`, which grows the further the pointer is past its top or bottom.
+fn autoscroll_speed(y: f64, height: f64, config: &Config) -> f64 {
+    let distance = if y < 0.0 {
+        y
+    } else if y > height {
+        y - height
+    } else {
+        return 0.0;
+    };
+    let max_speed =
+        config.editor.autoscroll_max_speed as f64 * config.editor.line_height as f64;
+    (distance * AUTOSCROLL_ACCELERATION).clamp(-max_speed, max_speed)
+}
+
 #[allow(dead_code)]
 fn get_workspace_edit_edits<'a>(
     url: &Url,
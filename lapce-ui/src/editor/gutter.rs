@@ -88,8 +88,17 @@ impl Widget<LapceTabData> for LapceEditorGutter {
     ) -> Size {
         let data = data.editor_view_content(self.view_id);
         let last_line = data.buffer.last_line() + 1;
-        let char_width = data.config.editor_text_width(ctx.text(), "W");
-        self.width = (char_width * last_line.to_string().len() as f64).ceil();
+        let char_width = data
+            .config
+            .char_width(ctx.text(), data.config.editor.font_size as f64);
+        // The diff view always needs the line numbers of both sides
+        self.width = if data.config.editor.line_numbers == "off"
+            && data.editor.compare.is_none()
+        {
+            0.0
+        } else {
+            (char_width * last_line.to_string().len() as f64).ceil()
+        };
         let mut width = self.width + 16.0 + char_width * 2.0;
         if data.editor.compare.is_some() {
             width += self.width + char_width * 2.0;
@@ -123,7 +132,9 @@ impl LapceEditorGutter {
             (scroll_offset.y + rect.height() / line_height).ceil() as usize;
         let current_line = data.editor.cursor.current_line(&data.buffer);
         let last_line = data.buffer.last_line();
-        let width = data.config.editor_text_width(ctx.text(), "W");
+        let width = data
+            .config
+            .char_width(ctx.text(), data.config.editor.font_size as f64);
 
         let mut line = 0;
         for change in changes.iter() {
@@ -383,19 +394,15 @@ impl LapceEditorGutter {
             .char_width(ctx.text(), data.config.editor.font_size as f64);
         let max_line_width = (last_line + 1).to_string().len() as f64 * char_width;
 
+        let relative = *data.main_split.active == Some(self.view_id)
+            && !data.editor.cursor.is_insert();
         let mut y = lens.height_of_line(start_line) as f64;
         for (line, line_height) in lens.iter_chunks(start_line..end_line + 1) {
-            let content = if *data.main_split.active != Some(self.view_id)
-                || data.editor.cursor.is_insert()
-                || line == cursor_line
-            {
-                line + 1
-            } else if line > cursor_line {
-                line - cursor_line
-            } else {
-                cursor_line - line
-            };
-            let content = content.to_string();
+            let content =
+                match data.config.editor.line_number(line, cursor_line, relative) {
+                    Some(content) => content.to_string(),
+                    None => break,
+                };
             let is_small = line_height < data.config.editor.line_height;
             let text_layout = ctx
                 .text()
@@ -410,11 +417,11 @@ impl LapceEditorGutter {
                 )
                 .text_color(if line == cursor_line {
                     data.config
-                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                        .get_color_unchecked(LapceTheme::EDITOR_CURRENT_LINE_NUMBER)
                         .clone()
                 } else {
                     data.config
-                        .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                        .get_color_unchecked(LapceTheme::EDITOR_LINE_NUMBER)
                         .clone()
                 })
                 .build()
@@ -444,13 +451,19 @@ impl LapceEditorGutter {
             if !actions.is_empty() {
                 let line_height = data.config.editor.line_height as f64;
                 let offset = data.editor.cursor.offset();
-                let (line, _) = data
+                let (line, col) = data
                     .buffer
                     .offset_to_line_col(offset, data.config.editor.tab_width);
+                let line = match data.wrap_layout(ctx.text()) {
+                    Some(wrap) => wrap.visual_line_of_col(line, col).0,
+                    None => line,
+                };
                 let svg = get_svg("lightbulb.svg").unwrap();
                 let width = 16.0;
                 let height = 16.0;
-                let char_width = data.config.editor_text_width(ctx.text(), "W");
+                let char_width = data
+                    .config
+                    .char_width(ctx.text(), data.config.editor.font_size as f64);
                 let rect =
                     Size::new(width, height).to_rect().with_origin(Point::new(
                         self.width + char_width + 3.0,
@@ -481,28 +494,47 @@ impl LapceEditorGutter {
             }
             let line_height = data.config.editor.line_height as f64;
             let scroll_offset = data.editor.scroll_offset;
+            let wrap = data.wrap_layout(ctx.text());
+            // The visual line a line starts on when the buffer is soft wrapped
+            let visual_line = |line: usize| match wrap.as_ref() {
+                Some(wrap) => wrap.visual_line(line),
+                None => line,
+            };
             let start_line = (scroll_offset.y / line_height).floor() as usize;
             let end_line =
                 (scroll_offset.y + rect.height() / line_height).ceil() as usize;
+            let (start_line, end_line) = match wrap.as_ref() {
+                Some(wrap) => (
+                    wrap.line_of_visual_line(start_line).0,
+                    wrap.line_of_visual_line(end_line).0,
+                ),
+                None => (start_line, end_line),
+            };
             let num_lines = (ctx.size().height / line_height).floor() as usize;
             let last_line = data.buffer.last_line();
             let current_line = data.editor.cursor.current_line(&data.buffer);
-            let width = data.config.editor_text_width(ctx.text(), "W");
-            for line in start_line..start_line + num_lines + 1 {
-                if line > last_line {
+            let width = data
+                .config
+                .char_width(ctx.text(), data.config.editor.font_size as f64);
+            let relative = *data.main_split.active == Some(data.view_id)
+                && !data.editor.cursor.is_insert();
+            let end_visual_line = visual_line(start_line) + num_lines + 1;
+            for line in start_line..last_line + 1 {
+                if visual_line(line) >= end_visual_line {
                     break;
                 }
-                let content = if *data.main_split.active != Some(data.view_id)
-                    || data.editor.cursor.is_insert()
-                    || line == current_line
-                {
-                    line + 1
-                } else if line > current_line {
-                    line - current_line
-                } else {
-                    current_line - line
+                // Lines hidden by a fold don't get a number
+                if wrap.as_ref().map(|w| w.is_hidden(line)).unwrap_or(false) {
+                    continue;
+                }
+                let content = match data.config.editor.line_number(
+                    line,
+                    current_line,
+                    relative,
+                ) {
+                    Some(content) => content.to_string(),
+                    None => break,
                 };
-                let content = content.to_string();
 
                 let text_layout = ctx
                     .text()
@@ -513,18 +545,20 @@ impl LapceEditorGutter {
                     )
                     .text_color(if line == current_line {
                         data.config
-                            .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                            .get_color_unchecked(
+                                LapceTheme::EDITOR_CURRENT_LINE_NUMBER,
+                            )
                             .clone()
                     } else {
                         data.config
-                            .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                            .get_color_unchecked(LapceTheme::EDITOR_LINE_NUMBER)
                             .clone()
                     })
                     .build()
                     .unwrap();
                 let x = ((last_line + 1).to_string().len() - content.len()) as f64
                     * width;
-                let y = line_height * line as f64 - scroll_offset.y
+                let y = line_height * visual_line(line) as f64 - scroll_offset.y
                     + (line_height - text_layout.size().height) / 2.0;
                 let pos = Point::new(x, y);
                 ctx.draw_text(&text_layout, pos);
@@ -577,12 +611,14 @@ impl LapceEditorGutter {
                             if len == 0 {
                                 removed_height
                             } else {
-                                line_height * len as f64
+                                line_height
+                                    * (visual_line(line) - visual_line(line - len))
+                                        as f64
                             },
                         );
                         let x = self.width + width;
-                        let mut y =
-                            (line - len) as f64 * line_height - scroll_offset.y;
+                        let mut y = visual_line(line - len) as f64 * line_height
+                            - scroll_offset.y;
                         if len == 0 {
                             y -= removed_height / 2.0;
                         }
@@ -7,10 +7,10 @@ use druid::{
     UpdateCtx, Widget, WidgetId,
 };
 use lapce_data::{
-    buffer::BufferContent,
+    buffer::{history_file, BufferContent},
     command::{CommandTarget, LapceCommand, LapceCommandNew, LAPCE_NEW_COMMAND},
     config::LapceTheme,
-    data::LapceTabData,
+    data::{DiffSide, LapceTabData},
     editor::LapceEditorBufferData,
     state::LapceWorkspace,
     svg::{file_svg_new, get_svg},
@@ -157,8 +157,26 @@ impl LapceEditorHeader {
                 if data.buffer.dirty {
                     file_name = "*".to_string() + &file_name;
                 }
-                if let Some(_compare) = data.editor.compare.as_ref() {
-                    file_name += " (Working tree)";
+                if let Some(compare) = data.editor.compare.as_ref() {
+                    let left = matches!(
+                        data.editor.diff_view.as_ref(),
+                        Some(diff_view) if diff_view.side == DiffSide::Left
+                    );
+                    match history_file(compare) {
+                        Some(other) if left => {
+                            file_name = other
+                                .file_name()
+                                .and_then(|s| s.to_str())
+                                .unwrap_or("")
+                                .to_string();
+                        }
+                        Some(_) => {}
+                        None if left => file_name += " (HEAD)",
+                        None => file_name += " (Working tree)",
+                    }
+                }
+                if data.buffer.changed_on_disk {
+                    file_name += " (Changed on disk)";
                 }
                 let text_layout = ctx
                     .text()
@@ -201,6 +219,34 @@ impl LapceEditorHeader {
                     ctx.draw_text(&text_layout, Point::new(30.0 + x + 5.0, 7.0));
                 }
             });
+        } else if let BufferContent::Scratch(_, name) = &data.buffer.content {
+            ctx.with_save(|ctx| {
+                ctx.clip(clip_rect);
+                let svg = get_svg("default_file.svg").unwrap();
+                let width = 13.0;
+                let height = 13.0;
+                let rect = Size::new(width, height).to_rect().with_origin(
+                    Point::new((30.0 - width) / 2.0, (30.0 - height) / 2.0),
+                );
+                ctx.draw_svg(&svg, rect, None);
+
+                let mut name = name.clone();
+                if data.buffer.dirty {
+                    name = "*".to_string() + &name;
+                }
+                let text_layout = ctx
+                    .text()
+                    .new_text_layout(name)
+                    .font(FontFamily::SYSTEM_UI, 13.0)
+                    .text_color(
+                        data.config
+                            .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                            .clone(),
+                    )
+                    .build()
+                    .unwrap();
+                ctx.draw_text(&text_layout, Point::new(30.0, 7.0));
+            });
         }
 
         if self.view_is_hot {
@@ -47,11 +47,21 @@ impl LapceEditorTab {
         self.children.clear();
         ctx.children_changed();
 
-        let editor_tab = data.main_split.editor_tabs.get(&self.widget_id).unwrap();
-        for child in editor_tab.children.iter() {
+        let editor_tab = data
+            .main_split
+            .editor_tabs
+            .get(&self.widget_id)
+            .unwrap()
+            .clone();
+        for (i, child) in editor_tab.children.iter().enumerate().rev() {
             match child {
                 EditorTabChild::Editor(view_id, _) => {
-                    data.main_split.editors.remove(view_id);
+                    data.main_split.remember_closed_editor(
+                        *view_id,
+                        i,
+                        &data.config,
+                    );
+                    data.main_split.remove_editor(view_id);
                 }
             }
         }
@@ -75,6 +85,14 @@ impl LapceEditorTab {
         self.children.remove(i);
         ctx.children_changed();
 
+        if delete {
+            let editor_tab =
+                data.main_split.editor_tabs.get(&self.widget_id).unwrap();
+            let view_id = editor_tab.children[i].widget_id();
+            data.main_split
+                .remember_closed_editor(view_id, i, &data.config);
+        }
+
         let editor_tab = data
             .main_split
             .editor_tabs
@@ -114,7 +132,7 @@ impl LapceEditorTab {
         if delete {
             match removed_child {
                 EditorTabChild::Editor(view_id, _) => {
-                    data.main_split.editors.remove(&view_id);
+                    data.main_split.remove_editor(&view_id);
                 }
             }
         }
@@ -320,10 +338,45 @@ impl Widget<LapceTabData> for LapceEditorTab {
                         return;
                     }
                     LapceUICommand::EditorTabRemove(index, delete, focus) => {
+                        if *delete {
+                            let editor_tab = data
+                                .main_split
+                                .editor_tabs
+                                .get(&self.widget_id)
+                                .unwrap();
+                            let view_id = editor_tab.children[*index].widget_id();
+                            let unsaved = data
+                                .main_split
+                                .unsaved_buffers_of_editors(&[view_id]);
+                            if !data
+                                .main_split
+                                .confirm_close(&unsaved, ctx.get_external_handle())
+                            {
+                                return;
+                            }
+                        }
                         self.remove_child(ctx, data, *index, *delete, *focus);
                         return;
                     }
                     LapceUICommand::SplitClose => {
+                        let editor_tab = data
+                            .main_split
+                            .editor_tabs
+                            .get(&self.widget_id)
+                            .unwrap();
+                        let view_ids: Vec<WidgetId> = editor_tab
+                            .children
+                            .iter()
+                            .map(|child| child.widget_id())
+                            .collect();
+                        let unsaved =
+                            data.main_split.unsaved_buffers_of_editors(&view_ids);
+                        if !data
+                            .main_split
+                            .confirm_close(&unsaved, ctx.get_external_handle())
+                        {
+                            return;
+                        }
                         self.clear_child(ctx, data);
                         return;
                     }
@@ -556,10 +609,11 @@ impl TabRectRenderer for TabRect {
         if !(ctx.is_hot() && self.rect.contains(mouse_pos)) {
             // See if any of the children are dirty
             let is_dirty = match &editor_tab.children[i] {
-                EditorTabChild::Editor(editor_id, _) => {
-                    let buffer = data.main_split.editor_buffer(*editor_id);
-                    buffer.dirty
-                }
+                EditorTabChild::Editor(editor_id, _) => data
+                    .main_split
+                    .editor_buffer(*editor_id)
+                    .map(|buffer| buffer.dirty)
+                    .unwrap_or(false),
             };
 
             if is_dirty {
@@ -255,13 +255,19 @@ impl Widget<LapceTabData> for LapceEditorTabHeaderContent {
             match child {
                 EditorTabChild::Editor(view_id, _) => {
                     let editor = data.main_split.editors.get(view_id).unwrap();
-                    if let BufferContent::File(path) = &editor.content {
-                        svg = file_svg_new(path);
-                        if let Some(file_name) = path.file_name() {
-                            if let Some(s) = file_name.to_str() {
-                                text = s.to_string();
+                    match &editor.content {
+                        BufferContent::File(path) => {
+                            svg = file_svg_new(path);
+                            if let Some(file_name) = path.file_name() {
+                                if let Some(s) = file_name.to_str() {
+                                    text = s.to_string();
+                                }
                             }
                         }
+                        BufferContent::Scratch(_, name) => {
+                            text = name.clone();
+                        }
+                        _ => {}
                     }
                 }
             }
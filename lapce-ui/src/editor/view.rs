@@ -1,9 +1,14 @@
-use std::{iter::Iterator, str::FromStr, sync::Arc};
+use std::{
+    iter::Iterator,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use druid::{
     piet::PietText, BoxConstraints, Command, Data, Env, Event, EventCtx, LayoutCtx,
     LifeCycle, LifeCycleCtx, Modifiers, PaintCtx, Point, Rect, RenderContext, Size,
-    Target, Vec2, Widget, WidgetExt, WidgetId, WidgetPod,
+    Target, TimerToken, Vec2, Widget, WidgetExt, WidgetId, WidgetPod,
 };
 use lapce_data::{
     buffer::{BufferContent, LocalBufferKind},
@@ -23,6 +28,7 @@ use crate::{
         container::LapceEditorContainer, header::LapceEditorHeader, LapceEditor,
     },
     find::FindBox,
+    scroll::LapceScrollNew,
 };
 
 pub struct LapceEditorView {
@@ -30,6 +36,19 @@ pub struct LapceEditorView {
     pub header: WidgetPod<LapceTabData, LapceEditorHeader>,
     pub editor: WidgetPod<LapceTabData, LapceEditorContainer>,
     pub find: Option<WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>>,
+    /// Fires when the buffer has been left unedited for long enough to be
+    /// saved automatically, every edit replaces it with a new one
+    auto_save_timer: TimerToken,
+    /// The running scroll animation when smooth scrolling is enabled
+    scroll_animation: Option<ScrollAnimation>,
+}
+
+/// Moves the scroll offset of the editor from `from` to `target` over the
+/// configured duration
+struct ScrollAnimation {
+    from: Point,
+    target: Point,
+    started: Instant,
 }
 
 pub fn editor_tab_child_widget(
@@ -56,6 +75,33 @@ impl LapceEditorView {
             header: WidgetPod::new(header),
             editor: WidgetPod::new(editor),
             find,
+            auto_save_timer: TimerToken::INVALID,
+            scroll_animation: None,
+        }
+    }
+
+    fn scroll_offset(&self) -> Vec2 {
+        self.editor.widget().editor.widget().inner().offset()
+    }
+
+    fn scroll_mut(&mut self) -> &mut LapceScrollNew<LapceTabData, LapceEditor> {
+        self.editor.widget_mut().editor.widget_mut().inner_mut()
+    }
+
+    /// Whether the event is a key press or a command, which can scroll the
+    /// editor in jumps that should be animated
+    fn is_keyboard_scroll_event(event: &Event) -> bool {
+        match event {
+            Event::KeyDown(_) => true,
+            Event::Command(cmd) if cmd.is(LAPCE_NEW_COMMAND) => true,
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => matches!(
+                cmd.get_unchecked(LAPCE_UI_COMMAND),
+                LapceUICommand::ScrollTo(_)
+                    | LapceUICommand::Scroll(_)
+                    | LapceUICommand::EnsureRectVisible(_)
+                    | LapceUICommand::EnsureCursorVisible(_)
+            ),
+            _ => false,
         }
     }
 
@@ -109,10 +155,11 @@ impl LapceEditorView {
             ));
         }
         match &editor.content {
-            BufferContent::File(_) => {
+            BufferContent::File(path) => {
                 data.focus_area = FocusArea::Editor;
                 data.main_split.active = Arc::new(Some(self.view_id));
                 data.main_split.active_tab = Arc::new(editor.tab_id);
+                data.main_split.touch_recent_buffer(path);
             }
             BufferContent::Local(kind) => match kind {
                 LocalBufferKind::Keymap => {}
@@ -134,6 +181,11 @@ impl LapceEditorView {
                 }
             },
             BufferContent::Value(_) => {}
+            BufferContent::Scratch(..) => {
+                data.focus_area = FocusArea::Editor;
+                data.main_split.active = Arc::new(Some(self.view_id));
+                data.main_split.active_tab = Arc::new(editor.tab_id);
+            }
         }
     }
 
@@ -294,7 +346,12 @@ impl LapceEditorView {
         let editor_size = *data.editor.size.borrow();
         let size = LapceEditor::get_size(data, ctx.text(), editor_size, panels, env);
 
-        let rect = Self::cursor_region(data, ctx.text());
+        // Keep `scroll_off` lines around the cursor, but never more than fits
+        // on either side of it, or the viewport would jump back and forth
+        let margin = (data.config.editor.scroll_off as f64 * line_height)
+            .min(((editor_size.height - line_height) / 2.0).floor())
+            .max(0.0);
+        let rect = Self::cursor_region(data, ctx.text()).inflate(0.0, margin);
         let scroll_id = self.editor.widget().scroll_id;
         let scroll = self.editor.widget_mut().editor.widget_mut().inner_mut();
         scroll.set_child_size(size);
@@ -326,8 +383,10 @@ impl LapceEditorView {
         let (line, col) = data
             .buffer
             .offset_to_line_col(offset, data.config.editor.tab_width);
-        let width = data.config.editor_text_width(text, "W");
-        let cursor_x = col as f64 * width;
+        let width = data
+            .config
+            .char_width(text, data.config.editor.font_size as f64);
+        let cursor_x = data.visual_col(line, col) as f64 * width;
         let line_height = data.config.editor.line_height as f64;
 
         let y = if data.editor.code_lens {
@@ -375,11 +434,21 @@ impl LapceEditorView {
             };
             line as f64 * line_height
         };
+        let (cursor_x, y) = match data.wrap_layout(text) {
+            Some(wrap) => {
+                let (visual_line, start_col) = wrap.visual_line_of_col(line, col);
+                (
+                    (col - start_col) as f64 * width,
+                    visual_line as f64 * line_height,
+                )
+            }
+            None => (cursor_x, y),
+        };
 
         Rect::ZERO
             .with_size(Size::new(width, line_height))
             .with_origin(Point::new(cursor_x, y))
-            .inflate(width, line_height)
+            .inflate(width, 0.0)
     }
 }
 
@@ -409,6 +478,18 @@ impl Widget<LapceTabData> for LapceEditorView {
             return;
         }
 
+        // Scrolls from the keyboard are computed from where a running
+        // animation ends, so that new ones retarget it instead of queueing
+        let animate_scroll = data.config.editor.smooth_scroll
+            && Self::is_keyboard_scroll_event(event);
+        let visible_offset = self.scroll_offset().to_point();
+        if animate_scroll {
+            if let Some(animation) = self.scroll_animation.as_ref() {
+                let target = animation.target;
+                self.scroll_mut().force_scroll_to(target);
+            }
+        }
+
         let editor = data.main_split.editors.get(&self.view_id).unwrap().clone();
         match event {
             Event::MouseDown(mouse_event) => match mouse_event.button {
@@ -431,25 +512,18 @@ impl Widget<LapceTabData> for LapceEditorView {
 
         let mut editor_data = data.editor_view_content(self.view_id);
         let buffer = editor_data.buffer.clone();
+        let cursor = editor_data.editor.cursor.clone();
 
         match event {
             Event::KeyDown(key_event) => {
                 ctx.set_handled();
                 let mut keypress = data.keypress.clone();
-                if Arc::make_mut(&mut keypress).key_down(
+                Arc::make_mut(&mut keypress).key_down(
                     ctx,
                     key_event,
                     &mut editor_data,
                     env,
-                ) {
-                    self.ensure_cursor_visible(
-                        ctx,
-                        &editor_data,
-                        &data.panels,
-                        None,
-                        env,
-                    );
-                }
+                );
                 editor_data.sync_buffer_position(
                     self.editor.widget().editor.widget().inner().offset(),
                 );
@@ -468,13 +542,6 @@ impl Widget<LapceTabData> for LapceEditorView {
                         Modifiers::empty(),
                         env,
                     );
-                    self.ensure_cursor_visible(
-                        ctx,
-                        &editor_data,
-                        &data.panels,
-                        None,
-                        env,
-                    );
                 }
             }
             Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
@@ -487,10 +554,87 @@ impl Widget<LapceTabData> for LapceEditorView {
                     env,
                 );
             }
+            Event::AnimFrame(_) => {
+                if let Some(animation) = self.scroll_animation.as_ref() {
+                    let duration = data.config.editor.smooth_scroll_duration.max(1);
+                    let progress = animation.started.elapsed().as_secs_f64()
+                        / Duration::from_millis(duration).as_secs_f64();
+                    let ease = data.config.editor.smooth_scroll_ease(progress);
+                    let offset = animation.from.lerp(animation.target, ease);
+                    if progress < 1.0 {
+                        ctx.request_anim_frame();
+                    } else {
+                        self.scroll_animation = None;
+                    }
+                    self.scroll_mut().scroll_to(offset);
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::ResetFade,
+                        Target::Widget(self.editor.widget().scroll_id),
+                    ));
+                }
+            }
+            Event::Timer(token) if *token == data.keypress.pending_timer() => {
+                let mut keypress = data.keypress.clone();
+                Arc::make_mut(&mut keypress).timer(
+                    ctx,
+                    *token,
+                    &mut editor_data,
+                    env,
+                );
+                data.keypress = keypress;
+            }
+            Event::Timer(token) if *token == self.auto_save_timer => {
+                self.auto_save_timer = TimerToken::INVALID;
+                if editor_data.buffer.dirty {
+                    editor_data.run_command(
+                        ctx,
+                        &LapceCommand::Save,
+                        None,
+                        Modifiers::empty(),
+                        env,
+                    );
+                }
+            }
             _ => (),
         }
+        // Whatever moved the cursor, be it a key, a command or an edit
+        // applied from a ui command, bring it back into view
+        if editor_data.editor.cursor != cursor {
+            self.ensure_cursor_visible(ctx, &editor_data, &data.panels, None, env);
+        }
+        if data.config.editor.auto_save
+            && editor_data.buffer.rev != buffer.rev
+            && matches!(editor_data.buffer.content, BufferContent::File(_))
+        {
+            self.auto_save_timer = ctx.request_timer(Duration::from_millis(
+                data.config.editor.auto_save_delay,
+            ));
+        }
         data.update_from_editor_buffer_data(editor_data, &editor, &buffer);
 
+        if animate_scroll {
+            let target = self.scroll_offset().to_point();
+            let retarget = self
+                .scroll_animation
+                .as_ref()
+                .map(|animation| animation.target != target)
+                .unwrap_or(true);
+            if target == visible_offset {
+                self.scroll_animation = None;
+            } else {
+                self.scroll_mut().force_scroll_to(visible_offset);
+                if retarget {
+                    self.scroll_animation = Some(ScrollAnimation {
+                        from: visible_offset,
+                        target,
+                        started: Instant::now(),
+                    });
+                    ctx.request_anim_frame();
+                }
+            }
+        }
+
         self.header.event(ctx, event, data, env);
         self.editor.event(ctx, event, data, env);
 
@@ -498,6 +642,22 @@ impl Widget<LapceTabData> for LapceEditorView {
         if editor.scroll_offset != offset {
             Arc::make_mut(data.main_split.editors.get_mut(&self.view_id).unwrap())
                 .scroll_offset = offset;
+            // The lines of the two sides of a diff view are aligned, so they
+            // scroll together
+            if let Some(diff_view) = editor.diff_view.as_ref() {
+                let other_offset = data
+                    .main_split
+                    .editors
+                    .get(&diff_view.other)
+                    .map(|other| other.scroll_offset);
+                if other_offset.is_some() && other_offset != Some(offset) {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::ForceScrollTo(offset.x, offset.y),
+                        Target::Widget(diff_view.other),
+                    ));
+                }
+            }
         }
     }
 
@@ -12,11 +12,14 @@ use lapce_data::{
     command::LapceUICommand,
     command::LAPCE_UI_COMMAND,
     config::{Config, LapceTheme},
-    data::LapceTabData,
+    data::{FocusArea, LapceTabData},
     split::SplitDirection,
     svg::{file_svg_new, get_svg},
 };
-use lapce_data::{data::PanelKind, explorer::FileExplorerData};
+use lapce_data::{
+    data::PanelKind,
+    explorer::{FileExplorerData, FileOperation},
+};
 use lapce_rpc::file::FileNodeItem;
 
 use crate::{
@@ -209,7 +212,8 @@ pub fn get_item_children_mut(
 
 pub struct FileExplorer {
     widget_id: WidgetId,
-    file_list: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
+    file_list:
+        WidgetPod<LapceTabData, LapceScrollNew<LapceTabData, FileExplorerFileList>>,
 }
 
 impl FileExplorer {
@@ -217,10 +221,25 @@ impl FileExplorer {
         let file_list = LapceScrollNew::new(FileExplorerFileList::new());
         Self {
             widget_id: data.widget_id,
-            file_list: WidgetPod::new(file_list.boxed()),
+            file_list: WidgetPod::new(file_list),
         }
     }
 
+    fn request_focus(&self, ctx: &mut EventCtx, data: &mut LapceTabData) {
+        ctx.request_focus();
+        data.focus_area = FocusArea::Panel(PanelKind::FileExplorer);
+        data.focus = self.widget_id;
+    }
+
+    fn ensure_active_visible(&mut self, data: &LapceTabData, env: &Env) {
+        let line_height = self.file_list.widget().child().line_height;
+        let index = data.file_explorer.active_selected.max(1) - 1;
+        let rect = Size::new(self.file_list.layout_rect().width(), line_height)
+            .to_rect()
+            .with_origin(Point::new(0.0, index as f64 * line_height));
+        self.file_list.widget_mut().scroll_to_visible(rect, env);
+    }
+
     pub fn new_panel(data: &LapceTabData) -> LapcePanel {
         let split_id = WidgetId::next();
         LapcePanel::new(
@@ -251,6 +270,43 @@ impl Widget<LapceTabData> for FileExplorer {
         data: &mut LapceTabData,
         env: &Env,
     ) {
+        match event {
+            Event::KeyDown(key_event) => {
+                let mut keypress = data.keypress.clone();
+                let mut file_explorer = data.file_explorer.clone();
+                Arc::make_mut(&mut keypress).key_down(
+                    ctx,
+                    key_event,
+                    Arc::make_mut(&mut file_explorer),
+                    env,
+                );
+                data.keypress = keypress;
+                data.file_explorer = file_explorer;
+                ctx.set_handled();
+            }
+            Event::Timer(token) => {
+                let mut keypress = data.keypress.clone();
+                let mut file_explorer = data.file_explorer.clone();
+                if Arc::make_mut(&mut keypress).timer(
+                    ctx,
+                    *token,
+                    Arc::make_mut(&mut file_explorer),
+                    env,
+                ) {
+                    data.keypress = keypress;
+                    data.file_explorer = file_explorer;
+                    ctx.set_handled();
+                }
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                if let LapceUICommand::Focus = command {
+                    self.request_focus(ctx, data);
+                    ctx.set_handled();
+                }
+            }
+            _ => (),
+        }
         self.file_list.event(ctx, event, data, env);
     }
 
@@ -267,11 +323,17 @@ impl Widget<LapceTabData> for FileExplorer {
     fn update(
         &mut self,
         ctx: &mut UpdateCtx,
-        _old_data: &LapceTabData,
+        old_data: &LapceTabData,
         data: &LapceTabData,
         env: &Env,
     ) {
         self.file_list.update(ctx, data, env);
+        if old_data.file_explorer.active_selected
+            != data.file_explorer.active_selected
+        {
+            self.ensure_active_visible(data, env);
+            ctx.request_paint();
+        }
     }
 
     fn layout(
@@ -395,53 +457,16 @@ impl Widget<LapceTabData> for FileExplorerFileList {
                 }
             }
             Event::MouseDown(mouse_event) => {
-                let file_explorer = Arc::make_mut(&mut data.file_explorer);
                 let index = ((mouse_event.pos.y + self.line_height)
                     / self.line_height) as usize;
-                if let Some(node) = file_explorer.get_node_by_index(index) {
-                    if node.is_dir {
-                        if node.read {
-                            node.open = !node.open;
-                        } else {
-                            let tab_id = data.id;
-                            let path = node.path_buf.clone();
-                            let event_sink = ctx.get_external_handle();
-                            data.proxy.read_dir(
-                                &node.path_buf,
-                                Box::new(move |result| {
-                                    if let Ok(res) = result {
-                                        let resp: Result<
-                                            Vec<FileNodeItem>,
-                                            serde_json::Error,
-                                        > = serde_json::from_value(res);
-                                        if let Ok(items) = resp {
-                                            let _ = event_sink.submit_command(
-                                                LAPCE_UI_COMMAND,
-                                                LapceUICommand::UpdateExplorerItems(
-                                                    index, path, items,
-                                                ),
-                                                Target::Widget(tab_id),
-                                            );
-                                        }
-                                    }
-                                }),
-                            );
-                        }
-                        let path = node.path_buf.clone();
-                        if let Some(paths) = file_explorer.node_tree(&path) {
-                            for path in paths.iter() {
-                                file_explorer.update_node_count(path);
-                            }
-                        }
-                    } else {
-                        ctx.submit_command(Command::new(
-                            LAPCE_UI_COMMAND,
-                            LapceUICommand::OpenFile(node.path_buf.clone()),
-                            Target::Widget(data.id),
-                        ));
-                    }
-                    file_explorer.active_selected = index;
-                }
+                let file_explorer = Arc::make_mut(&mut data.file_explorer);
+                file_explorer.operation = None;
+                file_explorer.activate(ctx, index);
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::Focus,
+                    Target::Widget(data.file_explorer.widget_id),
+                ));
             }
             _ => (),
         }
@@ -479,6 +504,12 @@ impl Widget<LapceTabData> for FileExplorerFileList {
         {
             ctx.request_layout();
         }
+        if old_data.file_explorer.active_selected
+            != data.file_explorer.active_selected
+            || old_data.file_explorer.operation != data.file_explorer.operation
+        {
+            ctx.request_paint();
+        }
     }
 
     fn layout(
@@ -525,9 +556,66 @@ impl Widget<LapceTabData> for FileExplorerFileList {
                     &mut HashMap::new(),
                 );
                 if i > max {
-                    return;
+                    break;
                 }
             }
         }
+
+        if let Some(operation) = data.file_explorer.operation.as_ref() {
+            self.paint_operation(ctx, data, operation, width);
+        }
+    }
+}
+
+impl FileExplorerFileList {
+    /// Paint the name being typed, or the deletion to confirm, over the
+    /// active entry
+    fn paint_operation(
+        &self,
+        ctx: &mut PaintCtx,
+        data: &LapceTabData,
+        operation: &FileOperation,
+        width: f64,
+    ) {
+        let text = match operation {
+            FileOperation::NewFile { name, .. } => format!("New file: {name}"),
+            FileOperation::Rename { name, .. } => format!("Rename to: {name}"),
+            FileOperation::Delete { path } => format!(
+                "Delete {}? Press Enter to confirm",
+                path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+            ),
+        };
+        let index = data.file_explorer.active_selected.max(1) - 1;
+        let rect = Size::new(width, self.line_height)
+            .to_rect()
+            .with_origin(Point::new(0.0, index as f64 * self.line_height));
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::EDITOR_BACKGROUND),
+        );
+        ctx.stroke(
+            rect.inflate(-0.5, -0.5),
+            data.config.get_color_unchecked(LapceTheme::EDITOR_FOCUS),
+            1.0,
+        );
+        let text_layout = ctx
+            .text()
+            .new_text_layout(text)
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(
+            &text_layout,
+            Point::new(
+                10.0,
+                rect.y0 + (self.line_height - text_layout.size().height) / 2.0,
+            ),
+        );
     }
 }
@@ -8,6 +8,7 @@ pub mod explorer;
 pub mod find;
 pub mod hover;
 pub mod keymap;
+pub mod location_list;
 pub mod menu;
 pub mod outline;
 pub mod palette;
@@ -25,4 +26,5 @@ pub mod status;
 mod tab;
 pub mod terminal;
 pub mod title;
+pub mod which_key;
 pub mod window;
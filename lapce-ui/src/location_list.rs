@@ -0,0 +1,223 @@
+use druid::{
+    piet::{Text, TextLayout as PietTextLayout, TextLayoutBuilder},
+    BoxConstraints, Command, Cursor, Data, Env, Event, EventCtx, FontFamily,
+    LayoutCtx, LifeCycle, LifeCycleCtx, MouseEvent, PaintCtx, Point, RenderContext,
+    Size, Target, UpdateCtx, Widget, WidgetExt,
+};
+use lapce_data::{
+    command::{LapceUICommand, LAPCE_UI_COMMAND},
+    config::LapceTheme,
+    data::{LapceTabData, PanelKind},
+    location_list::LocationListData,
+    split::SplitDirection,
+    svg::file_svg_new,
+};
+use std::sync::Arc;
+
+use crate::panel::{LapcePanel, PanelHeaderKind};
+
+pub fn new_location_list_panel(data: &LocationListData) -> LapcePanel {
+    LapcePanel::new(
+        PanelKind::LocationList,
+        data.widget_id,
+        data.split_id,
+        SplitDirection::Vertical,
+        PanelHeaderKind::Simple("Location List".to_string()),
+        vec![(
+            data.content_widget_id,
+            PanelHeaderKind::None,
+            LocationListContent::new().boxed(),
+            None,
+        )],
+    )
+}
+
+pub struct LocationListContent {
+    mouse_pos: Point,
+    content_height: f64,
+}
+
+impl LocationListContent {
+    pub fn new() -> Self {
+        Self {
+            mouse_pos: Point::ZERO,
+            content_height: 0.0,
+        }
+    }
+
+    fn mouse_down(
+        &self,
+        ctx: &mut EventCtx,
+        mouse_event: &MouseEvent,
+        data: &mut LapceTabData,
+    ) {
+        let line_height = data.config.editor.line_height as f64;
+        let n = (mouse_event.pos.y / line_height).floor() as usize;
+        let location_list = Arc::make_mut(&mut data.location_list);
+        if let Some(item) = location_list.select(n) {
+            ctx.submit_command(Command::new(
+                LAPCE_UI_COMMAND,
+                LapceUICommand::JumpToLocation(None, item.location()),
+                Target::Widget(data.id),
+            ));
+        }
+    }
+}
+
+impl Default for LocationListContent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Widget<LapceTabData> for LocationListContent {
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        _env: &Env,
+    ) {
+        match event {
+            Event::MouseMove(mouse_event) => {
+                self.mouse_pos = mouse_event.pos;
+
+                if mouse_event.pos.y < self.content_height {
+                    ctx.set_cursor(&Cursor::Pointer);
+                } else {
+                    ctx.clear_cursor();
+                }
+
+                ctx.request_paint();
+            }
+            Event::MouseDown(mouse_event) => {
+                self.mouse_down(ctx, mouse_event, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &LapceTabData,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        _env: &Env,
+    ) {
+        if !data.location_list.items.same(&old_data.location_list.items) {
+            ctx.request_layout();
+        } else if data.location_list.current != old_data.location_list.current {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        _env: &Env,
+    ) -> Size {
+        let line_height = data.config.editor.line_height as f64;
+        self.content_height = line_height * data.location_list.items.len() as f64;
+
+        Size::new(bc.max().width, self.content_height.max(bc.max().height))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, _env: &Env) {
+        let line_height = data.config.editor.line_height as f64;
+        let size = ctx.size();
+
+        if let Some(current) = data.location_list.current {
+            ctx.fill(
+                Size::new(size.width, line_height)
+                    .to_rect()
+                    .with_origin(Point::new(0.0, line_height * current as f64)),
+                data.config.get_color_unchecked(LapceTheme::PANEL_CURRENT),
+            );
+        }
+        if ctx.is_hot() && self.mouse_pos.y < self.content_height {
+            let n = (self.mouse_pos.y / line_height).floor() as usize;
+            ctx.fill(
+                Size::new(size.width, line_height)
+                    .to_rect()
+                    .with_origin(Point::new(0.0, line_height * n as f64)),
+                data.config.get_color_unchecked(LapceTheme::PANEL_HOVERED),
+            );
+        }
+
+        let rect = ctx.region().bounding_box();
+        let min = (rect.y0 / line_height).floor() as usize;
+        let max = (rect.y1 / line_height) as usize + 2;
+        let padding = (line_height - 14.0) / 2.0;
+
+        for (i, item) in data
+            .location_list
+            .items
+            .iter()
+            .enumerate()
+            .skip(min)
+            .take(max - min)
+        {
+            let y = line_height * i as f64;
+            let svg = file_svg_new(&item.path);
+            let rect = Size::new(line_height, line_height)
+                .to_rect()
+                .with_origin(Point::new(0.0, y))
+                .inflate(-padding, -padding);
+            ctx.draw_svg(&svg, rect, None);
+
+            let text_layout = ctx
+                .text()
+                .new_text_layout(format!(
+                    "{}:{}:{}",
+                    item.path.file_name().and_then(|s| s.to_str()).unwrap_or(""),
+                    item.start.line + 1,
+                    item.start.character + 1,
+                ))
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            let x = text_layout.size().width + line_height + 10.0;
+            ctx.draw_text(
+                &text_layout,
+                Point::new(
+                    line_height,
+                    y + (line_height - text_layout.size().height) / 2.0,
+                ),
+            );
+
+            let text_layout = ctx
+                .text()
+                .new_text_layout(
+                    item.message.lines().next().unwrap_or("").to_string(),
+                )
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(
+                &text_layout,
+                Point::new(x, y + (line_height - text_layout.size().height) / 2.0),
+            );
+        }
+    }
+}
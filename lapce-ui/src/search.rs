@@ -1,8 +1,8 @@
 use druid::{
     piet::{Text, TextAttribute, TextLayout as PietTextLayout, TextLayoutBuilder},
-    BoxConstraints, Command, Cursor, Data, Env, Event, EventCtx, FontFamily,
-    FontWeight, LayoutCtx, LifeCycle, LifeCycleCtx, MouseEvent, PaintCtx, Point,
-    RenderContext, Size, Target, UpdateCtx, Widget, WidgetExt, WidgetId,
+    BoxConstraints, Command, Cursor, Env, Event, EventCtx, FontFamily, FontWeight,
+    LayoutCtx, LifeCycle, LifeCycleCtx, MouseEvent, PaintCtx, Point, RenderContext,
+    Size, Target, UpdateCtx, Widget, WidgetExt, WidgetId,
 };
 use lapce_data::{
     command::{LapceUICommand, LAPCE_UI_COMMAND},
@@ -213,7 +213,7 @@ impl Widget<LapceTabData> for SearchContent {
         data: &LapceTabData,
         _env: &Env,
     ) {
-        if !old_data.search.matches.same(&data.search.matches) {
+        if !old_data.search.matches.ptr_eq(&data.search.matches) {
             ctx.request_layout();
         }
     }
@@ -1,65 +1,161 @@
-use lsp_types::ParameterLabel;
-use lsp_types::SignatureHelp;
+use std::sync::Arc;
 
-#[derive(Clone)]
-pub struct SignatureState {
-    pub offset: Option<usize>,
-    pub signature: Option<SignatureHelp>,
-    pub active: Option<(usize, usize)>,
+use druid::{
+    piet::{Text, TextAttribute, TextLayout, TextLayoutBuilder},
+    BoxConstraints, Env, Event, EventCtx, FontFamily, FontWeight, LayoutCtx,
+    LifeCycle, LifeCycleCtx, PaintCtx, Point, RenderContext, Size, UpdateCtx,
+    Widget, WidgetId,
+};
+use lapce_data::{
+    command::{LapceUICommand, LAPCE_UI_COMMAND},
+    config::LapceTheme,
+    data::LapceTabData,
+    signature::SignatureData,
+};
+
+/// The popup showing the signature of the function call under the cursor,
+/// with the active parameter highlighted
+pub struct SignatureContainer {
+    id: WidgetId,
 }
 
-impl SignatureState {
-    pub fn new() -> Self {
-        Self {
-            offset: None,
-            signature: None,
-            active: None,
-        }
+impl SignatureContainer {
+    const PADDING_X: f64 = 10.0;
+    const PADDING_Y: f64 = 5.0;
+
+    pub fn new(data: &SignatureData) -> Self {
+        Self { id: data.id }
     }
+}
 
-    pub fn update(&mut self, offset: usize, commas: Vec<usize>) -> Option<bool> {
-        let signature = self.signature.as_ref()?;
-        let params = signature.signatures[0].parameters.as_ref()?;
-        if params.is_empty() {
-            return None;
-        }
-        let mut index = commas.len();
-        for (i, c) in commas.iter().enumerate() {
-            if offset <= *c {
-                index = i;
-                break;
-            }
-        }
+impl Widget<LapceTabData> for SignatureContainer {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.id)
+    }
 
-        let label = signature.signatures[0].label.clone();
-        let active = if index >= params.len() {
-            None
-        } else {
-            match &params[index].label {
-                ParameterLabel::Simple(s) => {
-                    let start = label.find(s)?;
-                    Some((start, start + s.len()))
-                }
-                ParameterLabel::LabelOffsets(offsets) => {
-                    Some((offsets[0] as usize, offsets[1] as usize))
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        _env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                if let LapceUICommand::UpdateSignature(request_id, resp) = command {
+                    let signature = Arc::make_mut(&mut data.signature);
+                    signature.receive(*request_id, resp.to_owned());
+                    ctx.request_layout();
                 }
             }
-        };
+            _ => {}
+        }
+    }
 
-        let changed = self.active != active;
-        self.active = active;
-        Some(changed)
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &LapceTabData,
+        _env: &Env,
+    ) {
     }
 
-    pub fn clear(&mut self) {
-        self.offset = None;
-        self.signature = None;
-        self.active = None;
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        _env: &Env,
+    ) {
+        if !old_data.signature.same(&data.signature) {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        _env: &Env,
+    ) -> Size {
+        let info = match data.signature.active_signature() {
+            Some(info) => info,
+            None => return Size::ZERO,
+        };
+
+        let text_layout = ctx
+            .text()
+            .new_text_layout(info.label.clone())
+            .font(
+                FontFamily::new_unchecked(data.config.editor.font_family.clone()),
+                data.config.editor.font_size as f64,
+            )
+            .max_width(bc.max().width - Self::PADDING_X * 2.0)
+            .build()
+            .unwrap();
+        let text_size = text_layout.size();
+        Size::new(
+            text_size.width + Self::PADDING_X * 2.0,
+            text_size.height + Self::PADDING_Y * 2.0,
+        )
     }
-}
 
-impl Default for SignatureState {
-    fn default() -> Self {
-        Self::new()
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, _env: &Env) {
+        if !data.signature.is_active() {
+            return;
+        }
+        let info = match data.signature.active_signature() {
+            Some(info) => info,
+            None => return,
+        };
+
+        let size = ctx.size();
+        let rect = size.to_rect();
+        let shadow_width = 5.0;
+        ctx.blurred_rect(
+            rect,
+            shadow_width,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::HOVER_BACKGROUND),
+        );
+
+        let mut text_layout = ctx
+            .text()
+            .new_text_layout(info.label.clone())
+            .font(
+                FontFamily::new_unchecked(data.config.editor.font_family.clone()),
+                data.config.editor.font_size as f64,
+            )
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .max_width(size.width - Self::PADDING_X * 2.0);
+        if let Some((start, end)) = data.signature.active_parameter_range() {
+            text_layout = text_layout
+                .range_attribute(
+                    start..end,
+                    TextAttribute::TextColor(
+                        data.config
+                            .get_color_unchecked(LapceTheme::EDITOR_FOCUS)
+                            .clone(),
+                    ),
+                )
+                .range_attribute(
+                    start..end,
+                    TextAttribute::Weight(FontWeight::BOLD),
+                );
+        }
+        let text_layout = text_layout.build().unwrap();
+        ctx.draw_text(&text_layout, Point::new(Self::PADDING_X, Self::PADDING_Y));
     }
 }
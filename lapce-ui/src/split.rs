@@ -369,7 +369,7 @@ impl LapceSplitNew {
             ));
         }
         let view_id = self.children[index].widget.id();
-        data.main_split.editors.remove(&view_id);
+        data.main_split.remove_editor(&view_id);
         self.children.remove(index);
         self.children_ids.remove(index);
 
@@ -509,6 +509,11 @@ impl LapceSplitNew {
         );
         self.even_flex_children();
         ctx.children_changed();
+        ctx.submit_command(Command::new(
+            LAPCE_UI_COMMAND,
+            LapceUICommand::Focus,
+            Target::Widget(terminal_data.widget_id),
+        ));
     }
 
     pub fn split_terminal_close(
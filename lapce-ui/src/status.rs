@@ -1,18 +1,21 @@
 use druid::{
     piet::{Text, TextLayout, TextLayoutBuilder},
-    Color, Command, Event, EventCtx, FontFamily, MouseEvent, Point, RenderContext,
+    Command, Data, Event, EventCtx, FontFamily, MouseEvent, Point, RenderContext,
     Size, Target, Widget,
 };
 use lapce_data::{
+    buffer::BufferContent,
     command::{
         CommandTarget, LapceCommandNew, LapceWorkbenchCommand, LAPCE_NEW_COMMAND,
     },
     config::LapceTheme,
     data::{FocusArea, LapceTabData, PanelKind},
     panel::PanelPosition,
+    proxy::ProxyStatus,
     state::Mode,
-    svg::get_svg, 
+    svg::get_svg,
 };
+use lapce_rpc::buffer::LineEnding;
 
 use crate::tab::LapceIcon;
 
@@ -67,6 +70,9 @@ impl LapceStatusNew {
                     }
                     PanelKind::Search => LapceWorkbenchCommand::ToggleSearchVisual,
                     PanelKind::Problem => LapceWorkbenchCommand::ToggleProblemVisual,
+                    PanelKind::LocationList => {
+                        LapceWorkbenchCommand::ToggleLocationListVisual
+                    }
                 };
 
                 LapceIcon {
@@ -164,7 +170,11 @@ impl Widget<LapceTabData> for LapceStatusNew {
             data.main_split.active_editor(),
         ) {
             (Some(old_data), Some(data)) => {
-                if old_data.cursor.get_mode() != data.cursor.get_mode() {
+                if old_data.view_id != data.view_id
+                    || old_data.cursor.get_mode() != data.cursor.get_mode()
+                    || old_data.cursor.offset() != data.cursor.offset()
+                    || old_data.stats != data.stats
+                {
                     ctx.request_paint();
                 }
             }
@@ -174,12 +184,20 @@ impl Widget<LapceTabData> for LapceStatusNew {
 
         if old_data.main_split.warning_count != data.main_split.warning_count
             || old_data.main_split.error_count != data.main_split.error_count
+            || old_data.keypress.pending_keys() != data.keypress.pending_keys()
+            || old_data.proxy_status != data.proxy_status
         {
             ctx.request_paint();
             return;
         }
 
-        if !old_data.progresses.ptr_eq(&data.progresses) {
+        if !old_data.progresses.ptr_eq(&data.progresses)
+            || !old_data.focus_area.same(&data.focus_area)
+            || !old_data
+                .main_split
+                .open_files
+                .same(&data.main_split.open_files)
+        {
             ctx.request_paint();
         }
     }
@@ -234,10 +252,12 @@ impl Widget<LapceTabData> for LapceStatusNew {
                             .unwrap_or(Mode::Normal)
                     };
                 match mode {
-                    Mode::Normal => ("Normal", Color::rgb8(64, 120, 242)),
-                    Mode::Insert => ("Insert", Color::rgb8(228, 86, 73)),
-                    Mode::Visual => ("Visual", Color::rgb8(193, 132, 1)),
-                    Mode::Terminal => ("Terminal", Color::rgb8(228, 86, 73)),
+                    Mode::Normal => ("Normal", LapceTheme::STATUS_MODAL_NORMAL),
+                    Mode::Insert => ("Insert", LapceTheme::STATUS_MODAL_INSERT),
+                    Mode::Visual => ("Visual", LapceTheme::STATUS_MODAL_VISUAL),
+                    Mode::Terminal => {
+                        ("Terminal", LapceTheme::STATUS_MODAL_TERMINAL)
+                    }
                 }
             };
 
@@ -254,7 +274,7 @@ impl Widget<LapceTabData> for LapceStatusNew {
                 .unwrap();
             let text_size = text_layout.size();
             let fill_size = Size::new(text_size.width + 10.0, size.height);
-            ctx.fill(fill_size.to_rect(), &color);
+            ctx.fill(fill_size.to_rect(), data.config.get_color_unchecked(color));
             ctx.draw_text(&text_layout, Point::new(5.0, 4.0));
             left += text_size.width + 10.0;
         }
@@ -276,6 +296,44 @@ impl Widget<LapceTabData> for LapceStatusNew {
         ctx.draw_text(&text_layout, Point::new(left + 10.0, 4.0));
         left += 10.0 + text_layout.size().width;
 
+        let pending_keys = data.keypress.pending_keys();
+        if !pending_keys.is_empty() {
+            let text_layout = ctx
+                .text()
+                .new_text_layout(pending_keys)
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(&text_layout, Point::new(left + 10.0, 4.0));
+            left += 10.0 + text_layout.size().width;
+        }
+
+        let proxy_status = match *data.proxy_status {
+            ProxyStatus::Reconnecting => Some("Restarting the proxy ..."),
+            ProxyStatus::Disconnected => Some("Proxy disconnected"),
+            ProxyStatus::Connecting | ProxyStatus::Connected => None,
+        };
+        if let Some(proxy_status) = proxy_status {
+            let text_layout = ctx
+                .text()
+                .new_text_layout(proxy_status)
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::LAPCE_WARN)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(&text_layout, Point::new(left + 10.0, 4.0));
+            left += 10.0 + text_layout.size().width;
+        }
+
         for progress in data.progresses.iter() {
             let mut text = progress.title.clone();
             let message = progress.message.clone().unwrap_or_else(|| "".to_string());
@@ -298,6 +356,95 @@ impl Widget<LapceTabData> for LapceStatusNew {
             left += 10.0 + text_layout.size().width;
         }
 
+        let editor = data
+            .main_split
+            .active_editor()
+            .filter(|editor| matches!(editor.content, BufferContent::File(_)));
+        let buffer =
+            editor.and_then(|editor| data.main_split.editor_buffer(editor.view_id));
+        if let (Some(editor), Some(buffer)) = (editor, buffer) {
+            if let BufferContent::File(path) = &buffer.content {
+                let path = data
+                    .workspace
+                    .path
+                    .as_ref()
+                    .and_then(|workspace| path.strip_prefix(workspace).ok())
+                    .unwrap_or(path);
+                let mut text = path.to_string_lossy().to_string();
+                if buffer.dirty {
+                    text = "*".to_string() + &text;
+                }
+                let text_layout = ctx
+                    .text()
+                    .new_text_layout(text)
+                    .font(FontFamily::SYSTEM_UI, 13.0)
+                    .text_color(
+                        data.config
+                            .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                            .clone(),
+                    )
+                    .build()
+                    .unwrap();
+                ctx.draw_text(&text_layout, Point::new(left + 10.0, 4.0));
+            }
+
+            let (line, col) = buffer.offset_to_line_col(
+                editor.cursor.offset(),
+                data.config.editor.tab_width,
+            );
+            let mut text = if buffer.binary {
+                "Binary".to_string()
+            } else {
+                let line_ending = match buffer.line_ending {
+                    LineEnding::Lf => "LF",
+                    LineEnding::CrLf => "CRLF",
+                };
+                format!(
+                    "Ln {}, Col {}  {}  {}",
+                    line + 1,
+                    col + 1,
+                    buffer.encoding,
+                    line_ending
+                )
+            };
+            if buffer.read_only {
+                text = format!("Read Only  {}", text);
+            }
+            if let Some((total, selection)) = editor.stats.as_ref() {
+                let stats = match selection {
+                    Some(selection) => format!(
+                        "Selected {} of {} Words, {} of {} Chars, {} of {} Lines",
+                        selection.words,
+                        total.words,
+                        selection.chars,
+                        total.chars,
+                        selection.lines,
+                        total.lines
+                    ),
+                    None => format!(
+                        "{} Words, {} Chars, {} Lines",
+                        total.words, total.chars, total.lines
+                    ),
+                };
+                text = format!("{}  {}", stats, text);
+            }
+            let text_layout = ctx
+                .text()
+                .new_text_layout(text)
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(
+                &text_layout,
+                Point::new(size.width - text_layout.size().width - 10.0, 4.0),
+            );
+        }
+
         let icon_padding = (self.height - self.icon_size) / 2.0;
         for icon in self.panel_icons.iter() {
             if icon.rect.contains(self.mouse_pos) {
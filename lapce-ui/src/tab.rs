@@ -1,4 +1,4 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, sync::Arc};
 
 use druid::{
     kurbo::Line,
@@ -10,7 +10,7 @@ use druid::{
 };
 use itertools::Itertools;
 use lapce_data::{
-    buffer::LocalBufferKind,
+    buffer::{BufferContent, LocalBufferKind},
     command::{
         CommandTarget, LapceCommand, LapceCommandNew, LapceUICommand,
         LAPCE_NEW_COMMAND, LAPCE_UI_COMMAND,
@@ -18,16 +18,19 @@ use lapce_data::{
     completion::CompletionStatus,
     config::{Config, LapceTheme},
     data::{
-        DragContent, EditorDiagnostic, FocusArea, LapceTabData, PanelKind,
-        WorkProgress,
+        confirm_disk_change, DiskChangeChoice, DragContent, EditorDiagnostic,
+        FocusArea, LapceTabData, PanelKind, WorkProgress,
     },
     editor::EditorLocationNew,
     hover::HoverStatus,
     keypress::{DefaultKeyPressHandler, KeyPressData},
+    location_list::{
+        diagnostic_items, search_items, LocationListItem, LocationListSource,
+    },
     movement::{self, CursorMode, Selection},
     palette::PaletteStatus,
     panel::{PanelPosition, PanelResizePosition},
-    proxy::path_from_url,
+    proxy::{path_from_url, ProxyStatus},
     state::LapceWorkspaceType,
 };
 use lsp_types::DiagnosticSeverity;
@@ -35,11 +38,12 @@ use serde::Deserialize;
 
 use crate::{
     activity::ActivityBar, code_action::CodeAction, completion::CompletionContainer,
-    explorer::FileExplorer, hover::HoverContainer, palette::NewPalette,
-    picker::FilePicker, plugin::Plugin, problem::new_problem_panel,
-    search::new_search_panel, settings::LapceSettingsPanel,
+    explorer::FileExplorer, hover::HoverContainer,
+    location_list::new_location_list_panel, palette::NewPalette, picker::FilePicker,
+    plugin::Plugin, problem::new_problem_panel, search::new_search_panel,
+    settings::LapceSettingsPanel, signature::SignatureContainer,
     source_control::new_source_control_panel, split::split_data_widget,
-    status::LapceStatusNew, terminal::TerminalPanel,
+    status::LapceStatusNew, terminal::TerminalPanel, which_key::WhichKey,
 };
 
 pub struct LapceIcon {
@@ -60,9 +64,11 @@ pub struct LapceTabNew {
     main_split: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     completion: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     hover: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
+    signature: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     palette: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     code_action: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     status: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
+    which_key: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     picker: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     settings: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     panels:
@@ -86,6 +92,7 @@ impl LapceTabNew {
         let activity = ActivityBar::new();
         let completion = CompletionContainer::new(&data.completion);
         let hover = HoverContainer::new(&data.hover);
+        let signature = SignatureContainer::new(&data.signature);
         let palette = NewPalette::new(
             &data.palette,
             data.main_split
@@ -121,6 +128,12 @@ impl LapceTabNew {
         let problem = new_problem_panel(&data.problem);
         panels.insert(PanelKind::Problem, WidgetPod::new(problem.boxed()));
 
+        let location_list = new_location_list_panel(&data.location_list);
+        panels.insert(
+            PanelKind::LocationList,
+            WidgetPod::new(location_list.boxed()),
+        );
+
         let picker = FilePicker::new(data);
 
         let settings = LapceSettingsPanel::new(data);
@@ -131,10 +144,12 @@ impl LapceTabNew {
             main_split: WidgetPod::new(main_split.boxed()),
             completion: WidgetPod::new(completion.boxed()),
             hover: WidgetPod::new(hover.boxed()),
+            signature: WidgetPod::new(signature.boxed()),
             code_action: WidgetPod::new(code_action.boxed()),
             picker: WidgetPod::new(picker.boxed()),
             palette: WidgetPod::new(palette.boxed()),
             status: WidgetPod::new(status.boxed()),
+            which_key: WidgetPod::new(WhichKey::new().boxed()),
             settings: WidgetPod::new(settings.boxed()),
             panels,
             current_bar_hover: None,
@@ -319,11 +334,31 @@ impl Widget<LapceTabData> for LapceTabNew {
                     LapceUICommand::LoadBuffer {
                         path,
                         content,
+                        line_ending,
+                        encoding,
+                        binary,
+                        read_only,
                         locations,
                     } => {
-                        let buffer =
-                            data.main_split.open_files.get_mut(path).unwrap();
-                        Arc::make_mut(buffer).load_content(content);
+                        let buffer = Arc::make_mut(
+                            data.main_split.open_files.get_mut(path).unwrap(),
+                        );
+                        buffer.line_ending =
+                            data.config.editor.line_ending_for(*line_ending);
+                        buffer.encoding = encoding.clone();
+                        buffer.binary = *binary;
+                        buffer.read_only = *read_only || *binary;
+                        buffer.load_content(content);
+                        if let Ok(history) = data.db.get_undo_history(path) {
+                            buffer.restore_undo_history(history);
+                        }
+                        if data.config.editor.show_blame {
+                            buffer.retrieve_blame(
+                                data.id,
+                                data.proxy.clone(),
+                                ctx.get_external_handle(),
+                            );
+                        }
                         for (view_id, location) in locations {
                             data.main_split.go_to_location(
                                 ctx,
@@ -345,11 +380,15 @@ impl Widget<LapceTabData> for LapceTabNew {
                         }
                         if pattern.is_empty() {
                             Arc::make_mut(&mut data.find).unset();
-                            Arc::make_mut(&mut data.search).matches =
-                                Arc::new(HashMap::new());
+                            Arc::make_mut(&mut data.search).cancel(&data.proxy);
                         } else {
                             let find = Arc::make_mut(&mut data.find);
-                            find.set_find(pattern, false, false, false);
+                            find.set_find(
+                                pattern,
+                                data.search.case_sensitive,
+                                false,
+                                data.search.whole_word,
+                            );
                             find.visual = true;
                             if data.focus_area == FocusArea::Panel(PanelKind::Search)
                             {
@@ -367,50 +406,33 @@ impl Widget<LapceTabData> for LapceTabNew {
                                     ));
                                 }
                             }
-                            let pattern = pattern.to_string();
-                            let event_sink = ctx.get_external_handle();
-                            let tab_id = data.id;
-                            data.proxy.global_search(
-                                    pattern.clone(),
-                                    Box::new(move |result| {
-                                        if let Ok(matches) = result {
-                                            if let Ok(matches) =
-                                                serde_json::from_value::<
-                                                    HashMap<
-                                                        PathBuf,
-                                                        Vec<(
-                                                            usize,
-                                                            (usize, usize),
-                                                            String,
-                                                        )>,
-                                                    >,
-                                                >(
-                                                    matches
-                                                )
-                                            {
-                                                let _ = event_sink.submit_command(
-                                                    LAPCE_UI_COMMAND,
-                                                    LapceUICommand::GlobalSearchResult(
-                                                        pattern,
-                                                        Arc::new(matches),
-                                                    ),
-                                                    Target::Widget(tab_id),
-                                                );
-                                            }
-                                        }
-                                    }),
-                                )
+                            Arc::make_mut(&mut data.search)
+                                .search(pattern, &data.proxy);
+                        }
+                        // The matches of the old search are gone from the
+                        // search panel, so they go from the list too
+                        if matches!(
+                            data.location_list.source,
+                            Some(LocationListSource::Search(_))
+                        ) {
+                            Arc::make_mut(&mut data.location_list).set_items(
+                                LocationListSource::Search(data.search.search_id),
+                                Vec::new(),
+                            );
                         }
                     }
-                    LapceUICommand::GlobalSearchResult(pattern, matches) => {
-                        let buffer = data
-                            .main_split
-                            .local_buffers
-                            .get(&LocalBufferKind::Search)
-                            .unwrap();
-                        if &buffer.rope.to_string() == pattern {
-                            Arc::make_mut(&mut data.search).matches =
-                                matches.clone();
+                    LapceUICommand::GlobalSearchMatches(
+                        search_id,
+                        path,
+                        matches,
+                    ) => {
+                        Arc::make_mut(&mut data.search)
+                            .add_matches(*search_id, path, matches);
+                        if *search_id == data.search.search_id {
+                            Arc::make_mut(&mut data.location_list).add_items(
+                                LocationListSource::Search(*search_id),
+                                search_items(path, matches),
+                            );
                         }
                     }
                     LapceUICommand::LoadBufferHead { path, id, content } => {
@@ -420,6 +442,22 @@ impl Widget<LapceTabData> for LapceTabNew {
                         buffer.load_history(id, content.clone());
                         ctx.set_handled();
                     }
+                    LapceUICommand::UpdateBlame {
+                        id,
+                        path,
+                        rev,
+                        hunks,
+                    } => {
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            if buffer.id == *id {
+                                Arc::make_mut(buffer).blame =
+                                    Some((*rev, Arc::new(hunks.clone())));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
                     LapceUICommand::UpdateTerminalTitle(term_id, title) => {
                         let terminal_panel = Arc::make_mut(&mut data.terminal);
                         if let Some(terminal) =
@@ -433,6 +471,21 @@ impl Widget<LapceTabData> for LapceTabNew {
                         ctx.set_handled();
                     }
                     LapceUICommand::ProxyUpdateStatus(status) => {
+                        // a restarted proxy doesn't have the open buffers
+                        if *data.proxy_status == ProxyStatus::Reconnecting
+                            && *status == ProxyStatus::Connected
+                        {
+                            for (path, buffer) in data.main_split.open_files.iter() {
+                                if buffer.loaded {
+                                    data.proxy.reopen_buffer(
+                                        buffer.id,
+                                        path,
+                                        &buffer.rope.to_string(),
+                                        buffer.rev,
+                                    );
+                                }
+                            }
+                        }
                         data.proxy_status = Arc::new(*status);
                         ctx.set_handled();
                     }
@@ -441,6 +494,18 @@ impl Widget<LapceTabData> for LapceTabNew {
                         data.set_picker_pwd(path.clone());
                         ctx.set_handled();
                     }
+                    LapceUICommand::SettingsError(path, errors) => {
+                        tinyfiledialogs::message_box_ok(
+                            "Invalid Settings",
+                            &format!(
+                                "The following settings in {} aren't used:\n\n{}",
+                                path.display(),
+                                errors.join("\n")
+                            ),
+                            tinyfiledialogs::MessageBoxIcon::Warning,
+                        );
+                        ctx.set_handled();
+                    }
                     LapceUICommand::CloseTerminal(id) => {
                         let terminal_panel = Arc::make_mut(&mut data.terminal);
                         if let Some(terminal) = terminal_panel.terminals.get_mut(id)
@@ -485,6 +550,13 @@ impl Widget<LapceTabData> for LapceTabNew {
                                 data.proxy.clone(),
                                 ctx.get_external_handle(),
                             );
+                            if data.config.editor.show_blame {
+                                buffer.retrieve_blame(
+                                    data.id,
+                                    data.proxy.clone(),
+                                    ctx.get_external_handle(),
+                                );
+                            }
                         }
                         ctx.set_handled();
                     }
@@ -555,6 +627,15 @@ impl Widget<LapceTabData> for LapceTabNew {
                         data.main_split.error_count = errors;
                         data.main_split.warning_count = warnings;
 
+                        if data.location_list.source
+                            == Some(LocationListSource::Diagnostics)
+                        {
+                            Arc::make_mut(&mut data.location_list).set_items(
+                                LocationListSource::Diagnostics,
+                                diagnostic_items(&data.main_split.diagnostics),
+                            );
+                        }
+
                         ctx.set_handled();
                     }
                     LapceUICommand::DocumentFormatAndSave(path, rev, result) => {
@@ -577,22 +658,52 @@ impl Widget<LapceTabData> for LapceTabNew {
                         ctx.set_handled();
                     }
                     LapceUICommand::BufferSave(path, rev) => {
-                        let buffer =
-                            data.main_split.open_files.get_mut(path).unwrap();
-                        if buffer.rev == *rev {
-                            Arc::make_mut(buffer).dirty = false;
+                        // A scratch buffer saved as it was closed isn't open
+                        let buffer = data.main_split.open_files.get_mut(path);
+                        if let Some(buffer) =
+                            buffer.filter(|buffer| buffer.rev == *rev)
+                        {
+                            let buffer = Arc::make_mut(buffer);
+                            buffer.dirty = false;
+                            buffer.changed_on_disk = false;
+                            if data.config.editor.show_blame {
+                                buffer.retrieve_blame(
+                                    data.id,
+                                    data.proxy.clone(),
+                                    ctx.get_external_handle(),
+                                );
+                            }
                         }
                         ctx.set_handled();
                     }
+                    LapceUICommand::SaveScratchBuffer(id, path) => {
+                        data.main_split.save_scratch_buffer(
+                            *id,
+                            path.clone(),
+                            ctx.get_external_handle(),
+                        );
+                        ctx.set_handled();
+                    }
                     LapceUICommand::LoadBufferAndGoToPosition {
                         path,
                         content,
                         editor_view_id,
                         location,
                     } => {
-                        let buffer =
-                            data.main_split.open_files.get_mut(path).unwrap();
-                        Arc::make_mut(buffer).load_content(content);
+                        let buffer = Arc::make_mut(
+                            data.main_split.open_files.get_mut(path).unwrap(),
+                        );
+                        buffer.load_content(content);
+                        if let Ok(history) = data.db.get_undo_history(path) {
+                            buffer.restore_undo_history(history);
+                        }
+                        if data.config.editor.show_blame {
+                            buffer.retrieve_blame(
+                                data.id,
+                                data.proxy.clone(),
+                                ctx.get_external_handle(),
+                            );
+                        }
                         data.main_split.go_to_location(
                             ctx,
                             Some(*editor_view_id),
@@ -650,6 +761,10 @@ impl Widget<LapceTabData> for LapceTabNew {
                         KeyPressData::update_file(keymap, keys);
                     }
                     LapceUICommand::OpenFile(path) => {
+                        // Remote paths can't be checked to still exist
+                        if data.workspace.kind == LapceWorkspaceType::Local {
+                            Config::add_recent_file(path);
+                        }
                         data.main_split.jump_to_location(
                             ctx,
                             None,
@@ -771,15 +886,39 @@ impl Widget<LapceTabData> for LapceTabNew {
                     LapceUICommand::PaletteReferences(offset, locations) => {
                         if let Some(editor) = data.main_split.active_editor() {
                             if *offset == editor.cursor.offset() {
-                                let locations = locations
+                                let items = locations
                                     .iter()
-                                    .map(|l| EditorLocationNew {
-                                        path: path_from_url(&l.uri),
-                                        position: Some(l.range.start),
-                                        scroll_offset: None,
-                                        history: None,
+                                    .map(|l| {
+                                        let path = path_from_url(&l.uri);
+                                        let message = data
+                                            .main_split
+                                            .open_files
+                                            .get(&path)
+                                            .map(|buffer| {
+                                                buffer
+                                                    .line_content(
+                                                        l.range.start.line as usize,
+                                                    )
+                                                    .trim()
+                                                    .to_string()
+                                            })
+                                            .unwrap_or_default();
+                                        LocationListItem {
+                                            path,
+                                            start: l.range.start,
+                                            end: l.range.end,
+                                            message,
+                                        }
                                     })
+                                    .collect::<Vec<_>>();
+                                let locations = items
+                                    .iter()
+                                    .map(|item| item.location())
                                     .collect();
+                                Arc::make_mut(&mut data.location_list).set_items(
+                                    LocationListSource::References,
+                                    items,
+                                );
                                 ctx.submit_command(Command::new(
                                     LAPCE_UI_COMMAND,
                                     LapceUICommand::RunPaletteReferences(locations),
@@ -794,8 +933,10 @@ impl Widget<LapceTabData> for LapceTabNew {
                             if &buffer.id == id {
                                 if buffer.rev + 1 == *rev {
                                     let buffer = Arc::make_mut(buffer);
-                                    buffer.load_content(new_content);
-                                    buffer.rev = *rev;
+                                    // The reload can be undone like an edit
+                                    buffer.reload_content(new_content, *rev);
+                                    buffer.dirty = false;
+                                    buffer.changed_on_disk = false;
 
                                     for (_, editor) in
                                         data.main_split.editors.iter_mut()
@@ -838,13 +979,69 @@ impl Widget<LapceTabData> for LapceTabNew {
                         }
                         ctx.set_handled();
                     }
-                    LapceUICommand::UpdateSemanticStyles(_id, path, rev, styles) => {
+                    LapceUICommand::BufferChangedOnDisk(id, content) => {
+                        let path = data
+                            .main_split
+                            .open_files
+                            .iter()
+                            .find(|(_, buffer)| &buffer.id == id)
+                            .map(|(path, _)| path.clone());
+                        if let Some(path) = path {
+                            match confirm_disk_change(&path) {
+                                DiskChangeChoice::Reload => {
+                                    data.proxy.reload_buffer(*id);
+                                }
+                                DiskChangeChoice::Keep => {}
+                                DiskChangeChoice::Diff => {
+                                    // Reloading or keeping the changes is
+                                    // left to the commands for it
+                                    if let Some(buffer) =
+                                        data.main_split.open_files.get_mut(&path)
+                                    {
+                                        Arc::make_mut(buffer).changed_on_disk = true;
+                                    }
+                                    data.main_split.open_disk_diff_view(
+                                        ctx,
+                                        &path,
+                                        content.clone(),
+                                        &data.config,
+                                    );
+                                }
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateSignatureTriggerCharacters(
+                        id,
+                        characters,
+                    ) => {
+                        for (_, buffer) in data.main_split.open_files.iter_mut() {
+                            if &buffer.id == id {
+                                Arc::make_mut(buffer).signature_trigger_characters =
+                                    characters.clone();
+                                break;
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateSemanticStyles(
+                        _id,
+                        path,
+                        rev,
+                        range,
+                        styles,
+                    ) => {
                         let buffer =
                             data.main_split.open_files.get_mut(path).unwrap();
-                        if buffer.rev == *rev {
-                            let buffer = Arc::make_mut(buffer);
-                            buffer.semantic_styles = Some(styles.clone());
-                            buffer.line_styles.borrow_mut().clear();
+                        let applied = buffer.rev == *rev
+                            && Arc::make_mut(buffer).update_semantic_styles(
+                                range.clone(),
+                                styles.clone(),
+                            );
+                        // The proxy builds its next delta on these styles, so
+                        // it has to start over when they are dropped
+                        if !applied {
+                            data.proxy.refresh_semantic_tokens(buffer.id);
                         }
                         ctx.set_handled();
                     }
@@ -909,18 +1106,76 @@ impl Widget<LapceTabData> for LapceTabNew {
                         }
                         ctx.set_handled();
                     }
+                    LapceUICommand::SetLanguage(language) => {
+                        ctx.set_handled();
+                        if let Some(BufferContent::File(path)) = data
+                            .main_split
+                            .active_editor()
+                            .map(|editor| editor.content.clone())
+                        {
+                            if let Some(buffer) =
+                                data.main_split.open_files.get_mut(&path)
+                            {
+                                Arc::make_mut(buffer).set_language(*language);
+                            }
+                        }
+                    }
                     LapceUICommand::UpdateSyntax { path, rev, syntax } => {
                         ctx.set_handled();
-                        let buffer =
-                            data.main_split.open_files.get_mut(path).unwrap();
-                        let buffer = Arc::make_mut(buffer);
-                        if buffer.rev == *rev {
-                            buffer.syntax = Some(syntax.clone());
-                            if buffer.semantic_styles.is_none() {
-                                buffer.line_styles.borrow_mut().clear();
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            // Drop styles for a revision that has already been
+                            // superseded before touching the buffer, so that
+                            // stale updates don't trigger a repaint.
+                            // A parse in the language the buffer had before
+                            // it was changed is dropped as well
+                            if buffer.rev == *rev
+                                && buffer.syntax.as_ref().map(|s| s.language)
+                                    == Some(syntax.language)
+                            {
+                                let buffer = Arc::make_mut(buffer);
+                                buffer.syntax = Some(syntax.clone());
+                                if buffer.semantic_styles.is_none() {
+                                    buffer.line_styles.borrow_mut().clear();
+                                }
                             }
                         }
                     }
+                    LapceUICommand::UpdateInlayHints { path, rev, hints } => {
+                        ctx.set_handled();
+                        if let Some(buffer) = data
+                            .main_split
+                            .open_files
+                            .get_mut(path)
+                            .filter(|buffer| buffer.rev == *rev)
+                        {
+                            Arc::make_mut(buffer).set_inlay_hints(
+                                hints,
+                                data.config.editor.tab_width,
+                            );
+                        }
+                    }
+                    LapceUICommand::UpdateSyntaxHighlights {
+                        path,
+                        rev,
+                        range,
+                        styles,
+                    } => {
+                        ctx.set_handled();
+                        if let Some(buffer) = data
+                            .main_split
+                            .open_files
+                            .get_mut(path)
+                            .filter(|buffer| buffer.rev == *rev)
+                        {
+                            Arc::make_mut(buffer).update_syntax_highlights(
+                                *rev,
+                                range.clone(),
+                                styles.clone(),
+                            );
+                        }
+                    }
                     #[allow(unused_variables)]
                     LapceUICommand::UpdateHistoryChanges {
                         id,
@@ -948,13 +1203,16 @@ impl Widget<LapceTabData> for LapceTabNew {
                         ctx.set_handled();
                         let buffer =
                             data.main_split.open_files.get_mut(path).unwrap();
-                        Arc::make_mut(buffer)
-                            .history_styles
-                            .insert(history.to_string(), highlights.to_owned());
-                        buffer
-                            .history_line_styles
-                            .borrow_mut()
-                            .insert(history.to_string(), HashMap::new());
+                        // Unless the history was removed in the meantime
+                        if buffer.histories.contains_key(history) {
+                            Arc::make_mut(buffer)
+                                .history_styles
+                                .insert(history.to_string(), highlights.to_owned());
+                            buffer
+                                .history_line_styles
+                                .borrow_mut()
+                                .insert(history.to_string(), HashMap::new());
+                        }
                     }
                     LapceUICommand::UpdatePickerPwd(path) => {
                         Arc::make_mut(&mut data.picker).pwd = path.clone();
@@ -969,9 +1227,17 @@ impl Widget<LapceTabData> for LapceTabNew {
                     LapceUICommand::UpdateExplorerItems(_index, path, items) => {
                         let file_explorer = Arc::make_mut(&mut data.file_explorer);
                         if let Some(node) = file_explorer.get_node_mut(path) {
+                            // keep the entries already read, with their own
+                            // entries and whether they're expanded
+                            let mut children = std::mem::take(&mut node.children);
                             node.children = items
                                 .iter()
-                                .map(|item| (item.path_buf.clone(), item.clone()))
+                                .map(|item| {
+                                    let item = children
+                                        .remove(&item.path_buf)
+                                        .unwrap_or_else(|| item.clone());
+                                    (item.path_buf.clone(), item)
+                                })
                                 .collect();
                             node.read = true;
                             node.open = true;
@@ -994,6 +1260,7 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.palette.event(ctx, event, data, env);
         self.completion.event(ctx, event, data, env);
         self.hover.event(ctx, event, data, env);
+        self.signature.event(ctx, event, data, env);
         self.code_action.event(ctx, event, data, env);
         self.main_split.event(ctx, event, data, env);
         self.status.event(ctx, event, data, env);
@@ -1050,8 +1317,10 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.main_split.lifecycle(ctx, event, data, env);
         self.code_action.lifecycle(ctx, event, data, env);
         self.status.lifecycle(ctx, event, data, env);
+        self.which_key.lifecycle(ctx, event, data, env);
         self.completion.lifecycle(ctx, event, data, env);
         self.hover.lifecycle(ctx, event, data, env);
+        self.signature.lifecycle(ctx, event, data, env);
         self.picker.lifecycle(ctx, event, data, env);
         self.settings.lifecycle(ctx, event, data, env);
 
@@ -1100,8 +1369,10 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.main_split.update(ctx, data, env);
         self.completion.update(ctx, data, env);
         self.hover.update(ctx, data, env);
+        self.signature.update(ctx, data, env);
         self.code_action.update(ctx, data, env);
         self.status.update(ctx, data, env);
+        self.which_key.update(ctx, data, env);
         self.picker.update(ctx, data, env);
         self.settings.update(ctx, data, env);
         for (_, panel) in data.panels.iter() {
@@ -1372,6 +1643,17 @@ impl Widget<LapceTabData> for LapceTabNew {
             self.hover.set_origin(ctx, data, env, hover_origin);
         }
 
+        if data.signature.is_active() {
+            let signature_size = self.signature.layout(ctx, bc, data, env);
+            let signature_origin = data.signature_origin(
+                ctx.text(),
+                self_size,
+                signature_size,
+                &data.config,
+            );
+            self.signature.set_origin(ctx, data, env, signature_origin);
+        }
+
         if data.main_split.show_code_actions {
             let code_action_origin =
                 data.code_action_origin(ctx.text(), self_size, &data.config);
@@ -1380,6 +1662,19 @@ impl Widget<LapceTabData> for LapceTabNew {
                 .set_origin(ctx, data, env, code_action_origin);
         }
 
+        if !data.keypress.which_key.is_empty() {
+            let which_key_size = self.which_key.layout(ctx, bc, data, env);
+            self.which_key.set_origin(
+                ctx,
+                data,
+                env,
+                Point::new(
+                    10.0,
+                    self_size.height - status_size.height - which_key_size.height,
+                ),
+            );
+        }
+
         if data.palette.status != PaletteStatus::Inactive {
             let palette_size = self.palette.layout(ctx, bc, data, env);
             self.palette.set_origin(
@@ -1476,7 +1771,9 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.status.paint(ctx, data, env);
         self.completion.paint(ctx, data, env);
         self.hover.paint(ctx, data, env);
+        self.signature.paint(ctx, data, env);
         self.code_action.paint(ctx, data, env);
+        self.which_key.paint(ctx, data, env);
         self.palette.paint(ctx, data, env);
         self.picker.paint(ctx, data, env);
         self.settings.paint(ctx, data, env);
@@ -194,7 +194,8 @@ impl Widget<LapceTabData> for TerminalPanel {
         let rect = ctx.size().to_rect();
         ctx.fill(
             rect,
-            data.config.get_color_unchecked(LapceTheme::TERMINAL_BACKGROUND),
+            data.config
+                .get_color_unchecked(LapceTheme::TERMINAL_BACKGROUND),
         );
         self.split.paint(ctx, data, env);
     }
@@ -611,6 +612,11 @@ impl Widget<LapceTabData> for LapceTerminal {
                 }
                 data.keypress = keypress.clone();
             }
+            Event::Timer(token) if *token == data.keypress.pending_timer() => {
+                let mut keypress = data.keypress.clone();
+                Arc::make_mut(&mut keypress).timer(ctx, *token, &mut term_data, env);
+                data.keypress = keypress;
+            }
             Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
                 let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
                 if let LapceUICommand::Focus = command {
@@ -658,7 +664,9 @@ impl Widget<LapceTabData> for LapceTerminal {
         if self.width != size.width || self.height != size.height {
             self.width = size.width;
             self.height = size.height;
-            let width = data.config.editor_text_width(ctx.text(), "W");
+            let width = data
+                .config
+                .char_width(ctx.text(), data.config.editor.font_size as f64);
             let line_height = data.config.editor.line_height as f64;
             let width = if width > 0.0 {
                 (self.width / width).floor() as usize
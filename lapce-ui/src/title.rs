@@ -141,6 +141,9 @@ impl Widget<LapceWindowData> for Title {
                         format!("Connecting to SSH: {host} ...")
                     }
                     ProxyStatus::Connected => format!("SSH: {host}"),
+                    ProxyStatus::Reconnecting => {
+                        format!("Reconnecting to SSH: {host} ...")
+                    }
                     ProxyStatus::Disconnected => {
                         format!("Disconnected SSH: {host}")
                     }
@@ -162,6 +165,9 @@ impl Widget<LapceWindowData> for Title {
                 let text = match *tab.proxy_status {
                     ProxyStatus::Connecting => "Connecting to WSL ...".to_string(),
                     ProxyStatus::Connected => "WSL".to_string(),
+                    ProxyStatus::Reconnecting => {
+                        "Reconnecting to WSL ...".to_string()
+                    }
                     ProxyStatus::Disconnected => "Disconnected WSL".to_string(),
                 };
                 let text_layout = ctx
@@ -194,7 +200,9 @@ impl Widget<LapceWindowData> for Title {
             LapceWorkspaceType::Local => Color::rgb8(64, 120, 242),
             LapceWorkspaceType::RemoteSSH(_, _) | LapceWorkspaceType::RemoteWSL => {
                 match *tab.proxy_status {
-                    ProxyStatus::Connecting => Color::rgb8(193, 132, 1),
+                    ProxyStatus::Connecting | ProxyStatus::Reconnecting => {
+                        Color::rgb8(193, 132, 1)
+                    }
                     ProxyStatus::Connected => Color::rgb8(80, 161, 79),
                     ProxyStatus::Disconnected => Color::rgb8(228, 86, 73),
                 }
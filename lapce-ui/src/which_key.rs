@@ -0,0 +1,157 @@
+use druid::{
+    piet::{Text, TextLayout, TextLayoutBuilder},
+    BoxConstraints, Env, Event, EventCtx, FontFamily, LayoutCtx, LifeCycle,
+    LifeCycleCtx, PaintCtx, Point, RenderContext, Size, UpdateCtx, Widget,
+};
+use lapce_data::{config::LapceTheme, data::LapceTabData};
+
+/// The popup listing the keys that can follow a paused key sequence and the
+/// commands they run
+pub struct WhichKey {
+    /// The width of the keys column
+    key_width: f64,
+}
+
+impl WhichKey {
+    const PADDING_X: f64 = 10.0;
+    const PADDING_Y: f64 = 5.0;
+    const LINE_HEIGHT: f64 = 20.0;
+    const FONT_SIZE: f64 = 13.0;
+
+    pub fn new() -> Self {
+        Self { key_width: 0.0 }
+    }
+}
+
+impl Default for WhichKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Widget<LapceTabData> for WhichKey {
+    fn event(
+        &mut self,
+        _ctx: &mut EventCtx,
+        _event: &Event,
+        _data: &mut LapceTabData,
+        _env: &Env,
+    ) {
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &LapceTabData,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        _env: &Env,
+    ) {
+        if old_data.keypress.which_key != data.keypress.which_key {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        _env: &Env,
+    ) -> Size {
+        let items = &data.keypress.which_key;
+        if items.is_empty() {
+            return Size::ZERO;
+        }
+
+        let mut key_width: f64 = 0.0;
+        let mut desc_width: f64 = 0.0;
+        for (key, desc) in items {
+            let key_layout = ctx
+                .text()
+                .new_text_layout(key.clone())
+                .font(FontFamily::SYSTEM_UI, Self::FONT_SIZE)
+                .build()
+                .unwrap();
+            let desc_layout = ctx
+                .text()
+                .new_text_layout(desc.clone())
+                .font(FontFamily::SYSTEM_UI, Self::FONT_SIZE)
+                .build()
+                .unwrap();
+            key_width = key_width.max(key_layout.size().width);
+            desc_width = desc_width.max(desc_layout.size().width);
+        }
+        self.key_width = key_width;
+
+        let width = key_width + desc_width + Self::PADDING_X * 3.0;
+        let height = items.len() as f64 * Self::LINE_HEIGHT + Self::PADDING_Y * 2.0;
+        bc.constrain(Size::new(width, height))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, _env: &Env) {
+        let items = &data.keypress.which_key;
+        if items.is_empty() {
+            return;
+        }
+
+        let size = ctx.size();
+        let rect = size.to_rect();
+        ctx.blurred_rect(
+            rect,
+            5.0,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::HOVER_BACKGROUND),
+        );
+
+        ctx.with_save(|ctx| {
+            ctx.clip(rect);
+            for (i, (key, desc)) in items.iter().enumerate() {
+                let y = Self::PADDING_Y + i as f64 * Self::LINE_HEIGHT;
+                let key_layout = ctx
+                    .text()
+                    .new_text_layout(key.clone())
+                    .font(FontFamily::SYSTEM_UI, Self::FONT_SIZE)
+                    .text_color(
+                        data.config
+                            .get_color_unchecked(LapceTheme::EDITOR_FOCUS)
+                            .clone(),
+                    )
+                    .build()
+                    .unwrap();
+                let text_y =
+                    y + (Self::LINE_HEIGHT - key_layout.size().height) / 2.0;
+                ctx.draw_text(&key_layout, Point::new(Self::PADDING_X, text_y));
+
+                let desc_layout = ctx
+                    .text()
+                    .new_text_layout(desc.clone())
+                    .font(FontFamily::SYSTEM_UI, Self::FONT_SIZE)
+                    .text_color(
+                        data.config
+                            .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                            .clone(),
+                    )
+                    .build()
+                    .unwrap();
+                ctx.draw_text(
+                    &desc_layout,
+                    Point::new(self.key_width + Self::PADDING_X * 2.0, text_y),
+                );
+            }
+        });
+    }
+}
@@ -119,10 +119,22 @@ impl LapceWindowNew {
         index: usize,
     ) {
         if data.tabs.len() == 1 {
+            if data.config.lapce.close_window_on_last_tab {
+                ctx.submit_command(druid::commands::CLOSE_WINDOW);
+            }
             return;
         }
 
         let id = self.tabs[index].id();
+        if let Some(tab) = data.tabs.get(&id) {
+            let unsaved = tab.main_split.unsaved_buffers();
+            if !tab
+                .main_split
+                .confirm_close(&unsaved, ctx.get_external_handle())
+            {
+                return;
+            }
+        }
         self.tabs.remove(index);
         self.tab_headers.remove(index);
         if let Some(tab) = data.tabs.remove(&id) {
@@ -193,6 +205,11 @@ impl Widget<LapceWindowData> for LapceWindowNew {
                     *size
                 };
             }
+            Event::WindowCloseRequested | Event::ApplicationQuit => {
+                // The app delegate only lets the close or quit request
+                // through when it was cancelled because of unsaved changes
+                ctx.set_handled();
+            }
             Event::WindowConnected => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,